@@ -0,0 +1,265 @@
+use std::env;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::copy::{self, Strategy};
+use crate::units;
+
+pub const USAGE: &str = "Usage: bench <source> <destination> [--size SIZE]";
+
+/// Size of the file copied with each strategy, unless `--size` says otherwise.
+const DEFAULT_SIZE: u64 = 64 << 20;
+
+/// Throughput of one strategy between two filesystems.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub strategy: String,
+    /// `None` when the strategy can't be used between these filesystems.
+    pub bytes_per_sec: Option<f64>,
+}
+
+/// The measurements of a `bench` run, kept to pick a strategy for later runs
+/// between the same two filesystems.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchResult {
+    pub source_dev: u64,
+    pub dest_dev: u64,
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    pub measurements: Vec<Measurement>,
+}
+
+impl BenchResult {
+    /// The fastest strategy that worked, among those making a copy rather than a link.
+    pub fn best(&self) -> Option<&Measurement> {
+        self.measurements
+            .iter()
+            .filter(|m| m.bytes_per_sec.is_some() && m.strategy != Strategy::Hardlink.name())
+            .max_by(|a, b| a.bytes_per_sec.partial_cmp(&b.bytes_per_sec).unwrap())
+    }
+}
+
+/// All the results kept, one per pair of filesystems.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BenchResults {
+    pub results: Vec<BenchResult>,
+}
+
+impl BenchResults {
+    pub fn load(path: &Path) -> io::Result<BenchResults> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(BenchResults::default()),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid benchmark results {:?}: {}", path, e),
+            )
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// Keeps `result`, replacing the previous one for the same filesystems.
+    pub fn record(&mut self, result: BenchResult) {
+        self.results
+            .retain(|r| (r.source_dev, r.dest_dev) != (result.source_dev, result.dest_dev));
+        self.results.push(result);
+    }
+
+    /// The result between the filesystems `source_dev` and `dest_dev`, if they were benchmarked.
+    pub fn find(&self, source_dev: u64, dest_dev: u64) -> Option<&BenchResult> {
+        self.results
+            .iter()
+            .find(|r| r.source_dev == source_dev && r.dest_dev == dest_dev)
+    }
+}
+
+/// Where the results are kept: `mv-git/bench.json` in the user's cache directory.
+pub fn results_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    dir.map(|dir| dir.join("mv-git").join("bench.json"))
+}
+
+/// Identifier of the filesystem `path` is on.
+pub fn device(path: &Path) -> io::Result<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(fs::metadata(path)?.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(0)
+    }
+}
+
+/// Copies a file of `size` bytes from `source` to `dest` with every strategy,
+/// flushing each copy to disk before stopping the clock.
+pub fn measure(source: &Path, dest: &Path, size: u64) -> io::Result<BenchResult> {
+    let name = format!(".mv-git-bench-{}", process::id());
+    let src = source.join(&name);
+    {
+        let mut file = File::create(&src)?;
+        let block: Vec<u8> = (0..1 << 20).map(|i| (i * 31 % 251) as u8).collect();
+        let mut left = size;
+        while left > 0 {
+            let n = left.min(block.len() as u64) as usize;
+            file.write_all(&block[..n])?;
+            left -= n as u64;
+        }
+        file.sync_all()?;
+    }
+    let mut measurements = Vec::new();
+    for strategy in Strategy::ALL {
+        let dst = dest.join(format!("{}.copy", name));
+        let started = Instant::now();
+        let copied = copy::copy_with(strategy, &src, &dst).and_then(|written| {
+            File::open(&dst)?.sync_all()?;
+            Ok(written)
+        });
+        let elapsed = started.elapsed().as_secs_f64();
+        let _ = fs::remove_file(&dst);
+        let bytes_per_sec = match copied {
+            Ok(written) => Some(written as f64 / elapsed.max(1e-9)),
+            Err(e) if matches!(e.kind(), ErrorKind::Unsupported | ErrorKind::CrossesDevices) => {
+                None
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&src);
+                return Err(e);
+            }
+        };
+        measurements.push(Measurement {
+            strategy: strategy.name().to_string(),
+            bytes_per_sec,
+        });
+    }
+    fs::remove_file(&src)?;
+    Ok(BenchResult {
+        source_dev: device(source)?,
+        dest_dev: device(dest)?,
+        source: source.to_path_buf(),
+        dest: dest.to_path_buf(),
+        measurements,
+    })
+}
+
+/// The measurements as a table, followed by what to do with them.
+pub fn report(result: &BenchResult) -> String {
+    let mut out = String::new();
+    for m in &result.measurements {
+        let rate = match m.bytes_per_sec {
+            Some(rate) => format!("{}/s", units::format_size(rate as u64)),
+            None => "unsupported".to_string(),
+        };
+        let _ = writeln!(out, "{:<16} {}", m.strategy, rate);
+    }
+    match result.best() {
+        Some(best) => {
+            let _ = writeln!(
+                out,
+                "{} is the fastest, --strategy auto uses it between these filesystems \
+                 from now on, or run with --strategy {}.",
+                best.strategy, best.strategy
+            );
+        }
+        None => out.push_str("No copy strategy worked.\n"),
+    }
+    if result.source_dev == result.dest_dev {
+        out.push_str("Both are on the same filesystem, where hardlink shares the file rather than copying it.\n");
+    }
+    out
+}
+
+/// Runs `bench`, with `args` the arguments after the subcommand.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut dirs = Vec::new();
+    let mut size = DEFAULT_SIZE;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--size" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE))?;
+                size = units::parse_size(value)?;
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("Unknown option: {}\n{}", arg, USAGE).into());
+            }
+            _ => dirs.push(PathBuf::from(arg)),
+        }
+    }
+    let [source, dest] = &dirs[..] else {
+        return Err(USAGE.into());
+    };
+    let result = measure(source, dest, size)?;
+    print!("{}", report(&result));
+    if let Some(path) = results_path() {
+        let mut results = BenchResults::load(&path)?;
+        results.record(result);
+        results.save(&path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_measure() -> io::Result<()> {
+        let dir = tempdir()?;
+        let result = measure(dir.path(), dir.path(), 100_000)?;
+
+        assert_eq!(result.measurements.len(), Strategy::ALL.len());
+        let rate = |name: &str| {
+            result
+                .measurements
+                .iter()
+                .find(|m| m.strategy == name)
+                .and_then(|m| m.bytes_per_sec)
+        };
+        assert!(rate("read-write").is_some());
+        assert!(rate("hardlink").is_some());
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+        assert!(report(&result).ends_with(".\n"));
+
+        let path = dir.path().join("cache").join("bench.json");
+        let mut results = BenchResults::load(&path)?;
+        results.record(result.clone());
+        results.record(result.clone());
+        results.save(&path)?;
+        let results = BenchResults::load(&path)?;
+        assert_eq!(results.results.len(), 1);
+        assert_eq!(
+            results.results[0].measurements.len(),
+            result.measurements.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+
+/// A directory jumper whose database `--update-bookmarks` rewrites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jumper {
+    Zoxide,
+    Autojump,
+    Fasd,
+}
+
+impl Jumper {
+    pub const ALL: [Jumper; 3] = [Jumper::Zoxide, Jumper::Autojump, Jumper::Fasd];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Jumper::Zoxide => "zoxide",
+            Jumper::Autojump => "autojump",
+            Jumper::Fasd => "fasd",
+        }
+    }
+
+    /// Where its database is, as it looks for it, whether or not it exists.
+    pub fn database(self) -> Option<PathBuf> {
+        let home = env::var_os("HOME").map(PathBuf::from);
+        match self {
+            Jumper::Zoxide => {
+                if let Some(dir) = env::var_os("_ZO_DATA_DIR").filter(|dir| !dir.is_empty()) {
+                    return Some(Path::new(&dir).join("db.zo"));
+                }
+                let dir = if cfg!(target_os = "macos") {
+                    home.map(|home| home.join("Library/Application Support"))
+                } else if cfg!(windows) {
+                    env::var_os("LOCALAPPDATA").map(PathBuf::from)
+                } else {
+                    paths::user_data_dir()
+                };
+                dir.map(|dir| dir.join("zoxide/db.zo"))
+            }
+            Jumper::Autojump => {
+                let dir = if cfg!(target_os = "macos") {
+                    home.map(|home| home.join("Library"))
+                } else {
+                    paths::user_data_dir()
+                };
+                dir.map(|dir| dir.join("autojump/autojump.txt"))
+            }
+            Jumper::Fasd => env::var_os("_FASD_DATA")
+                .filter(|file| !file.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| home.map(|home| home.join(".fasd"))),
+        }
+    }
+
+    /// Its database `data` with the entries in moved repositories of `moves`,
+    /// pairs of source and destination, pointing at the destination, and how
+    /// many entries were rewritten.
+    fn rewrite(
+        self,
+        data: &[u8],
+        moves: &[(PathBuf, PathBuf)],
+    ) -> Result<(Vec<u8>, usize), String> {
+        if self == Jumper::Zoxide {
+            return rewrite_zoxide(data, moves);
+        }
+        let text = std::str::from_utf8(data).map_err(|e| e.to_string())?;
+        let mut rewritten = String::new();
+        let mut count = 0;
+        for line in text.split_inclusive('\n') {
+            let (body, end) = match line.strip_suffix('\n') {
+                Some(body) => (body, "\n"),
+                None => (line, ""),
+            };
+            // autojump writes `weight<TAB>path`, fasd `path|rank|time`.
+            let entry = match self {
+                Jumper::Autojump => body
+                    .split_once('\t')
+                    .map(|(weight, path)| (format!("{}\t", weight), path, String::new())),
+                _ => match body.rsplitn(3, '|').collect::<Vec<_>>()[..] {
+                    [time, rank, path] => {
+                        Some((String::new(), path, format!("|{}|{}", rank, time)))
+                    }
+                    _ => None,
+                },
+            };
+            match entry
+                .and_then(|(before, path, after)| Some((before, moved_to(path, moves)?, after)))
+            {
+                Some((before, moved, after)) => {
+                    rewritten.push_str(&format!("{}{}{}{}", before, moved, after, end));
+                    count += 1;
+                }
+                None => rewritten.push_str(line),
+            }
+        }
+        Ok((rewritten.into_bytes(), count))
+    }
+}
+
+/// Where `path` is now, if it is in one of the moved repositories of `moves`.
+fn moved_to(path: &str, moves: &[(PathBuf, PathBuf)]) -> Option<String> {
+    moves.iter().find_map(|(source, dest)| {
+        let rest = Path::new(path).strip_prefix(source).ok()?;
+        let moved = if rest.as_os_str().is_empty() {
+            dest.clone()
+        } else {
+            dest.join(rest)
+        };
+        Some(moved.to_string_lossy().into_owned())
+    })
+}
+
+/// An entry of the zoxide database.
+#[derive(Debug, Clone, PartialEq)]
+struct ZoxideDir {
+    path: String,
+    rank: f64,
+    /// Seconds since the Unix epoch.
+    last_accessed: u64,
+}
+
+/// Version of the zoxide database format that can be rewritten.
+const ZOXIDE_VERSION: u32 = 3;
+
+/// Reads the zoxide database: its version, then its entries as bincode writes
+/// them, little-endian with 64-bit lengths.
+fn decode_zoxide(data: &[u8]) -> Result<Vec<ZoxideDir>, String> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut rest = data;
+    let mut take = |len: usize| -> Result<&[u8], String> {
+        if rest.len() < len {
+            return Err("the zoxide database is truncated".to_string());
+        }
+        let (taken, after) = rest.split_at(len);
+        rest = after;
+        Ok(taken)
+    };
+    let version = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    if version != ZOXIDE_VERSION {
+        return Err(format!("unsupported zoxide database version {}", version));
+    }
+    let count = u64::from_le_bytes(take(8)?.try_into().unwrap());
+    let mut dirs = Vec::new();
+    for _ in 0..count {
+        let len = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let path = std::str::from_utf8(take(len as usize)?)
+            .map_err(|e| e.to_string())?
+            .to_string();
+        let rank = f64::from_le_bytes(take(8)?.try_into().unwrap());
+        let last_accessed = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        dirs.push(ZoxideDir {
+            path,
+            rank,
+            last_accessed,
+        });
+    }
+    if !rest.is_empty() {
+        return Err("unexpected data at the end of the zoxide database".to_string());
+    }
+    Ok(dirs)
+}
+
+fn encode_zoxide(dirs: &[ZoxideDir]) -> Vec<u8> {
+    let mut data = ZOXIDE_VERSION.to_le_bytes().to_vec();
+    data.extend((dirs.len() as u64).to_le_bytes());
+    for dir in dirs {
+        data.extend((dir.path.len() as u64).to_le_bytes());
+        data.extend(dir.path.as_bytes());
+        data.extend(dir.rank.to_le_bytes());
+        data.extend(dir.last_accessed.to_le_bytes());
+    }
+    data
+}
+
+/// Rewrites the zoxide database, merging the moved entries into those already
+/// at their destination the way zoxide does: ranks add up, the latest access wins.
+fn rewrite_zoxide(data: &[u8], moves: &[(PathBuf, PathBuf)]) -> Result<(Vec<u8>, usize), String> {
+    let mut dirs: Vec<ZoxideDir> = Vec::new();
+    let mut count = 0;
+    for mut dir in decode_zoxide(data)? {
+        if let Some(moved) = moved_to(&dir.path, moves) {
+            dir.path = moved;
+            count += 1;
+        }
+        match dirs.iter_mut().find(|known| known.path == dir.path) {
+            Some(known) => {
+                known.rank += dir.rank;
+                known.last_accessed = known.last_accessed.max(dir.last_accessed);
+            }
+            None => dirs.push(dir),
+        }
+    }
+    Ok((encode_zoxide(&dirs), count))
+}
+
+/// Points the entries of the jumpers' databases at the new location of the
+/// repositories of `moves`, pairs of source and destination. Returns how many
+/// entries were rewritten in each database found, or why it couldn't be.
+pub fn update(moves: &[(PathBuf, PathBuf)]) -> Vec<(Jumper, io::Result<usize>)> {
+    let mut updated = Vec::new();
+    for jumper in Jumper::ALL {
+        let Some(database) = jumper.database().filter(|db| db.is_file()) else {
+            continue;
+        };
+        updated.push((jumper, update_database(jumper, &database, moves)));
+    }
+    updated
+}
+
+fn update_database(
+    jumper: Jumper,
+    database: &Path,
+    moves: &[(PathBuf, PathBuf)],
+) -> io::Result<usize> {
+    let data = fs::read(database)?;
+    let (rewritten, count) = jumper
+        .rewrite(&data, moves)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    if count > 0 {
+        // Replaced whole, so that the jumper never reads half of it.
+        let mut tmp = database.as_os_str().to_owned();
+        tmp.push(".mv-git.tmp");
+        fs::write(&tmp, rewritten)?;
+        fs::rename(&tmp, database)?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite() {
+        let moves = vec![(PathBuf::from("/old/api"), PathBuf::from("/new/api"))];
+
+        let autojump = b"22.4\t/old/api\n10.0\t/old/api/src\n5.0\t/old/api-v2\n";
+        let (rewritten, count) = Jumper::Autojump.rewrite(autojump, &moves).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            "22.4\t/new/api\n10.0\t/new/api/src\n5.0\t/old/api-v2\n"
+        );
+
+        let fasd = b"/old/api|12.5|1700000000\n/home/me|3|1700000001";
+        let (rewritten, count) = Jumper::Fasd.rewrite(fasd, &moves).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            "/new/api|12.5|1700000000\n/home/me|3|1700000001"
+        );
+
+        let dir = |path: &str, rank, last_accessed| ZoxideDir {
+            path: path.to_string(),
+            rank,
+            last_accessed,
+        };
+        let zoxide = encode_zoxide(&[
+            dir("/new/api", 1.0, 20),
+            dir("/old/api", 4.0, 10),
+            dir("/home/me", 2.0, 30),
+        ]);
+        let (rewritten, count) = Jumper::Zoxide.rewrite(&zoxide, &moves).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            decode_zoxide(&rewritten).unwrap(),
+            vec![dir("/new/api", 5.0, 20), dir("/home/me", 2.0, 30)]
+        );
+        assert!(decode_zoxide(&zoxide[..zoxide.len() - 1]).is_err());
+    }
+}
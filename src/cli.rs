@@ -0,0 +1,426 @@
+/// An option of the main command line, as listed in completions and the man page.
+#[derive(Debug)]
+pub struct Flag {
+    /// Every spelling of the option, long ones last.
+    pub names: &'static [&'static str],
+    /// What the option's value is, `None` for switches. `FILE` values are completed
+    /// as paths.
+    pub value: Option<&'static str>,
+    /// Values that can be completed, when they are a fixed list.
+    pub choices: &'static [&'static str],
+    pub help: &'static str,
+}
+
+const fn switch(names: &'static [&'static str], help: &'static str) -> Flag {
+    Flag {
+        names,
+        value: None,
+        choices: &[],
+        help,
+    }
+}
+
+const fn valued(names: &'static [&'static str], value: &'static str, help: &'static str) -> Flag {
+    Flag {
+        names,
+        value: Some(value),
+        choices: &[],
+        help,
+    }
+}
+
+const fn choice(
+    names: &'static [&'static str],
+    value: &'static str,
+    choices: &'static [&'static str],
+    help: &'static str,
+) -> Flag {
+    Flag {
+        names,
+        value: Some(value),
+        choices,
+        help,
+    }
+}
+
+/// Options of the main command line, in the order they are documented.
+pub const FLAGS: &[Flag] = &[
+    switch(
+        &["-c", "--copy"],
+        "Copy the repositories instead of moving them",
+    ),
+    switch(
+        &["--sync"],
+        "Copy, skipping files unchanged since the previous sync",
+    ),
+    valued(
+        &["--min-interval"],
+        "DURATION",
+        "With --sync, leave the repositories synced less than this long ago",
+    ),
+    switch(
+        &["--overwrite-conflicts"],
+        "With --sync, also sync the repositories changed at the destination, over those changes",
+    ),
+    switch(
+        &["--compare-contents"],
+        "With --sync, don't copy again the files only touched, such as by a build",
+    ),
+    switch(
+        &["--assume-read-only"],
+        "Never write to the sources, e.g. snapshots or read-only mounts; needs --copy or --sync",
+    ),
+    switch(
+        &["--snapshot"],
+        "Copy from a temporary snapshot of sources on btrfs or ZFS, as they were when it started",
+    ),
+    switch(
+        &["--dedupe-objects"],
+        "Store the objects shared by related repositories once",
+    ),
+    switch(
+        &["--backfill"],
+        "Fetch what partial clones left out before transferring them",
+    ),
+    switch(
+        &["--unshallow"],
+        "Fetch the whole history of shallow clones before transferring them",
+    ),
+    choice(
+        &["--order"],
+        "ORDER",
+        &["largest-first", "smallest-first", "alpha", "mtime"],
+        "Order in which repositories and files are transferred",
+    ),
+    switch(
+        &["--leave-symlink"],
+        "Leave a symlink to the new location of moved repositories",
+    ),
+    switch(
+        &["--leave-junction"],
+        "Leave a junction to the new location of moved repositories",
+    ),
+    choice(
+        &["--preserve"],
+        "ITEMS",
+        &["xattrs", "acl", "selinux"],
+        "Extra metadata to copy, comma separated",
+    ),
+    switch(
+        &["--restorecon"],
+        "Relabel moved repositories with restorecon",
+    ),
+    valued(&["--chown"], "USER[:GROUP]", "Owner of the copied files"),
+    valued(&["--uid-map"], "FROM:TO", "Map the owner of copied files"),
+    valued(&["--gid-map"], "FROM:TO", "Map the group of copied files"),
+    choice(
+        &["--normalize"],
+        "FORM",
+        &["keep", "nfc", "nfd"],
+        "Unicode normalization of the copied file names",
+    ),
+    choice(
+        &["--target-fs"],
+        "FS",
+        &["posix", "ntfs", "exfat", "smb", "fat32"],
+        "Check names and downgrade symlinks and modes for this filesystem, not the detected one",
+    ),
+    switch(&["--special-files"], "Recreate FIFOs at the destination"),
+    valued(
+        &["--memory-limit"],
+        "SIZE",
+        "Spill the pre-scan to disk above this size",
+    ),
+    switch(
+        &["--one-file-system"],
+        "Don't descend into other filesystems",
+    ),
+    switch(
+        &["--prune-git-cruft"],
+        "Leave out temporary packs, lock files, gc.log and old reflogs in .git",
+    ),
+    switch(
+        &["--strip-reflogs"],
+        "Leave out the reflogs in .git, for exports that shouldn't share them",
+    ),
+    choice(
+        &["--hidden"],
+        "POLICY",
+        &["copy", "skip"],
+        "Whether untracked dotfiles outside of .git are transferred",
+    ),
+    valued(
+        &["--max-file-size"],
+        "SIZE",
+        "Leave out files larger than this, e.g. 100M",
+    ),
+    valued(
+        &["--only-extensions"],
+        "EXT,EXT...",
+        "Only transfer the worktree files with these extensions, e.g. rs,toml,md",
+    ),
+    valued(
+        &["--skip-extensions"],
+        "EXT,EXT...",
+        "Leave out the worktree files with these extensions, e.g. mp4,iso",
+    ),
+    valued(&["--buffer-size"], "SIZE", "Size of the copy buffer"),
+    valued(
+        &["--chunk-size"],
+        "SIZE",
+        "Copy large files in chunks of this size",
+    ),
+    switch(&["--direct-io"], "Bypass the page cache when copying"),
+    switch(&["--fsync"], "Flush copied files to disk"),
+    valued(&["--bwlimit"], "RATE", "Limit the copy rate, e.g. 50MB/s"),
+    valued(&["--retries"], "N", "Retries of a file that failed to copy"),
+    valued(
+        &["--retry-delay"],
+        "DURATION",
+        "Delay before the first retry",
+    ),
+    valued(
+        &["--file-timeout"],
+        "DURATION",
+        "Give up on files that take longer to copy",
+    ),
+    valued(
+        &["--time-budget"],
+        "DURATION",
+        "Stop between two files once the run took this long, e.g. 2h",
+    ),
+    valued(
+        &["--byte-budget"],
+        "SIZE",
+        "Stop between two files once the run copied this much, e.g. 200G",
+    ),
+    switch(
+        &["--force-delete"],
+        "Also delete what was not moved, such as ignored files, from the source",
+    ),
+    switch(
+        &["--confirm-delete"],
+        "Copy and verify, then delete the sources once confirmed, now or with finalize",
+    ),
+    switch(
+        &["--to-trash"],
+        "Send the source to the trash, whole, instead of deleting it",
+    ),
+    switch(
+        &["--purge-ignored-at-source"],
+        "Also delete the ignored files left at the source",
+    ),
+    choice(
+        &["--mkdir"],
+        "WHEN",
+        &["auto", "never"],
+        "Whether missing directories leading to the destination are created",
+    ),
+    valued(
+        &["--mode"],
+        "MODE",
+        "Permissions of the directories created, e.g. 755",
+    ),
+    choice(
+        &["--include-non-git"],
+        "WHAT",
+        &["skip", "move", "prompt"],
+        "Whether directories that aren't git repositories are transferred too",
+    ),
+    switch(
+        &["--prune-empty-dirs"],
+        "Remove the directories left empty on both sides",
+    ),
+    switch(
+        &["--flatten"],
+        "Also move nested repositories, directly into the destination",
+    ),
+    switch(
+        &["--preserve-paths"],
+        "Also move nested repositories, to the same path under the destination",
+    ),
+    choice(
+        &["--on-collision"],
+        "POLICY",
+        &["suffix", "fail", "prompt"],
+        "What to do when different repositories would land on the same destination",
+    ),
+    valued(
+        &["--tag"],
+        "TAG",
+        "Only transfer the repositories tagged TAG in the inventory",
+    ),
+    valued(
+        &["--from-query"],
+        "QUERY",
+        "Transfer the repositories of the inventory matching QUERY, e.g. 'size > 1GB'",
+    ),
+    valued(
+        &["--from-workspace"],
+        "FILE",
+        "Transfer the projects of a .code-workspace or JetBrains recentProjects.xml",
+    ),
+    valued(
+        &["--map"],
+        "FILE",
+        "Transfer each repository of a source_path,destination_path CSV where it says",
+    ),
+    valued(
+        &["--from-mrconfig"],
+        "FILE",
+        "Transfer the repositories of a myrepos .mrconfig, and point it at where they moved",
+    ),
+    valued(
+        &["--rewrite-workspace"],
+        "FILE",
+        "Point this workspace, recentProjects.xml or .envrc at the moved repositories",
+    ),
+    switch(
+        &["--update-bookmarks"],
+        "Point zoxide, autojump and fasd entries at the moved repositories",
+    ),
+    choice(
+        &["--strategy"],
+        "HOW",
+        &[
+            "auto",
+            "rename",
+            "reflink",
+            "copy-file-range",
+            "read-write",
+            "mmap",
+        ],
+        "How repositories are transferred, auto picking the fastest known",
+    ),
+    valued(
+        &["--rename"],
+        "PATTERN",
+        "Name repositories at the destination, e.g. {name}-{year} or s/old/new/",
+    ),
+    switch(
+        &["--to-ghq"],
+        "Move into the ghq root as host/owner/name, after the origin remote",
+    ),
+    valued(
+        &["--shard-across"],
+        "PATH,PATH...",
+        "Spread the repositories across these destinations, instead of one",
+    ),
+    valued(
+        &["--also-dest"],
+        "PATH",
+        "Also copy to this destination, can be repeated",
+    ),
+    choice(
+        &["--error-policy"],
+        "POLICY",
+        &["abort", "skip-file", "skip-repo"],
+        "On errors, for both phases or as copy=POLICY,delete=POLICY",
+    ),
+    choice(
+        &["--recover"],
+        "HOW",
+        &["finish", "rollback", "clean"],
+        "What to do with repositories an interrupted run left half-moved",
+    ),
+    valued(&["--nice"], "N", "Run with this nice level"),
+    choice(
+        &["--ionice"],
+        "CLASS",
+        &["idle", "best-effort", "realtime"],
+        "Run with this I/O scheduling class",
+    ),
+    switch(&["-v", "--verbose"], "Log more, can be repeated"),
+    switch(&["-vv"], "Log everything"),
+    switch(&["-q"], "Log less"),
+    switch(&["--quiet"], "Only print errors"),
+    choice(
+        &["--color"],
+        "WHEN",
+        &["auto", "always", "never"],
+        "Color the output",
+    ),
+    valued(
+        &["--log-file"],
+        "FILE",
+        "Also append the log to this file, with timestamps",
+    ),
+    valued(
+        &["--report"],
+        "FILE",
+        "Write the outcome of the run to this JSON file",
+    ),
+    switch(
+        &["--notify"],
+        "Show a desktop notification when the run is over",
+    ),
+    valued(
+        &["--webhook"],
+        "URL",
+        "POST the summary to this URL when the run is over",
+    ),
+    valued(&["--watch"], "INTERVAL", "Run again after each interval"),
+    valued(
+        &["--metrics"],
+        "ADDR",
+        "Serve Prometheus metrics of --watch runs on this address",
+    ),
+];
+
+/// Subcommands, with what they do.
+pub const SUBCOMMANDS: &[(&str, &str)] = &[
+    (
+        "install-service",
+        "Schedule --sync runs with a systemd timer or a launchd agent",
+    ),
+    (
+        "plan",
+        "Write what a run would do to a JSON file, for review",
+    ),
+    ("apply", "Carry out a plan written by plan"),
+    (
+        "diff",
+        "Compare the repositories of two directories, their HEAD and dirty state",
+    ),
+    (
+        "repair",
+        "Fix the paths recorded in a repository moved without mv-git",
+    ),
+    (
+        "list",
+        "List the repositories mv-git knows of, from its JSON inventory",
+    ),
+    ("tag", "Tag a repository in the inventory, for --tag"),
+    ("export", "Write the inventory as CSV or JSON, for reports"),
+    (
+        "query",
+        "List the repositories of the inventory matching an expression",
+    ),
+    ("history", "List the past runs kept as JSON for verify-op"),
+    (
+        "verify-op",
+        "Check that the files a past run transferred are still as it left them",
+    ),
+    (
+        "finalize",
+        "Delete the sources kept by a --confirm-delete run",
+    ),
+    (
+        "bench",
+        "Measure the copy strategies between two directories and recommend flags",
+    ),
+    (
+        "serve",
+        "Answer JSON-RPC requests on stdio, for frontends and editor plugins",
+    ),
+    (
+        "daemon",
+        "Run the moves queued with ctl one after the other, on a Unix socket",
+    ),
+    (
+        "ctl",
+        "Queue a move with the daemon, or list, check or cancel its jobs",
+    ),
+    ("completions", "Print a shell completion script"),
+    ("man", "Print this man page"),
+];
@@ -0,0 +1,275 @@
+use std::fmt::Write as _;
+
+use crate::cli::{Flag, FLAGS, SUBCOMMANDS};
+
+pub const USAGE: &str = "Usage: completions <bash|zsh|fish|powershell>";
+
+const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+/// The completion script for `shell`.
+pub fn script(shell: &str) -> Result<String, String> {
+    match shell {
+        "bash" => Ok(bash()),
+        "zsh" => Ok(zsh()),
+        "fish" => Ok(fish()),
+        "powershell" => Ok(powershell()),
+        _ => Err(format!(
+            "Unknown shell {:?}, expected bash, zsh, fish or powershell",
+            shell
+        )),
+    }
+}
+
+fn names(flags: impl Iterator<Item = &'static Flag>) -> Vec<&'static str> {
+    flags.flat_map(|flag| flag.names.iter().copied()).collect()
+}
+
+fn bash() -> String {
+    let mut values = String::new();
+    for flag in FLAGS.iter().filter(|flag| flag.value.is_some()) {
+        let reply = if !flag.choices.is_empty() {
+            format!(
+                "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                flag.choices.join(" ")
+            )
+        } else if flag.value == Some("FILE") {
+            "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+        } else {
+            "COMPREPLY=()".to_string()
+        };
+        let _ = writeln!(
+            values,
+            "        {})\n            {}\n            return\n            ;;",
+            flag.names.join("|"),
+            reply
+        );
+    }
+    let subcommands: Vec<&str> = SUBCOMMANDS.iter().map(|(name, _)| *name).collect();
+    format!(
+        r#"_mv_git() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    if [[ $COMP_CWORD -eq 2 && $prev == completions ]]; then
+        COMPREPLY=($(compgen -W "{shells}" -- "$cur"))
+        return
+    fi
+    case "$prev" in
+{values}    esac
+    if [[ $cur == -* ]]; then
+        COMPREPLY=($(compgen -W "{flags}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "{subcommands}" -- "$cur") $(compgen -d -- "$cur"))
+    else
+        COMPREPLY=($(compgen -d -- "$cur"))
+    fi
+}}
+complete -o filenames -F _mv_git mv-git
+"#,
+        shells = SHELLS.join(" "),
+        values = values,
+        flags = names(FLAGS.iter()).join(" "),
+        subcommands = subcommands.join(" "),
+    )
+}
+
+/// `text` escaped for a single-quoted `_arguments` spec.
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh() -> String {
+    let mut specs = String::new();
+    for flag in FLAGS {
+        for name in flag.names {
+            let action = match flag.value {
+                None => String::new(),
+                Some(_) if !flag.choices.is_empty() => {
+                    format!(":value:({})", flag.choices.join(" "))
+                }
+                Some("FILE") => ":file:_files".to_string(),
+                Some(value) => format!(":{}: ", zsh_escape(value)),
+            };
+            let _ = writeln!(
+                specs,
+                "        '{}[{}]{}' \\",
+                name,
+                zsh_escape(flag.help),
+                action
+            );
+        }
+    }
+    let subcommands: Vec<String> = SUBCOMMANDS
+        .iter()
+        .map(|(name, help)| format!("{}\\:{}", name, zsh_escape(&format!("\"{}\"", help))))
+        .collect();
+    format!(
+        r#"#compdef mv-git
+
+_mv_git() {{
+    if (( CURRENT == 3 )) && [[ $words[2] == completions ]]; then
+        _values shell {shells}
+        return
+    fi
+    if (( CURRENT == 2 )) && [[ $words[2] != -* ]]; then
+        _alternative \
+            'commands:command:(({subcommands}))' \
+            'directories:source:_files -/'
+        return
+    fi
+    _arguments \
+{specs}        '*:directory:_files -/'
+}}
+
+_mv_git "$@"
+"#,
+        shells = SHELLS.join(" "),
+        subcommands = subcommands.join(" "),
+        specs = specs,
+    )
+}
+
+/// `text` as a fish single-quoted string.
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish() -> String {
+    let mut out = String::from("complete -c mv-git -f -a '(__fish_complete_directories)'\n");
+    for (name, help) in SUBCOMMANDS {
+        let _ = writeln!(
+            out,
+            "complete -c mv-git -n __fish_use_subcommand -a {} -d {}",
+            name,
+            fish_quote(help)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "complete -c mv-git -n '__fish_seen_subcommand_from completions' -f -a {}",
+        fish_quote(&SHELLS.join(" "))
+    );
+    for flag in FLAGS {
+        let mut line = String::from("complete -c mv-git");
+        for name in flag.names {
+            let _ = match name.strip_prefix("--") {
+                Some(long) => write!(line, " -l {}", long),
+                None if name.len() == 2 => write!(line, " -s {}", &name[1..]),
+                None => write!(line, " -o {}", &name[1..]),
+            };
+        }
+        match flag.value {
+            None => {}
+            Some(_) if !flag.choices.is_empty() => {
+                let _ = write!(line, " -x -a {}", fish_quote(&flag.choices.join(" ")));
+            }
+            Some("FILE") => line.push_str(" -r -F"),
+            Some(_) => line.push_str(" -x"),
+        }
+        let _ = writeln!(out, "{} -d {}", line, fish_quote(flag.help));
+    }
+    out
+}
+
+/// `text` as a PowerShell single-quoted string.
+fn powershell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+fn powershell() -> String {
+    let mut choices = String::new();
+    let _ = writeln!(
+        choices,
+        "        'completions' {{ {} }}",
+        SHELLS
+            .iter()
+            .map(|s| powershell_quote(s))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    for flag in FLAGS.iter().filter(|flag| !flag.choices.is_empty()) {
+        let values: Vec<String> = flag.choices.iter().map(|c| powershell_quote(c)).collect();
+        for name in flag.names {
+            let _ = writeln!(choices, "        '{}' {{ {} }}", name, values.join(", "));
+        }
+    }
+    let mut results = String::new();
+    for flag in FLAGS {
+        for name in flag.names {
+            let _ = writeln!(
+                results,
+                "        [System.Management.Automation.CompletionResult]::new('{name}', '{name}', 'ParameterName', {help})",
+                name = name,
+                help = powershell_quote(flag.help)
+            );
+        }
+    }
+    let mut subcommands = String::new();
+    for (name, help) in SUBCOMMANDS {
+        let _ = writeln!(
+            subcommands,
+            "            [System.Management.Automation.CompletionResult]::new('{name}', '{name}', 'Command', {help})",
+            name = name,
+            help = powershell_quote(help)
+        );
+    }
+    format!(
+        r#"Register-ArgumentCompleter -Native -CommandName 'mv-git' -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $elements = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+    $previous = if ($wordToComplete) {{ $elements[-2] }} else {{ $elements[-1] }}
+    $values = switch ($previous) {{
+{choices}    }}
+    if ($values) {{
+        $values | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+            [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+        }}
+        return
+    }}
+    if ($wordToComplete -like '-*') {{
+        @(
+{results}        ) | Where-Object {{ $_.CompletionText -like "$wordToComplete*" }}
+    }} elseif ($elements.Count -le 2) {{
+        @(
+{subcommands}        ) | Where-Object {{ $_.CompletionText -like "$wordToComplete*" }}
+    }}
+}}
+"#,
+        choices = choices,
+        results = results,
+        subcommands = subcommands,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let bash = script("bash").unwrap();
+        assert!(bash.contains("complete -o filenames -F _mv_git mv-git"));
+        assert!(bash.contains(
+            "        --color)\n            COMPREPLY=($(compgen -W \"auto always never\" -- \"$cur\"))"
+        ));
+        assert!(bash.contains(" --dedupe-objects "));
+
+        let zsh = script("zsh").unwrap();
+        assert!(zsh.starts_with("#compdef mv-git\n"));
+        assert!(zsh.contains("'--color[Color the output]:value:(auto always never)' \\\n"));
+
+        let fish = script("fish").unwrap();
+        assert!(fish.contains(
+            "complete -c mv-git -s c -l copy -d 'Copy the repositories instead of moving them'\n"
+        ));
+        assert!(fish.contains("complete -c mv-git -l report -r -F -d"));
+        assert!(fish.contains("complete -c mv-git -o vv -d 'Log everything'\n"));
+
+        let powershell = script("powershell").unwrap();
+        assert!(powershell.contains("        '--color' { 'auto', 'always', 'never' }\n"));
+
+        assert!(script("tcsh").is_err());
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::cli::SUBCOMMANDS;
+use crate::git;
+use crate::paths;
+
+/// Name of the file a repository can carry at its root to change how it is transferred.
+pub const REPO_CONFIG_FILE: &str = ".mv-git.toml";
+
+/// Settings read from `config.toml`.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Command lines run as `mv-git <name> [more arguments]`, from the `[alias]` table.
+    pub alias: BTreeMap<String, String>,
+    /// Names left out of every repository, on top of its `.gitignore`.
+    pub exclude: Vec<String>,
+    /// Repositories never moved, from the `[pinned]` table.
+    pub pinned: Pinned,
+    /// Files naming repositories by path, from the `[[references]]` tables.
+    pub references: Vec<Reference>,
+}
+
+/// A file of another tool that names repositories by path, such as the
+/// repository list of gita or the `.mrconfig` of myrepos, and is pointed at
+/// the new location of those that moved.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Reference {
+    pub file: PathBuf,
+    /// The directory the file names repositories relative to, if it does, on
+    /// top of the absolute paths it names them by.
+    pub relative_to: Option<PathBuf>,
+}
+
+/// Repositories left where they are whatever the command line says, by path or
+/// by the URL of one of their remotes.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pinned {
+    pub paths: Vec<PathBuf>,
+    /// Matched whatever the scheme or user, e.g. `github.com/me/dotfiles` pins
+    /// `git@github.com:me/dotfiles.git`.
+    pub remotes: Vec<String>,
+}
+
+/// `url` without scheme, user and `.git` suffix, with `host:path` written as `host/path`.
+pub fn normalize_remote(url: &str) -> String {
+    let (rest, scp_like) = match url.split_once("://") {
+        Some((_, rest)) => (rest, false),
+        None => (url, true),
+    };
+    let rest = match rest.split_once('@') {
+        Some((user, rest)) if !user.contains('/') => rest,
+        _ => rest,
+    };
+    let mut url = if scp_like {
+        rest.replacen(':', "/", 1)
+    } else {
+        rest.to_string()
+    };
+    while url.ends_with('/') {
+        url.pop();
+    }
+    if let Some(stripped) = url.strip_suffix(".git") {
+        url.truncate(stripped.len());
+    }
+    url
+}
+
+impl Pinned {
+    /// Why `repo` is pinned, `None` if it isn't.
+    pub fn reason(&self, repo: &Path) -> Option<String> {
+        if !self.paths.is_empty() {
+            let repo = fs::canonicalize(repo).ok()?;
+            if self
+                .paths
+                .iter()
+                .any(|path| fs::canonicalize(path).is_ok_and(|path| path == repo))
+            {
+                return Some("pinned by path in the config".to_string());
+            }
+        }
+        if self.remotes.is_empty() {
+            return None;
+        }
+        let pinned: Vec<String> = self.remotes.iter().map(|r| normalize_remote(r)).collect();
+        git::remote_urls(repo)
+            .into_iter()
+            .find(|url| pinned.contains(&normalize_remote(url)))
+            .map(|url| format!("pinned by its remote {} in the config", url))
+    }
+}
+
+/// Settings of one repository, read from its `.mv-git.toml`.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    /// Names left out, on top of the `.gitignore` and the global `exclude`.
+    pub exclude: Vec<String>,
+    /// Where the repository goes, relative to the destination, instead of its name.
+    pub dest: Option<PathBuf>,
+    /// Never move, copy or sync the repository.
+    pub never_move: bool,
+}
+
+impl RepoConfig {
+    /// Reads the `.mv-git.toml` of `repo`, empty if there is none.
+    pub fn load(repo: &Path) -> io::Result<RepoConfig> {
+        let path = repo.join(REPO_CONFIG_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(RepoConfig::default()),
+            Err(e) => return Err(e),
+        };
+        let config: RepoConfig = toml::from_str(&text).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e.message()),
+            )
+        })?;
+        if let Some(dest) = &config.dest {
+            if !dest
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{}: dest must be a path inside the destination, not {:?}",
+                        path.display(),
+                        dest
+                    ),
+                ));
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Where the config is read from: `$MV_GIT_CONFIG`, or `mv-git/config.toml` in the
+/// user's config directory.
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("MV_GIT_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    dir.map(|dir| dir.join("mv-git").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the config at `path`, empty if there is no such file.
+    pub fn load(path: &Path) -> io::Result<Config> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e),
+        };
+        let mut config: Config = toml::from_str(&text).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e.message()),
+            )
+        })?;
+        let references = config.references.iter_mut().flat_map(|reference| {
+            [Some(&mut reference.file), reference.relative_to.as_mut()]
+                .into_iter()
+                .flatten()
+        });
+        for value in config.pinned.paths.iter_mut().chain(references) {
+            let expanded = paths::expand(&value.to_string_lossy()).map_err(|e| {
+                io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+            })?;
+            *value = PathBuf::from(expanded);
+        }
+        Ok(config)
+    }
+
+    /// `args` with the alias named by its first argument after the program name
+    /// replaced by the arguments it stands for. Aliases are not expanded recursively,
+    /// and can't replace subcommands.
+    pub fn expand_alias(&self, args: Vec<String>) -> Result<Vec<String>, String> {
+        let Some(name) = args.get(1) else {
+            return Ok(args);
+        };
+        if SUBCOMMANDS.iter().any(|(subcommand, _)| subcommand == name) {
+            return Ok(args);
+        }
+        let Some(alias) = self.alias.get(name) else {
+            return Ok(args);
+        };
+        let mut expanded = vec![args[0].clone()];
+        expanded
+            .extend(split_words(alias).map_err(|e| format!("Invalid alias {:?}: {}", args[1], e))?);
+        expanded.extend(args.into_iter().skip(2));
+        Ok(expanded)
+    }
+}
+
+/// Splits `line` into words like a POSIX shell would, without expanding anything:
+/// on unquoted whitespace, with `'...'`, `"..."` and backslash escapes.
+pub fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing backslash".to_string()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words(r#"a  'b c' "d \"e\"" f\ g ''"#),
+            Ok(strings(&["a", "b c", "d \"e\"", "f g", ""]))
+        );
+        assert!(split_words("'open").is_err());
+    }
+
+    #[test]
+    fn test_repo_config() -> io::Result<()> {
+        let dir = tempdir()?;
+        assert_eq!(RepoConfig::load(dir.path())?, RepoConfig::default());
+
+        let path = dir.path().join(REPO_CONFIG_FILE);
+        fs::write(&path, "exclude = [\"data\"]\ndest = \"work/api\"\n")?;
+        let config = RepoConfig::load(dir.path())?;
+        assert_eq!(config.exclude, vec!["data"]);
+        assert_eq!(config.dest, Some(PathBuf::from("work/api")));
+        assert!(!config.never_move);
+
+        fs::write(&path, "dest = \"../outside\"\n")?;
+        assert!(RepoConfig::load(dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pinned() -> io::Result<()> {
+        assert_eq!(
+            normalize_remote("git@github.com:me/dotfiles.git"),
+            "github.com/me/dotfiles"
+        );
+        assert_eq!(
+            normalize_remote("https://user@github.com/me/dotfiles/"),
+            "github.com/me/dotfiles"
+        );
+
+        let dir = tempdir()?;
+        let repo = dir.path().join("dotfiles");
+        git::init_repo_with_commit(&repo, "README.md")?;
+        git::git(
+            &repo,
+            ["remote", "add", "origin", "git@github.com:me/dotfiles.git"],
+        )?;
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            format!(
+                "[pinned]\npaths = [{:?}]\nremotes = [\"https://github.com/me/dotfiles\"]\n",
+                dir.path().join("notes")
+            ),
+        )?;
+        let pinned = Config::load(&path)?.pinned;
+        assert!(pinned.reason(&repo).unwrap().contains("remote"));
+        assert_eq!(pinned.reason(dir.path()), None);
+
+        fs::create_dir(dir.path().join("notes"))?;
+        assert!(pinned.reason(&dir.path().join("notes")).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_references() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            format!(
+                "[[references]]\nfile = \"{0}/gita/repos.csv\"\n\n\
+                 [[references]]\nfile = \"{0}/.mrconfig\"\nrelative_to = \"{0}\"\n",
+                dir.path().display()
+            ),
+        )?;
+        assert_eq!(
+            Config::load(&path)?.references,
+            vec![
+                Reference {
+                    file: dir.path().join("gita/repos.csv"),
+                    relative_to: None,
+                },
+                Reference {
+                    file: dir.path().join(".mrconfig"),
+                    relative_to: Some(dir.path().to_path_buf()),
+                },
+            ]
+        );
+
+        fs::write(&path, "[[references]]\npath = \"repos.csv\"\n")?;
+        assert!(Config::load(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_alias() -> io::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        assert_eq!(Config::load(&path)?, Config::default());
+
+        fs::write(
+            &path,
+            "[alias]\narchive-old = \"~/code '/mnt/old code' --copy\"\n",
+        )?;
+        let config = Config::load(&path)?;
+        assert_eq!(
+            config.expand_alias(strings(&["mv-git", "archive-old", "-v"])),
+            Ok(strings(&[
+                "mv-git",
+                "~/code",
+                "/mnt/old code",
+                "--copy",
+                "-v"
+            ]))
+        );
+        assert_eq!(
+            config.expand_alias(strings(&["mv-git", "src", "dst"])),
+            Ok(strings(&["mv-git", "src", "dst"]))
+        );
+
+        fs::write(&path, "[aliases]\n")?;
+        assert!(Config::load(&path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1385 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crc32fast::Hasher;
+
+use crate::downgrade::Downgrade;
+use crate::policy::ErrorPolicy;
+use crate::stats::Stats;
+use crate::{journal, metadata, owner, paths, signals};
+
+const MIN_BUFFER_SIZE: usize = 128 * 1024;
+#[cfg(unix)]
+const MAX_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+/// Alignment required for `O_DIRECT` buffers, offsets and sizes.
+const DIRECT_IO_ALIGN: usize = 4096;
+
+/// Files from this size on are copied from memory maps with [`Strategy::Mmap`],
+/// smaller ones through a buffer, unless `mmap_min_size` says otherwise.
+const MMAP_MIN_SIZE: u64 = 1 << 30;
+
+/// Size of the part of a file mapped at a time, a multiple of every page size.
+const MMAP_WINDOW: usize = 64 << 20;
+
+/// Most bytes a single `copy_file_range` call copies, for the progress of large
+/// files to move along.
+#[cfg(target_os = "linux")]
+const KERNEL_COPY_SIZE: usize = 64 << 20;
+
+/// Caps the throughput of every copy sharing it, by sleeping whenever the bytes
+/// written so far are ahead of what the rate allows.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: u64,
+    state: Mutex<(Instant, u64)>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Throttle {
+        Throttle {
+            bytes_per_sec: bytes_per_sec.max(1),
+            state: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Accounts for `bytes` just written, blocking as long as needed to stay under the rate.
+    pub fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let (start, total) = &mut *state;
+            *total += bytes;
+            let due = Duration::from_secs_f64(*total as f64 / self.bytes_per_sec as f64);
+            due.saturating_sub(start.elapsed())
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Accounts for the blocks a copy writes as it goes: against the bandwidth
+/// limit, and in the progress of the run until the whole file is recorded.
+#[derive(Debug, Default)]
+pub struct Written<'a> {
+    throttle: Option<&'a Throttle>,
+    stats: Option<&'a Stats>,
+    abandoned: Option<&'a AtomicBool>,
+    bytes: u64,
+}
+
+impl<'a> Written<'a> {
+    fn new(options: &'a CopyOptions) -> Written<'a> {
+        Written {
+            throttle: options.bwlimit.as_deref(),
+            stats: Some(&options.stats),
+            abandoned: options.abandoned.as_deref(),
+            bytes: 0,
+        }
+    }
+
+    /// Accounts for `bytes` just written, blocking as long as the bandwidth
+    /// limit needs. Fails once the copy was abandoned, so it stops writing.
+    pub fn add(&mut self, bytes: u64) -> io::Result<()> {
+        self.bytes += bytes;
+        if let Some(stats) = self.stats {
+            stats.add_in_flight(bytes);
+        }
+        if let Some(throttle) = self.throttle {
+            throttle.consume(bytes);
+        }
+        if self
+            .abandoned
+            .is_some_and(|abandoned| abandoned.load(Ordering::Relaxed))
+        {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "copy abandoned"));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Written<'_> {
+    fn drop(&mut self) {
+        if let Some(stats) = self.stats {
+            stats.remove_in_flight(self.bytes);
+        }
+    }
+}
+
+/// Limits past which a run stops between two files, see `--time-budget` and
+/// `--byte-budget`.
+#[derive(Debug, Default)]
+pub struct Budget {
+    pub time: Option<Duration>,
+    pub bytes: Option<u64>,
+    /// When the run started, and the bytes copied before it.
+    start: Mutex<Option<(Instant, u64)>>,
+}
+
+impl Budget {
+    pub fn new(time: Option<Duration>, bytes: Option<u64>) -> Budget {
+        Budget {
+            time,
+            bytes,
+            start: Mutex::new(None),
+        }
+    }
+
+    /// Starts counting the time and bytes of a new run.
+    pub fn start(&self, stats: &Stats) {
+        *self.start.lock().unwrap() = Some((Instant::now(), stats.totals().bytes));
+    }
+
+    /// The budget the run has spent, if it has spent one.
+    pub fn spent(&self, stats: &Stats) -> Option<&'static str> {
+        if self.time.is_none() && self.bytes.is_none() {
+            return None;
+        }
+        let (started, bytes_before) = *self
+            .start
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| (Instant::now(), stats.totals().bytes));
+        if self.time.is_some_and(|time| started.elapsed() >= time) {
+            return Some("time");
+        }
+        let copied = stats.totals().bytes - bytes_before;
+        if self.bytes.is_some_and(|bytes| copied >= bytes) {
+            return Some("byte");
+        }
+        None
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CopyOptions {
+    /// Buffer used by read/write copies; autodetected from the filesystems'
+    /// preferred I/O size when unset.
+    pub buffer_size: Option<usize>,
+    /// Read sources bypassing the page cache (`O_DIRECT` on Linux, `F_NOCACHE`
+    /// on macOS). Ignored on other platforms.
+    pub direct_io: bool,
+    /// Bandwidth limit shared by all copies of the run.
+    pub bwlimit: Option<Arc<Throttle>>,
+    /// How many times a file is copied again after a transient error.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each following one.
+    pub retry_delay: Duration,
+    /// Recreate FIFOs at the destination instead of skipping them.
+    pub special_files: bool,
+    pub preserve: metadata::Preserve,
+    /// Unicode normalization of the names created at the destination.
+    pub normalization: paths::Normalization,
+    pub ownership: owner::Ownership,
+    /// Flush every copied file and directory to disk before reporting success.
+    pub fsync: bool,
+    /// Give up on a file whose copy takes longer than this, e.g. because a read
+    /// hangs on a failing disk.
+    pub file_timeout: Option<Duration>,
+    /// Copy files of at least `journal::MIN_CHUNKS` chunks of this size in
+    /// resumable chunks, when they are copied through a buffer.
+    pub chunk_size: Option<u64>,
+    /// Counters of what the run copied.
+    pub stats: Arc<Stats>,
+    /// What to do when a file fails to copy.
+    pub on_error: ErrorPolicy,
+    /// How contents are copied, the platform's fastest way when unset. Files that
+    /// can't be cloned by [`Strategy::Reflink`] are copied the default way.
+    pub strategy: Option<Strategy>,
+    /// Set to stop the run after the file being copied, see `ctl cancel`.
+    pub cancel: Arc<AtomicBool>,
+    pub budget: Arc<Budget>,
+    /// What the destination can't hold, see [`Downgrade`].
+    pub downgrade: Option<Arc<Downgrade>>,
+    /// Where the checksums of the files read to copy them are kept, when the
+    /// copies are verified. Files are then copied through a buffer, unless
+    /// they are cloned.
+    pub checksums: Option<Arc<Checksums>>,
+    /// Size from which files are copied from memory maps with [`Strategy::Mmap`],
+    /// `MMAP_MIN_SIZE` when unset.
+    pub mmap_min_size: Option<u64>,
+    /// Set when a copy timed out, for it to stop at its next block. Only given
+    /// to copies running in a thread of their own, see `file_timeout`.
+    pub abandoned: Option<Arc<AtomicBool>>,
+}
+
+impl CopyOptions {
+    /// Whether the run was asked to stop, by `ctl cancel` or a signal, or spent
+    /// its budget.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+            || signals::received().is_some()
+            || self.budget.spent(&self.stats).is_some()
+    }
+
+    /// Whether the copy has to go through our own read/write loop rather than
+    /// the kernel or platform copy routines.
+    fn needs_buffered_copy(&self) -> bool {
+        self.buffer_size.is_some()
+            || self.direct_io
+            || self.bwlimit.is_some()
+            || matches!(self.strategy, Some(Strategy::ReadWrite | Strategy::Mmap))
+            || (self.checksums.is_some() && self.strategy != Some(Strategy::Reflink))
+    }
+}
+
+/// CRC32 of source files, computed from the buffers read to copy them, so that
+/// their copies can be verified without reading them again.
+#[derive(Debug, Default)]
+pub struct Checksums(Mutex<HashMap<PathBuf, u32>>);
+
+impl Checksums {
+    fn record(&self, src: &Path, crc: u32) {
+        self.0.lock().unwrap().insert(src.to_path_buf(), crc);
+    }
+
+    /// The checksum of `src` when it was copied, if it was read to copy it.
+    pub fn take(&self, src: &Path) -> Option<u32> {
+        self.0.lock().unwrap().remove(src)
+    }
+}
+
+/// CRC32 of the contents of the file `path`.
+pub fn checksum(path: &Path) -> io::Result<u32> {
+    let mut file = File::open(path)?;
+    let mut hasher = Hasher::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hasher.finalize()),
+            n => hasher.update(&buf[..n]),
+        }
+    }
+}
+
+/// A buffer size fitting both files: a generous multiple of their preferred I/O
+/// size, so large-block network and RAID filesystems get large requests.
+fn auto_buffer_size(src: &File, dst: &File) -> io::Result<usize> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let blksize = src.metadata()?.blksize().max(dst.metadata()?.blksize()) as usize;
+        Ok((blksize * 64).clamp(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (src, dst);
+        Ok(MIN_BUFFER_SIZE * 8)
+    }
+}
+
+/// Opens `src` for reading, bypassing the page cache when `direct` is set and the
+/// filesystem allows it.
+fn open_source(src: &Path, direct: bool) -> io::Result<File> {
+    #[cfg(target_os = "linux")]
+    if direct {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        match fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(src)
+        {
+            Ok(file) => return Ok(file),
+            // tmpfs and some FUSE filesystems don't support O_DIRECT.
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let file = File::open(src)?;
+    #[cfg(target_os = "macos")]
+    if direct {
+        use std::os::unix::io::AsRawFd;
+
+        unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) };
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let _ = direct;
+    Ok(file)
+}
+
+/// Copies with plain `read`/`write` calls through a userspace buffer of `size`
+/// bytes, rounded up and aligned so it is also usable with `O_DIRECT`.
+/// Each block read is written to every one of `dsts`, and fed to `hasher`.
+fn buffered_copy(
+    src: &mut File,
+    dsts: &mut [File],
+    size: usize,
+    progress: &mut Written,
+    mut hasher: Option<&mut Hasher>,
+) -> io::Result<u64> {
+    let size = size.max(1).next_multiple_of(DIRECT_IO_ALIGN);
+    let mut raw = vec![0; size + DIRECT_IO_ALIGN];
+    let offset = raw.as_ptr().align_offset(DIRECT_IO_ALIGN);
+    let buf = &mut raw[offset..offset + size];
+
+    let mut written = 0;
+    loop {
+        let n = match src.read(buf) {
+            Ok(0) => return Ok(written),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for dst in dsts.iter_mut() {
+            dst.write_all(&buf[..n])?;
+        }
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&buf[..n]);
+        }
+        written += n as u64;
+        progress.add(n as u64)?;
+    }
+}
+
+/// Copies in the kernel with `copy_file_range`, which also lets filesystems that
+/// support it share extents instead of duplicating data.
+///
+/// Returns `Ok(None)` when the syscall can't be used for this pair of files, before
+/// anything was written, so the caller can fall back to a buffered copy.
+#[cfg(target_os = "linux")]
+fn kernel_copy(src: &File, dst: &File, progress: &mut Written) -> io::Result<Option<u64>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut written: u64 = 0;
+    loop {
+        let n = unsafe {
+            libc::copy_file_range(
+                src.as_raw_fd(),
+                std::ptr::null_mut(),
+                dst.as_raw_fd(),
+                std::ptr::null_mut(),
+                KERNEL_COPY_SIZE,
+                0,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                Some(
+                    libc::ENOSYS | libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP | libc::EPERM,
+                ) if written == 0 => return Ok(None),
+                _ => return Err(err),
+            }
+        }
+        if n == 0 {
+            break;
+        }
+        written += n as u64;
+        progress.add(n as u64)?;
+    }
+
+    // Some pseudo filesystems (procfs, sysfs) report no data through
+    // `copy_file_range` even though reading them works.
+    if written == 0 && src.metadata()?.len() > 0 {
+        return Ok(None);
+    }
+    Ok(Some(written))
+}
+
+/// Copies `src` to every one of `dsts` by mapping it into memory, `window` bytes
+/// at a time, and writing straight from the mapping. This spares the copy into
+/// a buffer where the kernel has no copy routine of its own. As with any memory
+/// map, the run dies with `SIGBUS` if the file is truncated meanwhile.
+#[cfg(unix)]
+fn mmap_copy(
+    src: &File,
+    dsts: &mut [File],
+    window: usize,
+    progress: &mut Written,
+    mut hasher: Option<&mut Hasher>,
+) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    let len = src.metadata()?.len();
+    let mut offset = 0;
+    while offset < len {
+        let size = (len - offset).min(window as u64) as usize;
+        let map = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                src.as_raw_fd(),
+                offset as libc::off_t,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe {
+            libc::madvise(map, size, libc::MADV_SEQUENTIAL);
+        }
+        let data = unsafe { std::slice::from_raw_parts(map as *const u8, size) };
+        // Written in blocks, for throttled copies to keep a steady pace.
+        let written = data.chunks(MAX_BUFFER_SIZE).try_for_each(|block| {
+            for dst in dsts.iter_mut() {
+                dst.write_all(block)?;
+            }
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(block);
+            }
+            progress.add(block.len() as u64)?;
+            Ok::<_, io::Error>(())
+        });
+        unsafe {
+            libc::munmap(map, size);
+        }
+        written?;
+        offset += size as u64;
+    }
+    Ok(len)
+}
+
+#[cfg(not(unix))]
+fn mmap_copy(
+    _src: &File,
+    _dsts: &mut [File],
+    _window: usize,
+    _progress: &mut Written,
+    _hasher: Option<&mut Hasher>,
+) -> io::Result<u64> {
+    Err(unsupported(Strategy::Mmap))
+}
+
+/// Whether `options` copy a file of `len` bytes from a memory map.
+fn uses_mmap(options: &CopyOptions, len: u64) -> bool {
+    cfg!(unix)
+        && options.strategy == Some(Strategy::Mmap)
+        && !options.direct_io
+        && len >= options.mmap_min_size.unwrap_or(MMAP_MIN_SIZE)
+}
+
+/// Copies the contents of `src` to `dst`, feeding what was read to `hasher`,
+/// or setting it to `None` when the contents didn't go through a buffer.
+fn copy_contents(
+    src: &mut File,
+    dst: &mut File,
+    options: &CopyOptions,
+    hasher: &mut Option<Hasher>,
+) -> io::Result<u64> {
+    if uses_mmap(options, src.metadata()?.len()) {
+        return mmap_copy(
+            src,
+            std::slice::from_mut(dst),
+            MMAP_WINDOW,
+            &mut Written::new(options),
+            hasher.as_mut(),
+        );
+    }
+    #[cfg(target_os = "linux")]
+    if !options.needs_buffered_copy() {
+        *hasher = None;
+        if options.strategy == Some(Strategy::Reflink) && clone_file(src, dst)? {
+            return Ok(src.metadata()?.len());
+        }
+        if let Some(written) = kernel_copy(src, dst, &mut Written::new(options))? {
+            return Ok(written);
+        }
+    }
+    let size = match (options.buffer_size, &options.bwlimit) {
+        (Some(size), _) => size,
+        // Keep roughly ten writes per second so throttled copies don't stall in bursts.
+        (None, Some(throttle)) => {
+            auto_buffer_size(src, dst)?.min(throttle.bytes_per_sec as usize / 10)
+        }
+        (None, None) => auto_buffer_size(src, dst)?,
+    };
+    buffered_copy(
+        src,
+        std::slice::from_mut(dst),
+        size.max(1),
+        &mut Written::new(options),
+        hasher.as_mut(),
+    )
+}
+
+/// A way of copying file contents, compared by `bench`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// `read`/`write` calls through a userspace buffer.
+    ReadWrite,
+    /// `copy_file_range`, in the kernel.
+    CopyFileRange,
+    /// A copy-on-write clone sharing the extents of the source.
+    Reflink,
+    /// A second name for the same file, only within one filesystem.
+    Hardlink,
+    /// Writes straight from the source mapped into memory, for files of a
+    /// gigabyte or more.
+    Mmap,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 5] = [
+        Strategy::ReadWrite,
+        Strategy::CopyFileRange,
+        Strategy::Reflink,
+        Strategy::Hardlink,
+        Strategy::Mmap,
+    ];
+
+    /// Parses the name of a strategy making a copy, all but `hardlink`.
+    pub fn parse(value: &str) -> Option<Strategy> {
+        [
+            Strategy::ReadWrite,
+            Strategy::CopyFileRange,
+            Strategy::Reflink,
+            Strategy::Mmap,
+        ]
+        .into_iter()
+        .find(|strategy| strategy.name() == value)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::ReadWrite => "read-write",
+            Strategy::CopyFileRange => "copy-file-range",
+            Strategy::Reflink => "reflink",
+            Strategy::Hardlink => "hardlink",
+            Strategy::Mmap => "mmap",
+        }
+    }
+}
+
+fn unsupported(strategy: Strategy) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported here", strategy.name()),
+    )
+}
+
+/// Makes `dst` a clone of `src`, sharing its extents. Returns `false` when the
+/// filesystem can't clone these two files.
+#[cfg(target_os = "linux")]
+fn clone_file(src: &File, dst: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE as _, src.as_raw_fd()) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP | libc::EXDEV | libc::EINVAL | libc::ENOTTY) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+/// Clones `src` to the new file `dst`, sharing its extents.
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    let src = File::open(src)?;
+    let dst_file = File::create(dst)?;
+    match clone_file(&src, &dst_file) {
+        Ok(true) => Ok(()),
+        cloned => {
+            drop(dst_file);
+            let _ = fs::remove_file(dst);
+            Err(cloned
+                .err()
+                .unwrap_or_else(|| unsupported(Strategy::Reflink)))
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let src = CString::new(src.as_os_str().as_bytes())?;
+    let dst = CString::new(dst.as_os_str().as_bytes())?;
+    if unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), 0) } != 0 {
+        let err = io::Error::last_os_error();
+        return Err(match err.raw_os_error() {
+            Some(libc::ENOTSUP | libc::EXDEV) => unsupported(Strategy::Reflink),
+            _ => err,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(unsupported(Strategy::Reflink))
+}
+
+/// Copies the contents of `src` to the new file `dst` with `strategy` and nothing
+/// else, failing with `Unsupported` when it can't be used for these two files.
+pub fn copy_with(strategy: Strategy, src: &Path, dst: &Path) -> io::Result<u64> {
+    let len = fs::metadata(src)?.len();
+    match strategy {
+        Strategy::ReadWrite => {
+            let mut reader = File::open(src)?;
+            let mut writer = File::create(dst)?;
+            let size = auto_buffer_size(&reader, &writer)?;
+            buffered_copy(
+                &mut reader,
+                std::slice::from_mut(&mut writer),
+                size,
+                &mut Written::default(),
+                None,
+            )
+        }
+        #[cfg(target_os = "linux")]
+        Strategy::CopyFileRange => {
+            let reader = File::open(src)?;
+            let writer = File::create(dst)?;
+            kernel_copy(&reader, &writer, &mut Written::default())?
+                .ok_or_else(|| unsupported(strategy))
+        }
+        #[cfg(not(target_os = "linux"))]
+        Strategy::CopyFileRange => Err(unsupported(strategy)),
+        Strategy::Reflink => reflink(src, dst).map(|()| len),
+        Strategy::Hardlink => fs::hard_link(src, dst).map(|()| len),
+        // Whatever the size, to compare the mechanism.
+        Strategy::Mmap => {
+            let reader = File::open(src)?;
+            let writer = File::create(dst)?;
+            mmap_copy(
+                &reader,
+                &mut [writer],
+                MMAP_WINDOW,
+                &mut Written::default(),
+                None,
+            )
+        }
+    }
+}
+
+/// Creates a FIFO at `dst` with the permissions of the one at `src`.
+pub fn create_fifo(src: &Path, dst: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::symlink_metadata(src)?.permissions().mode();
+        let path = CString::new(dst.as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(path.as_ptr(), (mode & 0o7777) as libc::mode_t) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (src, dst);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "FIFOs are not supported on this platform",
+        ))
+    }
+}
+
+/// Whether `err` is the kind of intermittent failure network filesystems (SMB/NFS)
+/// produce, which may well succeed when tried again.
+pub fn is_transient(err: &io::Error) -> bool {
+    if err
+        .get_ref()
+        .is_some_and(|inner| inner.is::<CopyTimedOut>())
+    {
+        return false;
+    }
+    if matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    ) {
+        return true;
+    }
+    #[cfg(unix)]
+    let transient = [
+        libc::EIO,
+        libc::ETIMEDOUT,
+        libc::EAGAIN,
+        libc::ECONNRESET,
+        libc::ENETRESET,
+        libc::EHOSTDOWN,
+        libc::EHOSTUNREACH,
+    ];
+    // ERROR_UNEXP_NET_ERR, ERROR_NETNAME_DELETED, ERROR_SEM_TIMEOUT
+    #[cfg(windows)]
+    let transient = [59, 64, 121];
+    #[cfg(not(any(unix, windows)))]
+    let transient: [i32; 0] = [];
+    err.raw_os_error()
+        .is_some_and(|code| transient.contains(&code))
+}
+
+/// Copies `src` to `dst`, including permissions, using the fastest mechanism the
+/// platform offers unless `options` ask for a tuned read/write loop.
+///
+/// On Linux this is `copy_file_range` with a buffered fallback. Elsewhere
+/// `fs::copy` already uses the platform equivalents (`fcopyfile`/`clonefile` on
+/// macOS, `CopyFileEx` on Windows).
+///
+/// Transient errors are retried `options.retries` times with exponential backoff
+/// before giving up on the file.
+pub fn copy_file(src: &Path, dst: &Path, options: &CopyOptions) -> io::Result<u64> {
+    retrying(src, options, || copy_file_timed(src, dst, options))
+}
+
+/// Copies `src` to every one of `dsts`, reading it only once. A single destination
+/// goes through [`copy_file`]; several are written from the same buffered reads,
+/// with retries but without chunks or a timeout.
+pub fn copy_file_to_all(src: &Path, dsts: &[PathBuf], options: &CopyOptions) -> io::Result<u64> {
+    if let [dst] = dsts {
+        return copy_file(src, dst, options);
+    }
+    retrying(src, options, || {
+        let mut reader = open_source(src, options.direct_io)?;
+        let permissions = reader.metadata()?.permissions();
+        let mut writers = dsts
+            .iter()
+            .map(File::create)
+            .collect::<io::Result<Vec<_>>>()?;
+        let size = match options.buffer_size {
+            Some(size) => size,
+            None => auto_buffer_size(&reader, &writers[0])?,
+        };
+        let mut hasher = options.checksums.as_ref().map(|_| Hasher::new());
+        let written = if uses_mmap(options, reader.metadata()?.len()) {
+            mmap_copy(
+                &reader,
+                &mut writers,
+                MMAP_WINDOW,
+                &mut Written::new(options),
+                hasher.as_mut(),
+            )?
+        } else {
+            buffered_copy(
+                &mut reader,
+                &mut writers,
+                size.max(1),
+                &mut Written::new(options),
+                hasher.as_mut(),
+            )?
+        };
+        if let (Some(checksums), Some(hasher)) = (&options.checksums, hasher) {
+            checksums.record(src, hasher.finalize());
+        }
+        for (dst, writer) in dsts.iter().zip(&writers) {
+            set_permissions(src, dst, permissions.clone(), options)?;
+            metadata::copy_alternate_streams(src, dst)?;
+            if options.fsync {
+                writer.sync_all()?;
+            }
+        }
+        Ok(written)
+    })
+}
+
+/// Runs `copy`, again after transient errors as `options` say.
+fn retrying(
+    src: &Path,
+    options: &CopyOptions,
+    mut copy: impl FnMut() -> io::Result<u64>,
+) -> io::Result<u64> {
+    let mut delay = options.retry_delay;
+    let mut attempt = 0;
+    loop {
+        match copy() {
+            Err(e) if attempt < options.retries && is_transient(&e) => {
+                attempt += 1;
+                tracing::warn!(
+                    "Error copying {:?}: {}, retrying in {:?} ({}/{})",
+                    src,
+                    e,
+                    delay,
+                    attempt,
+                    options.retries
+                );
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Runs `copy_file_once` in a thread of its own when a timeout is set, so a hung
+/// read can be abandoned. The copy is written under a temporary name next to
+/// `dst` and only renamed into place once done in time, so an abandoned thread,
+/// left to stop at its next block or when the kernel gives up, never touches
+/// `dst`. It deletes what it wrote instead.
+fn copy_file_timed(src: &Path, dst: &Path, options: &CopyOptions) -> io::Result<u64> {
+    let Some(timeout) = options.file_timeout else {
+        return copy_file_once(src, dst, options);
+    };
+    let abandoned = Arc::new(AtomicBool::new(false));
+    // Held to rename the copy into place or to give up on it, never both.
+    let finishing = Arc::new(Mutex::new(()));
+    let (sender, receiver) = mpsc::channel();
+    let (src_owned, dst_owned, partial) = (src.to_path_buf(), dst.to_path_buf(), partial_path(dst));
+    let options_owned = CopyOptions {
+        abandoned: Some(abandoned.clone()),
+        ..options.clone()
+    };
+    let finishing_owned = finishing.clone();
+    thread::spawn(move || {
+        let copied = copy_file_once(&src_owned, &partial, &options_owned);
+        let _finishing = finishing_owned.lock().unwrap();
+        let result = copied.and_then(|written| {
+            if options_owned
+                .abandoned
+                .as_ref()
+                .unwrap()
+                .load(Ordering::Relaxed)
+            {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "copy abandoned"));
+            }
+            fs::rename(&partial, &dst_owned).map(|()| written)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&partial);
+            let _ = fs::remove_file(journal::journal_path(&partial));
+        }
+        let _ = sender.send(result);
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(_) => {
+            let _finishing = finishing.lock().unwrap();
+            abandoned.store(true, Ordering::Relaxed);
+            // Done while waiting for the lock.
+            if let Ok(result) = receiver.try_recv() {
+                return result;
+            }
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                CopyTimedOut {
+                    src: src.to_path_buf(),
+                    timeout,
+                },
+            ))
+        }
+    }
+}
+
+/// The error of a copy that took longer than `file_timeout`. It is not retried,
+/// as a read hanging once most likely hangs again.
+#[derive(Debug)]
+struct CopyTimedOut {
+    src: PathBuf,
+    timeout: Duration,
+}
+
+impl fmt::Display for CopyTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "copying {:?} took longer than {:?}",
+            self.src, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for CopyTimedOut {}
+
+/// A temporary name next to `dst` for a timed copy to write to, ending like the
+/// journals so that recovery removes it when left behind.
+fn partial_path(dst: &Path) -> PathBuf {
+    use std::sync::atomic::AtomicUsize;
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+    dst.with_file_name(format!(
+        ".{}.{}-{}{}",
+        name,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst),
+        journal::SUFFIX
+    ))
+}
+
+/// The size of the resumable chunks `options` copy a file of `len` bytes in, if
+/// they do: only where it would otherwise go through a plain buffer, as clones
+/// and kernel copies are faster, and `O_DIRECT` and memory maps read it their
+/// own way.
+fn journal_chunk_size(options: &CopyOptions, len: u64) -> Option<u64> {
+    options
+        .chunk_size
+        .filter(|size| *size > 0 && len >= size * journal::MIN_CHUNKS)
+        .filter(|_| options.needs_buffered_copy() && !options.direct_io && !uses_mmap(options, len))
+}
+
+fn copy_file_once(src: &Path, dst: &Path, options: &CopyOptions) -> io::Result<u64> {
+    let src_metadata = fs::metadata(src)?;
+    if let Some(chunk_size) = journal_chunk_size(options, src_metadata.len()) {
+        let mut hasher = options.checksums.as_ref().map(|_| Hasher::new());
+        let written = journal::copy_chunked(
+            src,
+            dst,
+            &src_metadata,
+            chunk_size,
+            options.buffer_size,
+            &mut Written::new(options),
+            hasher.as_mut(),
+        )?;
+        if let (Some(checksums), Some(hasher)) = (&options.checksums, hasher) {
+            checksums.record(src, hasher.finalize());
+        }
+        set_permissions(src, dst, src_metadata.permissions(), options)?;
+        metadata::copy_alternate_streams(src, dst)?;
+        if options.fsync {
+            fs::OpenOptions::new().write(true).open(dst)?.sync_all()?;
+        }
+        return Ok(written);
+    }
+    // Timed copies go through our own loop, to stop between blocks once abandoned.
+    if cfg!(not(target_os = "linux"))
+        && !options.needs_buffered_copy()
+        && options.abandoned.is_none()
+    {
+        let written = fs::copy(src, dst)?;
+        if options.fsync {
+            fs::OpenOptions::new().write(true).open(dst)?.sync_all()?;
+        }
+        return Ok(written);
+    }
+
+    let mut reader = open_source(src, options.direct_io)?;
+    let permissions = reader.metadata()?.permissions();
+    let mut writer = File::create(dst)?;
+    let mut hasher = options.checksums.as_ref().map(|_| Hasher::new());
+    let written = copy_contents(&mut reader, &mut writer, options, &mut hasher)?;
+    if let (Some(checksums), Some(hasher)) = (&options.checksums, hasher) {
+        checksums.record(src, hasher.finalize());
+    }
+    set_permissions(src, dst, permissions, options)?;
+    metadata::copy_alternate_streams(src, dst)?;
+    if options.fsync {
+        writer.sync_all()?;
+    }
+    Ok(written)
+}
+
+/// Gives `dst` the `permissions` of `src`, unless the destination can't hold them.
+fn set_permissions(
+    src: &Path,
+    dst: &Path,
+    permissions: fs::Permissions,
+    options: &CopyOptions,
+) -> io::Result<()> {
+    match options.downgrade.as_deref().filter(|d| d.file_mode) {
+        Some(downgrade) => {
+            downgrade.record_mode(src, &permissions);
+            Ok(())
+        }
+        None => fs::set_permissions(dst, permissions),
+    }
+}
+
+/// Whether the files `a` and `b` have the same contents.
+pub fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let (mut a_buf, mut b_buf) = (vec![0; 1 << 20], vec![0; 1 << 20]);
+    loop {
+        let n = a.read(&mut a_buf)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut b_buf[..n])?;
+        if a_buf[..n] != b_buf[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Flushes the entries of the directory `path` to disk, so the files created in
+/// it can't vanish in a crash. Windows journals directories itself and can't open
+/// them as files, so this is a no-op there.
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_file_timeout() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.txt");
+        fs::write(&src, vec![0u8; 1 << 20])?;
+        let options = CopyOptions {
+            file_timeout: Some(Duration::from_millis(50)),
+            buffer_size: Some(DIRECT_IO_ALIGN),
+            bwlimit: Some(Arc::new(Throttle::new(64 * 1024))),
+            retries: 3,
+            retry_delay: Duration::from_secs(10),
+            ..CopyOptions::default()
+        };
+        let slow = dir.path().join("slow.txt");
+        let started = Instant::now();
+        let err = copy_file(&src, &slow, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(!is_transient(&err));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // The abandoned copy never wrote to the destination, and deleted what
+        // it wrote once stopped.
+        assert!(!slow.exists());
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        let options = CopyOptions {
+            file_timeout: Some(Duration::from_secs(10)),
+            ..CopyOptions::default()
+        };
+        let fast = dir.path().join("fast.txt");
+        copy_file(&src, &fast, &options)?;
+        assert_eq!(fs::read(&fast)?.len(), 1 << 20);
+        assert_eq!(fs::read_dir(dir.path())?.count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_file_fsync() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.txt");
+        let dst = dir.path().join("dst.txt");
+        fs::write(&src, "durable")?;
+        let options = CopyOptions {
+            fsync: true,
+            ..CopyOptions::default()
+        };
+        copy_file(&src, &dst, &options)?;
+        sync_dir(dir.path())?;
+        assert_eq!(fs::read_to_string(&dst)?, "durable");
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_file() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.bin");
+        let dst = dir.path().join("dst.bin");
+        let content: Vec<u8> = (0..MIN_BUFFER_SIZE * 3 + 7).map(|i| i as u8).collect();
+        fs::write(&src, &content)?;
+
+        let written = copy_file(&src, &dst, &CopyOptions::default())?;
+
+        assert_eq!(written, content.len() as u64);
+        assert_eq!(fs::read(&dst)?, content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_file_tuned() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.bin");
+        let content: Vec<u8> = (0..DIRECT_IO_ALIGN * 5 + 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(&src, &content)?;
+
+        for (i, options) in [
+            CopyOptions {
+                buffer_size: Some(1000),
+                ..CopyOptions::default()
+            },
+            CopyOptions {
+                direct_io: true,
+                ..CopyOptions::default()
+            },
+        ]
+        .iter()
+        .enumerate()
+        {
+            let dst = dir.path().join(format!("dst{}.bin", i));
+            assert_eq!(copy_file(&src, &dst, options)?, content.len() as u64);
+            assert_eq!(fs::read(&dst)?, content);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_journal_chunk_size() {
+        let chunked = |options: CopyOptions| {
+            journal_chunk_size(
+                &CopyOptions {
+                    chunk_size: Some(1000),
+                    ..options
+                },
+                1000 * journal::MIN_CHUNKS,
+            )
+        };
+        // Left to the kernel or the platform.
+        assert_eq!(chunked(CopyOptions::default()), None);
+        assert_eq!(
+            chunked(CopyOptions {
+                buffer_size: Some(4096),
+                ..CopyOptions::default()
+            }),
+            Some(1000)
+        );
+        assert_eq!(
+            chunked(CopyOptions {
+                strategy: Some(Strategy::ReadWrite),
+                direct_io: true,
+                ..CopyOptions::default()
+            }),
+            None
+        );
+        assert_eq!(
+            journal_chunk_size(
+                &CopyOptions {
+                    chunk_size: Some(1000),
+                    buffer_size: Some(4096),
+                    ..CopyOptions::default()
+                },
+                1000 * journal::MIN_CHUNKS - 1
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_copy_file_chunked_checksum() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("big.pack");
+        let dst = dir.path().join("copy.pack");
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data)?;
+
+        let options = CopyOptions {
+            chunk_size: Some(1000),
+            checksums: Some(Arc::default()),
+            ..CopyOptions::default()
+        };
+        assert_eq!(journal_chunk_size(&options, data.len() as u64), Some(1000));
+        copy_file(&src, &dst, &options)?;
+        assert_eq!(fs::read(&dst)?, data);
+        let checksums = options.checksums.as_deref().unwrap();
+        assert_eq!(checksums.take(&src), Some(crc32fast::hash(&data)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffered_copy() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.txt");
+        let dst = dir.path().join("dst.txt");
+        fs::write(&src, "hello")?;
+
+        let mut hasher = Hasher::new();
+        let written = buffered_copy(
+            &mut File::open(&src)?,
+            &mut [File::create(&dst)?],
+            2,
+            &mut Written::default(),
+            Some(&mut hasher),
+        )?;
+
+        assert_eq!(written, 5);
+        assert_eq!(fs::read_to_string(&dst)?, "hello");
+        assert_eq!(hasher.finalize(), crc32fast::hash(b"hello"));
+        assert_eq!(checksum(&dst)?, crc32fast::hash(b"hello"));
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_kernel_copy_progress() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.bin");
+        let dst = dir.path().join("dst.bin");
+        fs::write(&src, vec![7u8; 1 << 20])?;
+
+        let stats = Stats::default();
+        stats.start_repo("repo");
+        let mut progress = Written {
+            stats: Some(&stats),
+            ..Written::default()
+        };
+        if let Some(written) = kernel_copy(&File::open(&src)?, &File::create(&dst)?, &mut progress)?
+        {
+            assert_eq!(written, 1 << 20);
+            assert_eq!(stats.progress().unwrap().bytes_done, 1 << 20);
+        }
+        drop(progress);
+        assert_eq!(stats.progress().unwrap().bytes_done, 0);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mmap_copy() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.bin");
+        let dsts = [dir.path().join("a.bin"), dir.path().join("b.bin")];
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let data: Vec<u8> = (0..page * 3 + 100).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data)?;
+
+        let mut writers = [File::create(&dsts[0])?, File::create(&dsts[1])?];
+        let mut hasher = Hasher::new();
+        let written = mmap_copy(
+            &File::open(&src)?,
+            &mut writers,
+            page,
+            &mut Written::default(),
+            Some(&mut hasher),
+        )?;
+        assert_eq!(written, data.len() as u64);
+        for dst in &dsts {
+            assert_eq!(fs::read(dst)?, data);
+        }
+        assert_eq!(hasher.finalize(), crc32fast::hash(&data));
+
+        fs::write(&src, "")?;
+        let mut writers = [File::create(&dsts[0])?];
+        assert_eq!(
+            mmap_copy(
+                &File::open(&src)?,
+                &mut writers,
+                page,
+                &mut Written::default(),
+                None
+            )?,
+            0
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_file_mmap() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("big.pack");
+        let dst = dir.path().join("copy.pack");
+        let min_size = 1 << 16;
+        let data: Vec<u8> = (0..min_size).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data)?;
+
+        // Large enough to be chunked too, were it copied through a buffer.
+        let options = CopyOptions {
+            strategy: Some(Strategy::Mmap),
+            mmap_min_size: Some(min_size),
+            chunk_size: Some(min_size / journal::MIN_CHUNKS),
+            checksums: Some(Arc::default()),
+            ..CopyOptions::default()
+        };
+        assert!(uses_mmap(&options, min_size));
+        assert_eq!(journal_chunk_size(&options, min_size), None);
+        assert_eq!(copy_file(&src, &dst, &options)?, data.len() as u64);
+        assert_eq!(fs::read(&dst)?, data);
+        let checksums = options.checksums.as_deref().unwrap();
+        assert_eq!(checksums.take(&src), Some(crc32fast::hash(&data)));
+
+        // Smaller files go through a buffer.
+        assert!(!uses_mmap(&options, min_size - 1));
+        fs::write(&src, &data[1..])?;
+        copy_file(&src, &dst, &options)?;
+        assert_eq!(fs::read(&dst)?, data[1..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_with() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.txt");
+        fs::write(&src, "hello")?;
+
+        for strategy in Strategy::ALL {
+            let dst = dir.path().join(strategy.name());
+            match copy_with(strategy, &src, &dst) {
+                Ok(written) => {
+                    assert_eq!(written, 5);
+                    assert_eq!(fs::read_to_string(&dst)?, "hello");
+                }
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::Unsupported, "{}", e),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_file_to_all() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src.txt");
+        let dsts = [dir.path().join("nas.txt"), dir.path().join("usb.txt")];
+        fs::write(&src, "hello")?;
+
+        let written = copy_file_to_all(&src, &dsts, &CopyOptions::default())?;
+
+        assert_eq!(written, 5);
+        for dst in &dsts {
+            assert_eq!(fs::read_to_string(dst)?, "hello");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&io::Error::from(io::ErrorKind::TimedOut)));
+        #[cfg(unix)]
+        assert!(is_transient(&io::Error::from_raw_os_error(libc::EIO)));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::NotFound)));
+        assert!(!is_transient(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+    }
+
+    #[test]
+    fn test_throttle() {
+        let throttle = Throttle::new(1000);
+        let start = Instant::now();
+        throttle.consume(100);
+        throttle.consume(100);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_fifo() -> io::Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let dir = tempdir()?;
+        let src = dir.path().join("src.fifo");
+        let dst = dir.path().join("dst.fifo");
+        let path = std::ffi::CString::new(src.to_str().unwrap())?;
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o640) }, 0);
+
+        create_fifo(&src, &dst)?;
+
+        assert!(fs::symlink_metadata(&dst)?.file_type().is_fifo());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_file_keeps_permissions() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir()?;
+        let src = dir.path().join("script.sh");
+        let dst = dir.path().join("copy.sh");
+        fs::write(&src, "#!/bin/sh\n")?;
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o755))?;
+
+        copy_file(&src, &dst, &CopyOptions::default())?;
+
+        assert_eq!(fs::metadata(&dst)?.permissions().mode() & 0o777, 0o755);
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+
+use crate::inventory::{self, Inventory};
+use crate::serve::{self, Output, RpcError};
+use crate::{cli, paths};
+
+pub const DAEMON_USAGE: &str = "Usage: daemon [--socket <path>]";
+pub const CTL_USAGE: &str =
+    "Usage: ctl [--socket <path>] move <source>... <destination> [options] | status | cancel <id> | list";
+
+/// Where a job of the daemon is at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", content = "detail", rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    /// Finished, with its summary.
+    Done(String),
+    Failed(String),
+    /// Cancelled before it started, or stopped after the file it was copying.
+    Cancelled,
+}
+
+/// A run queued with `ctl move`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: u64,
+    /// Command line of the run, without the program name.
+    pub args: Vec<String>,
+    #[serde(flatten)]
+    pub state: JobState,
+    /// Set to stop the run after the file it is copying.
+    #[serde(skip)]
+    pub cancel: Arc<AtomicBool>,
+}
+
+/// The jobs of the daemon, which runs them one at a time in order.
+#[derive(Default)]
+struct Queue {
+    jobs: Mutex<Vec<Job>>,
+    queued: Condvar,
+}
+
+impl Queue {
+    fn jobs(&self) -> std::sync::MutexGuard<'_, Vec<Job>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, args: Vec<String>) -> u64 {
+        let mut jobs = self.jobs();
+        let id = jobs.last().map_or(1, |job| job.id + 1);
+        jobs.push(Job {
+            id,
+            args,
+            state: JobState::Queued,
+            cancel: Arc::default(),
+        });
+        self.queued.notify_one();
+        id
+    }
+
+    /// Waits for the next queued job, and marks it running.
+    fn next(&self) -> Job {
+        let mut jobs = self.jobs();
+        loop {
+            if let Some(job) = jobs.iter_mut().find(|job| job.state == JobState::Queued) {
+                job.state = JobState::Running;
+                return job.clone();
+            }
+            jobs = self.queued.wait(jobs).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn finish(&self, id: u64, state: JobState) {
+        if let Some(job) = self.jobs().iter_mut().find(|job| job.id == id) {
+            job.state = state;
+        }
+    }
+
+    /// Cancels the job `id`: right away if it is queued, after the file it is
+    /// copying if it is running.
+    fn cancel(&self, id: u64) -> Result<(), String> {
+        let mut jobs = self.jobs();
+        let job = jobs
+            .iter_mut()
+            .find(|job| job.id == id)
+            .ok_or_else(|| format!("No job {}", id))?;
+        match job.state {
+            JobState::Queued => job.state = JobState::Cancelled,
+            JobState::Running => job.cancel.store(true, Ordering::Relaxed),
+            _ => return Err(format!("Job {} is already over", id)),
+        }
+        Ok(())
+    }
+}
+
+/// The state the daemon holds for the connections.
+struct Daemon {
+    queue: Queue,
+    /// Loaded once, and again after each job.
+    inventory: Mutex<Inventory>,
+}
+
+/// Where the daemon listens without `--socket`: `mv-git.sock` in the user's
+/// runtime directory, or else in mv-git's state directory.
+pub fn default_socket() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(&dir).join("mv-git.sock"))
+        .or_else(|| paths::state_dir().map(|dir| dir.join("mv-git.sock")))
+}
+
+/// Splits `--socket <path>` off the arguments of `daemon` or `ctl`, returning
+/// the socket and the other arguments.
+pub fn parse_socket(args: &[String]) -> Result<(PathBuf, Vec<String>), String> {
+    let (socket, rest) = match args {
+        [flag, socket, rest @ ..] if flag == "--socket" => {
+            (PathBuf::from(paths::expand(socket)?), rest.to_vec())
+        }
+        _ => (
+            default_socket().ok_or("HOME is not set, give the socket with --socket")?,
+            args.to_vec(),
+        ),
+    };
+    Ok((socket, rest))
+}
+
+/// Listens on `socket` and runs the jobs queued there one after the other with
+/// `run_job`, which gets the command line of the run and its cancel flag, and
+/// returns its summary.
+pub fn run(
+    socket: &Path,
+    run_job: impl Fn(&[String], Arc<AtomicBool>) -> Result<String, String> + Send + 'static,
+) -> io::Result<()> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            return Err(io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("A daemon is already listening on {:?}", socket),
+            ));
+        }
+        // Left by a daemon that didn't stop cleanly.
+        fs::remove_file(socket)?;
+    }
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    // Whoever can connect can move the user's repositories.
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+    info!("Listening on {:?}", socket);
+
+    let inventory = inventory::path()
+        .map(|path| Inventory::load(&path))
+        .transpose()?
+        .unwrap_or_default();
+    let daemon = Arc::new(Daemon {
+        queue: Queue::default(),
+        inventory: Mutex::new(inventory),
+    });
+    let worker = Arc::clone(&daemon);
+    thread::spawn(move || loop {
+        let job = worker.queue.next();
+        info!("Running job {}: {}", job.id, job.args.join(" "));
+        let state = match run_job(&job.args, Arc::clone(&job.cancel)) {
+            Ok(_) if job.cancel.load(Ordering::Relaxed) => JobState::Cancelled,
+            Ok(summary) => JobState::Done(summary),
+            Err(e) => JobState::Failed(e),
+        };
+        info!("Job {} is over: {:?}", job.id, state);
+        worker.queue.finish(job.id, state);
+        match inventory::path().map(|path| Inventory::load(&path)) {
+            Some(Ok(inventory)) => {
+                *worker.inventory.lock().unwrap_or_else(|e| e.into_inner()) = inventory
+            }
+            Some(Err(e)) => warn!("Could not reload the inventory: {}", e),
+            None => {}
+        }
+    });
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Could not accept a connection: {}", e);
+                continue;
+            }
+        };
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || {
+            if let Err(e) = connection(stream, &daemon) {
+                warn!("Connection closed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answers the requests of one connection until it is closed.
+fn connection(stream: UnixStream, daemon: &Daemon) -> io::Result<()> {
+    let input = BufReader::new(stream.try_clone()?);
+    serve::serve(input, &Output::new(stream), |method, params| {
+        request(method, params, daemon)
+    })
+}
+
+fn request(method: &str, params: &Value, daemon: &Daemon) -> Result<Value, RpcError> {
+    match method {
+        "move" => {
+            let args = serve::strings(params, "args")?;
+            Ok(json!({ "id": daemon.queue.push(args) }))
+        }
+        "status" => Ok(json!(*daemon.queue.jobs())),
+        "cancel" => {
+            let id = params
+                .get("id")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| RpcError::invalid_params("Expected the id of a job"))?;
+            daemon.queue.cancel(id).map_err(RpcError::failed)?;
+            Ok(json!({ "id": id }))
+        }
+        "list" => Ok(json!(
+            daemon
+                .inventory
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .repos
+        )),
+        _ => Err(RpcError {
+            code: serve::METHOD_NOT_FOUND,
+            message: format!(
+                "Unknown method {:?}, expected move, status, cancel or list",
+                method
+            ),
+        }),
+    }
+}
+
+/// Sends the request `method` to the daemon listening on `socket`, returning
+/// its result.
+pub fn send(socket: &Path, method: &str, params: Value) -> Result<Value, Box<dyn Error>> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Could not reach the daemon on {:?}: {}", socket, e))?;
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    writeln!(stream, "{}", request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: Value = serde_json::from_str(&line)?;
+    match response.get("error") {
+        Some(error) => Err(error["message"].as_str().unwrap_or("Failed").into()),
+        None => Ok(response["result"].clone()),
+    }
+}
+
+/// `args`, a command line of the run, with the relative paths in it made
+/// absolute from `cwd`: the daemon runs the job from a directory of its own.
+fn absolute_args(args: &[String], cwd: &Path) -> Vec<String> {
+    let absolute = |path: &str| {
+        if path.starts_with(['~', '$']) || Path::new(path).is_absolute() {
+            path.to_string()
+        } else {
+            cwd.join(path).to_string_lossy().into_owned()
+        }
+    };
+    let mut absolute_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg.len() == 1 {
+            absolute_args.push(absolute(arg));
+            continue;
+        }
+        absolute_args.push(arg.clone());
+        let Some(value) = cli::FLAGS
+            .iter()
+            .find(|flag| flag.names.contains(&arg.as_str()))
+            .and_then(|flag| flag.value)
+        else {
+            continue;
+        };
+        let Some(arg) = args.next() else {
+            break;
+        };
+        absolute_args.push(match value {
+            "FILE" | "PATH" => absolute(arg),
+            "PATH,PATH..." => arg.split(',').map(absolute).collect::<Vec<_>>().join(","),
+            _ => arg.clone(),
+        });
+    }
+    absolute_args
+}
+
+/// Runs `ctl` with the arguments after the subcommand.
+pub fn ctl(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (socket, args) = parse_socket(args)?;
+    match args.split_first() {
+        Some((command, args)) if command == "move" && !args.is_empty() => {
+            let args = absolute_args(args, &std::env::current_dir()?);
+            let result = send(&socket, "move", json!({ "args": args }))?;
+            println!("Queued as job {}", result["id"]);
+        }
+        Some((command, [])) if command == "status" => {
+            let jobs = send(&socket, "status", json!({}))?;
+            for job in jobs.as_array().into_iter().flatten() {
+                let args: Vec<&str> = job["args"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .collect();
+                println!(
+                    "{:>4}  {:<9}  {}",
+                    job["id"],
+                    job["state"].as_str().unwrap_or_default(),
+                    args.join(" ")
+                );
+                if let Some(detail) = job["detail"].as_str() {
+                    println!("      {}", detail);
+                }
+            }
+        }
+        Some((command, [id])) if command == "cancel" => {
+            let id: u64 = id.parse().map_err(|_| format!("Invalid job id {:?}", id))?;
+            send(&socket, "cancel", json!({ "id": id }))?;
+            println!("Cancelling job {}", id);
+        }
+        Some((command, [])) if command == "list" => {
+            let repos = send(&socket, "list", json!({}))?;
+            let inventory = Inventory {
+                repos: serde_json::from_value(repos)?,
+            };
+            print!("{}", inventory.table());
+        }
+        _ => return Err(CTL_USAGE.into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_daemon() -> Result<(), Box<dyn Error>> {
+        let dir = tempdir()?;
+        let socket = dir.path().join("mv-git.sock");
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let listening = socket.clone();
+        thread::spawn(move || {
+            run(&listening, move |args, cancel| {
+                if args[0] == "slow" {
+                    started_tx.send(()).unwrap();
+                    while !cancel.load(Ordering::Relaxed) {
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                }
+                Ok(format!("ran {}", args.join(" ")))
+            })
+        });
+        while !socket.exists() {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let slow = send(&socket, "move", json!({ "args": ["slow"] }))?;
+        let next = send(&socket, "move", json!({ "args": ["next", "dst"] }))?;
+        assert_eq!(
+            (slow["id"].as_u64(), next["id"].as_u64()),
+            (Some(1), Some(2))
+        );
+        started_rx.recv()?;
+        let jobs = send(&socket, "status", json!({}))?;
+        assert_eq!(jobs[0]["state"], "running");
+        assert_eq!(jobs[1]["state"], "queued");
+
+        send(&socket, "cancel", json!({ "id": 1 }))?;
+        while send(&socket, "status", json!({}))?[1]["state"] != "done" {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let jobs = send(&socket, "status", json!({}))?;
+        assert_eq!(jobs[0]["state"], "cancelled");
+        assert_eq!(jobs[1]["detail"], "ran next dst");
+        assert!(send(&socket, "cancel", json!({ "id": 2 })).is_err());
+        assert!(run(&socket, |_, _| Ok(String::new())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_absolute_args() {
+        let args: Vec<String> = [
+            "./proj",
+            "~/src",
+            "--report",
+            "out.json",
+            "--shard-across",
+            "/mnt/a,b",
+            "--min-interval",
+            "1h",
+            "../archive",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        assert_eq!(
+            absolute_args(&args, Path::new("/home/me")),
+            [
+                "/home/me/./proj",
+                "~/src",
+                "--report",
+                "/home/me/out.json",
+                "--shard-across",
+                "/mnt/a,/home/me/b",
+                "--min-interval",
+                "1h",
+                "/home/me/../archive",
+            ]
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::git::{self, git};
+use crate::units;
+
+/// Name of the bare repository, under the destination, holding shared objects.
+pub const SHARED_STORE: &str = ".mv-git-objects";
+
+#[derive(Debug, Default)]
+pub struct DedupeReport {
+    pub repos: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+impl DedupeReport {
+    pub fn saved(&self) -> u64 {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
+impl fmt::Display for DedupeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Deduplicated objects of {} repositories: {} -> {} ({} saved)",
+            self.repos,
+            units::format_size(self.bytes_before),
+            units::format_size(self.bytes_after),
+            units::format_size(self.saved())
+        )
+    }
+}
+
+/// Total size of the files under `path`, 0 if there is nothing there.
+pub fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    if !path.exists() {
+        return Ok(0);
+    }
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+fn root_commit(repo: &Path) -> Option<String> {
+    let roots = git(repo, ["rev-list", "--max-parents=0", "HEAD"]).ok()?;
+    roots.lines().min().map(|root| root.to_string())
+}
+
+/// Groups repositories by root commit, keeping only groups with at least two members.
+fn related_repos(repos: &[PathBuf]) -> Vec<Vec<PathBuf>> {
+    let mut groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for repo in repos {
+        if let Some(root) = root_commit(repo) {
+            groups.entry(root).or_default().push(repo.clone());
+        }
+    }
+    let mut groups: Vec<Vec<PathBuf>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    groups.sort();
+    groups
+}
+
+/// Path from the object directory `objects` of a repository to the shared store
+/// objects, relative so the link survives moving the whole destination when
+/// `objects` is under it.
+fn relative_alternate(dst: &Path, objects: &Path) -> PathBuf {
+    let Ok(rel) = objects.strip_prefix(dst) else {
+        return dst.join(SHARED_STORE).join("objects");
+    };
+    let mut path = PathBuf::new();
+    for _ in rel.components() {
+        path.push("..");
+    }
+    path.join(SHARED_STORE).join("objects")
+}
+
+/// Adds `alternate` to the alternates of the object directory `objects`,
+/// keeping those it already borrows objects from, as with `git clone --reference`.
+fn add_alternate(objects: &Path, alternate: &Path) -> io::Result<()> {
+    let info = objects.join("info");
+    let path = info.join("alternates");
+    let mut alternates = match fs::read_to_string(&path) {
+        Ok(alternates) => alternates,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let alternate = alternate.to_string_lossy();
+    if alternates.lines().any(|line| line == alternate) {
+        return Ok(());
+    }
+    if !alternates.is_empty() && !alternates.ends_with('\n') {
+        alternates.push('\n');
+    }
+    alternates.push_str(&alternate);
+    alternates.push('\n');
+    fs::create_dir_all(&info)?;
+    fs::write(path, alternates)
+}
+
+fn ref_namespace(dst: &Path, repo: &Path) -> String {
+    let rel = repo.strip_prefix(dst).unwrap_or(repo);
+    rel.to_string_lossy().replace(['/', '\\'], "-")
+}
+
+/// Moves the objects shared by related repositories (forks) under `dst` into a
+/// single bare store, and points each repository at it through `objects/info/alternates`.
+pub fn dedupe_objects(dst: &Path, repos: &[PathBuf]) -> io::Result<DedupeReport> {
+    let mut report = DedupeReport::default();
+    let groups = related_repos(repos);
+    if groups.is_empty() {
+        return Ok(report);
+    }
+
+    let store = dst.join(SHARED_STORE);
+    if !store.exists() {
+        git(
+            dst,
+            [
+                OsStr::new("init"),
+                OsStr::new("--bare"),
+                OsStr::new("-q"),
+                store.as_os_str(),
+            ],
+        )?;
+    }
+    let store_before = dir_size(&store.join("objects"))?;
+    // Resolved, as where the objects of a repository are may be told relative to it.
+    let root = fs::canonicalize(dst)?;
+
+    for repo in groups.iter().flatten() {
+        let objects = fs::canonicalize(git::common_dir(repo)?)?.join("objects");
+        report.bytes_before += dir_size(&objects)?;
+        report.repos += 1;
+
+        let refspec = format!("+refs/*:refs/mv-git/{}/*", ref_namespace(dst, repo));
+        git(
+            &store,
+            [
+                OsStr::new("fetch"),
+                OsStr::new("-q"),
+                OsStr::new("--no-tags"),
+                repo.as_os_str(),
+                OsStr::new(&refspec),
+            ],
+        )?;
+
+        add_alternate(&objects, &relative_alternate(&root, &objects))?;
+        git(repo, ["repack", "-a", "-d", "-l", "-q"])?;
+        git(repo, ["prune-packed"])?;
+
+        report.bytes_after += dir_size(&objects)?;
+    }
+
+    report.bytes_before += store_before;
+    report.bytes_after += dir_size(&store.join("objects"))?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::init_repo_with_commit;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_relative_alternate() {
+        let dst = Path::new("/dst");
+        assert_eq!(
+            relative_alternate(dst, Path::new("/dst/repo/.git/objects")),
+            PathBuf::from("../../../.mv-git-objects/objects")
+        );
+        assert_eq!(
+            relative_alternate(dst, Path::new("/elsewhere/repo.git/objects")),
+            PathBuf::from("/dst/.mv-git-objects/objects")
+        );
+    }
+
+    #[test]
+    fn test_dedupe_objects() -> io::Result<()> {
+        let dst = tempdir()?;
+        let origin = dst.path().join("origin");
+        let fork = dst.path().join("fork");
+        let other = dst.path().join("other");
+        init_repo_with_commit(&origin, "README.md")?;
+        init_repo_with_commit(&other, "other.txt")?;
+        git(
+            dst.path(),
+            [
+                OsStr::new("clone"),
+                OsStr::new("-q"),
+                OsStr::new("--no-local"),
+                origin.as_os_str(),
+                fork.as_os_str(),
+            ],
+        )?;
+
+        let repos = vec![origin.clone(), fork.clone(), other.clone()];
+        let report = dedupe_objects(dst.path(), &repos)?;
+
+        assert_eq!(report.repos, 2);
+        assert!(report.saved() > 0);
+        assert!(fork.join(".git/objects/info/alternates").exists());
+        assert!(!other.join(".git/objects/info/alternates").exists());
+        git(&fork, ["fsck", "--no-progress"])?;
+        git(&origin, ["log", "-1"])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedupe_objects_keeps_alternates() -> io::Result<()> {
+        let dst = tempdir()?;
+        let reference = tempdir()?;
+        let origin = reference.path().join("origin");
+        init_repo_with_commit(&origin, "README.md")?;
+        let clone = |args: &[&OsStr]| {
+            let mut clone = vec![OsStr::new("clone"), OsStr::new("-q")];
+            clone.extend_from_slice(args);
+            git(dst.path(), clone)
+        };
+        // Borrowing the objects of origin, out of the destination.
+        let borrowing = dst.path().join("borrowing");
+        clone(&[
+            OsStr::new("--reference"),
+            origin.as_os_str(),
+            origin.as_os_str(),
+            borrowing.as_os_str(),
+        ])?;
+        // With `.git` a file pointing at its objects.
+        let separate = dst.path().join("separate");
+        clone(&[
+            OsStr::new("--no-local"),
+            OsStr::new("--separate-git-dir"),
+            dst.path().join("separate.git").as_os_str(),
+            origin.as_os_str(),
+            separate.as_os_str(),
+        ])?;
+
+        let report = dedupe_objects(dst.path(), &[borrowing.clone(), separate.clone()])?;
+
+        assert_eq!(report.repos, 2);
+        let alternates = fs::read_to_string(borrowing.join(".git/objects/info/alternates"))?;
+        assert_eq!(alternates.lines().count(), 2, "{}", alternates);
+        assert!(dst
+            .path()
+            .join("separate.git/objects/info/alternates")
+            .exists());
+        for repo in [&borrowing, &separate] {
+            git(repo, ["fsck", "--no-progress"])?;
+        }
+
+        // Done again, nothing is added.
+        dedupe_objects(dst.path(), &[borrowing.clone(), separate])?;
+        assert_eq!(
+            fs::read_to_string(borrowing.join(".git/objects/info/alternates"))?,
+            alternates
+        );
+        Ok(())
+    }
+}
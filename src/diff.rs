@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use crate::color::{self, Color, ColorChoice};
+use crate::git;
+
+pub const USAGE: &str = "Usage: diff <dir-a> <dir-b> [--color <when>]";
+
+/// Parsed `diff` command line.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DiffOptions {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    pub color: ColorChoice,
+}
+
+/// Parses the `diff` arguments, after the subcommand.
+pub fn parse_args(args: &[String]) -> Result<DiffOptions, Box<dyn Error>> {
+    let mut opts = DiffOptions::default();
+    let mut dirs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--color" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE))?;
+                opts.color = ColorChoice::parse(value)?;
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("Unknown option: {}\n{}", arg, USAGE).into());
+            }
+            _ => dirs.push(PathBuf::from(arg)),
+        }
+    }
+    let [a, b] = <[PathBuf; 2]>::try_from(dirs).map_err(|_| USAGE)?;
+    opts.a = a;
+    opts.b = b;
+    Ok(opts)
+}
+
+/// `HEAD` and worktree state of one repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoState {
+    /// Commit of `HEAD`, `None` before the first commit.
+    pub head: Option<String>,
+    pub clean: bool,
+}
+
+impl RepoState {
+    pub fn of(repo: &Path) -> RepoState {
+        RepoState {
+            head: git::head_snapshot(repo).map(|(commit, _)| commit),
+            clean: git::is_clean(repo),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let head = match &self.head {
+            Some(commit) => commit.chars().take(12).collect(),
+            None => "no commit".to_string(),
+        };
+        if self.clean {
+            head
+        } else {
+            format!("{} (dirty)", head)
+        }
+    }
+}
+
+/// One repository name found on either side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoDiff {
+    pub name: String,
+    pub a: Option<RepoState>,
+    pub b: Option<RepoState>,
+}
+
+impl RepoDiff {
+    pub fn label(&self) -> &'static str {
+        match (&self.a, &self.b) {
+            (Some(_), None) => "only-a",
+            (None, Some(_)) => "only-b",
+            (Some(a), Some(b)) if a == b && a.clean => "same",
+            _ => "differs",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self.label() {
+            "same" => Color::Green,
+            "differs" => Color::Red,
+            _ => Color::Yellow,
+        }
+    }
+}
+
+/// Runs `diff` with the arguments after the subcommand, printing the comparison.
+/// Returns whether both sides hold the same repositories in the same state.
+pub fn run(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let opts = parse_args(args)?;
+    let diffs = compare(&opts.a, &opts.b)?;
+    print!(
+        "{}",
+        table(&diffs, opts.color.enabled(io::stdout().is_terminal()))
+    );
+    Ok(diffs.iter().all(|d| d.label() == "same"))
+}
+
+/// Git repositories directly in `dir`, by name.
+fn repos(dir: &Path) -> io::Result<BTreeMap<String, PathBuf>> {
+    let mut repos = BTreeMap::new();
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        if path.join(".git").exists() {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            repos.insert(name.into_owned(), path);
+        }
+    }
+    Ok(repos)
+}
+
+/// Compares the repositories of `a` and `b` by name, in name order.
+pub fn compare(a: &Path, b: &Path) -> io::Result<Vec<RepoDiff>> {
+    let mut a_repos = repos(a)?;
+    let mut b_repos = repos(b)?;
+    let mut names: Vec<String> = a_repos.keys().chain(b_repos.keys()).cloned().collect();
+    names.sort();
+    names.dedup();
+    Ok(names
+        .into_iter()
+        .map(|name| RepoDiff {
+            a: a_repos.remove(&name).map(|path| RepoState::of(&path)),
+            b: b_repos.remove(&name).map(|path| RepoState::of(&path)),
+            name,
+        })
+        .collect())
+}
+
+/// The comparison as aligned `status  name  a-state  b-state` columns.
+pub fn table(diffs: &[RepoDiff], colored: bool) -> String {
+    let name_width = diffs
+        .iter()
+        .map(|d| d.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let state_width = diffs
+        .iter()
+        .filter_map(|d| d.a.as_ref().map(|a| a.describe().len()))
+        .max()
+        .unwrap_or(1);
+    let mut table = String::new();
+    for diff in diffs {
+        let status = format!("{:<7}", diff.label());
+        let describe =
+            |state: &Option<RepoState>| state.as_ref().map_or("-".to_string(), RepoState::describe);
+        let _ = writeln!(
+            table,
+            "{}  {:<name_width$}  {:<state_width$}  {}",
+            color::paint(&status, diff.color(), colored),
+            diff.name,
+            describe(&diff.a),
+            describe(&diff.b),
+            name_width = name_width,
+            state_width = state_width,
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compare() -> io::Result<()> {
+        let a = tempdir()?;
+        let b = tempdir()?;
+        git::init_repo_with_commit(&a.path().join("both"), "README.md")?;
+        git::git(
+            b.path(),
+            [
+                OsStr::new("clone"),
+                OsStr::new("-q"),
+                a.path().join("both").as_os_str(),
+                OsStr::new("both"),
+            ],
+        )?;
+        git::init_repo_with_commit(&a.path().join("left"), "README.md")?;
+        git::init_repo_with_commit(&b.path().join("right"), "README.md")?;
+        fs::create_dir(b.path().join("not-a-repo"))?;
+
+        let diffs = compare(a.path(), b.path())?;
+        let labels: Vec<(&str, &str)> =
+            diffs.iter().map(|d| (d.name.as_str(), d.label())).collect();
+        assert_eq!(
+            labels,
+            [("both", "same"), ("left", "only-a"), ("right", "only-b")]
+        );
+
+        fs::write(b.path().join("both/README.md"), "changed")?;
+        let diffs = compare(a.path(), b.path())?;
+        assert_eq!(diffs[0].label(), "differs");
+        assert!(table(&diffs, false).contains(" (dirty)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args: Vec<String> = ["a", "--color", "never", "b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let opts = parse_args(&args).unwrap();
+        assert_eq!(opts.a, PathBuf::from("a"));
+        assert_eq!(opts.b, PathBuf::from("b"));
+        assert_eq!(opts.color, ColorChoice::Never);
+        assert!(parse_args(&args[..1]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::git;
+use crate::links::rebase_target;
+use crate::preflight::TargetFs;
+
+/// What a destination filesystem can't hold, and is written the way git checks
+/// out with `core.symlinks=false` and `core.fileMode=false`: symlinks as plain
+/// files holding their target, files without their execute bit.
+#[derive(Debug, Default)]
+pub struct Downgrade {
+    pub symlinks: bool,
+    pub file_mode: bool,
+    /// Symlinks written as plain files.
+    links: Mutex<Vec<PathBuf>>,
+    /// Executable files written without their execute bit.
+    executables: Mutex<Vec<PathBuf>>,
+}
+
+impl Downgrade {
+    /// What `target` needs downgraded, `None` when it holds everything.
+    pub fn for_target(target: TargetFs) -> Option<Downgrade> {
+        (target != TargetFs::Posix).then(|| Downgrade {
+            symlinks: true,
+            file_mode: true,
+            ..Downgrade::default()
+        })
+    }
+
+    /// Writes the symlink `src` at `dst` as a plain file holding its target,
+    /// rebased the way copied links are.
+    pub fn write_link(
+        &self,
+        src: &Path,
+        dst: &Path,
+        src_root: &Path,
+        dst_root: &Path,
+    ) -> io::Result<()> {
+        let target = rebase_target(&fs::read_link(src)?, src_root, dst_root);
+        fs::write(dst, target.to_string_lossy().as_bytes())?;
+        self.links.lock().unwrap().push(src.to_path_buf());
+        Ok(())
+    }
+
+    /// Notes that `src` was copied without its permissions, if it loses its
+    /// execute bit by that.
+    pub fn record_mode(&self, src: &Path, permissions: &fs::Permissions) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if permissions.mode() & 0o111 != 0 {
+                self.executables.lock().unwrap().push(src.to_path_buf());
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = (src, permissions);
+    }
+
+    /// Sets `core.symlinks` and `core.fileMode` to false in the repositories
+    /// at `repos` when they have downgraded entries, so that git doesn't see
+    /// them as changes. Returns what was downgraded, symlinks then executables,
+    /// starting over for the next repository.
+    pub fn apply(&self, repos: &[&Path]) -> io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        let links = std::mem::take(&mut *self.links.lock().unwrap());
+        let executables = std::mem::take(&mut *self.executables.lock().unwrap());
+        for repo in repos.iter().filter(|repo| repo.join(".git").exists()) {
+            if !links.is_empty() {
+                git::git(repo, ["config", "core.symlinks", "false"])?;
+            }
+            if !executables.is_empty() {
+                git::git(repo, ["config", "core.fileMode", "false"])?;
+            }
+        }
+        Ok((links, executables))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_downgrade() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        assert!(Downgrade::for_target(TargetFs::Posix).is_none());
+        let downgrade = Downgrade::for_target(TargetFs::Windows).unwrap();
+        let dir = tempdir()?;
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        git::init_repo_with_commit(&src, "README.md")?;
+        git::init_repo_with_commit(&dst, "README.md")?;
+        std::os::unix::fs::symlink("README.md", src.join("link"))?;
+        std::os::unix::fs::symlink(src.join("README.md"), src.join("absolute"))?;
+
+        downgrade.write_link(&src.join("link"), &dst.join("link"), &src, &dst)?;
+        downgrade.write_link(&src.join("absolute"), &dst.join("absolute"), &src, &dst)?;
+        assert_eq!(fs::read_to_string(dst.join("link"))?, "README.md");
+        assert_eq!(
+            fs::read_to_string(dst.join("absolute"))?,
+            dst.join("README.md").to_string_lossy()
+        );
+        downgrade.record_mode(&src.join("run.sh"), &fs::Permissions::from_mode(0o755));
+        downgrade.record_mode(&src.join("README.md"), &fs::Permissions::from_mode(0o644));
+
+        let (links, executables) = downgrade.apply(&[&dst])?;
+        assert_eq!(links, vec![src.join("link"), src.join("absolute")]);
+        assert_eq!(executables, vec![src.join("run.sh")]);
+        assert_eq!(git::git(&dst, ["config", "core.symlinks"])?, "false");
+        assert_eq!(git::git(&dst, ["config", "core.fileMode"])?, "false");
+        assert_eq!(downgrade.apply(&[&dst])?, (Vec::new(), Vec::new()));
+        Ok(())
+    }
+}
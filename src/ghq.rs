@@ -0,0 +1,86 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config;
+use crate::git;
+
+/// The root ghq clones into: the first of `$GHQ_ROOT`, what `ghq root` says,
+/// `ghq.root` in the git config, or `~/ghq`, as ghq itself decides.
+pub fn root() -> io::Result<PathBuf> {
+    if let Some(root) = env::var_os("GHQ_ROOT").filter(|root| !root.is_empty()) {
+        if let Some(first) = env::split_paths(&root).next() {
+            return Ok(first);
+        }
+    }
+    if let Ok(output) = Command::new("ghq").arg("root").output() {
+        let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if output.status.success() && !root.is_empty() {
+            return Ok(PathBuf::from(root));
+        }
+    }
+    let configured = Command::new("git")
+        .args(["config", "--global", "--get", "ghq.root"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|root| !root.is_empty());
+    if let Some(root) = configured {
+        return crate::paths::expand(&root)
+            .map(PathBuf::from)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+    env::var_os("HOME")
+        .map(|home| Path::new(&home).join("ghq"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))
+}
+
+/// Where ghq puts a clone of `url`, relative to its root: `host/owner/name`,
+/// e.g. `github.com/me/app` for `git@github.com:me/app.git`.
+pub fn layout(url: &str) -> Option<PathBuf> {
+    let normalized = config::normalize_remote(url);
+    let (host, path) = normalized.split_once('/')?;
+    // ghq leaves the port out, e.g. for ssh://git@host:2222/me/app.git.
+    let host = host.split_once(':').map_or(host, |(host, _)| host);
+    let parts: Vec<&str> = path.split('/').collect();
+    let valid = |part: &str| !matches!(part, "" | "." | "..") && !part.contains('\\');
+    if !valid(host) || parts.len() < 2 || !parts.iter().all(|part| valid(part)) {
+        return None;
+    }
+    Some(std::iter::once(host).chain(parts).collect())
+}
+
+/// Where `repo` goes under the ghq root, after the URL of its `origin` remote,
+/// or else of its first one.
+pub fn repo_layout(repo: &Path) -> Option<PathBuf> {
+    let origin = git::git(repo, ["config", "--get", "remote.origin.url"]).ok();
+    origin
+        .filter(|url| !url.is_empty())
+        .or_else(|| git::remote_urls(repo).into_iter().next())
+        .and_then(|url| layout(&url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(
+            layout("git@github.com:me/app.git"),
+            Some(PathBuf::from("github.com/me/app"))
+        );
+        assert_eq!(
+            layout("https://gitlab.example.com/group/sub/app/"),
+            Some(PathBuf::from("gitlab.example.com/group/sub/app"))
+        );
+        assert_eq!(
+            layout("ssh://git@git.example.com:2222/me/app.git"),
+            Some(PathBuf::from("git.example.com/me/app"))
+        );
+        assert_eq!(layout("/srv/git/app.git"), None);
+        assert_eq!(layout("https://github.com/../etc"), None);
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Runs `git -C <repo> <args>` and returns its trimmed stdout.
+pub fn git<I, S>(repo: &Path, args: I) -> io::Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "git failed in {:?}: {}",
+            repo,
+            stderr.trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Returns the commit and tree hashes of `HEAD`, if the repository has any commit.
+pub fn head_snapshot(repo: &Path) -> Option<(String, String)> {
+    let commit = git(repo, ["rev-parse", "--verify", "-q", "HEAD"]).ok()?;
+    let tree = git(repo, ["rev-parse", "--verify", "-q", "HEAD^{tree}"]).ok()?;
+    Some((commit, tree))
+}
+
+/// The directory holding the objects and refs of `repo`: its `.git`, unless
+/// `.git` is a file pointing elsewhere, as in worktrees and submodules.
+pub fn common_dir(repo: &Path) -> io::Result<PathBuf> {
+    Ok(repo.join(git(repo, ["rev-parse", "--git-common-dir"])?))
+}
+
+/// Whether the worktree has no staged, unstaged or untracked changes.
+pub fn is_clean(repo: &Path) -> bool {
+    git(repo, ["--no-optional-locks", "status", "--porcelain"])
+        .is_ok_and(|status| status.is_empty())
+}
+
+/// Whether the worktree of `repo` holds only what is committed: no changes,
+/// and no untracked or ignored files either.
+pub fn is_pristine(repo: &Path) -> bool {
+    git(
+        repo,
+        ["--no-optional-locks", "status", "--porcelain", "--ignored"],
+    )
+    .is_ok_and(|status| status.is_empty())
+}
+
+/// Paths of the files tracked in the index of `repo`, and of the directories
+/// leading to them.
+pub fn tracked_paths(repo: &Path) -> io::Result<HashSet<PathBuf>> {
+    let files = git(repo, ["ls-files", "-z"])?;
+    let mut paths = HashSet::new();
+    for file in files.split('\0').filter(|f| !f.is_empty()) {
+        let mut path: PathBuf = file.split('/').collect();
+        while paths.insert(path.clone()) && path.pop() && !path.as_os_str().is_empty() {}
+    }
+    Ok(paths)
+}
+
+/// Whether the worktree of `repo` is a sparse checkout, holding only the
+/// paths selected by `.git/info/sparse-checkout`.
+pub fn is_sparse(repo: &Path) -> bool {
+    git(repo, ["config", "--bool", "core.sparseCheckout"]).is_ok_and(|value| value == "true")
+}
+
+/// Paths of the files tracked in `repo` that its sparse checkout leaves out of
+/// the worktree.
+pub fn sparse_excluded(repo: &Path) -> io::Result<HashSet<PathBuf>> {
+    let files = git(repo, ["ls-files", "-t", "-z"])?;
+    Ok(files
+        .split('\0')
+        .filter_map(|file| file.strip_prefix("S "))
+        .map(|file| file.split('/').collect())
+        .collect())
+}
+
+/// The refs of `repo`, branches, tags, notes and stash alike, with the object
+/// each points to, and `HEAD` with the branch it is on, or its commit when
+/// detached.
+pub fn refs(repo: &Path) -> io::Result<BTreeMap<String, String>> {
+    let listed = git(repo, ["for-each-ref", "--format=%(refname) %(objectname)"])?;
+    let mut refs: BTreeMap<String, String> = listed
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, object)| (name.to_string(), object.to_string()))
+        .collect();
+    let head = git(repo, ["symbolic-ref", "-q", "HEAD"])
+        .or_else(|_| git(repo, ["rev-parse", "-q", "--verify", "HEAD"]))?;
+    refs.insert("HEAD".to_string(), head);
+    Ok(refs)
+}
+
+/// URLs of the remotes of `repo`, empty if it has none.
+pub fn remote_urls(repo: &Path) -> Vec<String> {
+    git(repo, ["config", "--get-regexp", r"^remote\..*\.url$"])
+        .map(|urls| {
+            urls.lines()
+                .filter_map(|line| line.split_once(' '))
+                .map(|(_, url)| url.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The path `key` is set to for `repo`, by its own config or the global or
+/// system one, and the scope it is set in: `local`, `global`, `system`...
+/// `~` is expanded and relative paths are resolved against `repo`.
+pub fn config_path(repo: &Path, key: &str) -> Option<(String, PathBuf)> {
+    let found = git(repo, ["config", "--show-scope", "--path", "--get", key]).ok()?;
+    let (scope, value) = found.split_once('\t')?;
+    Some((scope.to_string(), repo.join(value)))
+}
+
+/// The file of ignore patterns of every repository: `core.excludesFile`, or
+/// where git looks for it when unset.
+pub fn excludes_file(repo: &Path) -> Option<PathBuf> {
+    if let Some((_, file)) = config_path(repo, "core.excludesFile") {
+        return Some(file);
+    }
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("git").join("ignore"))
+}
+
+/// The config files, as seen from `repo`, that register `path` for `git
+/// maintenance` in `maintenance.repo`: the global one for `git maintenance
+/// start`, whose scheduled runs go through every repository it lists.
+pub fn maintenance_configs(repo: &Path, path: &Path) -> Vec<PathBuf> {
+    let found = git(
+        repo,
+        ["config", "--show-origin", "--get-all", "maintenance.repo"],
+    )
+    .unwrap_or_default();
+    let mut configs: Vec<PathBuf> = found
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(_, value)| Path::new(value) == path)
+        .filter_map(|(origin, _)| origin.strip_prefix("file:"))
+        .map(|file| repo.join(file))
+        .collect();
+    configs.dedup();
+    configs
+}
+
+/// Registers `new` for `git maintenance` in place of `old` in `config`.
+pub fn move_maintenance_repo(config: &Path, old: &Path, new: &Path) -> io::Result<()> {
+    let mut args = vec![OsStr::new("config"), OsStr::new("--file")];
+    args.extend([config.as_os_str(), OsStr::new("--fixed-value")]);
+    args.extend([OsStr::new("--replace-all"), OsStr::new("maintenance.repo")]);
+    args.extend([new.as_os_str(), old.as_os_str()]);
+    git(Path::new("."), args)?;
+    Ok(())
+}
+
+/// The promisor remotes of `repo`, from which a partial clone fetches the
+/// objects its filter left out when they are needed.
+pub fn promisor_remotes(repo: &Path) -> Vec<String> {
+    let config = git(
+        repo,
+        [
+            "config",
+            "--get-regexp",
+            r"^remote\..*\.promisor$|^extensions\.partialclone$",
+        ],
+    )
+    .unwrap_or_default();
+    let mut remotes = Vec::new();
+    for (key, value) in config.lines().filter_map(|line| line.split_once(' ')) {
+        let remote = match key.strip_suffix(".promisor") {
+            Some(key) if value == "true" => key.strip_prefix("remote.").unwrap_or(key),
+            Some(_) => continue,
+            None => value,
+        };
+        if !remotes.iter().any(|known| known == remote) {
+            remotes.push(remote.to_string());
+        }
+    }
+    remotes
+}
+
+/// Number of objects reachable in `repo` that are missing from it.
+pub fn missing_objects(repo: &Path) -> io::Result<usize> {
+    let objects = git(repo, ["rev-list", "--objects", "--all", "--missing=print"])?;
+    Ok(objects.lines().filter(|line| line.starts_with('?')).count())
+}
+
+/// Fetches from `remote` what the partial clone `repo` left out, then makes it
+/// a complete clone that no longer needs the remote.
+pub fn backfill(repo: &Path, remote: &str) -> io::Result<()> {
+    // Fails when there is no filter to remove, which is fine.
+    let _ = git(
+        repo,
+        [
+            "config",
+            "--unset-all",
+            &format!("remote.{}.partialclonefilter", remote),
+        ],
+    );
+    // No gc in the background, repacking what is about to be copied.
+    git(repo, ["fetch", "-q", "--refetch", "--no-auto-gc", remote])?;
+    let missing = missing_objects(repo)?;
+    if missing > 0 {
+        return Err(io::Error::other(format!(
+            "{} objects are still missing after fetching from {}",
+            missing, remote
+        )));
+    }
+    for key in [
+        format!("remote.{}.promisor", remote),
+        "extensions.partialclone".to_string(),
+    ] {
+        let _ = git(repo, ["config", "--unset-all", &key]);
+    }
+    Ok(())
+}
+
+/// Whether `repo` is a shallow clone, whose history stops at the commits
+/// listed in `.git/shallow`.
+pub fn is_shallow(repo: &Path) -> bool {
+    repo.join(".git").join("shallow").is_file()
+}
+
+/// Fetches the history the shallow clone `repo` lacks.
+pub fn unshallow(repo: &Path) -> io::Result<()> {
+    git(repo, ["fetch", "-q", "--unshallow", "--no-auto-gc"])?;
+    Ok(())
+}
+
+/// Whether `a` and `b` hold the same content: same `HEAD` commit and tree,
+/// with nothing uncommitted on either side.
+pub fn is_identical_copy(a: &Path, b: &Path) -> bool {
+    match (head_snapshot(a), head_snapshot(b)) {
+        (Some(a_head), Some(b_head)) => a_head == b_head && is_clean(a) && is_clean(b),
+        _ => false,
+    }
+}
+
+/// Creates a repository at `dir` with a single commit containing `file`.
+#[cfg(test)]
+pub fn init_repo_with_commit(dir: &Path, file: &str) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    git(dir, ["init", "-q"])?;
+    std::fs::write(dir.join(file), file)?;
+    commit_all(dir, "initial")
+}
+
+/// Commits everything in the worktree of `repo`.
+#[cfg(test)]
+pub fn commit_all(repo: &Path, message: &str) -> io::Result<()> {
+    git(repo, ["add", "."])?;
+    git(
+        repo,
+        [
+            "-c",
+            "user.name=mv-git",
+            "-c",
+            "user.email=mv-git@example.com",
+            "commit",
+            "-q",
+            "-m",
+            message,
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_config_path() -> io::Result<()> {
+        let dir = tempdir()?;
+        let repo = dir.path().join("repo");
+        init_repo_with_commit(&repo, "README.md")?;
+        assert_eq!(config_path(&repo, "mv-git.unset"), None);
+        git(&repo, ["config", "core.excludesFile", "ignored.txt"])?;
+        assert_eq!(
+            config_path(&repo, "core.excludesFile"),
+            Some(("local".to_string(), repo.join("ignored.txt")))
+        );
+        assert_eq!(excludes_file(&repo), Some(repo.join("ignored.txt")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_maintenance_repo() -> io::Result<()> {
+        let dir = tempdir()?;
+        let repo = dir.path().join("repo");
+        init_repo_with_commit(&repo, "README.md")?;
+        let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+        assert!(maintenance_configs(&repo, &old).is_empty());
+        for registered in [&dir.path().join("other"), &old] {
+            let mut args = vec![OsStr::new("config"), OsStr::new("--add")];
+            args.extend([OsStr::new("maintenance.repo"), registered.as_os_str()]);
+            git(&repo, args)?;
+        }
+        let config = repo.join(".git/config");
+        assert_eq!(maintenance_configs(&repo, &old), vec![config.clone()]);
+
+        move_maintenance_repo(&config, &old, &new)?;
+        assert!(maintenance_configs(&repo, &old).is_empty());
+        assert_eq!(maintenance_configs(&repo, &new), vec![config]);
+        assert_eq!(
+            git(&repo, ["config", "--get-all", "maintenance.repo"])?,
+            format!("{}\n{}", dir.path().join("other").display(), new.display())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_excluded() -> io::Result<()> {
+        let dir = tempdir()?;
+        let repo = dir.path().join("repo");
+        init_repo_with_commit(&repo, "README.md")?;
+        std::fs::create_dir_all(repo.join("docs"))?;
+        std::fs::write(repo.join("docs/guide.md"), "guide")?;
+        commit_all(&repo, "docs")?;
+        assert!(!is_sparse(&repo));
+        git(&repo, ["sparse-checkout", "set", "src"])?;
+        assert!(is_sparse(&repo));
+        assert_eq!(
+            sparse_excluded(&repo)?,
+            HashSet::from([PathBuf::from("docs/guide.md")])
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_backfill() -> io::Result<()> {
+        let dir = tempdir()?;
+        let origin = dir.path().join("origin");
+        let clone = dir.path().join("clone");
+        init_repo_with_commit(&origin, "README.md")?;
+        git(&origin, ["config", "uploadpack.allowFilter", "true"])?;
+        git(
+            dir.path(),
+            [
+                OsStr::new("clone"),
+                OsStr::new("-q"),
+                OsStr::new("--filter=blob:none"),
+                OsStr::new("--no-checkout"),
+                format!("file://{}", origin.display()).as_ref(),
+                clone.as_os_str(),
+            ],
+        )?;
+        assert_eq!(promisor_remotes(&clone), vec!["origin".to_string()]);
+        assert_eq!(missing_objects(&clone)?, 1);
+
+        backfill(&clone, "origin")?;
+        assert!(promisor_remotes(&clone).is_empty());
+        assert_eq!(missing_objects(&clone)?, 0);
+        assert!(promisor_remotes(&origin).is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unshallow() -> io::Result<()> {
+        let dir = tempdir()?;
+        let origin = dir.path().join("origin");
+        let clone = dir.path().join("clone");
+        init_repo_with_commit(&origin, "README.md")?;
+        std::fs::write(origin.join("README.md"), "more")?;
+        commit_all(&origin, "more")?;
+        git(
+            dir.path(),
+            [
+                OsStr::new("clone"),
+                OsStr::new("-q"),
+                OsStr::new("--depth=1"),
+                format!("file://{}", origin.display()).as_ref(),
+                clone.as_os_str(),
+            ],
+        )?;
+        assert!(is_shallow(&clone));
+        assert!(!is_shallow(&origin));
+
+        unshallow(&clone)?;
+        assert!(!is_shallow(&clone));
+        assert_eq!(git(&clone, ["rev-list", "--count", "HEAD"])?, "2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_identical_copy() -> io::Result<()> {
+        let dir = tempdir()?;
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        init_repo_with_commit(&a, "README.md")?;
+        git(
+            dir.path(),
+            [
+                OsStr::new("clone"),
+                OsStr::new("-q"),
+                a.as_os_str(),
+                b.as_os_str(),
+            ],
+        )?;
+
+        assert!(is_identical_copy(&a, &b));
+
+        std::fs::write(b.join("untracked.txt"), "new")?;
+        assert!(!is_identical_copy(&a, &b));
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+/// Whether `pattern` has any of the wildcards [`matches`] understands.
+pub fn is_pattern(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Whether the file name `name` matches `pattern`: `*` stands for any characters,
+/// `?` for one, `[abc]`, `[a-z]` and `[!abc]` for one of a set. Like in a shell,
+/// names starting with `.` are only matched by patterns starting with `.`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches_from(&pattern[1..], &name[1..]),
+        Some('[') => match (class_len(pattern), name.first()) {
+            (Some(len), Some(&c)) => {
+                in_class(&pattern[1..len - 1], c) && matches_from(&pattern[len..], &name[1..])
+            }
+            (Some(_), None) => false,
+            // An unclosed `[` is an ordinary character.
+            (None, _) => name.first() == Some(&'[') && matches_from(&pattern[1..], &name[1..]),
+        },
+        Some(&c) => name.first() == Some(&c) && matches_from(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Length of the `[...]` class at the start of `pattern`, brackets included.
+fn class_len(pattern: &[char]) -> Option<usize> {
+    let mut i = 1;
+    if pattern.get(i) == Some(&'!') {
+        i += 1;
+    }
+    // A `]` right after the opening bracket is part of the set.
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+    pattern[i..]
+        .iter()
+        .position(|&c| c == ']')
+        .map(|end| i + end + 1)
+}
+
+fn in_class(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some(('!', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+/// The directories matching `pattern`, sorted. A path without wildcards is
+/// returned as is, whether it exists or not, and so is one naming an existing
+/// path, such as `site[old]` quoted or already expanded by the shell.
+pub fn expand(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    if !is_pattern(pattern) || Path::new(pattern).exists() {
+        return Ok(vec![PathBuf::from(pattern)]);
+    }
+    let mut found = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let Component::Normal(part) = component else {
+            for path in &mut found {
+                path.push(component);
+            }
+            continue;
+        };
+        let part = part.to_string_lossy();
+        if !is_pattern(&part) {
+            for path in &mut found {
+                path.push(&*part);
+            }
+            continue;
+        }
+        let mut next = Vec::new();
+        for dir in found {
+            let read_from = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                &dir
+            };
+            let Ok(entries) = fs::read_dir(read_from) else {
+                continue;
+            };
+            for entry in entries {
+                let entry = entry?;
+                if matches(&part, &entry.file_name().to_string_lossy()) {
+                    next.push(dir.join(entry.file_name()));
+                }
+            }
+        }
+        found = next;
+    }
+    found.retain(|path| path.is_dir());
+    found.sort();
+    if found.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("No directory matches {:?}", pattern),
+        ));
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("work-*", "work-api"));
+        assert!(!matches("work-*", "personal"));
+        assert!(matches("v?.[0-9]", "v1.2"));
+        assert!(!matches("v?.[!0-9]", "v1.2"));
+        assert!(!matches("*", ".hidden"));
+        assert!(matches(".*", ".hidden"));
+        assert!(matches("a[b", "a[b"));
+    }
+
+    #[test]
+    fn test_expand() -> io::Result<()> {
+        let dir = tempdir()?;
+        for sub in ["work-api/repos", "work-web/repos", "personal/repos"] {
+            fs::create_dir_all(dir.path().join(sub))?;
+        }
+        fs::write(dir.path().join("work-notes.txt"), "")?;
+
+        let pattern = dir.path().join("work-*").join("repos");
+        assert_eq!(
+            expand(&pattern.to_string_lossy())?,
+            vec![
+                dir.path().join("work-api/repos"),
+                dir.path().join("work-web/repos")
+            ]
+        );
+        assert_eq!(expand("no/such/dir")?, vec![PathBuf::from("no/such/dir")]);
+        assert!(expand(&dir.path().join("nothing-*").to_string_lossy()).is_err());
+
+        let literal = dir.path().join("site[old]");
+        fs::create_dir(&literal)?;
+        assert_eq!(expand(&literal.to_string_lossy())?, vec![literal]);
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::scan::FileStamp;
+use crate::{git, paths};
+
+pub const VERIFY_USAGE: &str = "Usage: verify-op <id>";
+
+/// A file as it was at the destination right after the transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedFile {
+    /// Relative to the repository.
+    pub path: PathBuf,
+    pub size: u64,
+    /// Nanoseconds since the Unix epoch.
+    pub mtime: i64,
+}
+
+/// A repository transferred by an operation, with the files it left at the destination.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRepo {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    pub files: Vec<RecordedFile>,
+}
+
+impl RecordedRepo {
+    /// Records the files at `dest`, the destination of `source`.
+    pub fn record(source: &Path, dest: &Path) -> io::Result<RecordedRepo> {
+        let mut files = Vec::new();
+        walk(dest, Path::new(""), &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(RecordedRepo {
+            source: std::path::absolute(source)?,
+            dest: std::path::absolute(dest)?,
+            files,
+        })
+    }
+}
+
+fn walk(root: &Path, rel: &Path, files: &mut Vec<RecordedFile>) -> io::Result<()> {
+    for entry in fs::read_dir(root.join(rel))? {
+        let entry = entry?;
+        let path = rel.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(root, &path, files)?;
+        } else if file_type.is_file() {
+            let stamp = FileStamp::from_metadata(&entry.metadata()?);
+            files.push(RecordedFile {
+                path,
+                size: stamp.size,
+                mtime: stamp.mtime,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// One run, as kept in the history.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Operation {
+    /// Seconds since the Unix epoch.
+    pub started: u64,
+    pub mode: String,
+    pub repos: Vec<RecordedRepo>,
+}
+
+impl Operation {
+    /// Adds the operation to the history in `dir`, returning its id.
+    pub fn save_new(&self, dir: &Path) -> io::Result<String> {
+        paths::write_new(dir, &(serde_json::to_string(self)? + "\n"))
+    }
+
+    pub fn load(dir: &Path, id: &str) -> io::Result<Operation> {
+        let path = dir.join(format!("{}.json", id));
+        let text = match fs::read_to_string(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("No operation {:?} in the history", id),
+                ))
+            }
+            text => text?,
+        };
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid operation {:?}: {}", path, e),
+            )
+        })
+    }
+}
+
+/// Where the history of operations is kept: `mv-git/history` in the user's
+/// state directory.
+pub fn dir() -> Option<PathBuf> {
+    paths::state_dir().map(|dir| dir.join("history"))
+}
+
+/// What became of a recorded file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    Missing,
+    /// Its size or modification time is no longer the recorded one.
+    Changed,
+}
+
+/// The files of `op` that are no longer at their destination as they were
+/// recorded, with what is wrong with them.
+pub fn verify(op: &Operation) -> Vec<(PathBuf, Problem)> {
+    let mut problems = Vec::new();
+    for repo in &op.repos {
+        // Files a sparse checkout left out since are not missing.
+        let excluded = if git::is_sparse(&repo.dest) {
+            git::sparse_excluded(&repo.dest).unwrap_or_default()
+        } else {
+            HashSet::new()
+        };
+        for file in &repo.files {
+            let path = repo.dest.join(&file.path);
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_file() => {
+                    let stamp = FileStamp::from_metadata(&metadata);
+                    if (stamp.size, stamp.mtime) != (file.size, file.mtime) {
+                        problems.push((path, Problem::Changed));
+                    }
+                }
+                Ok(_) => problems.push((path, Problem::Changed)),
+                Err(_) if excluded.contains(&file.path) => {}
+                Err(_) => problems.push((path, Problem::Missing)),
+            }
+        }
+    }
+    problems
+}
+
+/// Runs `verify-op` with the arguments after the subcommand. Returns whether
+/// every file is still as recorded.
+pub fn run(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let [id] = args else {
+        return Err(VERIFY_USAGE.into());
+    };
+    let dir = dir().ok_or("HOME is not set, there is no history")?;
+    let op = Operation::load(&dir, id)?;
+    let problems = verify(&op);
+    for (path, problem) in &problems {
+        let label = match problem {
+            Problem::Missing => "missing",
+            Problem::Changed => "changed",
+        };
+        println!("{:<8} {}", label, path.display());
+    }
+    let files: usize = op.repos.iter().map(|r| r.files.len()).sum();
+    println!(
+        "{} of {} files in {} repositories as recorded",
+        files - problems.len(),
+        files,
+        op.repos.len()
+    );
+    Ok(problems.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_verify() -> io::Result<()> {
+        let dir = tempdir()?;
+        let dest = dir.path().join("repo");
+        fs::create_dir_all(dest.join("src"))?;
+        fs::write(dest.join("README.md"), "readme")?;
+        fs::write(dest.join("src/main.rs"), "fn main() {}")?;
+
+        let op = Operation {
+            started: 0,
+            mode: "move".to_string(),
+            repos: vec![RecordedRepo::record(&dir.path().join("old"), &dest)?],
+        };
+        let history = dir.path().join("history");
+        let id = op.save_new(&history)?;
+        let op = Operation::load(&history, &id)?;
+        assert_eq!(op.repos[0].files.len(), 2);
+        assert!(verify(&op).is_empty());
+
+        fs::remove_file(dest.join("README.md"))?;
+        fs::write(dest.join("src/main.rs"), "fn main() { panic!() }")?;
+        assert_eq!(
+            verify(&op),
+            vec![
+                (dest.join("README.md"), Problem::Missing),
+                (dest.join("src/main.rs"), Problem::Changed),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_sparse_checkout() -> io::Result<()> {
+        let dir = tempdir()?;
+        let dest = dir.path().join("repo");
+        git::init_repo_with_commit(&dest, "README.md")?;
+        fs::create_dir_all(dest.join("docs"))?;
+        fs::write(dest.join("docs/guide.md"), "guide")?;
+        git::commit_all(&dest, "docs")?;
+
+        let op = Operation {
+            started: 0,
+            mode: "move".to_string(),
+            repos: vec![RecordedRepo::record(&dir.path().join("old"), &dest)?],
+        };
+        git::git(&dest, ["sparse-checkout", "set", "src"])?;
+        assert!(!dest.join("docs/guide.md").exists());
+        // Only git's own files changed, the guide is left out rather than missing.
+        assert!(verify(&op)
+            .iter()
+            .all(|(path, _)| path.starts_with(dest.join(".git"))));
+        Ok(())
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::scan::{EntryKind, FileStamp, Scan};
+
+/// Name of the cache file kept at the root of a sync destination.
+pub const INDEX_FILE: &str = ".mv-git-index";
+
+const MAGIC: &[u8; 4] = b"MVGI";
+const VERSION: u32 = 2;
+
+/// File stamps recorded by previous runs, per repository, for one destination.
+///
+/// The on-disk format is a small binary file: a magic, a version, then for each
+/// repository its path in the destination followed by `(path, size, mtime)`
+/// records of the source, then since version 2 the same records of the copy at
+/// the destination.
+#[derive(Debug, Default)]
+pub struct Index {
+    path: PathBuf,
+    repos: HashMap<String, HashMap<PathBuf, FileStamp>>,
+    /// Stamps of the copies, which don't keep the mtime of their source, to
+    /// tell when they were changed at the destination.
+    dests: HashMap<String, HashMap<PathBuf, FileStamp>>,
+}
+
+/// Whether a change of `rel` at the destination is the user's doing, and
+/// would be lost by syncing over it: the files of the worktree and the refs.
+/// The rest of `.git` is rewritten by git itself, or by mv-git repairing it.
+fn is_watched(rel: &Path) -> bool {
+    match rel.strip_prefix(".git") {
+        Ok(inside) => {
+            inside == Path::new("HEAD")
+                || inside == Path::new("packed-refs")
+                || inside.starts_with("refs")
+        }
+        Err(_) => true,
+    }
+}
+
+fn write_u32(w: &mut impl Write, value: u32) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_u64(w: &mut impl Write, value: u64) -> io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_str(w: &mut impl Write, value: &str) -> io::Result<()> {
+    write_u32(w, value.len() as u32)?;
+    w.write_all(value.as_bytes())
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_str(r: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(r)? as usize;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+fn write_stamps(w: &mut impl Write, files: &HashMap<PathBuf, FileStamp>) -> io::Result<()> {
+    write_u64(w, files.len() as u64)?;
+    for (rel, stamp) in files {
+        write_str(w, &rel.to_string_lossy())?;
+        write_u64(w, stamp.size)?;
+        write_u64(w, stamp.mtime as u64)?;
+    }
+    Ok(())
+}
+
+fn read_stamps(r: &mut impl Read) -> io::Result<HashMap<PathBuf, FileStamp>> {
+    let mut files = HashMap::new();
+    for _ in 0..read_u64(r)? {
+        let rel = PathBuf::from(read_str(r)?);
+        let size = read_u64(r)?;
+        let mtime = read_u64(r)? as i64;
+        files.insert(rel, FileStamp { size, mtime });
+    }
+    Ok(files)
+}
+
+impl Index {
+    /// Loads the index of `dst`, starting empty if there is none yet.
+    pub fn load(dst: &Path) -> io::Result<Index> {
+        let path = dst.join(INDEX_FILE);
+        let mut index = Index {
+            path: path.clone(),
+            ..Index::default()
+        };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(index),
+            Err(e) => return Err(e),
+        };
+
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        let version = if &magic == MAGIC {
+            read_u32(&mut reader)?
+        } else {
+            0
+        };
+        if !(1..=VERSION).contains(&version) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?} is not a mv-git index", path),
+            ));
+        }
+
+        for _ in 0..read_u64(&mut reader)? {
+            let repo = read_str(&mut reader)?;
+            index.repos.insert(repo.clone(), read_stamps(&mut reader)?);
+            // Indexes of version 1 only tell what the source was like.
+            if version >= 2 {
+                index.dests.insert(repo, read_stamps(&mut reader)?);
+            }
+        }
+        Ok(index)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            writer.write_all(MAGIC)?;
+            write_u32(&mut writer, VERSION)?;
+            write_u64(&mut writer, self.repos.len() as u64)?;
+            let none = HashMap::new();
+            for (repo, files) in &self.repos {
+                write_str(&mut writer, repo)?;
+                write_stamps(&mut writer, files)?;
+                write_stamps(&mut writer, self.dests.get(repo).unwrap_or(&none))?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(tmp, &self.path)
+    }
+
+    /// Stamp recorded for `rel` in `repo` by the last run.
+    pub fn stamp(&self, repo: &str, rel: &Path) -> Option<FileStamp> {
+        self.repos.get(repo)?.get(rel).copied()
+    }
+
+    /// Whether the files of `scan` are exactly those recorded for `repo` by the last run.
+    pub fn is_unchanged(&self, repo: &str, scan: &Scan) -> io::Result<bool> {
+        let Some(files) = self.repos.get(repo) else {
+            return Ok(false);
+        };
+        let mut count = 0;
+        for batch in scan.batches()? {
+            for entry in batch?.iter().filter(|e| e.kind == EntryKind::File) {
+                if files.get(&entry.rel) != Some(&entry.stamp) {
+                    return Ok(false);
+                }
+                count += 1;
+            }
+        }
+        Ok(count == files.len())
+    }
+
+    pub fn update(&mut self, repo: &str, scan: &Scan) -> io::Result<()> {
+        let mut files = HashMap::new();
+        for batch in scan.batches()? {
+            for entry in batch?.iter().filter(|e| e.kind == EntryKind::File) {
+                files.insert(entry.rel.clone(), entry.stamp);
+            }
+        }
+        self.repos.insert(repo.to_string(), files);
+        Ok(())
+    }
+
+    /// Records how the files of `repo` that [`changed_at`](Index::changed_at)
+    /// watches are at `dst`, where they were just synced.
+    pub fn update_dest(&mut self, repo: &str, dst: &Path) -> io::Result<()> {
+        let mut files = HashMap::new();
+        for rel in self.repos.get(repo).into_iter().flat_map(HashMap::keys) {
+            if !is_watched(rel) {
+                continue;
+            }
+            match fs::symlink_metadata(dst.join(rel)) {
+                Ok(metadata) => {
+                    files.insert(rel.clone(), FileStamp::from_metadata(&metadata));
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.dests.insert(repo.to_string(), files);
+        Ok(())
+    }
+
+    /// The files of `repo` changed or deleted at `dst` since the last run
+    /// synced them there, among those of the worktree and the refs.
+    pub fn changed_at(&self, repo: &str, dst: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        for (rel, stamp) in self.dests.get(repo).into_iter().flatten() {
+            match fs::symlink_metadata(dst.join(rel)) {
+                Ok(metadata) if FileStamp::from_metadata(&metadata) == *stamp => {}
+                Ok(_) => changed.push(rel.clone()),
+                Err(e) if e.kind() == ErrorKind::NotFound => changed.push(rel.clone()),
+                Err(e) => return Err(e),
+            }
+        }
+        changed.sort();
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::Entry;
+    use tempfile::tempdir;
+
+    fn file(rel: &str, size: u64, mtime: i64) -> Entry {
+        Entry {
+            rel: PathBuf::from(rel),
+            kind: EntryKind::File,
+            stamp: FileStamp { size, mtime },
+        }
+    }
+
+    #[test]
+    fn test_index_roundtrip() -> io::Result<()> {
+        let dir = tempdir()?;
+        let entries = vec![file("a.txt", 1, 10), file("sub/b.txt", 2, 20)];
+        let scan = Scan::from(entries.clone());
+
+        let mut index = Index::load(dir.path())?;
+        assert!(!index.is_unchanged("repo", &scan)?);
+        index.update("repo", &scan)?;
+        index.save()?;
+
+        let index = Index::load(dir.path())?;
+        assert!(index.is_unchanged("repo", &scan)?);
+        assert_eq!(
+            index.stamp("repo", Path::new("sub/b.txt")),
+            Some(FileStamp { size: 2, mtime: 20 })
+        );
+        let touched = Scan::from(vec![file("a.txt", 1, 11), file("sub/b.txt", 2, 20)]);
+        assert!(!index.is_unchanged("repo", &touched)?);
+        assert!(!index.is_unchanged("repo", &Scan::from(entries[..1].to_vec()))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_at() -> io::Result<()> {
+        let dir = tempdir()?;
+        let dst = dir.path().join("repo");
+        fs::create_dir_all(dst.join(".git/refs/heads"))?;
+        for rel in ["a.txt", "b.txt", ".git/index", ".git/refs/heads/main"] {
+            fs::write(dst.join(rel), rel)?;
+        }
+        let scan = Scan::from(vec![
+            file("a.txt", 5, 10),
+            file("b.txt", 5, 10),
+            file(".git/index", 10, 10),
+            file(".git/refs/heads/main", 20, 10),
+        ]);
+        let mut index = Index::load(dir.path())?;
+        assert!(index.changed_at("repo", &dst)?.is_empty());
+        index.update("repo", &scan)?;
+        index.update_dest("repo", &dst)?;
+        index.save()?;
+
+        let index = Index::load(dir.path())?;
+        assert!(index.changed_at("repo", &dst)?.is_empty());
+        fs::write(dst.join("a.txt"), "changed at the destination")?;
+        fs::remove_file(dst.join("b.txt"))?;
+        fs::write(dst.join(".git/index"), "refreshed by git status")?;
+        fs::write(dst.join(".git/refs/heads/main"), "committed")?;
+        assert_eq!(
+            index.changed_at("repo", &dst)?,
+            vec![
+                PathBuf::from(".git/refs/heads/main"),
+                PathBuf::from("a.txt"),
+                PathBuf::from("b.txt")
+            ]
+        );
+        Ok(())
+    }
+}
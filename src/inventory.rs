@@ -0,0 +1,418 @@
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::history::{self, Operation};
+use crate::{dedupe, git, paths, units};
+
+pub const LIST_USAGE: &str = "Usage: list [--scan <dir>]...";
+pub const HISTORY_USAGE: &str = "Usage: history";
+pub const TAG_USAGE: &str = "Usage: tag <repo> [<tag>...] [--remove]";
+pub const EXPORT_USAGE: &str = "Usage: export [--format csv|json] [-o <file>]";
+
+/// Columns of `export --format csv`.
+const CSV_HEADER: &str = "host,path,remote,size,last_commit,dirty,tags,seen";
+
+/// A repository as it was when mv-git last saw it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryRepo {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remotes: Vec<String>,
+    /// Bytes, `.git` included.
+    pub size: u64,
+    /// Date of the last commit, as `YYYY-MM-DD`, `None` without commits.
+    pub last_commit: Option<String>,
+    /// Whether it had uncommitted changes.
+    pub dirty: bool,
+    /// When it was looked at, in seconds since the Unix epoch.
+    pub seen: u64,
+    /// Given with `tag`, and kept when it moves.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// When a `--sync` run last brought it up to date, in seconds since the
+    /// Unix epoch, for `--min-interval`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced: Option<u64>,
+}
+
+impl InventoryRepo {
+    /// Looks at the repository at `path`.
+    pub fn inspect(path: &Path) -> io::Result<InventoryRepo> {
+        let last_commit = git::git(path, ["log", "-1", "--format=%cs"])
+            .ok()
+            .filter(|date| !date.is_empty());
+        Ok(InventoryRepo {
+            path: std::path::absolute(path)?,
+            remotes: git::remote_urls(path),
+            size: dedupe::dir_size(path)?,
+            last_commit,
+            dirty: !git::is_clean(path),
+            seen: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            tags: Vec::new(),
+            synced: None,
+        })
+    }
+}
+
+/// Every repository mv-git found or transferred, so that they can be listed
+/// without walking the disks again. Kept as a JSON file, see [`path`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Inventory {
+    pub repos: Vec<InventoryRepo>,
+}
+
+impl Inventory {
+    pub fn load(path: &Path) -> io::Result<Inventory> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Inventory::default()),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid inventory {:?}: {}", path, e),
+            )
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// Records `repo`, replacing what was known of the same path but its tags
+    /// and when it was last synced.
+    pub fn record(&mut self, mut repo: InventoryRepo) {
+        if let Some(known) = self.find(&repo.path) {
+            for tag in &known.tags {
+                if !repo.tags.contains(tag) {
+                    repo.tags.push(tag.clone());
+                }
+            }
+            repo.synced = repo.synced.or(known.synced);
+        }
+        self.forget(&repo.path);
+        self.repos.push(repo);
+        self.repos.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    /// What is known of the repository at `path`, which is absolute.
+    pub fn find(&self, path: &Path) -> Option<&InventoryRepo> {
+        self.repos.iter().find(|r| r.path == path)
+    }
+
+    /// Forgets the repository at `path`, e.g. once it was moved away.
+    pub fn forget(&mut self, path: &Path) {
+        self.repos.retain(|r| r.path != path);
+    }
+
+    /// Records the repositories under `dir`, forgetting those known there that
+    /// are gone. Returns how many were found.
+    pub fn scan(&mut self, dir: &Path) -> io::Result<usize> {
+        let dir = std::path::absolute(dir)?;
+        let mut found = Vec::new();
+        find_repos(&dir, &mut found)?;
+        self.repos
+            .retain(|r| !r.path.starts_with(&dir) || found.contains(&r.path));
+        for repo in &found {
+            self.record(InventoryRepo::inspect(repo)?);
+        }
+        Ok(found.len())
+    }
+
+    /// The repositories as a table, largest first.
+    pub fn table(&self) -> String {
+        let mut repos: Vec<&InventoryRepo> = self.repos.iter().collect();
+        repos.sort_by(|a, b| b.size.cmp(&a.size).then(a.path.cmp(&b.path)));
+        let mut out = String::new();
+        for repo in repos {
+            let _ = writeln!(
+                out,
+                "{:>8}  {:<10}  {:<5}  {}  {}",
+                units::format_size(repo.size),
+                repo.last_commit.as_deref().unwrap_or("-"),
+                if repo.dirty { "dirty" } else { "clean" },
+                repo.path.display(),
+                repo.remotes.first().map_or("", String::as_str)
+            );
+        }
+        out
+    }
+}
+
+/// A field of a CSV row, quoted when it has to be.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl Inventory {
+    /// The inventory as CSV, one row per repository, each saying it is on `host`.
+    pub fn to_csv(&self, host: &str) -> String {
+        let mut out = format!("{}\n", CSV_HEADER);
+        for repo in &self.repos {
+            let row = [
+                host.to_string(),
+                repo.path.to_string_lossy().into_owned(),
+                repo.remotes.join(" "),
+                repo.size.to_string(),
+                repo.last_commit.clone().unwrap_or_default(),
+                repo.dirty.to_string(),
+                repo.tags.join(" "),
+                repo.seen.to_string(),
+            ];
+            let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            let _ = writeln!(out, "{}", row.join(","));
+        }
+        out
+    }
+
+    /// The inventory as JSON, saying it is on `host`.
+    pub fn to_json(&self, host: &str) -> io::Result<String> {
+        #[derive(Serialize)]
+        struct Export<'a> {
+            host: &'a str,
+            repos: &'a [InventoryRepo],
+        }
+        let export = Export {
+            host,
+            repos: &self.repos,
+        };
+        Ok(serde_json::to_string_pretty(&export)? + "\n")
+    }
+}
+
+/// Name of this machine, to tell exports from several apart.
+fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            return String::from_utf8_lossy(&buf[..len]).into_owned();
+        }
+        String::new()
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").unwrap_or_default()
+    }
+}
+
+/// Runs `export` with the arguments after the subcommand.
+pub fn run_export(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut format = "csv".to_string();
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}\n{}", arg, EXPORT_USAGE))
+        };
+        match arg.as_str() {
+            "--format" => format = value()?,
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            _ => return Err(format!("Unknown argument: {}\n{}", arg, EXPORT_USAGE).into()),
+        }
+    }
+    let inventory = Inventory::load(&path().ok_or("HOME is not set, there is no inventory")?)?;
+    let host = hostname();
+    let text = match format.as_str() {
+        "csv" => inventory.to_csv(&host),
+        "json" => inventory.to_json(&host)?,
+        _ => {
+            return Err(format!("Invalid --format {:?}, expected csv or json", format).into());
+        }
+    };
+    match output {
+        Some(output) => fs::write(output, text)?,
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// Git repositories under `dir`, not looking inside them or hidden directories.
+pub fn find_repos(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    if dir.join(".git").exists() {
+        found.push(dir.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.') {
+            find_repos(&entry.path(), found)?;
+        }
+    }
+    Ok(())
+}
+
+/// Where the inventory is kept: `mv-git/inventory.json` in the user's data directory.
+pub fn path() -> Option<PathBuf> {
+    paths::data_dir().map(|dir| dir.join("inventory.json"))
+}
+
+/// Runs `list` with the arguments after the subcommand, printing the repositories
+/// of the JSON inventory at [`path`].
+pub fn run_list(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut scan = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scan" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, LIST_USAGE))?;
+                scan.push(PathBuf::from(value));
+            }
+            _ => return Err(format!("Unknown argument: {}\n{}", arg, LIST_USAGE).into()),
+        }
+    }
+    let path = path().ok_or("HOME is not set, there is no inventory")?;
+    let mut inventory = Inventory::load(&path)?;
+    if !scan.is_empty() {
+        for dir in &scan {
+            inventory.scan(dir)?;
+        }
+        inventory.save(&path)?;
+    }
+    print!("{}", inventory.table());
+    Ok(())
+}
+
+/// Runs `tag` with the arguments after the subcommand: adds the tags to the
+/// repository, or removes them with `--remove`, then prints its tags.
+pub fn run_tag(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut remove = false;
+    let mut words = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--remove" => remove = true,
+            _ if arg.starts_with('-') => {
+                return Err(format!("Unknown option: {}\n{}", arg, TAG_USAGE).into());
+            }
+            _ => words.push(arg.as_str()),
+        }
+    }
+    let Some((repo, tags)) = words.split_first() else {
+        return Err(TAG_USAGE.into());
+    };
+    let repo = Path::new(repo);
+    let path = path().ok_or("HOME is not set, there is no inventory")?;
+    let mut inventory = Inventory::load(&path)?;
+    let absolute = std::path::absolute(repo)?;
+    if inventory.find(&absolute).is_none() {
+        if !repo.join(".git").exists() {
+            return Err(format!("{} is not a git repository", repo.display()).into());
+        }
+        inventory.record(InventoryRepo::inspect(repo)?);
+    }
+    let known = inventory
+        .repos
+        .iter_mut()
+        .find(|r| r.path == absolute)
+        .ok_or("The repository vanished from the inventory")?;
+    for tag in tags {
+        if remove {
+            known.tags.retain(|t| t != tag);
+        } else if !known.tags.iter().any(|t| t == tag) {
+            known.tags.push(tag.to_string());
+        }
+    }
+    known.tags.sort();
+    println!("{}", known.tags.join(" "));
+    inventory.save(&path)?;
+    Ok(())
+}
+
+/// Runs `history` with the arguments after the subcommand, listing the
+/// operations kept for `verify-op`, one JSON file each in [`history::dir`],
+/// oldest first.
+pub fn run_history(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if !args.is_empty() {
+        return Err(HISTORY_USAGE.into());
+    }
+    let dir = history::dir().ok_or("HOME is not set, there is no history")?;
+    let mut ids: Vec<String> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().to_string_lossy().into_owned();
+                name.strip_suffix(".json").map(str::to_string)
+            })
+            .collect(),
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    ids.sort();
+    for id in ids {
+        let op = Operation::load(&dir, &id)?;
+        let files: usize = op.repos.iter().map(|r| r.files.len()).sum();
+        println!(
+            "{:<12}  {:<4}  {} repositories, {} files",
+            id,
+            op.mode,
+            op.repos.len(),
+            files
+        );
+        for repo in &op.repos {
+            println!("    {} -> {}", repo.source.display(), repo.dest.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scan() -> io::Result<()> {
+        let dir = tempdir()?;
+        git::init_repo_with_commit(&dir.path().join("a"), "README.md")?;
+        git::init_repo_with_commit(&dir.path().join("clients/b"), "README.md")?;
+        fs::create_dir_all(dir.path().join(".cache/c/.git"))?;
+
+        let mut inventory = Inventory::default();
+        assert_eq!(inventory.scan(dir.path())?, 2);
+        let b = &inventory.repos[1];
+        assert_eq!(b.path, dir.path().join("clients/b"));
+        assert!(!b.dirty);
+        assert!(b.last_commit.as_ref().is_some_and(|date| date.len() == 10));
+
+        let path = dir.path().join("inventory.json");
+        inventory.save(&path)?;
+        let mut inventory = Inventory::load(&path)?;
+        inventory.repos[1].tags.push("client-x".to_string());
+        fs::remove_dir_all(dir.path().join("a"))?;
+        assert_eq!(inventory.scan(dir.path())?, 1);
+        assert_eq!(inventory.repos.len(), 1);
+        assert_eq!(inventory.repos[0].tags, vec!["client-x".to_string()]);
+        assert!(inventory.table().contains("clean"));
+
+        inventory.repos[0].remotes = vec!["a,b".to_string(), "c".to_string()];
+        let csv = inventory.to_csv("laptop");
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], CSV_HEADER);
+        assert!(rows[1].starts_with(&format!(
+            "laptop,{},\"a,b c\",",
+            inventory.repos[0].path.display()
+        )));
+        assert!(rows[1].ends_with(&format!(",false,client-x,{}", inventory.repos[0].seen)));
+        Ok(())
+    }
+}
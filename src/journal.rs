@@ -0,0 +1,237 @@
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crc32fast::Hasher;
+
+use crate::copy::Written;
+use crate::scan::FileStamp;
+
+const MAGIC: &[u8; 4] = b"MVGJ";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 4 + 4 + 8 + 8 + 8;
+
+/// Files are copied in chunks once they are at least this many chunks long.
+pub const MIN_CHUNKS: u64 = 16;
+
+const IO_SIZE: usize = 1024 * 1024;
+
+/// Suffix of the journal files, see [`journal_path`].
+pub const SUFFIX: &str = ".mv-git-part";
+
+/// Journal kept next to a large file while it is being copied, e.g.
+/// `.pack-1234.pack.mv-git-part` for `pack-1234.pack`.
+///
+/// It starts with a header identifying the source (magic, version, size, mtime,
+/// chunk size), followed by the CRC32 of every chunk already written and synced.
+pub fn journal_path(dst: &Path) -> PathBuf {
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+    dst.with_file_name(format!(".{}{}", name, SUFFIX))
+}
+
+fn header(stamp: FileStamp, chunk_size: u64) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.extend_from_slice(&stamp.size.to_le_bytes());
+    header.extend_from_slice(&stamp.mtime.to_le_bytes());
+    header.extend_from_slice(&chunk_size.to_le_bytes());
+    header
+}
+
+/// Checksums recorded by an interrupted copy of the same source with the same
+/// chunk size, if there is one.
+fn recorded_checksums(journal: &Path, stamp: FileStamp, chunk_size: u64) -> Vec<u32> {
+    let Ok(data) = fs::read(journal) else {
+        return Vec::new();
+    };
+    match data.split_at_checked(HEADER_LEN as usize) {
+        Some((head, checksums)) if head == header(stamp, chunk_size) => checksums
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Reads up to `len` bytes of `file` from its current position into `writer`,
+/// returning the CRC32 of what was read and how much that was.
+fn hash_chunk(
+    file: &mut File,
+    len: u64,
+    mut writer: Option<&mut File>,
+    io_size: usize,
+    mut progress: Option<&mut Written>,
+) -> io::Result<(u32, u64)> {
+    let mut hasher = Hasher::new();
+    let mut buf = vec![0; io_size];
+    let mut done = 0;
+    while done < len {
+        let want = (len - done).min(io_size as u64) as usize;
+        let n = match file.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+        if let Some(writer) = writer.as_mut() {
+            writer.write_all(&buf[..n])?;
+        }
+        if let Some(progress) = progress.as_mut() {
+            progress.add(n as u64)?;
+        }
+        done += n as u64;
+    }
+    Ok((hasher.finalize(), done))
+}
+
+/// How many leading chunks of `dst` match the checksums recorded in the journal.
+fn verified_chunks(dst: &mut File, checksums: &[u32], chunk_size: u64) -> io::Result<usize> {
+    dst.seek(SeekFrom::Start(0))?;
+    for (i, expected) in checksums.iter().enumerate() {
+        if hash_chunk(dst, chunk_size, None, IO_SIZE, None)?.0 != *expected {
+            return Ok(i);
+        }
+    }
+    Ok(checksums.len())
+}
+
+/// Copies `src` to `dst` chunk by chunk, recording each synced chunk in the
+/// journal, so that a copy interrupted by a crash or a kill resumes from the last
+/// good chunk instead of starting over. The journal is removed once done.
+/// Reads and writes are of `buffer_size` bytes, or a default size when unset.
+/// The checksums of the chunks add up to that of the whole file in `hasher`.
+pub fn copy_chunked(
+    src: &Path,
+    dst: &Path,
+    metadata: &Metadata,
+    chunk_size: u64,
+    buffer_size: Option<usize>,
+    progress: &mut Written,
+    mut hasher: Option<&mut Hasher>,
+) -> io::Result<u64> {
+    let io_size = buffer_size.unwrap_or(IO_SIZE).max(1);
+    let stamp = FileStamp::from_metadata(metadata);
+    let journal_path = journal_path(dst);
+    let checksums = recorded_checksums(&journal_path, stamp, chunk_size);
+
+    let mut writer = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dst)?;
+    let resume = verified_chunks(&mut writer, &checksums, chunk_size)?;
+    if resume > 0 {
+        tracing::info!(
+            "Resuming copy of {:?} at {} of {} bytes",
+            src,
+            resume as u64 * chunk_size,
+            stamp.size
+        );
+    }
+
+    let mut journal = File::create(&journal_path)?;
+    journal.write_all(&header(stamp, chunk_size))?;
+    for checksum in &checksums[..resume] {
+        journal.write_all(&checksum.to_le_bytes())?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.combine(&Hasher::new_with_initial_len(*checksum, chunk_size));
+        }
+    }
+
+    let mut reader = File::open(src)?;
+    let mut offset = resume as u64 * chunk_size;
+    reader.seek(SeekFrom::Start(offset))?;
+    writer.seek(SeekFrom::Start(offset))?;
+    while offset < stamp.size {
+        let (checksum, len) = hash_chunk(
+            &mut reader,
+            chunk_size,
+            Some(&mut writer),
+            io_size,
+            Some(&mut *progress),
+        )?;
+        if len == 0 {
+            break;
+        }
+        // The chunk must be on disk before the journal says it is.
+        writer.sync_data()?;
+        journal.write_all(&checksum.to_le_bytes())?;
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.combine(&Hasher::new_with_initial_len(checksum, len));
+        }
+        offset += len;
+    }
+    writer.set_len(offset)?;
+    drop(journal);
+    fs::remove_file(&journal_path)?;
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_chunked() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("big.pack");
+        let dst = dir.path().join("copy.pack");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data)?;
+        let metadata = fs::metadata(&src)?;
+
+        assert_eq!(
+            copy_chunked(
+                &src,
+                &dst,
+                &metadata,
+                1000,
+                None,
+                &mut Written::default(),
+                None
+            )?,
+            10_000
+        );
+        assert_eq!(fs::read(&dst)?, data);
+        assert!(!journal_path(&dst).exists());
+
+        // An interrupted copy: two good chunks, a torn third one, and a journal
+        // recording all three.
+        let stamp = FileStamp::from_metadata(&metadata);
+        let mut journal = header(stamp, 1000);
+        for chunk in data.chunks(1000).take(3) {
+            journal.extend_from_slice(&crc32fast::hash(chunk).to_le_bytes());
+        }
+        fs::write(journal_path(&dst), journal)?;
+        let mut torn = data[..2500].to_vec();
+        torn.extend_from_slice(&[0; 500]);
+        fs::write(&dst, &torn)?;
+
+        let checksums = recorded_checksums(&journal_path(&dst), stamp, 1000);
+        assert_eq!(checksums.len(), 3);
+        assert_eq!(
+            verified_chunks(&mut File::open(&dst)?, &checksums, 1000)?,
+            2
+        );
+        assert!(recorded_checksums(&journal_path(&dst), stamp, 4096).is_empty());
+
+        let mut hasher = Hasher::new();
+        copy_chunked(
+            &src,
+            &dst,
+            &metadata,
+            1000,
+            None,
+            &mut Written::default(),
+            Some(&mut hasher),
+        )?;
+        assert_eq!(fs::read(&dst)?, data);
+        assert!(!journal_path(&dst).exists());
+        assert_eq!(hasher.finalize(), crc32fast::hash(&data));
+        Ok(())
+    }
+}
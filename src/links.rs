@@ -0,0 +1,176 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Kind of link left at the source location after a move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Symlink,
+    /// NTFS junction, which unlike a symlink needs no special privilege to create.
+    Junction,
+}
+
+/// Target for the copy of a link: absolute targets pointing inside `src_root`
+/// are redirected to the same place under `dst_root`, everything else is kept.
+pub fn rebase_target(target: &Path, src_root: &Path, dst_root: &Path) -> PathBuf {
+    if target.is_absolute() {
+        if let Ok(rest) = target.strip_prefix(src_root) {
+            return dst_root.join(rest);
+        }
+    }
+    target.to_path_buf()
+}
+
+#[cfg(windows)]
+fn create_junction(target: &Path, link: &Path) -> io::Result<()> {
+    let target = std::path::absolute(link.parent().unwrap_or(Path::new("")).join(target))?;
+    let output = std::process::Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(link)
+        .arg(target)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "mklink /J {:?} failed: {}",
+            link,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Creates a link at `link` pointing to `target`.
+///
+/// On Windows, directory links are created as symlinks when the process holds the
+/// symlink privilege (or Developer Mode is on), and as junctions otherwise.
+fn create_link(target: &Path, link: &Path, is_dir: bool) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let _ = is_dir;
+        std::os::unix::fs::symlink(target, link)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::{symlink_dir, symlink_file};
+
+        // ERROR_PRIVILEGE_NOT_HELD
+        const PRIVILEGE_NOT_HELD: i32 = 1314;
+        if !is_dir {
+            return symlink_file(target, link);
+        }
+        match symlink_dir(target, link) {
+            Err(e) if e.raw_os_error() == Some(PRIVILEGE_NOT_HELD) => create_junction(target, link),
+            result => result,
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (target, link, is_dir);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "links are not supported on this platform",
+        ))
+    }
+}
+
+/// Recreates the link `src` (below `src_root`) as `dst` (below `dst_root`), instead
+/// of copying what it points to.
+pub fn copy_link(src: &Path, dst: &Path, src_root: &Path, dst_root: &Path) -> io::Result<()> {
+    let target = rebase_target(&fs::read_link(src)?, src_root, dst_root);
+    if fs::symlink_metadata(dst).is_ok() {
+        if fs::read_link(dst).is_ok_and(|existing| existing == target) {
+            return Ok(());
+        }
+        remove_link(dst)?;
+    }
+    // Whether the link points to a directory matters to Windows only.
+    let is_dir = fs::metadata(src).is_ok_and(|m| m.is_dir());
+    create_link(&target, dst, is_dir)
+}
+
+fn remove_link(link: &Path) -> io::Result<()> {
+    // Directory links are directories as far as Windows is concerned.
+    fs::remove_file(link).or_else(|_| fs::remove_dir(link))
+}
+
+/// After `src` was moved to `dst`, leaves a link at `src` so existing paths keep working.
+pub fn leave_link(src: &Path, dst: &Path, kind: LinkKind) -> io::Result<()> {
+    let dst = fs::canonicalize(dst)?;
+    match kind {
+        LinkKind::Symlink => create_link(&dst, src, true),
+        #[cfg(windows)]
+        LinkKind::Junction => create_junction(&dst, src),
+        #[cfg(not(windows))]
+        LinkKind::Junction => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "junctions are only supported on Windows, use --leave-symlink",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebase_target() {
+        let src = Path::new("/src/repo");
+        let dst = Path::new("/dst/repo");
+        assert_eq!(
+            rebase_target(Path::new("/src/repo/docs"), src, dst),
+            PathBuf::from("/dst/repo/docs")
+        );
+        assert_eq!(
+            rebase_target(Path::new("/etc/hosts"), src, dst),
+            PathBuf::from("/etc/hosts")
+        );
+        assert_eq!(
+            rebase_target(Path::new("../docs"), src, dst),
+            PathBuf::from("../docs")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_link() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::create_dir_all(&src)?;
+        fs::create_dir_all(&dst)?;
+        std::os::unix::fs::symlink("README.md", src.join("relative"))?;
+        std::os::unix::fs::symlink(src.join("README.md"), src.join("absolute"))?;
+        std::os::unix::fs::symlink("missing", src.join("dangling"))?;
+
+        for name in ["relative", "absolute", "dangling"] {
+            copy_link(&src.join(name), &dst.join(name), &src, &dst)?;
+        }
+
+        assert_eq!(
+            fs::read_link(dst.join("relative"))?,
+            PathBuf::from("README.md")
+        );
+        assert_eq!(fs::read_link(dst.join("absolute"))?, dst.join("README.md"));
+        assert_eq!(
+            fs::read_link(dst.join("dangling"))?,
+            PathBuf::from("missing")
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_leave_link() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::create_dir_all(&dst)?;
+        fs::write(dst.join("file.txt"), "moved")?;
+
+        leave_link(&src, &dst, LinkKind::Symlink)?;
+
+        assert_eq!(fs::read_to_string(src.join("file.txt"))?, "moved");
+        assert!(leave_link(&dir.path().join("other"), &dst, LinkKind::Junction).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::{DefaultFields, Writer};
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Console level for a `-q`/`-v` count: negative for each `-q`, positive for each `-v`.
+/// `--quiet` is `i8::MIN`, which turns the console off.
+pub fn level(verbosity: i8) -> LevelFilter {
+    match verbosity {
+        i8::MIN => LevelFilter::OFF,
+        -127..=-2 => LevelFilter::ERROR,
+        -1 => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Span fields formatted for the log file. Formatted fields are cached per formatter
+/// type, so without a type of its own the file would get the console's colored ones.
+struct FileFields(DefaultFields);
+
+impl<'w> FormatFields<'w> for FileFields {
+    fn format_fields<R: RecordFields>(&self, writer: Writer<'w>, fields: R) -> std::fmt::Result {
+        self.0.format_fields(writer, fields)
+    }
+}
+
+/// Installs the global subscriber: progress on stdout and problems on stderr at the
+/// level picked by `verbosity`, colored if `color` is set, plus, with `--log-file`,
+/// a timestamped copy of everything from INFO up (or more with `-v`) appended to
+/// `log_file`.
+pub fn init(verbosity: i8, color: bool, log_file: Option<&Path>) -> io::Result<()> {
+    let console = tracing_subscriber::fmt::layer()
+        .with_ansi(color)
+        .without_time()
+        .with_target(false)
+        .with_writer(
+            io::stderr
+                .with_max_level(tracing::Level::WARN)
+                .or_else(io::stdout),
+        )
+        .with_filter(level(verbosity));
+
+    let file = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .fmt_fields(FileFields(DefaultFields::new()))
+                    .with_writer(Mutex::new(file))
+                    .with_filter(level(verbosity.max(0))),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .try_init()
+        .map_err(io::Error::other)
+}
+
+/// Hands every event to a function, as its level and message, for `serve` to
+/// send them as notifications.
+struct Forward<F>(F);
+
+/// The message of an event, followed by its other fields as `name=value`.
+#[derive(Default)]
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            self.0.push_str(&format!("{:?}", value));
+        } else {
+            self.0.push_str(&format!("{}={:?}", field.name(), value));
+        }
+    }
+}
+
+impl<S: Subscriber, F: Fn(&Level, String) + 'static> Layer<S> for Forward<F> {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut message = Message::default();
+        event.record(&mut message);
+        (self.0)(event.metadata().level(), message.0);
+    }
+}
+
+/// Installs a global subscriber handing the events at the level picked by
+/// `verbosity` to `forward` instead of writing them out.
+pub fn init_forward(
+    verbosity: i8,
+    forward: impl Fn(&Level, String) + Send + Sync + 'static,
+) -> io::Result<()> {
+    tracing_subscriber::registry()
+        .with(Forward(forward).with_filter(level(verbosity)))
+        .try_init()
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(-3), LevelFilter::ERROR);
+        assert_eq!(level(i8::MIN), LevelFilter::OFF);
+        assert_eq!(level(-1), LevelFilter::WARN);
+        assert_eq!(level(0), LevelFilter::INFO);
+        assert_eq!(level(1), LevelFilter::DEBUG);
+        assert_eq!(level(2), LevelFilter::TRACE);
+    }
+}
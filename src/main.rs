@@ -1,9 +1,96 @@
+mod bench;
+mod bookmarks;
+mod cli;
+mod color;
+mod completions;
+mod config;
+mod copy;
+#[cfg(unix)]
+mod daemon;
+mod dedupe;
+mod diff;
+mod downgrade;
+mod ghq;
+mod git;
+mod glob;
+mod history;
+mod index;
+mod inventory;
+mod journal;
+mod links;
+mod logging;
+mod man;
+mod mapping;
+mod metadata;
+mod metrics;
+mod mkdir;
+mod mrconfig;
+mod notify;
+mod order;
+mod owner;
+mod paths;
+mod pending;
+mod plan;
+mod policy;
+mod preflight;
+mod priority;
+mod query;
+mod recovery;
+mod rename;
+mod repair;
+mod scan;
+mod serve;
+mod service;
+mod shard;
+mod signals;
+mod snapshot;
+mod spill;
+mod stats;
+mod strategy;
+mod summary;
+mod trash;
+mod units;
+mod webhook;
+mod workspace;
+
+use std::collections::HashSet;
 use std::env;
 use std::error::Error;
+use std::fs;
 use std::fs::File;
-use std::fs::{self, DirEntry};
-use std::io::{self, BufRead, ErrorKind};
-use std::path::Path;
+use std::io::{self, BufRead, ErrorKind, IsTerminal};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bench::BenchResults;
+use color::ColorChoice;
+use config::{Config, Pinned, Reference, RepoConfig};
+use copy::{Budget, CopyOptions, Throttle};
+use downgrade::Downgrade;
+use index::Index;
+use links::LinkKind;
+use mapping::Mapping;
+use metrics::Metrics;
+use mkdir::Mkdir;
+use order::Order;
+use pending::{PendingDelete, PendingRepo};
+use plan::{Action, Collision, Layout, NonGit, Plan, PlannedRepo};
+use policy::ErrorPolicy;
+use preflight::TargetFs;
+use priority::IoClass;
+use query::Query;
+use recovery::Recovery;
+use rename::Rename;
+use scan::{Entry, EntryKind, Extensions, FileStamp, Hidden, Scan, ScanOptions};
+use shard::{Placement, ShardIndex, Shards};
+use snapshot::Snapshot;
+use strategy::StrategyChoice;
+use summary::{Outcome, Summary, Timing};
+use tracing::{debug, error, info, info_span, warn};
 
 fn read_gitignore(file: &Path) -> io::Result<Vec<String>> {
     let file = File::open(file)?;
@@ -16,37 +103,759 @@ fn read_gitignore(file: &Path) -> io::Result<Vec<String>> {
     Ok(lines)
 }
 
-fn copy_entry(entry: &DirEntry, dst: &Path, gitignore: &Option<Vec<String>>) -> io::Result<()> {
-    let file_type = entry.file_type()?;
-    let dest_path = dst.join(entry.file_name());
+/// Copies the entries of the pre-scan `scan` of `src` into each of `dsts`, reading
+/// every file once, and leaving out the files for which `up_to_date` returns true
+/// when they already exist at the first destination.
+fn copy_entries(
+    src: &Path,
+    dsts: &[&Path],
+    scan: &Scan,
+    options: &CopyOptions,
+    up_to_date: impl Fn(&Entry) -> bool,
+) -> io::Result<usize> {
+    let _span = info_span!("copy").entered();
+    let src = &paths::extended(src);
+    let dsts: Vec<PathBuf> = dsts.iter().map(|dst| paths::extended(dst)).collect();
+    for dst in &dsts {
+        if !dst.exists() {
+            fs::create_dir_all(dst)?;
+        }
+    }
+
+    let mut expected = 0;
+    for batch in scan.batches()? {
+        expected += batch?
+            .iter()
+            .filter(|entry| entry.kind == EntryKind::File && !up_to_date(entry))
+            .map(|entry| entry.stamp.size)
+            .sum::<u64>();
+    }
+    options.stats.expect_bytes(expected);
 
-    if file_type.is_dir() {
-        copy_dir_recursive(&entry.path(), &dest_path, gitignore)?;
-    } else {
-        fs::copy(&entry.path(), &dest_path)?;
+    let mut copied = 0;
+    let mut timed_out = Vec::new();
+    let mut failed = Vec::new();
+    for batch in scan.batches()? {
+        let batch = batch?;
+        for entry in batch.iter() {
+            signals::wait_while_paused();
+            if options.is_cancelled() {
+                return Err(signals::interrupted());
+            }
+            if cfg!(windows) {
+                paths::check_windows_name(&entry.rel)?;
+            }
+            let rel = options.normalization.apply(&entry.rel);
+            let dest_paths: Vec<PathBuf> = dsts.iter().map(|dst| dst.join(&rel)).collect();
+            match entry.kind {
+                EntryKind::Dir => {
+                    for dest_path in &dest_paths {
+                        if !dest_path.exists() {
+                            fs::create_dir_all(dest_path)?;
+                        }
+                    }
+                }
+                EntryKind::File => {
+                    if dest_paths[0].exists() && up_to_date(entry) {
+                        continue;
+                    }
+                    let started = Instant::now();
+                    let copy = || {
+                        let written =
+                            copy::copy_file_to_all(&src.join(&entry.rel), &dest_paths, options)?;
+                        for dest_path in &dest_paths {
+                            metadata::preserve(
+                                &src.join(&entry.rel),
+                                dest_path,
+                                &options.preserve,
+                            )?;
+                            owner::apply(&src.join(&entry.rel), dest_path, &options.ownership)?;
+                        }
+                        Ok::<_, io::Error>(written)
+                    };
+                    match copy() {
+                        Ok(written) => {
+                            options.stats.record_file(written);
+                            options
+                                .stats
+                                .record_time(&entry.rel, written, started.elapsed());
+                            copied += 1;
+                        }
+                        Err(e) if e.kind() == ErrorKind::TimedOut => {
+                            warn!("{}, moving on to the other files", e);
+                            timed_out.push(entry.rel.clone());
+                        }
+                        Err(e) if options.on_error == ErrorPolicy::SkipFile => {
+                            warn!(
+                                "Could not copy {:?}: {}, moving on to the other files",
+                                entry.rel, e
+                            );
+                            failed.push(entry.rel.clone());
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                EntryKind::Symlink => {
+                    let downgrade = options.downgrade.as_deref().filter(|d| d.symlinks);
+                    let copy = || {
+                        for (dst, dest_path) in dsts.iter().zip(&dest_paths) {
+                            match downgrade {
+                                Some(downgrade) => downgrade.write_link(
+                                    &src.join(&entry.rel),
+                                    dest_path,
+                                    src,
+                                    dst,
+                                )?,
+                                None => {
+                                    links::copy_link(&src.join(&entry.rel), dest_path, src, dst)?
+                                }
+                            }
+                            owner::apply(&src.join(&entry.rel), dest_path, &options.ownership)?;
+                        }
+                        Ok::<_, io::Error>(())
+                    };
+                    match copy() {
+                        Err(e) if options.on_error == ErrorPolicy::SkipFile => {
+                            warn!(
+                                "Could not copy {:?}: {}, moving on to the other files",
+                                entry.rel, e
+                            );
+                            failed.push(entry.rel.clone());
+                        }
+                        result => result?,
+                    }
+                }
+                EntryKind::Fifo if options.special_files => {
+                    for dest_path in &dest_paths {
+                        if !dest_path.exists() {
+                            copy::create_fifo(&src.join(&entry.rel), dest_path)?;
+                        }
+                    }
+                }
+                EntryKind::Fifo | EntryKind::Socket | EntryKind::Device => {
+                    warn!(
+                        "Skipping special file {:?} ({:?})",
+                        src.join(&entry.rel),
+                        entry.kind
+                    );
+                }
+            }
+        }
+    }
+
+    for dst in &dsts {
+        // Directories last, innermost first, as copying their content changes them.
+        // They are never spilled, so all of them are in memory.
+        for entry in scan
+            .entries
+            .iter()
+            .rev()
+            .filter(|e| e.kind == EntryKind::Dir)
+        {
+            let dest_path = dst.join(options.normalization.apply(&entry.rel));
+            metadata::preserve(&src.join(&entry.rel), &dest_path, &options.preserve)?;
+            owner::apply(&src.join(&entry.rel), &dest_path, &options.ownership)?;
+            if options.fsync {
+                copy::sync_dir(&dest_path)?;
+            }
+        }
+        owner::apply(src, dst, &options.ownership)?;
+        if options.fsync {
+            copy::sync_dir(dst)?;
+            if let Some(parent) = dst.parent() {
+                copy::sync_dir(parent)?;
+            }
+        }
+    }
+    if !timed_out.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::TimedOut,
+            format!("{} files timed out: {:?}", timed_out.len(), timed_out),
+        ));
+    }
+    if !failed.is_empty() {
+        return Err(io::Error::other(format!(
+            "{} files could not be copied: {:?}",
+            failed.len(),
+            failed
+        )));
+    }
+    debug!("Copied {} files to {:?}", copied, dsts);
+    Ok(copied)
+}
+
+/// Pre-scans `src`, warning about filesystems mounted inside it and files over
+/// `--max-file-size` or left out for their extension, and refusing names that
+/// `--normalize` would turn into the same one.
+fn scan_source(src: &Path, gitignore: &Option<Vec<String>>, opts: &Options) -> io::Result<Scan> {
+    let _span = info_span!("scan").entered();
+    let mut scan = scan::scan(src, gitignore, &opts.scan)?;
+    debug!(
+        "Found {} entries in {:?}{}",
+        scan.entries.len(),
+        src,
+        if scan.is_spilled() {
+            " (and more spilled to disk)"
+        } else {
+            ""
+        }
+    );
+    if let Some(order) = opts.order {
+        if scan.is_spilled() {
+            warn!(
+                "{:?} is too large for --memory-limit, --order only applies to part of it",
+                src
+            );
+        }
+        order.sort_entries(&mut scan.entries);
+    }
+    // ASCII names are the same in every normalization form, only keep the others
+    // around to look for collisions.
+    let mut non_ascii = Vec::new();
+    for batch in scan.batches()? {
+        non_ascii.extend(
+            batch?
+                .iter()
+                .filter(|e| !e.rel.to_str().is_some_and(|rel| rel.is_ascii()))
+                .map(|e| e.rel.clone()),
+        );
+    }
+    let collisions = paths::normalization_collisions(non_ascii.iter().map(|rel| rel.as_path()));
+    for group in &collisions {
+        warn!(
+            "{:?} differ only in Unicode normalization and are the same file on macOS",
+            group
+        );
+    }
+    if !collisions.is_empty() && opts.transfer.normalization != paths::Normalization::Keep {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{:?} has names that differ only in Unicode normalization, \
+                 rename them or use --normalize keep",
+                src
+            ),
+        ));
     }
+    for mount_point in &scan.mount_points {
+        let mount_point = src.join(mount_point);
+        if opts.scan.one_file_system {
+            warn!("{:?} is a mount point, not descending into it", mount_point);
+        } else if opts.copy {
+            warn!("{:?} is a mount point, its content is copied", mount_point);
+        } else {
+            warn!(
+                "{:?} is a mount point, its content is moved and deleted",
+                mount_point
+            );
+        }
+    }
+    for (rel, size) in &scan.too_large {
+        warn!(
+            "Leaving out {:?}: {} is over --max-file-size",
+            src.join(rel),
+            units::format_size(*size)
+        );
+    }
+    if !scan.too_large.is_empty() {
+        info!(
+            "Left out {} files over --max-file-size from {:?}",
+            scan.too_large.len(),
+            src
+        );
+    }
+    if scan.filtered > 0 {
+        info!(
+            "Left out {} files of {:?} for their extension",
+            scan.filtered, src
+        );
+    }
+    Ok(scan)
+}
+
+/// Where `dst`, a repository under `--dest`, is mirrored to under each `--also-dest`,
+/// creating the directories leading to them.
+fn mirror_dests(dst: &Path, opts: &Options) -> io::Result<Vec<PathBuf>> {
+    if opts.also_dest.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rel = dst.strip_prefix(&opts.dest).map_err(|_| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} is not under the destination {:?}", dst, opts.dest),
+        )
+    })?;
+    let mut mirrors = Vec::new();
+    for root in &opts.also_dest {
+        let mirror = Path::new(root).join(rel);
+        if let Some(parent) = mirror.parent() {
+            mkdir::ensure_dir(parent, opts.mkdir, opts.dir_mode)?;
+        }
+        mirrors.push(mirror);
+    }
+    Ok(mirrors)
+}
+
+/// The copy options of `opts` for files going from `src` to `dst`, with the copy
+/// strategy picked for them.
+fn transfer_options(src: &Path, dsts: &[&Path], opts: &Options) -> CopyOptions {
+    let dst = dsts[0];
+    let strategy = match opts.strategy {
+        StrategyChoice::Copy(strategy) => Some(strategy),
+        StrategyChoice::Auto => strategy::pick_copy(src, dst, &opts.bench),
+        StrategyChoice::Rename => None,
+    };
+    if let Some(strategy) = strategy {
+        debug!("Copying {:?} with {}", src, strategy.name());
+    }
+    // Written for the most limited of the destinations.
+    let downgrade = dsts
+        .iter()
+        .filter_map(|dst| opts.target_fs.or_else(|| preflight::detect(dst)))
+        .find_map(Downgrade::for_target);
+    CopyOptions {
+        strategy,
+        downgrade: downgrade.map(Arc::new),
+        ..opts.transfer.clone()
+    }
+}
+
+/// Reports what `options` downgraded for the destination `dsts` of a repository,
+/// and tells git there about it.
+fn report_downgrade(dsts: &[&Path], options: &CopyOptions) {
+    let Some(downgrade) = &options.downgrade else {
+        return;
+    };
+    match downgrade.apply(dsts) {
+        Ok((links, executables)) => {
+            if !links.is_empty() {
+                warn!(
+                    "{:?} can't hold symlinks, wrote {} as plain files holding their \
+                     target and set core.symlinks=false: {:?}",
+                    dsts[0],
+                    links.len(),
+                    links
+                );
+            }
+            if !executables.is_empty() {
+                warn!(
+                    "{:?} can't hold file modes, dropped the execute bit of {} files \
+                     and set core.fileMode=false: {:?}",
+                    dsts[0],
+                    executables.len(),
+                    executables
+                );
+            }
+        }
+        Err(e) => warn!(
+            "Could not set core.symlinks and core.fileMode in {:?}: {}",
+            dsts[0], e
+        ),
+    }
+}
+
+#[cfg(test)]
+fn copy_dir_recursive(
+    src: &Path,
+    dst: &Path,
+    gitignore: &Option<Vec<String>>,
+    opts: &Options,
+) -> io::Result<Scan> {
+    let scan = scan_source(src, gitignore, opts)?;
+    copy_scanned(src, dst, &scan, opts)?;
+    Ok(scan)
+}
+
+/// Copies what the pre-scan `scan` found in `src` to `dst` and its mirrors.
+fn copy_scanned(src: &Path, dst: &Path, scan: &Scan, opts: &Options) -> io::Result<()> {
+    let mirrors = mirror_dests(dst, opts)?;
+    let mut dsts = vec![dst];
+    dsts.extend(mirrors.iter().map(PathBuf::as_path));
+    for dst in &dsts {
+        preflight::check(dst, scan, opts.target_fs)?;
+    }
+    let options = transfer_options(src, &dsts, opts);
+    copy_entries(src, &dsts, scan, &options, |_| false)?;
+    report_downgrade(&dsts, &options);
+    for dst in &dsts {
+        keep_sparse_checkout(src, dst)?;
+        verify_refs(src, dst)?;
+    }
+    Ok(())
+}
+
+/// Makes sure the sparse checkout of `src` carries over to its copy at `dst`,
+/// even when its patterns were left out with the ignored names, as the copy's
+/// worktree would otherwise look like it lost the files left out of it.
+fn keep_sparse_checkout(src: &Path, dst: &Path) -> io::Result<()> {
+    let patterns = Path::new(".git").join("info").join("sparse-checkout");
+    if !src.join(&patterns).is_file() || dst.join(&patterns).exists() || !git::is_sparse(src) {
+        return Ok(());
+    }
+    debug!("Keeping the sparse checkout of {:?}", src);
+    fs::create_dir_all(dst.join(".git").join("info"))?;
+    fs::copy(src.join(&patterns), dst.join(&patterns))?;
     Ok(())
 }
 
-fn copy_dir_recursive(src: &Path, dst: &Path, gitignore: &Option<Vec<String>>) -> io::Result<()> {
-    if !dst.exists() {
-        fs::create_dir_all(dst)?;
+/// Checks that the copy `dst` of the repository `src` has every ref of `src`,
+/// pointing to the same object, and the same `HEAD`.
+fn verify_refs(src: &Path, dst: &Path) -> io::Result<()> {
+    if !src.join(".git").exists() {
+        return Ok(());
+    }
+    let _span = info_span!("verify").entered();
+    let dst_refs = git::refs(dst)?;
+    let wrong: Vec<String> = git::refs(src)?
+        .into_iter()
+        .filter(|(name, target)| dst_refs.get(name) != Some(target))
+        .map(|(name, _)| name)
+        .collect();
+    if wrong.is_empty() {
+        return Ok(());
     }
+    Err(io::Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "the copy at {:?} lacks refs of the source or has them elsewhere: {}",
+            dst,
+            wrong.join(", ")
+        ),
+    ))
+}
 
-    for entry in fs::read_dir(src)? {
+/// Number of files, directories and links under `dir`.
+fn count_entries(dir: &Path) -> io::Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(dir)? {
         let entry = entry?;
-        let entry_name = entry.file_name().to_string_lossy().into_owned();
-        if gitignore
-            .as_ref()
-            .map_or(true, |gi| !gi.contains(&entry_name))
+        count += 1;
+        if entry.file_type()?.is_dir() {
+            count += count_entries(&entry.path())?;
+        }
+    }
+    Ok(count)
+}
+
+/// Moves `src` to `dst` with a single rename when `--strategy` allows it: both
+/// are on the same filesystem and, unless asked for `rename`, the pre-scan
+/// `scan` found everything there is, so nothing would be left behind. Returns
+/// whether it did.
+fn rename_repo(src: &Path, dst: &Path, scan: &Scan, opts: &Options) -> io::Result<bool> {
+    let forced = match opts.strategy {
+        StrategyChoice::Rename => true,
+        StrategyChoice::Auto => false,
+        StrategyChoice::Copy(_) => return Ok(false),
+    };
+    if opts.copy
+        || opts.sync
+        || opts.confirm_delete
+        || !opts.also_dest.is_empty()
+        || dst.exists()
+        || !strategy::same_filesystem(src, dst)
+    {
+        if forced && !opts.copy {
+            warn!("Can't rename {:?} to {:?}, copying it instead", src, dst);
+        }
+        return Ok(false);
+    }
+    if !forced {
+        if opts.transfer.ownership.is_set()
+            || opts.transfer.normalization != paths::Normalization::Keep
+            || !scan.mount_points.is_empty()
         {
-            copy_entry(&entry, dst, gitignore)?;
+            return Ok(false);
+        }
+        let mut scanned = 0;
+        for batch in scan.batches()? {
+            scanned += batch?.len();
+        }
+        if scanned != count_entries(src)? {
+            return Ok(false);
+        }
+    }
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(src, dst)?;
+    debug!("Renamed {:?} to {:?}", src, dst);
+    Ok(true)
+}
+
+/// Copies `src` into `dst` like `--copy`, but only the files that changed since the
+/// last sync recorded in `index` under `key`. Returns [`Outcome::Unchanged`] when the whole
+/// repository was unchanged, and [`Outcome::Conflicted`] without copying anything
+/// when `dst` was changed too, unless `--overwrite-conflicts` says to copy over it.
+/// With `--compare-contents`, files whose size didn't change are only copied
+/// when their contents differ from the copy.
+fn sync_dir(
+    src: &Path,
+    dst: &Path,
+    gitignore: &Option<Vec<String>>,
+    index: &mut Index,
+    key: &str,
+    opts: &Options,
+) -> io::Result<Outcome> {
+    let scan = scan_source(src, gitignore, opts)?;
+    if dst.exists() && index.is_unchanged(key, &scan)? {
+        return Ok(Outcome::Unchanged);
+    }
+    let changed = index.changed_at(key, dst)?;
+    if !changed.is_empty() && !opts.overwrite_conflicts {
+        let mut listed: Vec<String> = changed
+            .iter()
+            .take(3)
+            .map(|rel| rel.display().to_string())
+            .collect();
+        if changed.len() > listed.len() {
+            listed.push(format!("{} more", changed.len() - listed.len()));
+        }
+        return Ok(Outcome::Conflicted(format!(
+            "changed at the destination too: {}",
+            listed.join(", ")
+        )));
+    }
+
+    preflight::check(dst, &scan, opts.target_fs)?;
+    let options = transfer_options(src, &[dst], opts);
+    // What changed at the destination is overwritten even where the source is
+    // as it was.
+    let changed: HashSet<PathBuf> = changed.into_iter().collect();
+    let touched = if opts.compare_contents {
+        only_touched(src, dst, &scan, index, key, &changed, opts)?
+    } else {
+        HashSet::new()
+    };
+    copy_entries(src, &[dst], &scan, &options, |entry| {
+        (index.stamp(key, &entry.rel) == Some(entry.stamp) || touched.contains(&entry.rel))
+            && !changed.contains(&entry.rel)
+    })?;
+    report_downgrade(&[dst], &options);
+    keep_sparse_checkout(src, dst)?;
+    verify_refs(src, dst)?;
+    index.update(key, &scan)?;
+    index.update_dest(key, dst)?;
+    Ok(Outcome::Synced)
+}
+
+/// The files of `scan` that changed in `src` since the last sync recorded in
+/// `index` but are still the same as their copy in `dst`, such as those a build
+/// touched. Files changed at the destination, in `changed`, are never.
+fn only_touched(
+    src: &Path,
+    dst: &Path,
+    scan: &Scan,
+    index: &Index,
+    key: &str,
+    changed: &HashSet<PathBuf>,
+    opts: &Options,
+) -> io::Result<HashSet<PathBuf>> {
+    let mut touched = HashSet::new();
+    for batch in scan.batches()? {
+        for entry in batch?.iter() {
+            let candidate = entry.kind == EntryKind::File
+                && !changed.contains(&entry.rel)
+                && index
+                    .stamp(key, &entry.rel)
+                    .is_some_and(|stamp| stamp != entry.stamp && stamp.size == entry.stamp.size);
+            if !candidate {
+                continue;
+            }
+            let copy = dst.join(opts.transfer.normalization.apply(&entry.rel));
+            // Empty files are the same without reading them.
+            let same = (entry.stamp.size == 0 && copy.is_file())
+                || copy::same_contents(&src.join(&entry.rel), &copy).unwrap_or(false);
+            if same {
+                touched.insert(entry.rel.clone());
+            }
+        }
+    }
+    if !touched.is_empty() {
+        debug!(
+            "{} files of {:?} were only touched since the last sync",
+            touched.len(),
+            src
+        );
+    }
+    Ok(touched)
+}
+
+/// Sends the source `src` of a moved repository to the trash, see `--to-trash`.
+fn trash_source(src: &Path) -> io::Result<()> {
+    let trashed = trash::trash(src)?;
+    info!("{:?} sent to the trash at {:?}", src, trashed);
+    Ok(())
+}
+
+/// Leaves the link asked for with `--leave-symlink`/`--leave-junction` at the
+/// now removed `src`, pointing to `dst`.
+fn leave_link(src: &Path, dst: &Path, opts: &Options) {
+    if let Some(kind) = opts.leave_link {
+        if let Err(e) = links::leave_link(src, dst, kind) {
+            warn!("Could not leave a {:?} at {:?}: {}", kind, src, e);
+        }
+    }
+}
+
+/// Removes `dir` like `fs::remove_dir_all`, but carries on past what can't be
+/// removed, failing at the end with the list of what is left.
+fn remove_dir_each(dir: &Path) -> io::Result<()> {
+    fn remove(path: &Path, failed: &mut Vec<PathBuf>) {
+        let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+        if is_dir {
+            match fs::read_dir(path) {
+                Ok(entries) => {
+                    for entry in entries {
+                        match entry {
+                            Ok(entry) => remove(&entry.path(), failed),
+                            Err(e) => warn!("Could not list {:?}: {}", path, e),
+                        }
+                    }
+                }
+                Err(e) => warn!("Could not list {:?}: {}", path, e),
+            }
+        }
+        let removed = if is_dir {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        };
+        if let Err(e) = removed {
+            // Directories still holding what failed are not worth listing again.
+            if !is_dir || !failed.iter().any(|f| f.starts_with(path)) {
+                warn!("Could not remove {:?}: {}", path, e);
+                failed.push(path.to_path_buf());
+            }
+        }
+    }
+
+    let mut failed = Vec::new();
+    remove(dir, &mut failed);
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} entries could not be removed: {:?}",
+            failed.len(),
+            failed
+        )))
+    }
+}
+
+/// Removes what `gitignore` matches under `dir`, returning how many bytes it held.
+fn purge_ignored(dir: &Path, gitignore: &Option<Vec<String>>) -> io::Result<u64> {
+    let mut purged = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        if scan::is_ignored(&entry.file_name().to_string_lossy(), gitignore) {
+            if is_dir {
+                purged += dedupe::dir_size(&path)?;
+                fs::remove_dir_all(&path)?;
+            } else {
+                purged += entry.metadata()?.len();
+                fs::remove_file(&path)?;
+            }
+        } else if is_dir {
+            purged += purge_ignored(&path, gitignore)?;
+        }
+    }
+    Ok(purged)
+}
+
+/// Removes from `src` what its pre-scan `scan` found and the copy transferred,
+/// leaving what was left out: ignored files, unless `--purge-ignored-at-source`,
+/// files over `--max-file-size`, special files, and the directories holding them.
+fn remove_moved(
+    src: &Path,
+    scan: &Scan,
+    gitignore: &Option<Vec<String>>,
+    policy: ErrorPolicy,
+    opts: &Options,
+) -> io::Result<()> {
+    let src = &paths::extended(src);
+    let mut failed = Vec::new();
+    let remove = |path: &Path, removed: io::Result<()>, failed: &mut Vec<PathBuf>| match removed {
+        Err(e) if policy == ErrorPolicy::SkipFile => {
+            warn!("Could not remove {:?}: {}", path, e);
+            failed.push(path.to_path_buf());
+            Ok(())
+        }
+        result => result,
+    };
+    for batch in scan.batches()? {
+        for entry in batch?.iter() {
+            let transferred = match entry.kind {
+                EntryKind::File | EntryKind::Symlink => true,
+                EntryKind::Fifo => opts.transfer.special_files,
+                _ => false,
+            };
+            if transferred {
+                let path = src.join(&entry.rel);
+                remove(&path, fs::remove_file(&path), &mut failed)?;
+            }
         }
     }
+    // What `--prune-git-cruft` and `--strip-reflogs` left out of `.git` is of
+    // no use without the rest.
+    if (opts.scan.prune_git_cruft || opts.scan.strip_reflogs)
+        && failed.is_empty()
+        && src.join(".git").is_dir()
+    {
+        fs::remove_dir_all(src.join(".git"))?;
+    }
+    if opts.purge_ignored && failed.is_empty() {
+        let purged = purge_ignored(src, gitignore)?;
+        if purged > 0 {
+            info!(
+                "Purged {} of ignored files from {:?}",
+                units::format_size(purged),
+                src
+            );
+        }
+    }
+    for entry in scan
+        .entries
+        .iter()
+        .rev()
+        .filter(|e| e.kind == EntryKind::Dir)
+        .map(|e| src.join(&e.rel))
+        .chain(iter::once(src.clone()))
+    {
+        if entry.read_dir().is_ok_and(|mut d| d.next().is_none()) {
+            remove(&entry, fs::remove_dir(&entry), &mut failed)?;
+        }
+    }
+    if !failed.is_empty() {
+        return Err(io::Error::other(format!(
+            "{} entries could not be removed: {:?}",
+            failed.len(),
+            failed
+        )));
+    }
+    if src.exists() {
+        let left: Vec<PathBuf> = fs::read_dir(src)?
+            .map(|entry| entry.map(|e| PathBuf::from(e.file_name())))
+            .collect::<io::Result<_>>()?;
+        warn!(
+            "Left what was not moved in {:?}, use --force-delete to remove it: {:?}",
+            src, left
+        );
+    }
     Ok(())
 }
 
-fn move_dir(src: &Path, dst: &Path, gitignore: &Option<Vec<String>>, copy: bool) -> io::Result<()> {
+/// Moves (or copies) `src` to `dst`, returning how that went.
+fn move_dir(
+    src: &Path,
+    dst: &Path,
+    gitignore: &Option<Vec<String>>,
+    opts: &Options,
+) -> io::Result<Outcome> {
     if !src.exists() {
         return Err(io::Error::new(
             ErrorKind::NotFound,
@@ -54,25 +863,129 @@ fn move_dir(src: &Path, dst: &Path, gitignore: &Option<Vec<String>>, copy: bool)
         ));
     }
 
+    let scan = scan_source(src, gitignore, opts).map_err(|e| opts.transfer.on_error.escalate(e))?;
+    if rename_repo(src, dst, &scan, opts)? {
+        leave_link(src, dst, opts);
+        return Ok(Outcome::Moved);
+    }
+
+    recovery::begin(src, dst)?;
     // Handle potential errors during the copy process
-    if let Err(e) = copy_dir_recursive(src, dst, gitignore) {
-        eprintln!("Error copying directory: {}", e);
-        return Err(e); // Propagate the error
+    if let Err(e) = copy_scanned(src, dst, &scan, opts) {
+        if e.kind() != ErrorKind::Interrupted {
+            error!("Error copying directory: {}", e);
+        }
+        return Err(opts.transfer.on_error.escalate(e));
     }
 
-    if !copy {
-        if let Err(e) = fs::remove_dir_all(src) {
-            eprintln!("Error removing source directory: {}", e);
-            return Err(e); // Propagate the error
+    let outcome = if opts.copy {
+        Outcome::Copied
+    } else if opts.confirm_delete {
+        verify_copy(src, dst, &scan, opts)?;
+        info!(
+            "{:?} copied and verified, keeping it until the delete is confirmed",
+            src
+        );
+        Outcome::Verified
+    } else {
+        recovery::deleting(dst)?;
+        if delete_source(src, dst, &scan, gitignore, opts)? {
+            Outcome::Moved
+        } else {
+            Outcome::Kept
         }
+    };
+
+    recovery::done(dst)?;
+    Ok(outcome)
+}
+
+/// Deletes what was moved from `src` to `dst`, as the pre-scan `scan` found it,
+/// then leaves the link asked for in its place. Returns `false` when it kept
+/// `src` whole instead, as `--one-file-system` left out the mount points in it.
+fn delete_source(
+    src: &Path,
+    dst: &Path,
+    scan: &Scan,
+    gitignore: &Option<Vec<String>>,
+    opts: &Options,
+) -> io::Result<bool> {
+    if opts.scan.one_file_system && !scan.mount_points.is_empty() {
+        warn!(
+            "Not removing source directory {:?}: mount points inside it were not moved",
+            src
+        );
+        return Ok(false);
+    }
+    let _span = info_span!("delete").entered();
+    let removed = match (opts.force_delete, opts.on_delete_error) {
+        _ if opts.to_trash => trash_source(src),
+        (true, ErrorPolicy::SkipFile) => remove_dir_each(src),
+        (true, _) => fs::remove_dir_all(src),
+        (false, policy) => remove_moved(src, scan, gitignore, policy, opts),
+    };
+    if let Err(e) = removed {
+        error!("Error removing source directory: {}", e);
+        return Err(opts.on_delete_error.escalate(e));
+    }
+    if src.exists() && opts.leave_link.is_some() {
+        warn!(
+            "Not leaving a link at {:?}: it still holds what was not moved",
+            src
+        );
+    } else if !src.exists() {
+        leave_link(src, dst, opts);
     }
+    Ok(true)
+}
 
+/// Checks that every file the pre-scan `scan` found in `src` is at `dst` with
+/// the same contents, before `--confirm-delete` lets the source go. The files
+/// whose checksum was taken while copying them aren't read again, only checked
+/// to be unchanged since the pre-scan.
+fn verify_copy(src: &Path, dst: &Path, scan: &Scan, opts: &Options) -> io::Result<()> {
+    let _span = info_span!("verify").entered();
+    let copied_from = paths::extended(src);
+    for batch in scan.batches()? {
+        for entry in batch?.iter() {
+            let copy = dst.join(opts.transfer.normalization.apply(&entry.rel));
+            let checksum = opts
+                .transfer
+                .checksums
+                .as_ref()
+                .and_then(|checksums| checksums.take(&copied_from.join(&entry.rel)));
+            let same = match (entry.kind, checksum) {
+                (EntryKind::File, Some(checksum)) => {
+                    FileStamp::from_metadata(&fs::metadata(src.join(&entry.rel))?) == entry.stamp
+                        && copy::checksum(&copy)? == checksum
+                }
+                (EntryKind::File, None) => copy::same_contents(&src.join(&entry.rel), &copy)?,
+                // Or the plain file holding its target, where symlinks can't be.
+                (EntryKind::Symlink, _) => fs::symlink_metadata(&copy)
+                    .is_ok_and(|m| m.file_type().is_symlink() || m.is_file()),
+                _ => true,
+            };
+            if !same {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{:?} does not match its copy {:?}",
+                        src.join(&entry.rel),
+                        copy
+                    ),
+                ));
+            }
+        }
+    }
     Ok(())
 }
 
 fn is_git_dir(path: &Path) -> io::Result<(bool, Option<Vec<String>>)> {
     let mut is_dir = false;
     let mut git_ignore = None;
+    if !path.is_dir() {
+        return Ok((is_dir, git_ignore));
+    }
 
     for entry in path.read_dir()? {
         let entry = entry?;
@@ -85,120 +998,3279 @@ fn is_git_dir(path: &Path) -> io::Result<(bool, Option<Vec<String>>)> {
     Ok((is_dir, git_ignore))
 }
 
-fn parse_args(args: Vec<String>) -> Result<(String, String, bool), Box<dyn Error>> {
-    if args.len() < 3 {
-        return Err("Usage: <source> <destination> [--copy | -c]".into());
-    }
-    let source = args[1].clone();
-    let dest = args[2].clone();
-    let copy = args
-        .get(3)
-        .map_or(false, |arg| arg == "--copy" || arg == "-c");
+const USAGE: &str = "Usage: <source>... <destination> [--copy | -c] [--sync] [--dedupe-objects]";
+
+#[derive(Debug, Default)]
+struct Options {
+    /// Directories whose repositories are transferred, in order.
+    sources: Vec<String>,
+    dest: String,
+    copy: bool,
+    /// Copy, skipping what is unchanged since the previous sync to the same destination.
+    sync: bool,
+    dedupe_objects: bool,
+    transfer: CopyOptions,
+    scan: ScanOptions,
+    /// Filesystem whose naming rules the destination is checked against, and
+    /// whose lack of symlinks and file modes is written for, instead of the
+    /// detected one.
+    target_fs: Option<TargetFs>,
+    /// Relabel moved repositories with `restorecon` instead of copying SELinux contexts.
+    restorecon: bool,
+    /// Order in which repositories and their files are transferred, instead of
+    /// directory order.
+    order: Option<Order>,
+    /// Link to leave at the source location of moved repositories.
+    leave_link: Option<LinkKind>,
+    /// Number of `-v` minus number of `-q`, `i8::MIN` with `--quiet`.
+    verbosity: i8,
+    /// JSON file the outcome of the run is written to.
+    report: Option<PathBuf>,
+    /// File the log is also appended to, with timestamps.
+    log_file: Option<PathBuf>,
+    color: ColorChoice,
+    /// Show a desktop notification when the run is over.
+    notify: bool,
+    /// URL the summary is POSTed to when the run is over.
+    webhook: Option<String>,
+    /// With `sync`, leave the repositories synced less than this long ago.
+    min_interval: Option<Duration>,
+    /// Fetch what partial clones left out before transferring them.
+    backfill: bool,
+    /// Fetch the whole history of shallow clones before transferring them.
+    unshallow: bool,
+    /// Sync the repositories changed at the destination too, over those changes.
+    overwrite_conflicts: bool,
+    /// With `sync`, compare the contents of the files only touched since the
+    /// last sync with their copies before copying them again.
+    compare_contents: bool,
+    /// Never write to the sources, which may be snapshots or read-only mounts.
+    assume_read_only: bool,
+    /// Copy from a temporary snapshot of the sources on btrfs or ZFS.
+    snapshot: bool,
+    /// Run again after each interval instead of once.
+    watch: Option<Duration>,
+    /// Address the Prometheus metrics of `--watch` runs are served on.
+    metrics: Option<String>,
+    /// Names left out of every repository, from the config.
+    exclude: Vec<String>,
+    /// Repositories never moved, from the config.
+    pinned: Pinned,
+    /// Files of other tools naming repositories by path, from the config.
+    references: Vec<Reference>,
+    /// What to do with repositories an interrupted run left half-moved, instead of asking.
+    recover: Option<Recovery>,
+    /// Repositories of the plan given to `apply`, instead of those found in the source.
+    applied: Option<Vec<PlannedRepo>>,
+    /// What to do when the source of a copied repository can't be removed.
+    on_delete_error: ErrorPolicy,
+    /// Remove the whole source of moved repositories, including what was not moved.
+    force_delete: bool,
+    /// Also remove the ignored files left at the source of moved repositories.
+    purge_ignored: bool,
+    /// Send the source of moved repositories to the trash, whole, instead of deleting it.
+    to_trash: bool,
+    /// Copy and verify, then only delete the sources once confirmed.
+    confirm_delete: bool,
+    /// Command line of the run, without the program name, kept for `finalize`.
+    args: Vec<String>,
+    /// Remove the directories left empty at the source and destination of transfers.
+    prune_empty_dirs: bool,
+    /// Whether directories that aren't git repositories are transferred too.
+    non_git: NonGit,
+    /// Whether missing directories leading to the destination are created.
+    mkdir: Mkdir,
+    /// Permissions of the directories created by `mkdir`.
+    dir_mode: Option<u32>,
+    /// More destinations the repositories are copied to, from the same reads.
+    also_dest: Vec<String>,
+    /// Destinations the repositories are spread across, instead of `dest`.
+    shard_across: Vec<String>,
+    /// How repositories are named at the destination, instead of as in the source.
+    rename: Option<Rename>,
+    /// Place repositories under the ghq root after their remote, see `--to-ghq`.
+    to_ghq: bool,
+    /// Where repositories nested deeper in the source go at the destination.
+    layout: Layout,
+    /// What to do when `--flatten` would put different repositories in the same place.
+    on_collision: Collision,
+    /// Transfer the repositories of the inventory that this matches, see `--from-query`.
+    from_query: Option<Query>,
+    /// Projects of the editor workspaces given to `--from-workspace`, and the
+    /// repositories of the myrepos configs given to `--from-mrconfig`, which
+    /// are transferred instead of those found in the sources.
+    from_workspace: Option<Vec<PathBuf>>,
+    /// Editor files whose paths into moved repositories are rewritten after the run.
+    rewrite_workspaces: Vec<PathBuf>,
+    /// The file of `--map`, and where it says each repository goes, instead of
+    /// the sources and the destination.
+    map: Option<(PathBuf, Vec<Mapping>)>,
+    /// Myrepos configs of `--from-mrconfig`, pointed at the moved repositories
+    /// after the run.
+    mrconfigs: Vec<PathBuf>,
+    /// Point the entries of zoxide, autojump and fasd at moved repositories.
+    update_bookmarks: bool,
+    /// Only transfer the repositories with all these tags in the inventory.
+    tags: Vec<String>,
+    /// The inventory, loaded for `--from-query` and `--tag`.
+    inventory: inventory::Inventory,
+    /// How repositories are transferred.
+    strategy: StrategyChoice,
+    /// Results of `bench`, for `--strategy auto` to pick from.
+    bench: BenchResults,
+    nice: Option<i32>,
+    ionice: Option<IoClass>,
+}
 
-    Ok((source, dest, copy))
+impl Options {
+    /// `move`, `copy` or `sync`, for reports.
+    fn mode(&self) -> &'static str {
+        match (self.sync, self.copy) {
+            (true, _) => "sync",
+            (false, true) => "copy",
+            (false, false) => "move",
+        }
+    }
 }
 
-fn move_recursive(path: &Path, dst: String, copy: bool) -> io::Result<()> {
-    if path.exists() && path.is_dir() {
-        for entry in path.read_dir()? {
-            let entry_path = entry?.path();
-            let path_name = entry_path
-                .canonicalize()
-                .unwrap()
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_owned();
+/// Takes the value following `flag` on the command line.
+fn flag_value(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<String, Box<dyn Error>> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for {}\n{}", flag, USAGE).into())
+}
 
-            let (is_git, gitignore) = is_git_dir(&entry_path)?;
-            let new_dest_path = &Path::new(&dst).join(path_name.clone());
+fn parse_args(args: Vec<String>) -> Result<Options, Box<dyn Error>> {
+    let mut opts = Options {
+        transfer: CopyOptions {
+            retry_delay: Duration::from_millis(500),
+            chunk_size: Some(64 << 20),
+            ..CopyOptions::default()
+        },
+        args: args.iter().skip(1).cloned().collect(),
+        ..Options::default()
+    };
+    let mut positional = Vec::new();
+    let mut time_budget = None;
+    let mut byte_budget = None;
 
-            if is_git {
-                move_dir(&entry_path, &new_dest_path, &gitignore, copy)?;
-            } else {
-                println!("{:?} is not a git dir!", path)
+    let mut args = args.into_iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--copy" | "-c" => opts.copy = true,
+            "-v" | "--verbose" => opts.verbosity = opts.verbosity.saturating_add(1),
+            "-vv" => opts.verbosity = opts.verbosity.saturating_add(2),
+            "-q" => opts.verbosity = opts.verbosity.saturating_sub(1),
+            "--quiet" => opts.verbosity = i8::MIN,
+            "--report" => opts.report = Some(PathBuf::from(flag_value(&mut args, &arg)?)),
+            "--notify" => opts.notify = true,
+            "--watch" => opts.watch = Some(units::parse_duration(&flag_value(&mut args, &arg)?)?),
+            "--min-interval" => {
+                opts.min_interval = Some(units::parse_duration(&flag_value(&mut args, &arg)?)?);
+            }
+            "--overwrite-conflicts" => opts.overwrite_conflicts = true,
+            "--metrics" => opts.metrics = Some(flag_value(&mut args, &arg)?),
+            "--webhook" => opts.webhook = Some(flag_value(&mut args, &arg)?),
+            "--color" => opts.color = ColorChoice::parse(&flag_value(&mut args, &arg)?)?,
+            "--log-file" => opts.log_file = Some(PathBuf::from(flag_value(&mut args, &arg)?)),
+            "--sync" => {
+                opts.sync = true;
+                opts.copy = true;
+            }
+            "--dedupe-objects" => opts.dedupe_objects = true,
+            "--backfill" => opts.backfill = true,
+            "--unshallow" => opts.unshallow = true,
+            "--assume-read-only" => opts.assume_read_only = true,
+            "--compare-contents" => opts.compare_contents = true,
+            "--snapshot" => opts.snapshot = true,
+            "--buffer-size" => {
+                let size = units::parse_size(&flag_value(&mut args, &arg)?)?;
+                opts.transfer.buffer_size = Some(size as usize);
+            }
+            "--chunk-size" => {
+                opts.transfer.chunk_size = Some(units::parse_size(&flag_value(&mut args, &arg)?)?);
+            }
+            "--order" => opts.order = Some(Order::parse(&flag_value(&mut args, &arg)?)?),
+            "--leave-symlink" => opts.leave_link = Some(LinkKind::Symlink),
+            "--leave-junction" => opts.leave_link = Some(LinkKind::Junction),
+            "--preserve" => {
+                opts.transfer.preserve = metadata::Preserve::parse(&flag_value(&mut args, &arg)?)?
+            }
+            "--restorecon" => opts.restorecon = true,
+            "--chown" => opts
+                .transfer
+                .ownership
+                .parse_chown(&flag_value(&mut args, &arg)?)?,
+            "--uid-map" => opts
+                .transfer
+                .ownership
+                .parse_uid_map(&flag_value(&mut args, &arg)?)?,
+            "--gid-map" => opts
+                .transfer
+                .ownership
+                .parse_gid_map(&flag_value(&mut args, &arg)?)?,
+            "--normalize" => {
+                opts.transfer.normalization =
+                    paths::Normalization::parse(&flag_value(&mut args, &arg)?)?
+            }
+            "--target-fs" => opts.target_fs = Some(TargetFs::parse(&flag_value(&mut args, &arg)?)?),
+            "--special-files" => opts.transfer.special_files = true,
+            "--memory-limit" => {
+                let limit = units::parse_size(&flag_value(&mut args, &arg)?)?;
+                opts.scan.memory_limit = Some(limit as usize);
+            }
+            "--one-file-system" => opts.scan.one_file_system = true,
+            "--prune-git-cruft" => opts.scan.prune_git_cruft = true,
+            "--strip-reflogs" => opts.scan.strip_reflogs = true,
+            "--max-file-size" => {
+                opts.scan.max_file_size = Some(units::parse_size(&flag_value(&mut args, &arg)?)?);
+            }
+            "--only-extensions" | "--skip-extensions" => {
+                if opts.scan.extensions.is_some() {
+                    return Err(
+                        "--only-extensions and --skip-extensions can't be used together".into(),
+                    );
+                }
+                let extensions = Extensions::parse_list(&flag_value(&mut args, &arg)?)?;
+                opts.scan.extensions = Some(if arg == "--only-extensions" {
+                    Extensions::Only(extensions)
+                } else {
+                    Extensions::Skip(extensions)
+                });
+            }
+            "--hidden" => opts.scan.hidden = Hidden::parse(&flag_value(&mut args, &arg)?)?,
+            "--direct-io" => opts.transfer.direct_io = true,
+            "--fsync" => opts.transfer.fsync = true,
+            "--bwlimit" => {
+                let value = flag_value(&mut args, &arg)?;
+                let rate = units::parse_size(value.strip_suffix("/s").unwrap_or(&value))?;
+                opts.transfer.bwlimit = Some(Arc::new(Throttle::new(rate)));
+            }
+            "--nice" => {
+                let value = flag_value(&mut args, &arg)?;
+                opts.nice = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid nice value: {}", value))?,
+                );
+            }
+            "--retries" => {
+                let value = flag_value(&mut args, &arg)?;
+                opts.transfer.retries = value
+                    .parse()
+                    .map_err(|_| format!("Invalid number of retries: {}", value))?;
             }
+            "--time-budget" => {
+                time_budget = Some(units::parse_duration(&flag_value(&mut args, &arg)?)?);
+            }
+            "--byte-budget" => {
+                byte_budget = Some(units::parse_size(&flag_value(&mut args, &arg)?)?);
+            }
+            "--file-timeout" => {
+                opts.transfer.file_timeout =
+                    Some(units::parse_duration(&flag_value(&mut args, &arg)?)?);
+            }
+            "--retry-delay" => {
+                opts.transfer.retry_delay = units::parse_duration(&flag_value(&mut args, &arg)?)?;
+            }
+            "--force-delete" => opts.force_delete = true,
+            "--to-trash" => opts.to_trash = true,
+            "--confirm-delete" => opts.confirm_delete = true,
+            "--purge-ignored-at-source" => opts.purge_ignored = true,
+            "--prune-empty-dirs" => opts.prune_empty_dirs = true,
+            "--tag" => opts.tags.push(flag_value(&mut args, &arg)?),
+            "--from-query" => {
+                opts.from_query = Some(Query::parse(&flag_value(&mut args, &arg)?)?);
+            }
+            "--from-workspace" => {
+                let file = paths::expand(&flag_value(&mut args, &arg)?)?;
+                let projects = workspace::projects(Path::new(&file))
+                    .map_err(|e| format!("Could not read the workspace {:?}: {}", file, e))?;
+                opts.from_workspace
+                    .get_or_insert_with(Vec::new)
+                    .extend(projects);
+            }
+            "--from-mrconfig" => {
+                let file = PathBuf::from(paths::expand(&flag_value(&mut args, &arg)?)?);
+                let projects = mrconfig::projects(&file)
+                    .map_err(|e| format!("Could not read the myrepos config {:?}: {}", file, e))?;
+                opts.from_workspace
+                    .get_or_insert_with(Vec::new)
+                    .extend(projects);
+                opts.mrconfigs.push(file);
+            }
+            "--map" => {
+                let file = PathBuf::from(paths::expand(&flag_value(&mut args, &arg)?)?);
+                let mappings = mapping::load(&file)?;
+                opts.map = Some((file, mappings));
+            }
+            "--rewrite-workspace" => opts
+                .rewrite_workspaces
+                .push(PathBuf::from(paths::expand(&flag_value(&mut args, &arg)?)?)),
+            "--update-bookmarks" => opts.update_bookmarks = true,
+            "--strategy" => opts.strategy = StrategyChoice::parse(&flag_value(&mut args, &arg)?)?,
+            "--on-collision" => {
+                opts.on_collision = Collision::parse(&flag_value(&mut args, &arg)?)?;
+            }
+            "--flatten" | "--preserve-paths" => {
+                let layout = if arg == "--flatten" {
+                    Layout::Flatten
+                } else {
+                    Layout::Preserve
+                };
+                if opts.layout != Layout::Top && opts.layout != layout {
+                    return Err("--flatten and --preserve-paths can't be used together".into());
+                }
+                opts.layout = layout;
+            }
+            "--rename" => opts.rename = Some(Rename::parse(&flag_value(&mut args, &arg)?)?),
+            "--to-ghq" => opts.to_ghq = true,
+            "--shard-across" => {
+                opts.shard_across = shard::parse_roots(&flag_value(&mut args, &arg)?)?
+                    .iter()
+                    .map(|root| paths::expand(root))
+                    .collect::<Result<_, _>>()?;
+            }
+            "--also-dest" => opts
+                .also_dest
+                .push(paths::expand(&flag_value(&mut args, &arg)?)?),
+            "--mkdir" => opts.mkdir = Mkdir::parse(&flag_value(&mut args, &arg)?)?,
+            "--mode" => opts.dir_mode = Some(mkdir::parse_mode(&flag_value(&mut args, &arg)?)?),
+            "--include-non-git" => opts.non_git = NonGit::parse(&flag_value(&mut args, &arg)?)?,
+            "--error-policy" => {
+                (opts.transfer.on_error, opts.on_delete_error) =
+                    ErrorPolicy::parse(&flag_value(&mut args, &arg)?)?;
+            }
+            "--recover" => opts.recover = Some(Recovery::parse(&flag_value(&mut args, &arg)?)?),
+            "--ionice" => opts.ionice = Some(IoClass::parse(&flag_value(&mut args, &arg)?)?),
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("Unknown option: {}\n{}", arg, USAGE).into());
+            }
+            _ => positional.push(arg),
         }
-    } else {
-        println!("{:?} is not a dir or does not exists", path)
     }
-    Ok(())
+
+    opts.transfer.budget = Arc::new(Budget::new(time_budget, byte_budget));
+    let sharded = !opts.shard_across.is_empty();
+    let mapped = opts.map.is_some();
+    let from_query = opts.from_query.is_some() || opts.from_workspace.is_some() || mapped;
+    let placed = sharded || opts.to_ghq || mapped;
+    if positional.len() < usize::from(!placed) + usize::from(!from_query) {
+        return Err(USAGE.into());
+    }
+    if opts.restorecon && opts.transfer.preserve.selinux {
+        return Err("--restorecon and --preserve selinux can't be used together".into());
+    }
+    if opts.metrics.is_some() && opts.watch.is_none() {
+        return Err("--metrics is only served with --watch".into());
+    }
+    if opts.min_interval.is_some() && !opts.sync {
+        return Err("--min-interval only applies to --sync".into());
+    }
+    if opts.overwrite_conflicts && !opts.sync {
+        return Err("--overwrite-conflicts only applies to --sync".into());
+    }
+    if opts.compare_contents && !opts.sync {
+        return Err("--compare-contents only applies to --sync".into());
+    }
+    if opts.assume_read_only && !(opts.copy || opts.sync) {
+        return Err(
+            "--assume-read-only never deletes the sources, use it with --copy or --sync".into(),
+        );
+    }
+    if opts.assume_read_only && (opts.backfill || opts.unshallow || opts.confirm_delete) {
+        return Err(
+            "--assume-read-only can't be used with --backfill, --unshallow or --confirm-delete, \
+             which write to the sources"
+                .into(),
+        );
+    }
+    if opts.snapshot && !(opts.copy || opts.sync) {
+        return Err(
+            "--snapshot would delete the sources as they are now, not as copied, \
+             use it with --copy or --sync"
+                .into(),
+        );
+    }
+    if opts.snapshot && opts.assume_read_only {
+        return Err("--snapshot writes to the filesystem of the sources, \
+             it can't be used with --assume-read-only"
+            .into());
+    }
+    if opts.sync && !opts.also_dest.is_empty() {
+        return Err("--also-dest can't be used with --sync".into());
+    }
+    if sharded && (opts.sync || !opts.also_dest.is_empty()) {
+        return Err("--shard-across can't be used with --sync or --also-dest".into());
+    }
+    if opts.to_ghq && (sharded || opts.rename.is_some() || opts.layout == Layout::Preserve) {
+        return Err(
+            "--to-ghq places the repositories itself, it can't be used with --shard-across, \
+             --rename or --preserve-paths"
+                .into(),
+        );
+    }
+    if mapped
+        && (opts.sync
+            || !opts.also_dest.is_empty()
+            || sharded
+            || opts.to_ghq
+            || opts.rename.is_some()
+            || opts.layout != Layout::Top
+            || opts.from_query.is_some()
+            || opts.from_workspace.is_some()
+            || !opts.tags.is_empty())
+    {
+        return Err(
+            "--map says which repositories go where, it can't be used with --sync, --also-dest, \
+             --shard-across, --to-ghq, --rename, --flatten, --preserve-paths, --from-query, \
+             --from-workspace, --from-mrconfig or --tag"
+                .into(),
+        );
+    }
+    if mapped && !positional.is_empty() {
+        return Err("--map says which repositories go where, give no source or destination".into());
+    }
+    let mut positional = positional
+        .iter()
+        .map(|arg| paths::expand(arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    if opts.to_ghq {
+        opts.dest = ghq::root()?.to_string_lossy().into_owned();
+    } else if !sharded && !mapped {
+        opts.dest = positional.pop().unwrap_or_default();
+    }
+    if opts.from_query.is_some() && opts.from_workspace.is_some() {
+        return Err("--from-query can't be used with --from-workspace or --from-mrconfig".into());
+    }
+    if from_query && !positional.is_empty() {
+        return Err(
+            "--from-query, --from-workspace and --from-mrconfig pick the repositories, \
+             give only the destination"
+                .into(),
+        );
+    }
+    opts.sources = positional;
+    // What `--confirm-delete` verifies the copies against, read while copying.
+    if opts.confirm_delete && !opts.copy {
+        opts.transfer.checksums = Some(Arc::default());
+    }
+
+    Ok(opts)
 }
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let (fp, dest, copy) = parse_args(args).unwrap();
+/// Parses `apply <plan> [options]`: the options the plan was made with, followed
+/// by `options`.
+fn parse_apply_args(args: &[String]) -> Result<Options, Box<dyn Error>> {
+    let path = args.get(2).ok_or(plan::APPLY_USAGE)?;
+    let plan = Plan::load(Path::new(path))?;
+    let run_args = iter::once(args[0].clone())
+        .chain(plan.args)
+        .chain(args[3..].iter().cloned())
+        .collect();
+    let mut opts = parse_args(run_args)?;
+    if opts.watch.is_some() {
+        return Err("apply carries out the plan once, it can't be used with --watch".into());
+    }
+    opts.applied = Some(plan.repos);
+    Ok(opts)
+}
 
-    let p = Path::new(&fp);
-    move_recursive(p, dest, copy)?;
+/// Runs `repair` with the arguments after the subcommand.
+fn repair_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (repo, from) = repair::parse_args(args)?;
+    let old = match from {
+        Some(from) => from,
+        None => repair::guess_old_location(&repo)?
+            .ok_or("Could not tell where the repository was before, use --from")?,
+    };
+    for file in repair::repair(&repo, &old)? {
+        println!("fixed  {}", file.display());
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::tempdir;
+/// Total size of the files of the repository at `path`, 0 if it isn't one.
+fn repo_size(path: &Path, opts: &Options) -> u64 {
+    let Ok((true, gitignore)) = is_git_dir(path) else {
+        return 0;
+    };
+    let Ok(scan) = scan::scan(path, &gitignore, &opts.scan) else {
+        return 0;
+    };
+    let Ok(batches) = scan.batches() else {
+        return 0;
+    };
+    batches
+        .flatten()
+        .map(|batch| {
+            batch
+                .iter()
+                .filter(|e| e.kind == EntryKind::File)
+                .map(|e| e.stamp.size)
+                .sum::<u64>()
+        })
+        .sum()
+}
 
-    #[test]
-    fn test_read_gitignore() -> io::Result<()> {
-        let dir = tempdir()?;
-        let file_path = dir.path().join(".gitignore");
+/// Sorts the candidate repositories as asked with `--order`. Sizes take a pre-scan
+/// of every repository.
+fn sort_repos(paths: &mut Vec<PathBuf>, order: Order, opts: &Options) {
+    let mut keyed: Vec<(PathBuf, u64, i64)> = paths
+        .drain(..)
+        .map(|path| {
+            let size = if order.needs_size() {
+                repo_size(&path, opts)
+            } else {
+                0
+            };
+            let mtime = fs::metadata(&path).map_or(0, |m| FileStamp::from_metadata(&m).mtime);
+            (path, size, mtime)
+        })
+        .collect();
+    order.sort(&mut keyed, |(path, size, mtime)| {
+        (path.as_path(), *size, *mtime)
+    });
+    paths.extend(keyed.into_iter().map(|(path, _, _)| path));
+}
+
+/// Moves (or copies, or syncs) the repository `repo.source` to `repo.dest`, as
+/// `repo.action` says. Syncs record it in `index` under `key`.
+fn transfer_repo(
+    repo: &PlannedRepo,
+    key: &str,
+    gitignore: &Option<Vec<String>>,
+    index: Option<&mut Index>,
+    opts: &Options,
+) -> io::Result<Outcome> {
+    let (src, dst) = (repo.source.as_path(), repo.dest.as_path());
+    match repo.action {
+        Action::Deduplicate => {
+            if !info_span!("verify").in_scope(|| git::is_identical_copy(src, dst)) {
+                return Err(io::Error::other(
+                    "the copy at the destination is no longer identical",
+                ));
+            }
+            if index.is_some() {
+                info!("{:?} is unchanged, identical to {:?}", src, dst);
+                return Ok(Outcome::Unchanged);
+            }
+            if !opts.copy {
+                check_duplicate(src, dst)?;
+            }
+            if opts.confirm_delete && !opts.copy {
+                info!(
+                    "{:?} has an identical copy at {:?}, keeping it until the delete is confirmed",
+                    src, dst
+                );
+                return Ok(Outcome::Verified);
+            }
+            if !opts.copy {
+                let scan = scan_source(src, gitignore, opts)?;
+                if !delete_source(src, dst, &scan, gitignore, opts)? {
+                    return Ok(Outcome::Kept);
+                }
+            }
+            info!(
+                "{:?} deduplicated: identical copy already at {:?}",
+                src, dst
+            );
+            return Ok(Outcome::Deduplicated);
+        }
+        Action::Transfer if dst.exists() && index.is_none() => {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                "the destination appeared since the plan",
+            ));
+        }
+        Action::Transfer | Action::Merge | Action::Skip | Action::Pin => {}
+    }
+    if let Some(parent) = dst.parent() {
+        mkdir::ensure_dir(parent, opts.mkdir, opts.dir_mode)?;
+    }
+    check_partial_clone(src, dst, opts)?;
+    check_shallow(src, dst, opts)?;
+    let old = fs::canonicalize(src)?;
+    // Taken after the checks above, which may fetch into the source.
+    let snapshot = if opts.snapshot {
+        match Snapshot::take(src) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                warn!("Copying {:?} as it changes, without a snapshot: {}", src, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let from = snapshot.as_ref().map_or(src, Snapshot::repo);
+    let outcome = if let Some(index) = index {
+        match sync_dir(from, dst, gitignore, index, key, opts)
+            .map_err(|e| opts.transfer.on_error.escalate(e))?
         {
-            let mut file = File::create(&file_path)?;
-            writeln!(file, "target/")?;
-            writeln!(file, "node_modules/")?;
+            Outcome::Unchanged => {
+                info!("{:?} is unchanged since the last sync", src);
+                return Ok(Outcome::Unchanged);
+            }
+            Outcome::Conflicted(changed) => {
+                warn!(
+                    "Leaving {:?} and {:?} as they are, both changed since the last sync, {}",
+                    src, dst, changed
+                );
+                return Ok(Outcome::Conflicted(changed));
+            }
+            outcome => outcome,
+        }
+    } else {
+        match move_dir(from, dst, gitignore, opts) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                if snapshot.is_some() {
+                    // The snapshot is gone by the time the copy is recovered.
+                    recovery::resume_from(dst, &old)?;
+                }
+                return Err(e);
+            }
+        }
+    };
+    if opts.prune_empty_dirs {
+        // Copies leave the source as it was.
+        let source_kept = opts.copy || opts.confirm_delete || outcome == Outcome::Kept;
+        for dir in [dst, src] {
+            if dir.exists() && !(source_kept && dir == src) {
+                if let Err(e) = prune_empty_dirs(dir) {
+                    warn!("Could not prune the empty directories of {:?}: {}", dir, e);
+                }
+            }
         }
+    }
+    match repair::repair(dst, &old) {
+        Ok(fixed) if !fixed.is_empty() => debug!("Rewrote paths to {:?} in {:?}", old, fixed),
+        Ok(_) => {}
+        Err(e) => warn!("Could not repair the paths recorded in {:?}: {}", dst, e),
+    }
+    if !src.exists() {
+        warn_shared_config_paths(dst, &old);
+        update_maintenance(dst, &old);
+    }
+    Ok(outcome)
+}
 
-        let gitignore = read_gitignore(&file_path)?;
-        assert_eq!(gitignore, vec!["target", "node_modules"]);
-        Ok(())
+/// Checks that deleting `src` loses nothing its identical copy `dst` lacks:
+/// both have the same refs, and `src` holds no untracked or ignored files.
+fn check_duplicate(src: &Path, dst: &Path) -> io::Result<()> {
+    let _span = info_span!("verify").entered();
+    if git::refs(src)? != git::refs(dst)? {
+        return Err(io::Error::other(
+            "the copy at the destination has other refs, keeping the source",
+        ));
     }
+    if !git::is_pristine(src) {
+        return Err(io::Error::other(
+            "the source holds untracked or ignored files, keeping it",
+        ));
+    }
+    Ok(())
+}
 
-    #[test]
-    fn test_copy_dir_recursive() -> io::Result<()> {
-        let src_dir = tempdir()?;
-        let dst_dir = tempdir()?;
+/// Warns about the paths into the moved repository that the global or system
+/// git config hold, which `repair` leaves to the user as other repositories
+/// read them too.
+fn warn_shared_config_paths(dst: &Path, old: &Path) {
+    for key in ["core.hooksPath", "core.excludesFile"] {
+        match git::config_path(dst, key) {
+            Some((scope, path)) if scope != "local" && path.starts_with(old) => warn!(
+                "{} is {:?} in the {} git config, in {:?} which is now at {:?}",
+                key, path, scope, old, dst
+            ),
+            _ => {}
+        }
+    }
+}
 
-        let gitignore = Some(vec!["ignored".to_string()]);
+/// Registers the moved repository for `git maintenance` at its new location
+/// where it was at the old one, or warns that its scheduled runs will fail.
+fn update_maintenance(dst: &Path, old: &Path) {
+    let configs = git::maintenance_configs(dst, old);
+    if configs.is_empty() {
+        return;
+    }
+    let new = match fs::canonicalize(dst) {
+        Ok(new) => new,
+        Err(e) => {
+            warn!("Could not register {:?} for git maintenance: {}", dst, e);
+            return;
+        }
+    };
+    for config in configs {
+        match git::move_maintenance_repo(&config, old, &new) {
+            Ok(()) => info!(
+                "Registered {:?} for git maintenance in place of {:?} in {:?}",
+                new, old, config
+            ),
+            Err(e) => warn!(
+                "{:?} is still registered for git maintenance in {:?}, which will fail on it: {}",
+                old, config, e
+            ),
+        }
+    }
+}
+
+/// Warns that the partial clone `src` still needs its promisor remotes, or
+/// fetches what it lacks from them first with `--backfill`.
+fn check_partial_clone(src: &Path, dst: &Path, opts: &Options) -> io::Result<()> {
+    let remotes = git::promisor_remotes(src);
+    if remotes.is_empty() {
+        return Ok(());
+    }
+    if !opts.backfill {
+        warn!(
+            "{:?} is a partial clone: the objects its filter left out are fetched from {} \
+             when needed, so {:?} still depends on it. Use --backfill to fetch them first",
+            src,
+            remotes.join(", "),
+            dst
+        );
+        return Ok(());
+    }
+    let _span = info_span!("backfill").entered();
+    for remote in &remotes {
+        info!("Fetching the objects {:?} lacks from {}", src, remote);
+        git::backfill(src, remote)
+            .map_err(|e| io::Error::other(format!("could not backfill from {}: {}", remote, e)))?;
+    }
+    Ok(())
+}
+
+/// Warns that the shallow clone `src` lacks history, or fetches it first with
+/// `--unshallow`.
+fn check_shallow(src: &Path, dst: &Path, opts: &Options) -> io::Result<()> {
+    if !git::is_shallow(src) {
+        return Ok(());
+    }
+    if !opts.unshallow {
+        warn!(
+            "{:?} is a shallow clone: its history is truncated, and so is that of {:?}. \
+             Use --unshallow to fetch all of it first",
+            src, dst
+        );
+        return Ok(());
+    }
+    let _span = info_span!("unshallow").entered();
+    info!("Fetching the history {:?} lacks", src);
+    git::unshallow(src).map_err(|e| io::Error::other(format!("could not unshallow: {}", e)))
+}
+
+/// Directories of `repo` that the transfer would leave empty, for the plan.
+fn planned_empty_dirs(repo: &Path, opts: &Options) -> Vec<PathBuf> {
+    let empty_dirs = is_git_dir(repo)
+        .and_then(|(_, gitignore)| with_excludes(gitignore, repo, opts))
+        .and_then(|gitignore| scan::scan(repo, &gitignore, &opts.scan))
+        .and_then(|scan| scan.empty_dirs());
+    empty_dirs.unwrap_or_else(|e| {
+        warn!(
+            "Could not tell which directories of {:?} end up empty: {}",
+            repo, e
+        );
+        Vec::new()
+    })
+}
+
+/// Removes the empty directories under `dir`, outside of `.git`, and `dir` itself
+/// if it ends up empty too. Returns whether it did.
+fn prune_empty_dirs(dir: &Path) -> io::Result<bool> {
+    let mut empty = true;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name() != ".git"
+            && entry.file_type()?.is_dir()
+            && prune_empty_dirs(&entry.path())?
+        {
+            continue;
+        }
+        empty = false;
+    }
+    if empty {
+        debug!("Removing empty directory {:?}", dir);
+        fs::remove_dir(dir)?;
+    }
+    Ok(empty)
+}
+
+/// Decides what to do with every directory of `path`, without changing anything.
+/// With `shards`, each repository goes to the destination they pick for it.
+fn plan_repos(
+    path: &Path,
+    mut shards: Option<&mut Shards>,
+    opts: &Options,
+) -> io::Result<Vec<PlannedRepo>> {
+    let mut repos = Vec::new();
+    if !path.is_dir() {
+        warn!("{:?} is not a dir or does not exists", path);
+        return Ok(repos);
+    }
+    plan_dir(path, path, &mut shards, opts, &mut repos)?;
+    Ok(repos)
+}
+
+/// Whether the repository at `path` is one of the projects of `--from-workspace`,
+/// and the inventory says it matches `--from-query` and has the tags of `--tag`.
+fn is_picked(path: &Path, opts: &Options) -> bool {
+    if let Some(projects) = &opts.from_workspace {
+        if !std::path::absolute(path).is_ok_and(|path| projects.contains(&path)) {
+            return false;
+        }
+    }
+    if opts.from_query.is_none() && opts.tags.is_empty() {
+        return true;
+    }
+    let Some(repo) = std::path::absolute(path)
+        .ok()
+        .and_then(|path| opts.inventory.find(&path))
+    else {
+        return false;
+    };
+    opts.from_query
+        .as_ref()
+        .is_none_or(|query| query.matches(repo))
+        && opts.tags.iter().all(|tag| repo.tags.contains(tag))
+}
+
+/// Plans the directories of `dir`, under the source `root`, into `repos`. With
+/// `--flatten` or `--preserve-paths`, descends into the directories that aren't
+/// repositories but hold some. Returns whether any repository was found.
+fn plan_dir(
+    root: &Path,
+    dir: &Path,
+    shards: &mut Option<&mut Shards>,
+    opts: &Options,
+    repos: &mut Vec<PlannedRepo>,
+) -> io::Result<bool> {
+    let mut found = false;
+    let mut children = dir
+        .read_dir()?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    if let Some(order) = opts.order {
+        sort_repos(&mut children, order, opts);
+    }
+    for entry_path in children {
+        let picking =
+            opts.from_query.is_some() || opts.from_workspace.is_some() || !opts.tags.is_empty();
+        if picking && !is_picked(&entry_path, opts) {
+            continue;
+        }
+        let Some(path_name) = entry_path.file_name().and_then(|name| name.to_str()) else {
+            warn!("Skipping {:?}, its name is not valid UTF-8", entry_path);
+            continue;
+        };
+        let path_name = path_name.to_owned();
+        let is_git = is_git_dir(&entry_path)?.0;
+        found |= is_git;
+        if !is_git
+            && opts.layout != Layout::Top
+            && fs::symlink_metadata(&entry_path).is_ok_and(|m| m.is_dir())
+        {
+            let mut nested = Vec::new();
+            if plan_dir(root, &entry_path, shards, opts, &mut nested)? {
+                repos.extend(nested);
+                found = true;
+                continue;
+            }
+        }
+        let is_plain_included =
+            !is_git && entry_path.is_dir() && opts.non_git.includes(&entry_path)?;
+        let repo_config = if is_git {
+            RepoConfig::load(&entry_path)
+        } else {
+            Ok(RepoConfig::default())
+        };
+        let pinned = if is_git {
+            opts.pinned.reason(&entry_path)
+        } else {
+            None
+        };
+        let placed = (is_git || is_plain_included)
+            && pinned.is_none()
+            && repo_config.as_ref().is_ok_and(|config| !config.never_move);
+        let mut dst = match shards.as_deref_mut() {
+            Some(shards) if placed => shards.place(&entry_path)?,
+            _ => PathBuf::from(&opts.dest),
+        };
+        if opts.layout == Layout::Preserve {
+            dst.push(dir.strip_prefix(root).unwrap_or(Path::new("")));
+        }
+        let ghq_layout = opts.to_ghq.then(|| ghq::repo_layout(&entry_path));
+        let new_dest_path = match (&repo_config, &ghq_layout) {
+            (
+                Ok(RepoConfig {
+                    dest: Some(dest), ..
+                }),
+                _,
+            ) => dst.join(dest),
+            (_, Some(Some(layout))) => dst.join(layout),
+            _ => match &opts.rename {
+                Some(rename) if placed => dst.join(rename.apply(&path_name, &entry_path)),
+                _ => dst.join(&path_name),
+            },
+        };
+
+        let unplaced = ghq_layout == Some(None)
+            && pinned.is_none()
+            && repo_config
+                .as_ref()
+                .is_ok_and(|config| config.dest.is_none() && !config.never_move);
+        let (action, reason) = if !is_git && !is_plain_included {
+            (Action::Skip, "not a git repository".to_string())
+        } else if unplaced {
+            (
+                Action::Skip,
+                "no remote to place it under the ghq root".to_string(),
+            )
+        } else {
+            plan_action(
+                &entry_path,
+                &new_dest_path,
+                is_git,
+                repo_config,
+                pinned,
+                opts,
+            )
+        };
+        let empty_dirs =
+            if opts.prune_empty_dirs && matches!(action, Action::Transfer | Action::Merge) {
+                planned_empty_dirs(&entry_path, opts)
+            } else {
+                Vec::new()
+            };
+        repos.push(PlannedRepo {
+            source: entry_path,
+            dest: new_dest_path,
+            action,
+            reason,
+            empty_dirs,
+        });
+    }
+    Ok(found)
+}
+
+/// Plans the repositories of `--map`, each to where the map says.
+fn plan_mapped(mappings: &[Mapping], opts: &Options) -> Vec<PlannedRepo> {
+    mappings
+        .iter()
+        .map(|mapping| {
+            let source = &mapping.source;
+            let (action, reason) = plan_action(
+                source,
+                &mapping.dest,
+                true,
+                RepoConfig::load(source),
+                opts.pinned.reason(source),
+                opts,
+            );
+            let empty_dirs =
+                if opts.prune_empty_dirs && matches!(action, Action::Transfer | Action::Merge) {
+                    planned_empty_dirs(source, opts)
+                } else {
+                    Vec::new()
+                };
+            PlannedRepo {
+                source: source.clone(),
+                dest: mapping.dest.clone(),
+                action,
+                reason,
+                empty_dirs,
+            }
+        })
+        .collect()
+}
+
+/// What to do with `source`, a repository or with `--include-non-git` a plain
+/// directory, placed at `dest`, and why.
+fn plan_action(
+    source: &Path,
+    dest: &Path,
+    is_git: bool,
+    repo_config: io::Result<RepoConfig>,
+    pinned: Option<String>,
+    opts: &Options,
+) -> (Action, String) {
+    let synced_ago = opts
+        .min_interval
+        .and_then(|interval| synced_within(dest, interval, opts));
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+    // Only checked once nothing else decided, as it runs git on both sides.
+    let identical = || {
+        is_git
+            && info_span!("verify", name = %name).in_scope(|| git::is_identical_copy(source, dest))
+    };
+    match (repo_config, pinned) {
+        (Err(e), _) => (Action::Skip, e.to_string()),
+        (Ok(config), _) if config.never_move => (
+            Action::Pin,
+            format!("never_move in its {}", config::REPO_CONFIG_FILE),
+        ),
+        (Ok(_), Some(reason)) => (Action::Pin, reason),
+        (Ok(_), None) if synced_ago.is_some() => (
+            Action::Skip,
+            format!(
+                "synced {} ago, within --min-interval",
+                units::format_duration(synced_ago.unwrap_or_default())
+            ),
+        ),
+        (Ok(_), None) if !dest.exists() => (
+            Action::Transfer,
+            if is_git {
+                String::new()
+            } else {
+                "not a git repository, included by --include-non-git".to_string()
+            },
+        ),
+        (Ok(_), None) if identical() => (
+            Action::Deduplicate,
+            "identical copy already at the destination".to_string(),
+        ),
+        (Ok(_), None) if opts.sync => (Action::Transfer, "synced before".to_string()),
+        (Ok(_), None) => (
+            Action::Merge,
+            "destination exists, files are copied over it".to_string(),
+        ),
+    }
+}
+
+/// How long ago `dest` was synced, if it was less than `interval` ago
+/// according to the inventory.
+fn synced_within(dest: &Path, interval: Duration, opts: &Options) -> Option<Duration> {
+    let synced = opts
+        .inventory
+        .find(&std::path::absolute(dest).ok()?)?
+        .synced?;
+    let ago = SystemTime::now()
+        .duration_since(UNIX_EPOCH + Duration::from_secs(synced))
+        .unwrap_or_default();
+    (ago < interval).then_some(ago)
+}
+
+/// `gitignore` of `repo`, with the names excluded by the config and the
+/// repository's `.mv-git.toml` added.
+fn with_excludes(
+    gitignore: Option<Vec<String>>,
+    repo: &Path,
+    opts: &Options,
+) -> io::Result<Option<Vec<String>>> {
+    let repo_excludes = RepoConfig::load(repo)?.exclude;
+    // Those of git's `core.excludesFile`, wherever its config puts it.
+    let git_excludes = match git::excludes_file(repo) {
+        Some(file) if repo.join(".git").exists() && file.is_file() => read_gitignore(&file)?,
+        _ => Vec::new(),
+    };
+    if opts.exclude.is_empty() && repo_excludes.is_empty() && git_excludes.is_empty() {
+        return Ok(gitignore);
+    }
+    let mut names = gitignore.unwrap_or_default();
+    names.extend(opts.exclude.iter().cloned());
+    names.extend(repo_excludes);
+    names.extend(git_excludes);
+    Ok(Some(names))
+}
+
+/// Carries out `repos`, as planned by [`plan_repos`].
+fn apply_plan(repos: &[PlannedRepo], opts: &Options) -> io::Result<Summary> {
+    let dst = &opts.dest;
+    let mut summary = Summary::default();
+    let mut index = if opts.sync {
+        mkdir::ensure_dir(Path::new(dst), opts.mkdir, opts.dir_mode)?;
+        Some(Index::load(Path::new(dst))?)
+    } else {
+        None
+    };
+    let mut aborted = false;
+    for repo in repos {
+        let name = repo
+            .dest
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let _span = info_span!("repo", name = %name).entered();
+        // Repositories of the same name can be synced to different directories
+        // of the destination, with --preserve-paths.
+        let key = repo
+            .dest
+            .strip_prefix(dst)
+            .unwrap_or(&repo.dest)
+            .to_string_lossy()
+            .into_owned();
+
+        let before = opts.transfer.stats.totals();
+        let started = Instant::now();
+        let outcome = if repo.action == Action::Pin {
+            info!("Leaving pinned {:?}: {}", repo.source, repo.reason);
+            Outcome::Pinned
+        } else if repo.action == Action::Skip || aborted || opts.transfer.is_cancelled() {
+            let reason = if aborted {
+                "the run was aborted"
+            } else if opts.transfer.is_cancelled() {
+                &stop_reason(opts)
+            } else if repo.reason.is_empty() {
+                "skipped in the plan"
+            } else {
+                &repo.reason
+            };
+            info!("Skipping {:?}: {}", repo.source, reason);
+            Outcome::Skipped
+        } else {
+            opts.transfer
+                .stats
+                .start_repo(&repo.source.to_string_lossy());
+            let transferred = is_git_dir(&repo.source)
+                .and_then(|(_, gitignore)| with_excludes(gitignore, &repo.source, opts))
+                .and_then(|gitignore| transfer_repo(repo, &key, &gitignore, index.as_mut(), opts));
+            opts.transfer.stats.end_repo();
+            transferred.unwrap_or_else(|e| {
+                if e.kind() == ErrorKind::Interrupted && opts.transfer.is_cancelled() {
+                    warn!(
+                        "Stopped transferring {:?}, {}: {}",
+                        repo.source,
+                        stop_reason(opts),
+                        e
+                    );
+                    return Outcome::Skipped;
+                }
+                error!("Could not transfer {:?}: {}", repo.source, e);
+                if policy::is_abort(&e) {
+                    error!("Aborting the run, as asked by --error-policy");
+                    aborted = true;
+                }
+                Outcome::Failed(e.to_string())
+            })
+        };
+        let transferred = opts.transfer.stats.totals() - before;
+        let slowest = opts.transfer.stats.take_slowest();
+        let timing = match outcome {
+            Outcome::Skipped | Outcome::Pinned => Timing::default(),
+            _ => Timing::new(started.elapsed(), transferred.bytes, slowest),
+        };
+        summary.push(
+            repo.source.clone(),
+            repo.dest.clone(),
+            outcome,
+            transferred,
+            timing,
+        );
+    }
+    if let Some(index) = index {
+        index.save()?;
+    }
+    Ok(summary)
+}
+
+/// Why the run stopped before transferring everything.
+fn stop_reason(opts: &Options) -> String {
+    match opts.transfer.budget.spent(&opts.transfer.stats) {
+        Some(budget) => format!("the {} budget was spent", budget),
+        None => "the run was interrupted".to_string(),
+    }
+}
+
+/// The source directories of `opts`, with glob patterns expanded, or those
+/// holding the repositories picked by `--from-query` or `--from-workspace`.
+fn source_dirs(opts: &Options) -> io::Result<Vec<PathBuf>> {
+    let picked: Option<Vec<&Path>> = match (&opts.from_query, &opts.from_workspace) {
+        (Some(query), _) => Some(
+            query::select(&opts.inventory, query)
+                .iter()
+                .map(|repo| repo.path.as_path())
+                .collect(),
+        ),
+        (None, Some(projects)) => Some(
+            projects
+                .iter()
+                .filter(|project| {
+                    let found = project.is_dir();
+                    if !found {
+                        warn!(
+                            "{:?} is in the workspace or myrepos config but not found, \
+                             skipping it",
+                            project
+                        );
+                    }
+                    found
+                })
+                .map(PathBuf::as_path)
+                .collect(),
+        ),
+        (None, None) => None,
+    };
+    if let Some(picked) = picked {
+        let mut dirs: Vec<PathBuf> = picked
+            .iter()
+            .filter_map(|repo| repo.parent().map(Path::to_path_buf))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        return Ok(dirs);
+    }
+    let mut dirs = Vec::new();
+    for source in &opts.sources {
+        dirs.extend(glob::expand(source)?);
+    }
+    Ok(dirs)
+}
+
+/// The destinations of `opts`: those of `--shard-across`, the directories
+/// `--map` moves repositories into, or else the one destination.
+fn dest_roots(opts: &Options) -> Vec<PathBuf> {
+    if let Some((_, mappings)) = &opts.map {
+        let mut roots: Vec<PathBuf> = mappings
+            .iter()
+            .filter_map(|mapping| mapping.dest.parent().map(Path::to_path_buf))
+            .collect();
+        roots.sort();
+        roots.dedup();
+        roots
+    } else if opts.shard_across.is_empty() {
+        vec![PathBuf::from(&opts.dest)]
+    } else {
+        opts.shard_across.iter().map(PathBuf::from).collect()
+    }
+}
+
+/// Plans the repositories of all of `sources`, each only once when sources
+/// overlap.
+fn plan_sources(sources: &[&Path], opts: &Options) -> io::Result<Vec<PlannedRepo>> {
+    let mut shards = if opts.shard_across.is_empty() {
+        None
+    } else {
+        let roots = dest_roots(opts);
+        for root in &roots {
+            mkdir::ensure_dir(root, opts.mkdir, opts.dir_mode)?;
+        }
+        Some(Shards::new(&roots)?)
+    };
+    let mut seen = HashSet::new();
+    let mut repos = Vec::new();
+    for source in sources {
+        for repo in plan_repos(source, shards.as_mut(), opts)? {
+            let canonical = fs::canonicalize(&repo.source).unwrap_or_else(|_| repo.source.clone());
+            if seen.insert(canonical) {
+                repos.push(repo);
+            } else {
+                debug!("{:?} was already found in another source", repo.source);
+            }
+        }
+    }
+    // Flattened, or from different sources, repositories can share a name.
+    plan::resolve_collisions(&mut repos, opts.on_collision)?;
+    Ok(repos)
+}
+
+fn move_recursive(sources: &[&Path], opts: &Options) -> io::Result<Summary> {
+    match (&opts.applied, &opts.map) {
+        (Some(repos), _) => apply_plan(repos, opts),
+        (None, Some((_, mappings))) => apply_plan(&plan_mapped(mappings, opts), opts),
+        (None, None) => apply_plan(&plan_sources(sources, opts)?, opts),
+    }
+}
+
+/// Finishes, rolls back or cleans up what an interrupted run left half-moved at
+/// the destination, as `--recover` says or the user answers.
+fn recover_interrupted(opts: &Options) -> io::Result<()> {
+    let mut found = Vec::new();
+    for root in dest_roots(opts) {
+        found.extend(recovery::find(&root)?);
+    }
+    for (dst, marker) in found {
+        // Finishing or rolling back a move writes to its source.
+        if opts.assume_read_only {
+            warn!(
+                "{:?} was left half-moved from {:?} by an interrupted run, \
+                 leaving it as it is with --assume-read-only",
+                dst, marker.source
+            );
+            continue;
+        }
+        let Some(recovery) = opts
+            .recover
+            .map_or_else(|| recovery::ask(&dst, &marker), |r| Ok(Some(r)))?
+        else {
+            warn!(
+                "{:?} was left half-moved from {:?} by an interrupted run, \
+                 use --recover to finish, roll it back or clean it up",
+                dst, marker.source
+            );
+            continue;
+        };
+        let recovered = recovery::recover(&dst, &marker, recovery, |src, dst| {
+            let (_, gitignore) = is_git_dir(src)?;
+            move_dir(src, dst, &with_excludes(gitignore, src, opts)?, opts).map(drop)
+        });
+        match recovered {
+            Ok(()) => info!("{:?} recovered: {:?}", dst, recovery),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {
+                warn!("Stopped recovering {:?}, {}: {}", dst, stop_reason(opts), e)
+            }
+            Err(e) => error!("Could not {:?} {:?}: {}", recovery, dst, e),
+        }
+    }
+    Ok(())
+}
+
+/// Records where the repositories of `summary` landed in the index of every
+/// `--shard-across` destination.
+fn record_shards(summary: &Summary, opts: &Options) -> io::Result<()> {
+    let roots = dest_roots(opts);
+    let mut index = ShardIndex::load_all(&roots)?;
+    for repo in &summary.repos {
+        if matches!(
+            repo.outcome,
+            Outcome::Moved
+                | Outcome::Copied
+                | Outcome::Deduplicated
+                | Outcome::Verified
+                | Outcome::Kept
+        ) {
+            index.record(Placement {
+                source: repo.source.clone(),
+                dest: repo.dest.clone(),
+            });
+        }
+    }
+    index.save_all(&roots)
+}
+
+/// Adds the run to the history, with the files it left at every destination,
+/// for `verify-op` to check later.
+fn record_history(summary: &Summary, opts: &Options, started: SystemTime) -> io::Result<()> {
+    let repos = summary
+        .repos
+        .iter()
+        .filter(|r| {
+            !matches!(
+                r.outcome,
+                Outcome::Skipped
+                    | Outcome::Pinned
+                    | Outcome::Unchanged
+                    | Outcome::Conflicted(_)
+                    | Outcome::Failed(_)
+            )
+        })
+        .map(|r| history::RecordedRepo::record(&r.source, &r.dest))
+        .collect::<io::Result<Vec<_>>>()?;
+    if repos.is_empty() {
+        return Ok(());
+    }
+    let dir =
+        history::dir().ok_or_else(|| io::Error::new(ErrorKind::NotFound, "HOME is not set"))?;
+    let op = history::Operation {
+        started: started
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        mode: opts.mode().to_string(),
+        repos,
+    };
+    let id = op.save_new(&dir)?;
+    info!(
+        "Recorded as operation {}, check it later with verify-op {}",
+        id, id
+    );
+    Ok(())
+}
+
+/// Records in the inventory where the repositories of `summary` are now.
+fn update_inventory(summary: &Summary, opts: &Options) -> io::Result<()> {
+    let path =
+        inventory::path().ok_or_else(|| io::Error::new(ErrorKind::NotFound, "HOME is not set"))?;
+    let mut inventory = inventory::Inventory::load(&path)?;
+    for repo in &summary.repos {
+        let at = match repo.outcome {
+            Outcome::Skipped | Outcome::Conflicted(_) | Outcome::Failed(_) => continue,
+            Outcome::Pinned => &repo.source,
+            _ => &repo.dest,
+        };
+        if !is_git_dir(at)?.0 {
+            continue;
+        }
+        let mut inspected = inventory::InventoryRepo::inspect(at)?;
+        if opts.sync {
+            inspected.synced = Some(inspected.seen);
+        }
+        // Tags go with the repository.
+        let source = std::path::absolute(&repo.source)?;
+        if let Some(known) = inventory.find(&source) {
+            inspected.tags = known.tags.clone();
+        }
+        if !opts.copy && !opts.sync && !repo.source.exists() {
+            inventory.forget(&source);
+        }
+        inventory.record(inspected);
+    }
+    inventory.save(&path)
+}
+
+/// The repositories of `summary` that are no longer at their source, as
+/// absolute pairs of source and destination.
+fn moved_repos(summary: &Summary) -> Vec<(PathBuf, PathBuf)> {
+    summary
+        .repos
+        .iter()
+        .filter(|r| {
+            matches!(
+                r.outcome,
+                Outcome::Moved | Outcome::Deduplicated | Outcome::Verified
+            ) && !r.source.exists()
+        })
+        .filter_map(|r| {
+            Some((
+                std::path::absolute(&r.source).ok()?,
+                std::path::absolute(&r.dest).ok()?,
+            ))
+        })
+        .collect()
+}
+
+/// Points the files of `--rewrite-workspace` and `--from-mrconfig` and the
+/// `references` of the config, and with `--update-bookmarks` the databases of
+/// directory jumpers, at the new location of the repositories that moved.
+fn rewrite_references(summary: &Summary, opts: &Options) {
+    if opts.rewrite_workspaces.is_empty()
+        && opts.mrconfigs.is_empty()
+        && opts.references.is_empty()
+        && !opts.update_bookmarks
+    {
+        return;
+    }
+    let moves = moved_repos(summary);
+    if moves.is_empty() {
+        return;
+    }
+    for file in &opts.rewrite_workspaces {
+        match workspace::rewrite(file, &moves) {
+            Ok(0) => {}
+            Ok(n) => info!("Rewrote {} paths in {:?}", n, file),
+            Err(e) => warn!("Could not rewrite {:?}: {}", file, e),
+        }
+    }
+    for file in &opts.mrconfigs {
+        match mrconfig::rewrite(file, &moves) {
+            Ok(0) => {}
+            Ok(n) => info!(
+                "Pointed {} repositories of {:?} at where they moved",
+                n, file
+            ),
+            Err(e) => warn!("Could not rewrite {:?}: {}", file, e),
+        }
+    }
+    for reference in &opts.references {
+        let file = &reference.file;
+        match workspace::rewrite_reference(file, &moves, reference.relative_to.as_deref()) {
+            Ok(0) => {}
+            Ok(n) => info!("Rewrote {} paths in {:?}", n, file),
+            // Listed for every machine, but not every one has it.
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Could not rewrite {:?}: {}", file, e),
+        }
+    }
+    if opts.update_bookmarks {
+        for (jumper, updated) in bookmarks::update(&moves) {
+            match updated {
+                Ok(0) => {}
+                Ok(n) => info!(
+                    "Pointed {} {} entries at the moved repositories",
+                    n,
+                    jumper.name()
+                ),
+                Err(e) => warn!("Could not update the {} database: {}", jumper.name(), e),
+            }
+        }
+    }
+}
+
+/// Deletes the sources `--confirm-delete` kept once confirmed on the terminal,
+/// or else keeps them for `finalize`.
+fn confirm_deletes(summary: &Summary, opts: &Options) -> io::Result<()> {
+    let mut repos: Vec<PendingRepo> = summary
+        .repos
+        .iter()
+        .filter(|r| r.outcome == Outcome::Verified)
+        .map(|r| {
+            // Absolute, for finalize to find them from anywhere.
+            Ok(PendingRepo {
+                source: std::path::absolute(&r.source)?,
+                dest: std::path::absolute(&r.dest)?,
+            })
+        })
+        .collect::<io::Result<_>>()?;
+    if repos.is_empty() {
+        return Ok(());
+    }
+    // Quiet runs keep them without asking, like runs without a terminal.
+    if opts.verbosity >= 0 && pending::ask(&repos)? {
+        repos = finalize(&repos, opts);
+        if repos.is_empty() {
+            return Ok(());
+        }
+    }
+    let dir = pending::dir().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            "HOME is not set, there is nowhere to keep the deletions to confirm",
+        )
+    })?;
+    let pending = PendingDelete {
+        args: opts.args.clone(),
+        repos,
+    };
+    let id = pending.save_new(&dir)?;
+    if opts.verbosity >= 0 {
+        println!(
+            "Kept the sources of {} repositories, delete them with: mv-git finalize {}",
+            pending.repos.len(),
+            id
+        );
+    }
+    Ok(())
+}
+
+/// Deletes the sources of `repos`, once their copies are checked again.
+/// Returns those whose source is still there.
+fn finalize(repos: &[PendingRepo], opts: &Options) -> Vec<PendingRepo> {
+    let mut left = Vec::new();
+    for repo in repos {
+        let (src, dst) = (repo.source.as_path(), repo.dest.as_path());
+        if !src.exists() {
+            info!("{:?} is already gone", src);
+            continue;
+        }
+        let deleted = is_git_dir(src)
+            .and_then(|(_, gitignore)| with_excludes(gitignore, src, opts))
+            .and_then(|gitignore| {
+                let scan = scan_source(src, &gitignore, opts)?;
+                verify_copy(src, dst, &scan, opts)?;
+                delete_source(src, dst, &scan, &gitignore, opts)
+            });
+        match deleted {
+            Ok(true) => info!("Deleted {:?}, moved to {:?}", src, dst),
+            Ok(false) => left.push(repo.clone()),
+            Err(e) => {
+                error!("Not deleting {:?}: {}", src, e);
+                left.push(repo.clone());
+            }
+        }
+    }
+    left
+}
+
+/// Runs `finalize` with the arguments after the subcommand, and the names
+/// excluded by the config.
+fn finalize_command(args: &[String], exclude: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let id = pending::parse_args(args)?;
+    let dir = pending::dir().ok_or("HOME is not set, there are no deletions to confirm")?;
+    let mut pending = PendingDelete::load(&dir, &id)?;
+    let run_args = iter::once(String::new())
+        .chain(pending.args.iter().cloned())
+        .collect();
+    let mut opts = parse_args(run_args)?;
+    opts.confirm_delete = false;
+    opts.exclude = exclude;
+    let color = opts.color.enabled(io::stderr().is_terminal());
+    logging::init(opts.verbosity, color, opts.log_file.as_deref())?;
+    pending.repos = finalize(&pending.repos, &opts);
+    pending.save(&dir, &id)?;
+    if !pending.repos.is_empty() {
+        return Err(format!(
+            "The sources of {} repositories were not deleted, run finalize {} again once fixed",
+            pending.repos.len(),
+            id
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Completes `opts` with the config, and with what `--from-query`, `--tag`,
+/// `--min-interval` and `--strategy auto` need loaded.
+fn configure(opts: &mut Options, config: &Config) -> Result<(), String> {
+    opts.exclude = config.exclude.clone();
+    opts.pinned = config.pinned.clone();
+    opts.references = config.references.clone();
+    if opts.from_query.is_some() || !opts.tags.is_empty() || opts.min_interval.is_some() {
+        match inventory::path().map(|path| inventory::Inventory::load(&path)) {
+            Some(Ok(inventory)) => opts.inventory = inventory,
+            Some(Err(e)) => return Err(format!("Could not read the inventory: {}", e)),
+            None => {
+                return Err(
+                    "HOME is not set, there is no inventory to pick repositories from".to_string(),
+                )
+            }
+        }
+    }
+    if opts.strategy == StrategyChoice::Auto {
+        match bench::results_path()
+            .map(|path| BenchResults::load(&path))
+            .transpose()
+        {
+            Ok(results) => opts.bench = results.unwrap_or_default(),
+            Err(e) => eprintln!("Could not read the benchmark results: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// The destination of `opts` as reported: the file of `--map`, the roots of
+/// `--shard-across` joined with commas, or else the one destination.
+fn dest_label(opts: &Options) -> String {
+    if let Some((file, _)) = &opts.map {
+        format!("map {}", file.display())
+    } else if opts.shard_across.is_empty() {
+        opts.dest.clone()
+    } else {
+        opts.shard_across.join(",")
+    }
+}
+
+/// Runs `serve` with the arguments after the subcommand, until stdin ends.
+fn serve_command(args: &[String], config: &Config) -> Result<(), Box<dyn Error>> {
+    let serve_opts = serve::parse_args(args)?;
+    let output = serve::Output::new(io::stdout());
+    let progress = output.clone();
+    // Stdout carries the messages, so the log goes there as `log` notifications,
+    // down to the debug events that tell each repository's progress.
+    logging::init_forward(1, move |level, message| {
+        let params = serde_json::json!({ "level": level.as_str(), "message": message });
+        let _ = progress.notify("log", params);
+    })?;
+    serve::serve(io::stdin().lock(), &output, |method, params| {
+        serve_request(method, params, config, &serve_opts, &output)
+    })?;
+    Ok(())
+}
+
+/// Runs `daemon` with the arguments after the subcommand.
+#[cfg(unix)]
+fn daemon_command(args: &[String], config: Config) -> Result<(), Box<dyn Error>> {
+    let (socket, rest) = daemon::parse_socket(args)?;
+    if !rest.is_empty() {
+        return Err(daemon::DAEMON_USAGE.into());
+    }
+    logging::init(0, io::stderr().is_terminal(), None)?;
+    daemon::run(&socket, move |args, cancel| run_job(args, cancel, &config))?;
+    Ok(())
+}
+
+/// Runs a job queued with `ctl move`, returning its summary.
+#[cfg(unix)]
+fn run_job(
+    args: &[String],
+    cancel: Arc<std::sync::atomic::AtomicBool>,
+    config: &Config,
+) -> Result<String, String> {
+    let mut opts = parse_args(
+        iter::once(String::new())
+            .chain(args.iter().cloned())
+            .collect(),
+    )
+    .map_err(|e| e.to_string())?;
+    if opts.watch.is_some() || opts.confirm_delete {
+        return Err("--watch and --confirm-delete can't be used in a job".to_string());
+    }
+    configure(&mut opts, config)?;
+    opts.transfer.cancel = cancel;
+    let started = SystemTime::now();
+    let result = run(&opts, started);
+    post_webhook(&opts, &result, started);
+    Ok(result.map_err(|e| e.to_string())?.describe())
+}
+
+/// Answers a request of `serve`:
+/// - `discover`, with `sources`: the repositories under them, as the inventory
+///   records them.
+/// - `plan`, with `args`: the plan of a run with these arguments.
+/// - `run`, with `args`: the summary of the run, as `--report` writes it, with
+///   `progress` events telling how far the copy of each repository got meanwhile:
+///   its `repo`, `bytes_done`, `bytes_total` and `files_done`.
+///
+/// Whatever the request, the log is sent along as `log` events, with the
+/// `level` and `message` of each line.
+fn serve_request<W: io::Write + Send + 'static>(
+    method: &str,
+    params: &serde_json::Value,
+    config: &Config,
+    serve_opts: &serve::ServeOptions,
+    output: &serve::Output<W>,
+) -> Result<serde_json::Value, serve::RpcError> {
+    if method == "discover" {
+        let mut repos = Vec::new();
+        for source in serve::strings(params, "sources")? {
+            let dir = paths::expand(&source).map_err(serve::RpcError::invalid_params)?;
+            let mut found = Vec::new();
+            inventory::find_repos(Path::new(&dir), &mut found).map_err(serve::RpcError::failed)?;
+            for repo in found {
+                repos.push(
+                    inventory::InventoryRepo::inspect(&repo).map_err(serve::RpcError::failed)?,
+                );
+            }
+        }
+        return Ok(serde_json::json!(repos));
+    }
+    if method != "plan" && method != "run" {
+        return Err(serve::RpcError {
+            code: serve::METHOD_NOT_FOUND,
+            message: format!(
+                "Unknown method {:?}, expected discover, plan or run",
+                method
+            ),
+        });
+    }
+    let args = serve::strings(params, "args")?;
+    let mut opts = parse_args(iter::once(String::new()).chain(args.clone()).collect())
+        .map_err(serve::RpcError::invalid_params)?;
+    if opts.watch.is_some() || opts.confirm_delete {
+        return Err(serve::RpcError::invalid_params(
+            "--watch and --confirm-delete can't be used over serve",
+        ));
+    }
+    configure(&mut opts, config).map_err(serve::RpcError::failed)?;
+    // The summary is the result, not a table on stdout.
+    opts.verbosity = opts.verbosity.min(-1);
+    let sources = source_dirs(&opts).map_err(serve::RpcError::failed)?;
+    let sources: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+    if method == "plan" {
+        let repos = plan_sources(&sources, &opts).map_err(serve::RpcError::failed)?;
+        return serde_json::to_value(Plan { args, repos }).map_err(serve::RpcError::failed);
+    }
+    let started = SystemTime::now();
+    let stats = Arc::clone(&opts.transfer.stats);
+    let progress = output.clone();
+    let ticker = serve::Ticker::start(serve_opts.progress_interval, move || {
+        if let Some(params) = stats.progress().and_then(|p| serde_json::to_value(p).ok()) {
+            let _ = progress.notify("progress", params);
+        }
+    });
+    let summary = run(&opts, started);
+    drop(ticker);
+    post_webhook(&opts, &summary, started);
+    let summary = summary.map_err(serve::RpcError::failed)?;
+    Ok(summary.to_json(&opts.sources, &dest_label(&opts), opts.mode(), started))
+}
+
+/// Runs the transfer described by `opts`, then everything that follows it.
+fn run(opts: &Options, started: SystemTime) -> io::Result<Summary> {
+    if opts.mkdir == Mkdir::Never {
+        for root in dest_roots(opts) {
+            mkdir::ensure_dir(&root, opts.mkdir, None)?;
+        }
+    }
+    opts.transfer.budget.start(&opts.transfer.stats);
+    recover_interrupted(opts)?;
+    let sources = source_dirs(opts)?;
+    let sources: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+    let summary = move_recursive(&sources, opts)?;
+    if opts.verbosity >= 0 {
+        print!(
+            "{}",
+            summary.table(opts.color.enabled(io::stdout().is_terminal()))
+        );
+    }
+    let stopped_by = match signals::received() {
+        Some(signal) => Some(signals::name(signal)),
+        None => opts
+            .transfer
+            .budget
+            .spent(&opts.transfer.stats)
+            .map(|budget| format!("the {} budget", budget)),
+    };
+    if let Some(stopped_by) = stopped_by {
+        let left: Vec<&Path> = summary
+            .repos
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Skipped | Outcome::Failed(_)))
+            .map(|r| r.source.as_path())
+            .collect();
+        warn!(
+            "Stopped by {}: {} repositories were transferred and {} were not{}. Run the same \
+             command again to go on, the one left half-copied is picked up by --recover",
+            stopped_by,
+            summary.transferred().len(),
+            left.len(),
+            if left.is_empty() {
+                String::new()
+            } else {
+                format!(": {:?}", left)
+            }
+        );
+    }
+    let conflicted = summary.conflicted();
+    if !conflicted.is_empty() {
+        warn!(
+            "{} repositories changed both here and at the destination since the last sync, \
+             and were left alone: {:?}. Bring the changes made at the destination over, then \
+             sync them with --overwrite-conflicts",
+            conflicted.len(),
+            conflicted
+        );
+    }
+    let moved = summary.transferred();
+    if !opts.shard_across.is_empty() {
+        record_shards(&summary, opts)?;
+    }
+    let dest = dest_label(opts);
+    if let Some(report) = &opts.report {
+        summary.write_report(report, &opts.sources, &dest, opts.mode(), started)?;
+    }
+    if opts.restorecon {
+        for repo in &moved {
+            if let Err(e) = metadata::restorecon(repo) {
+                warn!("Could not relabel {:?}: {}", repo, e);
+            }
+        }
+    }
+
+    if opts.confirm_delete {
+        confirm_deletes(&summary, opts)?;
+    }
+    if let Err(e) = record_history(&summary, opts, started) {
+        warn!("Could not record the operation in the history: {}", e);
+    }
+    if let Err(e) = update_inventory(&summary, opts) {
+        warn!("Could not update the inventory: {}", e);
+    }
+    rewrite_references(&summary, opts);
+
+    if opts.dedupe_objects {
+        for root in dest_roots(opts) {
+            let repos: Vec<PathBuf> = moved
+                .iter()
+                .filter(|repo| repo.starts_with(&root))
+                .cloned()
+                .collect();
+            match dedupe::dedupe_objects(&root, &repos) {
+                Ok(report) => info!("{}", report),
+                Err(e) => warn!("Could not deduplicate the objects under {:?}: {}", root, e),
+            }
+        }
+    }
+    Ok(summary)
+}
+
+fn main() -> io::Result<()> {
+    let started = SystemTime::now();
+    let config = match config::path().map(|path| Config::load(&path)).transpose() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Could not read the config: {}", e);
+            process::exit(2);
+        }
+    };
+    let args = match config.expand_alias(env::args().collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    if args.get(1).map(String::as_str) == Some("install-service") {
+        let installed = service::parse_args(&args[2..]).and_then(|opts| service::install(&opts));
+        if let Err(e) = installed {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("completions") {
+        match args
+            .get(2)
+            .ok_or(completions::USAGE.to_string())
+            .and_then(|shell| completions::script(shell))
+        {
+            Ok(script) => print!("{}", script),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("diff") {
+        match diff::run(&args[2..]) {
+            Ok(true) => return Ok(()),
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        }
+    }
+    if args.get(1).map(String::as_str) == Some("repair") {
+        if let Err(e) = repair_command(&args[2..]) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("list") {
+        if let Err(e) = inventory::run_list(&args[2..]) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("export") {
+        if let Err(e) = inventory::run_export(&args[2..]) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("tag") {
+        if let Err(e) = inventory::run_tag(&args[2..]) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("query") {
+        if let Err(e) = query::run(&args[2..]) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("history") {
+        if let Err(e) = inventory::run_history(&args[2..]) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("verify-op") {
+        match history::run(&args[2..]) {
+            Ok(true) => return Ok(()),
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        }
+    }
+    if matches!(args.get(1).map(String::as_str), Some("daemon" | "ctl")) {
+        #[cfg(unix)]
+        let result = if args[1] == "daemon" {
+            daemon_command(&args[2..], config)
+        } else {
+            daemon::ctl(&args[2..])
+        };
+        #[cfg(not(unix))]
+        let result: Result<(), Box<dyn Error>> =
+            Err("daemon and ctl need Unix sockets, use serve --stdio instead".into());
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("serve") {
+        if let Err(e) = serve_command(&args[2..], &config) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("finalize") {
+        if let Err(e) = finalize_command(&args[2..], config.exclude) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("bench") {
+        if let Err(e) = bench::run(&args[2..]) {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("man") {
+        print!("{}", man::page());
+        return Ok(());
+    }
+    let mut plan_output = None;
+    let parsed = match args.get(1).map(String::as_str) {
+        Some("plan") => plan::parse_args(&args[2..]).and_then(|(output, run_args)| {
+            plan_output = Some((output, run_args.clone()));
+            parse_args(iter::once(args[0].clone()).chain(run_args).collect())
+        }),
+        Some("apply") => parse_apply_args(&args),
+        _ => parse_args(args),
+    };
+    let mut opts = match parsed {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = configure(&mut opts, &config) {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+    let color = opts.color.enabled(io::stderr().is_terminal());
+    if let Err(e) = logging::init(opts.verbosity, color, opts.log_file.as_deref()) {
+        eprintln!("Could not open log file {:?}: {}", opts.log_file, e);
+        process::exit(2);
+    }
+
+    if let Some(nice) = opts.nice {
+        if let Err(e) = priority::set_nice(nice) {
+            warn!("Could not set nice level {}: {}", nice, e);
+        }
+    }
+    if let Some(class) = opts.ionice {
+        if let Err(e) = priority::set_ionice(class) {
+            warn!("Could not set I/O priority {:?}: {}", class, e);
+        }
+    }
+
+    if let Some((output, args)) = plan_output {
+        let plan = Plan {
+            args,
+            repos: plan_sources(
+                &source_dirs(&opts)?
+                    .iter()
+                    .map(PathBuf::as_path)
+                    .collect::<Vec<_>>(),
+                &opts,
+            )?,
+        };
+        if opts.verbosity >= 0 {
+            for repo in &plan.repos {
+                println!(
+                    "{:<11}  {}  {}",
+                    repo.action,
+                    repo.source.display(),
+                    repo.dest.display()
+                );
+                for dir in &repo.empty_dirs {
+                    println!("{:<11}  {}", "prune", repo.dest.join(dir).display());
+                }
+            }
+        }
+        return plan.save(&output);
+    }
+
+    signals::install();
+    let Some(interval) = opts.watch else {
+        let result = run(&opts, started);
+        notify(&opts, &result);
+        post_webhook(&opts, &result, started);
+        let failed = result?.failed();
+        if let Some(signal) = signals::received() {
+            process::exit(128 + signal);
+        }
+        if failed > 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    };
+
+    let metrics = Arc::new(Metrics::default());
+    if let Some(addr) = &opts.metrics {
+        if let Err(e) = metrics::serve(addr, Arc::clone(&metrics)) {
+            eprintln!("Could not serve metrics on {}: {}", addr, e);
+            process::exit(2);
+        }
+    }
+    loop {
+        if opts.min_interval.is_some() {
+            // Runs record their syncs, which the next one needs to know of.
+            if let Err(e) = configure(&mut opts, &config) {
+                error!("{}", e);
+            }
+        }
+        let started = SystemTime::now();
+        let result = run(&opts, started);
+        metrics.record(result.as_ref());
+        if let Err(e) = &result {
+            error!("Run failed: {}", e);
+        }
+        notify(&opts, &result);
+        post_webhook(&opts, &result, started);
+        let next = Instant::now() + interval;
+        while signals::received().is_none() && Instant::now() < next {
+            thread::sleep(Duration::from_secs(1).min(next - Instant::now()));
+        }
+        if let Some(signal) = signals::received() {
+            process::exit(128 + signal);
+        }
+    }
+}
+
+/// Shows the desktop notification asked for with `--notify`.
+fn notify(opts: &Options, result: &io::Result<Summary>) {
+    if !opts.notify {
+        return;
+    }
+    let (title, body) = match result {
+        Ok(summary) if summary.failed() > 0 => ("mv-git finished with errors", summary.describe()),
+        Ok(summary) => ("mv-git finished", summary.describe()),
+        Err(e) => ("mv-git failed", e.to_string()),
+    };
+    if let Err(e) = notify::desktop(title, &body) {
+        warn!("Could not send a desktop notification: {}", e);
+    }
+}
+
+/// POSTs how the run went to the URL of `--webhook`: its summary, or the error
+/// that stopped it.
+fn post_webhook(opts: &Options, result: &io::Result<Summary>, started: SystemTime) {
+    let Some(url) = &opts.webhook else {
+        return;
+    };
+    let dest = dest_label(opts);
+    let payload = match result {
+        Ok(summary) => summary.to_json(&opts.sources, &dest, opts.mode(), started),
+        Err(e) => Summary::failure_json(&opts.sources, &dest, opts.mode(), started, e),
+    };
+    if let Err(e) = webhook::post(url, &payload) {
+        warn!("Could not post the summary to {}: {}", url, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_gitignore() -> io::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join(".gitignore");
+        {
+            let mut file = File::create(&file_path)?;
+            writeln!(file, "target/")?;
+            writeln!(file, "node_modules/")?;
+        }
+
+        let gitignore = read_gitignore(&file_path)?;
+        assert_eq!(gitignore, vec!["target", "node_modules"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_dir_recursive_reflogs() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src");
+        git::init_repo_with_commit(&src, "README.md")?;
+        assert!(src.join(".git/logs/HEAD").is_file());
+
+        copy_dir_recursive(&src, &dir.path().join("kept"), &None, &Options::default())?;
+        assert_eq!(
+            fs::read(dir.path().join("kept/.git/logs/HEAD"))?,
+            fs::read(src.join(".git/logs/HEAD"))?
+        );
+
+        let mut opts = Options::default();
+        opts.scan.strip_reflogs = true;
+        let stripped = dir.path().join("stripped");
+        copy_dir_recursive(&src, &stripped, &None, &opts)?;
+        assert!(!stripped.join(".git/logs").exists());
+        assert!(git::head_snapshot(&stripped).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_refs() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        git::init_repo_with_commit(&src, "README.md")?;
+        git::git(&src, ["tag", "v1"])?;
+        git::git(&src, ["pack-refs", "--all"])?;
+        copy_dir_recursive(&src, &dst, &None, &Options::default())?;
+        verify_refs(&src, &dst)?;
+
+        fs::remove_file(dst.join(".git/packed-refs"))?;
+        let err = verify_refs(&src, &dst).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("refs/tags/v1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_dir_recursive() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+
+        let gitignore = Some(vec!["ignored".to_string()]);
+
+        // Create some files and directories in the source directory
+        File::create(src_dir.path().join("file1.txt"))?;
+        fs::create_dir(src_dir.path().join("ignored"))?;
+        File::create(src_dir.path().join("ignored").join("file2.txt"))?;
+
+        copy_dir_recursive(
+            src_dir.path(),
+            dst_dir.path(),
+            &gitignore,
+            &Options::default(),
+        )?;
+
+        // Check that file1.txt exists in the destination
+        assert!(dst_dir.path().join("file1.txt").exists());
+        // Check that the ignored directory does not exist in the destination
+        assert!(!dst_dir.path().join("ignored").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_dir() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?.path().join("moved");
+
+        let gitignore = Some(vec!["ignored".to_string()]);
+
+        // Create some files and directories in the source directory
+        File::create(src_dir.path().join("file1.txt"))?;
+        fs::create_dir(src_dir.path().join("ignored"))?;
+        File::create(src_dir.path().join("ignored").join("file2.txt"))?;
+
+        move_dir(src_dir.path(), &dst_dir, &gitignore, &Options::default())?;
+
+        // Check that only what was moved is removed from the source
+        assert!(!src_dir.path().join("file1.txt").exists());
+        assert!(src_dir.path().join("ignored").join("file2.txt").exists());
+        // Check that file1.txt exists in the destination
+        assert!(dst_dir.join("file1.txt").exists());
+        // Check that the ignored directory does not exist in the destination
+        assert!(!dst_dir.join("ignored").exists());
+
+        let options = Options {
+            purge_ignored: true,
+            ..Options::default()
+        };
+        move_dir(src_dir.path(), &dst_dir, &gitignore, &options)?;
+        // Check that the ignored directory is purged with the rest
+        assert!(!src_dir.path().exists());
+        assert!(!dst_dir.join("ignored").exists());
+
+        fs::create_dir(src_dir.path())?;
+        File::create(src_dir.path().join("file1.txt"))?;
+        File::create(src_dir.path().join("kept.bin"))?;
+        let options = Options {
+            force_delete: true,
+            ..Options::default()
+        };
+        let gitignore = Some(vec!["kept.bin".to_string()]);
+        move_dir(src_dir.path(), &dst_dir, &gitignore, &options)?;
+        // Check that the source directory is removed
+        assert!(!src_dir.path().exists());
+
+        Ok(())
+    }
 
-        // Create some files and directories in the source directory
+    #[test]
+    fn test_delete_source_keeps_mount_points() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        fs::create_dir(src_dir.path().join("data"))?;
         File::create(src_dir.path().join("file1.txt"))?;
-        fs::create_dir(src_dir.path().join("ignored"))?;
-        File::create(src_dir.path().join("ignored").join("file2.txt"))?;
+        let mut scan = Scan::from(vec![scan::Entry {
+            rel: PathBuf::from("file1.txt"),
+            kind: EntryKind::File,
+            stamp: FileStamp::from_metadata(&fs::metadata(src_dir.path().join("file1.txt"))?),
+        }]);
+        scan.mount_points.push(PathBuf::from("data"));
 
-        copy_dir_recursive(src_dir.path(), dst_dir.path(), &gitignore)?;
+        let mut opts = Options::default();
+        opts.scan.one_file_system = true;
+        assert!(!delete_source(
+            src_dir.path(),
+            dst_dir.path(),
+            &scan,
+            &None,
+            &opts
+        )?);
+        assert!(src_dir.path().join("file1.txt").exists());
 
-        // Check that file1.txt exists in the destination
-        assert!(dst_dir.path().join("file1.txt").exists());
-        // Check that the ignored directory does not exist in the destination
-        assert!(!dst_dir.path().join("ignored").exists());
+        opts.scan.one_file_system = false;
+        assert!(delete_source(
+            src_dir.path(),
+            dst_dir.path(),
+            &scan,
+            &None,
+            &opts
+        )?);
+        assert!(!src_dir.path().join("file1.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = [
+            "mv-git",
+            "src",
+            "--buffer-size",
+            "1M",
+            "dst",
+            "-c",
+            "--dedupe-objects",
+            "--bwlimit",
+            "50MB/s",
+            "--ionice",
+            "idle",
+            "--hidden",
+            "skip",
+            "-vv",
+            "-q",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let opts = parse_args(args).unwrap();
+        assert_eq!(opts.sources, ["src"]);
+        assert_eq!(opts.dest, "dst");
+        assert!(opts.copy);
+        assert!(opts.dedupe_objects);
+        assert_eq!(opts.transfer.buffer_size, Some(1024 * 1024));
+        assert!(opts.transfer.bwlimit.is_some());
+        assert_eq!(opts.ionice, Some(IoClass::Idle));
+        assert_eq!(opts.scan.hidden, Hidden::Skip);
+        assert_eq!(opts.verbosity, 1);
+
+        let args = ["mv-git", "src", "dst", "--quiet", "--report", "report.json"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let opts = parse_args(args).unwrap();
+        assert_eq!(opts.verbosity, i8::MIN);
+        assert_eq!(opts.report, Some(PathBuf::from("report.json")));
+
+        let args = [
+            "mv-git",
+            "src",
+            "dst",
+            "--sync",
+            "--watch",
+            "1h",
+            "--metrics",
+            ":9184",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let opts = parse_args(args).unwrap();
+        assert_eq!(opts.watch, Some(Duration::from_secs(3600)));
+        assert_eq!(opts.metrics.as_deref(), Some(":9184"));
+
+        let args = ["mv-git", "src", "dst", "--metrics", ":9184"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_args(args).is_err());
+
+        let args = ["mv-git", "work", "-c", "personal", "dst"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let opts = parse_args(args).unwrap();
+        assert_eq!(opts.sources, ["work", "personal"]);
+        assert_eq!(opts.dest, "dst");
+
+        let args = vec!["mv-git".to_string(), "src".to_string()];
+        assert!(parse_args(args).is_err());
+
+        // Moving deletes the sources.
+        for (mode, valid) in [("--sync", true), ("--confirm-delete", false)] {
+            let args = ["mv-git", "--assume-read-only", mode, "src", "dst"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            assert_eq!(parse_args(args).is_ok(), valid, "{}", mode);
+        }
+        for (mode, valid) in [("--copy", true), ("--assume-read-only", false)] {
+            let args = ["mv-git", "--snapshot", "--sync", mode, "src", "dst"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            assert_eq!(parse_args(args).is_ok(), valid, "{}", mode);
+        }
+        let args = [
+            "mv-git",
+            "--only-extensions",
+            "rs",
+            "--skip-extensions",
+            "iso",
+            "src",
+            "dst",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert!(parse_args(args).is_err());
+        let args = ["mv-git", "--snapshot", "src", "dst"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(parse_args(args).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_knows_completed_flags() {
+        for flag in cli::FLAGS {
+            for name in flag.names {
+                let args = ["mv-git", "src", "dst", name]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect();
+                if let Err(e) = parse_args(args) {
+                    assert!(!e.to_string().starts_with("Unknown option"), "{}", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_move_recursive_deduplicates_identical_copy() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let src_repo = src_dir.path().join("repo");
+        let dst_repo = dst_dir.path().join("repo");
+        git::init_repo_with_commit(&src_repo, "README.md")?;
+        copy_dir_recursive(&src_repo, &dst_repo, &None, &Options::default())?;
+
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            ..Options::default()
+        };
+        let moved = move_recursive(&[src_dir.path()], &opts)?.transferred();
 
+        assert_eq!(moved, vec![dst_repo.clone()]);
+        assert!(!src_repo.exists());
+        assert!(dst_repo.join("README.md").exists());
         Ok(())
     }
 
     #[test]
-    fn test_move_dir() -> io::Result<()> {
+    fn test_dedupe_keeps_source_with_more_than_its_copy() -> io::Result<()> {
         let src_dir = tempdir()?;
-        let dst_dir = tempdir()?.path().join("moved");
+        let dst_dir = tempdir()?;
+        let src_repo = src_dir.path().join("repo");
+        git::init_repo_with_commit(&src_repo, "README.md")?;
+        copy_dir_recursive(
+            &src_repo,
+            &dst_dir.path().join("repo"),
+            &None,
+            &Options::default(),
+        )?;
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            ..Options::default()
+        };
+
+        // A branch only the source has.
+        git::git(&src_repo, ["branch", "topic"])?;
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+        assert!(matches!(summary.repos[0].outcome, Outcome::Failed(_)));
+        assert!(src_repo.join("README.md").exists());
+
+        // An ignored file only the source has.
+        git::git(&src_repo, ["branch", "-D", "topic"])?;
+        fs::write(
+            src_repo.join(".git").join("info").join("exclude"),
+            "*.log\n",
+        )?;
+        fs::write(src_repo.join("build.log"), "log")?;
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+        assert!(matches!(summary.repos[0].outcome, Outcome::Failed(_)));
+        assert!(src_repo.join("build.log").exists());
+
+        // Synced, an identical copy is only unchanged.
+        let opts = Options { sync: true, ..opts };
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+        assert_eq!(summary.repos[0].outcome, Outcome::Unchanged);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_plan_follows_edited_actions() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        git::init_repo_with_commit(&src_dir.path().join("a"), "README.md")?;
+        git::init_repo_with_commit(&src_dir.path().join("b"), "README.md")?;
+        fs::create_dir(src_dir.path().join("notes"))?;
+
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            ..Options::default()
+        };
+        let mut repos = plan_repos(src_dir.path(), None, &opts)?;
+        repos.sort_by(|a, b| a.source.cmp(&b.source));
+        let actions: Vec<Action> = repos.iter().map(|r| r.action).collect();
+        assert_eq!(actions, [Action::Transfer, Action::Transfer, Action::Skip]);
+        assert!(!dst_dir.path().join("a").exists());
+
+        repos[1].action = Action::Skip;
+        let summary = apply_plan(&repos, &opts)?;
+        assert_eq!(summary.transferred(), vec![dst_dir.path().join("a")]);
+        assert!(!src_dir.path().join("a").exists());
+        assert!(src_dir.path().join("b").exists());
+        assert!(!dst_dir.path().join("b").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_plan_aborts() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        git::init_repo_with_commit(&src_dir.path().join("a"), "README.md")?;
+        git::init_repo_with_commit(&src_dir.path().join("b"), "README.md")?;
+        // A file where the copy of `a` should go.
+        fs::write(dst_dir.path().join("a"), "")?;
+
+        let mut opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            ..Options::default()
+        };
+        let mut repos = plan_repos(src_dir.path(), None, &opts)?;
+        repos.sort_by(|a, b| a.source.cmp(&b.source));
+        let summary = apply_plan(&repos, &opts)?;
+        assert_eq!(summary.describe(), "1 failed, 1 moved");
+
+        git::init_repo_with_commit(&src_dir.path().join("c"), "README.md")?;
+        let mut repos = plan_repos(src_dir.path(), None, &opts)?;
+        repos.sort_by(|a, b| a.source.cmp(&b.source));
+        opts.transfer.on_error = ErrorPolicy::Abort;
+        let summary = apply_plan(&repos, &opts)?;
+        assert_eq!(summary.describe(), "1 failed, 1 skipped");
+        assert!(src_dir.path().join("c").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_recursive_includes_non_git() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        git::init_repo_with_commit(&src_dir.path().join("repo"), "README.md")?;
+        fs::create_dir(src_dir.path().join("notes"))?;
+        fs::write(src_dir.path().join("notes").join("todo.md"), "")?;
+        fs::write(src_dir.path().join("stray.txt"), "")?;
+
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            non_git: NonGit::Move,
+            ..Options::default()
+        };
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+
+        let mut moved = summary.transferred();
+        moved.sort();
+        assert_eq!(
+            moved,
+            vec![dst_dir.path().join("notes"), dst_dir.path().join("repo")]
+        );
+        assert!(dst_dir.path().join("notes").join("todo.md").exists());
+        assert!(!src_dir.path().join("notes").exists());
+        assert!(src_dir.path().join("stray.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_same_named_repos() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        for dir in ["a", "b"] {
+            git::init_repo_with_commit(&src_dir.path().join(dir).join("api"), "README.md")?;
+        }
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            sync: true,
+            layout: Layout::Preserve,
+            ..Options::default()
+        };
+        let outcomes = |opts: &Options| -> io::Result<Vec<Outcome>> {
+            let mut repos = move_recursive(&[src_dir.path()], opts)?.repos;
+            repos.sort_by(|a, b| a.dest.cmp(&b.dest));
+            Ok(repos.into_iter().map(|r| r.outcome).collect())
+        };
+        assert_eq!(outcomes(&opts)?, [Outcome::Synced, Outcome::Synced]);
+
+        for dir in ["a", "b"] {
+            fs::write(src_dir.path().join(dir).join("api/notes.txt"), dir)?;
+        }
+        assert_eq!(outcomes(&opts)?, [Outcome::Synced, Outcome::Synced]);
+        for dir in ["a", "b"] {
+            let copy = dst_dir.path().join(dir).join("api/notes.txt");
+            assert_eq!(fs::read_to_string(copy)?, dir);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_sources_collisions() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let sources = [src_dir.path().join("a"), src_dir.path().join("b")];
+        for source in &sources {
+            git::init_repo_with_commit(&source.join("api"), "README.md")?;
+        }
+        let sources: Vec<&Path> = sources.iter().map(PathBuf::as_path).collect();
+        let mut opts = Options {
+            dest: "dst".to_string(),
+            ..Options::default()
+        };
+        let err = plan_sources(&sources, &opts).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+        opts.on_collision = Collision::Suffix;
+        let mut dests: Vec<_> = plan_sources(&sources, &opts)?
+            .into_iter()
+            .map(|repo| repo.dest)
+            .collect();
+        dests.sort();
+        assert_eq!(
+            dests,
+            [PathBuf::from("dst/api-a"), PathBuf::from("dst/api-b")]
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_plan_repos_odd_names() -> io::Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let src_dir = tempdir()?;
+        git::init_repo_with_commit(&src_dir.path().join("docs/api"), "README.md")?;
+        std::os::unix::fs::symlink("nowhere", src_dir.path().join("docs/dangling"))?;
+        fs::create_dir(
+            src_dir
+                .path()
+                .join("docs")
+                .join(std::ffi::OsStr::from_bytes(b"caf\xe9")),
+        )?;
+        let opts = Options {
+            dest: "dst".to_string(),
+            layout: Layout::Preserve,
+            ..Options::default()
+        };
+        let repos = plan_repos(src_dir.path(), None, &opts)?;
+        let dests: Vec<_> = repos.iter().map(|repo| repo.dest.as_path()).collect();
+        assert!(dests.contains(&Path::new("dst/docs/api")), "{:?}", dests);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_repos_layout() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        git::init_repo_with_commit(&src_dir.path().join("clients/acme/api"), "README.md")?;
+        git::init_repo_with_commit(&src_dir.path().join("tools"), "README.md")?;
+        fs::create_dir_all(src_dir.path().join("docs/notes"))?;
+
+        let dests = |layout| -> io::Result<Vec<(PathBuf, Action)>> {
+            let opts = Options {
+                dest: "dst".to_string(),
+                layout,
+                ..Options::default()
+            };
+            let mut repos: Vec<_> = plan_repos(src_dir.path(), None, &opts)?
+                .into_iter()
+                .map(|repo| (repo.dest, repo.action))
+                .collect();
+            repos.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok(repos)
+        };
+        assert_eq!(
+            dests(Layout::Top)?,
+            vec![
+                (PathBuf::from("dst/clients"), Action::Skip),
+                (PathBuf::from("dst/docs"), Action::Skip),
+                (PathBuf::from("dst/tools"), Action::Transfer),
+            ]
+        );
+        assert_eq!(
+            dests(Layout::Flatten)?,
+            vec![
+                (PathBuf::from("dst/api"), Action::Transfer),
+                (PathBuf::from("dst/docs"), Action::Skip),
+                (PathBuf::from("dst/tools"), Action::Transfer),
+            ]
+        );
+        assert_eq!(
+            dests(Layout::Preserve)?,
+            vec![
+                (PathBuf::from("dst/clients/acme/api"), Action::Transfer),
+                (PathBuf::from("dst/docs"), Action::Skip),
+                (PathBuf::from("dst/tools"), Action::Transfer),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_recursive_shard_across() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        for name in ["api", "web"] {
+            git::init_repo_with_commit(&src_dir.path().join(name), "README.md")?;
+        }
+        let roots = [dst_dir.path().join("a"), dst_dir.path().join("b")];
+
+        let opts = Options {
+            shard_across: roots
+                .iter()
+                .map(|root| root.to_string_lossy().into_owned())
+                .collect(),
+            ..Options::default()
+        };
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+        record_shards(&summary, &opts)?;
+
+        let moved = summary.transferred();
+        assert_eq!(moved.len(), 2);
+        for repo in &moved {
+            assert!(roots
+                .iter()
+                .any(|root| repo.parent() == Some(root.as_path())));
+            assert!(repo.join("README.md").exists());
+        }
+        for root in &roots {
+            let index = ShardIndex::load(root)?;
+            let api = index.dest_of(&src_dir.path().join("api"));
+            assert!(api.is_some_and(|dest| moved
+                .iter()
+                .any(|repo| repo == dest && repo.ends_with("api"))));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_repos_min_interval() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        git::init_repo_with_commit(&src_dir.path().join("api"), "README.md")?;
+        git::init_repo_with_commit(&src_dir.path().join("web"), "README.md")?;
+        for name in ["api", "web"] {
+            fs::create_dir(dst_dir.path().join(name))?;
+        }
+
+        let mut opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            copy: true,
+            sync: true,
+            min_interval: Some(Duration::from_secs(86400)),
+            ..Options::default()
+        };
+        let mut synced = inventory::InventoryRepo::inspect(&dst_dir.path().join("api"))?;
+        synced.synced = Some(synced.seen - 3600);
+        opts.inventory.record(synced);
+        let mut stale = inventory::InventoryRepo::inspect(&dst_dir.path().join("web"))?;
+        stale.synced = Some(stale.seen - 2 * 86400);
+        opts.inventory.record(stale);
+
+        let mut repos = plan_repos(src_dir.path(), None, &opts)?;
+        repos.sort_by(|a, b| a.dest.cmp(&b.dest));
+        assert_eq!(repos[0].action, Action::Skip);
+        assert_eq!(repos[0].reason, "synced 1h ago, within --min-interval");
+        assert_eq!(repos[1].action, Action::Transfer);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_repos_reads_repo_config() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let pinned = src_dir.path().join("pinned");
+        let api = src_dir.path().join("api");
+        git::init_repo_with_commit(&pinned, "README.md")?;
+        git::init_repo_with_commit(&api, "README.md")?;
+        fs::write(pinned.join(config::REPO_CONFIG_FILE), "never_move = true\n")?;
+        fs::write(
+            api.join(config::REPO_CONFIG_FILE),
+            "dest = \"work/api\"\nexclude = [\"data\"]\n",
+        )?;
+        fs::create_dir(api.join("data"))?;
+        fs::write(api.join("data").join("dump.sql"), "")?;
+
+        let notes = src_dir.path().join("notes");
+        git::init_repo_with_commit(&notes, "README.md")?;
+
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            pinned: Pinned {
+                paths: vec![notes.clone()],
+                ..Pinned::default()
+            },
+            ..Options::default()
+        };
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+
+        assert_eq!(summary.transferred(), vec![dst_dir.path().join("work/api")]);
+        assert!(summary.describe().contains("2 pinned"));
+        assert!(pinned.exists());
+        assert!(notes.exists());
+        assert!(dst_dir.path().join("work/api/README.md").exists());
+        assert!(!dst_dir.path().join("work/api/data").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_recursive_to_ghq() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let app = src_dir.path().join("app");
+        git::init_repo_with_commit(&app, "README.md")?;
+        git::git(
+            &app,
+            ["remote", "add", "origin", "git@github.com:me/app.git"],
+        )?;
+        git::init_repo_with_commit(&src_dir.path().join("scratch"), "README.md")?;
 
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            to_ghq: true,
+            ..Options::default()
+        };
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+
+        assert_eq!(
+            summary.transferred(),
+            vec![dst_dir.path().join("github.com/me/app")]
+        );
+        assert!(dst_dir.path().join("github.com/me/app/README.md").exists());
+        assert!(src_dir.path().join("scratch").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_recursive_cancelled() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        git::init_repo_with_commit(&src_dir.path().join("api"), "README.md")?;
+
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            ..Options::default()
+        };
+        opts.transfer
+            .cancel
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+        assert_eq!(summary.repos[0].outcome, Outcome::Skipped);
+        assert!(src_dir.path().join("api/README.md").exists());
+
+        let scan = scan_source(&src_dir.path().join("api"), &None, &opts)?;
+        let err = copy_scanned(
+            &src_dir.path().join("api"),
+            &dst_dir.path().join("api"),
+            &scan,
+            &opts,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_recursive_downgrades_for_target() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let api = src_dir.path().join("api");
+        git::init_repo_with_commit(&api, "README.md")?;
+        std::os::unix::fs::symlink("README.md", api.join("link"))?;
+        fs::write(api.join("run.sh"), "#!/bin/sh\n")?;
+        fs::set_permissions(api.join("run.sh"), fs::Permissions::from_mode(0o755))?;
+        git::commit_all(&api, "link")?;
+
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            copy: true,
+            target_fs: Some(TargetFs::Windows),
+            ..Options::default()
+        };
+        move_recursive(&[src_dir.path()], &opts)?;
+        let copy = dst_dir.path().join("api");
+        assert_eq!(fs::read_to_string(copy.join("link"))?, "README.md");
+        assert_eq!(
+            fs::metadata(copy.join("run.sh"))?.permissions().mode() & 0o111,
+            0
+        );
+        assert_eq!(git::git(&copy, ["config", "core.symlinks"])?, "false");
+        assert!(git::is_clean(&copy));
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_recursive_git_excludes() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let api = src_dir.path().join("api");
+        git::init_repo_with_commit(&api, "README.md")?;
+        fs::write(src_dir.path().join("excludes"), "notes.txt\n")?;
+        git::git(&api, ["config", "core.excludesFile", "../excludes"])?;
+        fs::write(api.join("notes.txt"), "mine")?;
+
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            copy: true,
+            ..Options::default()
+        };
+        move_recursive(&[src_dir.path()], &opts)?;
+        assert!(dst_dir.path().join("api/README.md").exists());
+        assert!(!dst_dir.path().join("api/notes.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_recursive_byte_budget() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        git::init_repo_with_commit(&src_dir.path().join("api"), "README.md")?;
+        git::init_repo_with_commit(&src_dir.path().join("web"), "README.md")?;
+
+        let mut opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            copy: true,
+            ..Options::default()
+        };
+        opts.transfer.budget = Arc::new(Budget::new(None, Some(1)));
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+        assert_eq!(summary.repos.len(), 2);
+        assert!(summary.repos.iter().all(|r| r.outcome == Outcome::Skipped));
+        assert!(summary.repos[0].transferred.bytes > 0);
+        assert_eq!(stop_reason(&opts), "the byte budget was spent");
+        assert!(src_dir.path().join("api/README.md").exists());
+        assert!(src_dir.path().join("web/README.md").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_recursive_assume_read_only() -> io::Result<()> {
+        fn stamps(dir: &Path, found: &mut Vec<(PathBuf, scan::FileStamp)>) -> io::Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                let metadata = fs::symlink_metadata(&path)?;
+                if metadata.is_dir() {
+                    stamps(&path, found)?;
+                }
+                found.push((path, scan::FileStamp::from_metadata(&metadata)));
+            }
+            Ok(())
+        }
+
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let src = src_dir.path().join("api");
+        git::init_repo_with_commit(&src, "README.md")?;
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            copy: true,
+            assume_read_only: true,
+            ..Options::default()
+        };
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+        assert_eq!(summary.repos[0].outcome, Outcome::Copied);
+
+        // Written again, so that checking the copy with git status would refresh
+        // the index of the source.
+        thread::sleep(Duration::from_millis(10));
+        fs::write(src.join("README.md"), fs::read(src.join("README.md"))?)?;
+        let mut before = Vec::new();
+        stamps(src_dir.path(), &mut before)?;
+        let summary = move_recursive(&[src_dir.path()], &opts)?;
+        assert_eq!(summary.repos[0].outcome, Outcome::Deduplicated);
+        let mut after = Vec::new();
+        stamps(src_dir.path(), &mut after)?;
+        assert_eq!(before, after);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_dir_skips_unchanged_files() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let dst = dst_dir.path().join("repo");
+        fs::write(src_dir.path().join("a.txt"), "a")?;
+        fs::write(src_dir.path().join("b.txt"), "b")?;
+
+        let options = Options::default();
+        let mut index = Index::load(dst_dir.path())?;
+        assert_eq!(
+            sync_dir(src_dir.path(), &dst, &None, &mut index, "repo", &options)?,
+            Outcome::Synced
+        );
+        assert_eq!(fs::read_to_string(dst.join("a.txt"))?, "a");
+
+        // A file changed only at the destination is left alone: the source did not change.
+        fs::write(dst.join("a.txt"), "local")?;
+        assert_eq!(
+            sync_dir(src_dir.path(), &dst, &None, &mut index, "repo", &options)?,
+            Outcome::Unchanged
+        );
+        assert_eq!(fs::read_to_string(dst.join("a.txt"))?, "local");
+
+        // Changed on both sides: neither is overwritten, unless asked to.
+        fs::write(src_dir.path().join("b.txt"), "changed")?;
+        let outcome = sync_dir(src_dir.path(), &dst, &None, &mut index, "repo", &options)?;
+        assert_eq!(
+            outcome,
+            Outcome::Conflicted("changed at the destination too: a.txt".to_string())
+        );
+        assert_eq!(fs::read_to_string(dst.join("b.txt"))?, "b");
+
+        let overwrite = Options {
+            overwrite_conflicts: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            sync_dir(src_dir.path(), &dst, &None, &mut index, "repo", &overwrite)?,
+            Outcome::Synced
+        );
+        assert_eq!(fs::read_to_string(dst.join("b.txt"))?, "changed");
+        assert_eq!(fs::read_to_string(dst.join("a.txt"))?, "a");
+
+        fs::write(src_dir.path().join("b.txt"), "changed again")?;
+        assert_eq!(
+            sync_dir(src_dir.path(), &dst, &None, &mut index, "repo", &options)?,
+            Outcome::Synced
+        );
+        assert_eq!(fs::read_to_string(dst.join("b.txt"))?, "changed again");
+
+        // Only touched, a file is copied again unless its contents are compared.
+        let touch = |path: &Path, secs| {
+            File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+        };
+        let compare = Options {
+            compare_contents: true,
+            ..Options::default()
+        };
+        for (secs, opts, copied) in [(1, &compare, 0), (2, &options, 1)] {
+            touch(&src_dir.path().join("b.txt"), secs)?;
+            let before = opts.transfer.stats.totals();
+            assert_eq!(
+                sync_dir(src_dir.path(), &dst, &None, &mut index, "repo", opts)?,
+                Outcome::Synced
+            );
+            assert_eq!((opts.transfer.stats.totals() - before).files, copied);
+        }
+        // Same size, other contents.
+        fs::write(src_dir.path().join("b.txt"), "CHANGED AGAIN")?;
+        sync_dir(src_dir.path(), &dst, &None, &mut index, "repo", &compare)?;
+        assert_eq!(fs::read_to_string(dst.join("b.txt"))?, "CHANGED AGAIN");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_dir_recursive_skips_special_files() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let _socket = std::os::unix::net::UnixListener::bind(src_dir.path().join("socket"))?;
+        File::create(src_dir.path().join("file.txt"))?;
+
+        copy_dir_recursive(src_dir.path(), dst_dir.path(), &None, &Options::default())?;
+
+        assert!(dst_dir.path().join("file.txt").exists());
+        assert!(!dst_dir.path().join("socket").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_dir_leaves_symlink() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::create_dir(&src)?;
+        fs::write(src.join("file.txt"), "content")?;
+        std::os::unix::fs::symlink("file.txt", src.join("link.txt"))?;
+
+        let opts = Options {
+            leave_link: Some(LinkKind::Symlink),
+            ..Options::default()
+        };
+        move_dir(&src, &dst, &None, &opts)?;
+
+        assert!(fs::symlink_metadata(&src)?.file_type().is_symlink());
+        assert_eq!(
+            fs::read_link(dst.join("link.txt"))?,
+            PathBuf::from("file.txt")
+        );
+        assert_eq!(fs::read_to_string(src.join("link.txt"))?, "content");
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_dir_renames() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst").join("repo");
+        fs::create_dir(&src)?;
+        fs::write(src.join("file.txt"), "content")?;
+        fs::create_dir(src.join("ignored"))?;
+        fs::write(src.join("ignored").join("cache.bin"), "cache")?;
         let gitignore = Some(vec!["ignored".to_string()]);
+        let scan = scan_source(&src, &gitignore, &Options::default())?;
 
-        // Create some files and directories in the source directory
-        File::create(src_dir.path().join("file1.txt"))?;
-        fs::create_dir(src_dir.path().join("ignored"))?;
-        File::create(src_dir.path().join("ignored").join("file2.txt"))?;
+        // Ignored files would be left behind, so auto copies instead
+        assert!(!rename_repo(&src, &dst, &scan, &Options::default())?);
+        let copy = Options {
+            strategy: StrategyChoice::Copy(copy::Strategy::ReadWrite),
+            ..Options::default()
+        };
+        assert!(!rename_repo(&src, &dst, &scan, &copy)?);
 
-        move_dir(src_dir.path(), &dst_dir, &gitignore, false)?;
+        let rename = Options {
+            strategy: StrategyChoice::Rename,
+            ..Options::default()
+        };
+        move_dir(&src, &dst, &gitignore, &rename)?;
+        assert!(!src.exists());
+        assert!(dst.join("ignored").join("cache.bin").exists());
 
-        // Check that the source directory is removed
-        assert!(!src_dir.path().exists());
-        // Check that file1.txt exists in the destination
-        assert!(dst_dir.join("file1.txt").exists());
-        // Check that the ignored directory does not exist in the destination
-        assert!(!dst_dir.join("ignored").exists());
+        fs::remove_dir_all(dst.join("ignored"))?;
+        let scan = scan_source(&dst, &None, &Options::default())?;
+        assert!(rename_repo(&dst, &src, &scan, &Options::default())?);
+        assert_eq!(fs::read_to_string(src.join("file.txt"))?, "content");
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_dir_confirm_delete() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::create_dir(&src)?;
+        fs::write(src.join("file.txt"), "content")?;
+
+        let opts = Options {
+            confirm_delete: true,
+            ..Options::default()
+        };
+        move_dir(&src, &dst, &None, &opts)?;
+        assert!(src.join("file.txt").exists());
+        assert_eq!(fs::read_to_string(dst.join("file.txt"))?, "content");
+        assert!(recovery::find(dir.path())?.is_empty());
+
+        // A source changed since its copy is not deleted
+        let repos = [PendingRepo {
+            source: src.clone(),
+            dest: dst.clone(),
+        }];
+        fs::write(src.join("file.txt"), "changed")?;
+        assert_eq!(finalize(&repos, &Options::default()), repos);
+        assert!(src.exists());
+
+        fs::write(src.join("file.txt"), "content")?;
+        assert!(finalize(&repos, &Options::default()).is_empty());
+        assert!(!src.exists());
+        assert!(dst.join("file.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_copy_with_checksums() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::create_dir(&src)?;
+        fs::write(src.join("file.txt"), "content")?;
+        let opts = parse_args(
+            ["mv-git", "--confirm-delete", "src", "dst"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        )
+        .unwrap();
+        let checksums = opts.transfer.checksums.clone().unwrap();
+
+        let scan = copy_dir_recursive(&src, &dst, &None, &opts)?;
+        verify_copy(&src, &dst, &scan, &opts)?;
+        assert_eq!(checksums.take(&src.join("file.txt")), None);
+
+        let scan = copy_dir_recursive(&src, &dst, &None, &opts)?;
+        fs::write(dst.join("file.txt"), "c0ntent")?;
+        assert!(verify_copy(&src, &dst, &scan, &opts).is_err());
+
+        // Touched since the pre-scan, the source may no longer be what was copied.
+        let scan = copy_dir_recursive(&src, &dst, &None, &opts)?;
+        File::options()
+            .write(true)
+            .open(src.join("file.txt"))?
+            .set_modified(SystemTime::UNIX_EPOCH)?;
+        assert!(verify_copy(&src, &dst, &scan, &opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_dir_recursive_within_memory_limit() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        for i in 0..20 {
+            fs::create_dir(src_dir.path().join(format!("dir{}", i)))?;
+            fs::write(src_dir.path().join(format!("dir{}/file.txt", i)), "data")?;
+        }
+
+        let mut opts = Options::default();
+        opts.scan.memory_limit = Some(512);
+        let scan = copy_dir_recursive(src_dir.path(), dst_dir.path(), &None, &opts)?;
+
+        assert!(scan.is_spilled());
+        for i in 0..20 {
+            assert!(dst_dir.path().join(format!("dir{}/file.txt", i)).exists());
+        }
+        Ok(())
+    }
 
+    #[test]
+    fn test_copy_dir_recursive_also_dest() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        let mirror_dir = tempdir()?;
+        fs::create_dir(src_dir.path().join("src"))?;
+        fs::write(src_dir.path().join("src/main.rs"), "fn main() {}")?;
+
+        let opts = Options {
+            dest: dst_dir.path().to_string_lossy().into_owned(),
+            also_dest: vec![mirror_dir.path().join("nas").to_string_lossy().into_owned()],
+            ..Options::default()
+        };
+        copy_dir_recursive(src_dir.path(), &dst_dir.path().join("repo"), &None, &opts)?;
+
+        for root in [dst_dir.path(), &mirror_dir.path().join("nas")] {
+            assert_eq!(
+                fs::read_to_string(root.join("repo/src/main.rs"))?,
+                "fn main() {}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_dir_recursive_normalizes_names() -> io::Result<()> {
+        let src_dir = tempdir()?;
+        let dst_dir = tempdir()?;
+        File::create(src_dir.path().join("cafe\u{301}.md"))?;
+
+        let mut opts = Options::default();
+        opts.transfer.normalization = paths::Normalization::Nfc;
+        copy_dir_recursive(src_dir.path(), dst_dir.path(), &None, &opts)?;
+        assert!(dst_dir.path().join("caf\u{e9}.md").exists());
+
+        // Both forms can only coexist where names aren't normalized.
+        if File::create(src_dir.path().join("caf\u{e9}.md")).is_ok()
+            && fs::read_dir(src_dir.path())?.count() == 2
+        {
+            assert!(copy_dir_recursive(src_dir.path(), dst_dir.path(), &None, &opts).is_err());
+        }
         Ok(())
     }
 
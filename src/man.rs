@@ -0,0 +1,148 @@
+use std::fmt::Write as _;
+
+use crate::cli::{FLAGS, SUBCOMMANDS};
+use crate::{
+    bench, completions, diff, history, inventory, pending, plan, query, repair, serve, service,
+};
+
+/// `text` escaped for roff, where `\` starts an escape and `-` is a hyphen
+/// rather than the minus sign of options.
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with('.') || text.starts_with('\'') {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+/// The mv-git(1) man page, in roff.
+pub fn page() -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        ".TH MV\\-GIT 1 \"\" \"mv\\-git {}\" \"User Commands\"",
+        env!("CARGO_PKG_VERSION")
+    );
+    out.push_str(".SH NAME\nmv\\-git \\- move git repositories, leaving out what they ignore\n");
+    out.push_str(".SH SYNOPSIS\n");
+    let usages = [
+        crate::USAGE,
+        plan::USAGE,
+        plan::APPLY_USAGE,
+        diff::USAGE,
+        repair::USAGE,
+        service::USAGE,
+        bench::USAGE,
+        pending::FINALIZE_USAGE,
+        inventory::LIST_USAGE,
+        query::USAGE,
+        inventory::TAG_USAGE,
+        inventory::EXPORT_USAGE,
+        inventory::HISTORY_USAGE,
+        history::VERIFY_USAGE,
+        serve::USAGE,
+        #[cfg(unix)]
+        crate::daemon::DAEMON_USAGE,
+        #[cfg(unix)]
+        crate::daemon::CTL_USAGE,
+        completions::USAGE,
+        "Usage: man",
+    ];
+    for (i, usage) in usages.iter().enumerate() {
+        if i > 0 {
+            out.push_str(".br\n");
+        }
+        let usage = usage.strip_prefix("Usage: ").unwrap_or(usage);
+        let _ = writeln!(out, "\\fBmv\\-git\\fR {}", escape(usage));
+    }
+    out.push_str(
+        ".SH DESCRIPTION\n\
+         Moves every git repository found directly in \\fIsource\\fR to \\fIdestination\\fR, \
+         leaving out the files matched by their \\fB.gitignore\\fR. \
+         Directories that are not git repositories are left where they are.\n",
+    );
+    out.push_str(".SH OPTIONS\n");
+    for flag in FLAGS {
+        let names: Vec<String> = flag
+            .names
+            .iter()
+            .map(|name| format!("\\fB{}\\fR", escape(name)))
+            .collect();
+        let _ = write!(out, ".TP\n{}", names.join(", "));
+        if let Some(value) = flag.value {
+            let _ = write!(out, " \\fI{}\\fR", escape(value));
+        }
+        let _ = write!(out, "\n{}.", escape(flag.help));
+        if !flag.choices.is_empty() {
+            let _ = write!(out, " One of: {}.", escape(&flag.choices.join(", ")));
+        }
+        out.push('\n');
+    }
+    out.push_str(".SH COMMANDS\n");
+    for (name, help) in SUBCOMMANDS {
+        let _ = writeln!(out, ".TP\n\\fB{}\\fR\n{}.", escape(name), escape(help));
+    }
+    out.push_str(
+        ".SH FILES\n\
+         .TP\n\
+         \\fI$XDG_CONFIG_HOME/mv\\-git/config.toml\\fR\n\
+         Configuration, read from \\fI$MV_GIT_CONFIG\\fR instead when it is set. \
+         Its \\fB[alias]\\fR table names command lines, run as \\fBmv\\-git\\fR \\fIname\\fR \
+         followed by more arguments, its \\fBexclude\\fR list names what is left out of \
+         every repository, its \\fB[pinned]\\fR table the \\fBpaths\\fR and \
+         \\fBremotes\\fR of repositories that are never moved, and each of its \
+         \\fB[[references]]\\fR tables a \\fBfile\\fR of another tool naming \
+         repositories by path, absolute or relative to \\fBrelative_to\\fR, which \
+         is pointed at those that moved.\n\
+         .TP\n\
+         \\fI.mv\\-git.toml\\fR\n\
+         At the root of a repository: \\fBexclude\\fR lists more names to leave out, \
+         \\fBdest\\fR where it goes relative to the destination, and \
+         \\fBnever_move = true\\fR keeps it where it is.\n\
+         .TP\n\
+         \\fI$XDG_CACHE_HOME/mv\\-git/bench.json\\fR\n\
+         Results of \\fBbench\\fR, per pair of filesystems.\n\
+         .TP\n\
+         \\fI$XDG_STATE_HOME/mv\\-git/pending/\\fR\n\
+         Deletions kept by \\fB\\-\\-confirm\\-delete\\fR, until \\fBfinalize\\fR.\n\
+         .TP\n\
+         \\fI$XDG_STATE_HOME/mv\\-git/history/\\fR\n\
+         The files each run left at the destination, checked by \\fBverify\\-op\\fR.\n\
+         .TP\n\
+         \\fI$XDG_DATA_HOME/mv\\-git/inventory.json\\fR\n\
+         The repositories found by runs and \\fBlist \\-\\-scan\\fR, for \\fBlist\\fR.\n",
+    );
+    out.push_str(
+        ".SH SIGNALS\n\
+         .TP\n\
+         \\fBSIGINT\\fR, \\fBSIGTERM\\fR\n\
+         Stop after the file being copied, leaving what is left for the next run. \
+         A second one stops right away.\n\
+         .TP\n\
+         \\fBSIGUSR1\\fR\n\
+         Pause after the file being copied, until the next \\fBSIGUSR1\\fR.\n",
+    );
+    out.push_str(
+        ".SH EXIT STATUS\n\
+         0 when every repository was transferred, 1 when some failed, 2 on usage errors.\n",
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page() {
+        let page = page();
+        assert!(page.starts_with(".TH MV\\-GIT 1 "));
+        assert!(page.contains(".TP\n\\fB\\-c\\fR, \\fB\\-\\-copy\\fR\nCopy the repositories"));
+        assert!(page.contains(
+            ".TP\n\\fB\\-\\-color\\fR \\fIWHEN\\fR\nColor the output. One of: auto, always, never.\n"
+        ));
+        assert!(page.contains(".TP\n\\fBinstall\\-service\\fR\n"));
+        assert_eq!(escape(".hidden"), "\\&.hidden");
+    }
+}
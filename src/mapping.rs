@@ -0,0 +1,249 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use crate::paths;
+
+/// Header a `--map` file may start with.
+const HEADER: &str = "source_path,destination_path";
+
+/// Where `--map` says one repository goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    /// Line of the map file the mapping is on, for messages.
+    pub line: usize,
+    pub source: PathBuf,
+    pub dest: PathBuf,
+}
+
+/// Reads the `--map` file `path`: `source_path,destination_path` rows, with
+/// relative paths resolved against the directory of the file. Blank lines and
+/// lines starting with `#` are ignored.
+///
+/// The whole map is checked before anything moves: every source must be a git
+/// repository, and no two rows may share a source or a destination, or put a
+/// repository inside another one that is moved too.
+pub fn load(path: &Path) -> io::Result<Vec<Mapping>> {
+    let text = fs::read_to_string(path)?;
+    let base = std::path::absolute(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let mut mappings = Vec::new();
+    let mut problems = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line_number = number + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (number == 0 && line == HEADER) {
+            continue;
+        }
+        let fields = match split_row(line) {
+            Ok(fields) => fields,
+            Err(e) => {
+                problems.push(format!("line {}: {}", line_number, e));
+                continue;
+            }
+        };
+        let [source, dest] = fields.as_slice() else {
+            problems.push(format!("line {}: expected {}", line_number, HEADER));
+            continue;
+        };
+        let resolve = |field: &str| paths::expand(field).map(|field| normalize(&base.join(field)));
+        match (resolve(source), resolve(dest)) {
+            (Ok(source), Ok(dest)) => mappings.push(Mapping {
+                line: line_number,
+                // Symlinks are resolved, to tell when two rows name the same repository.
+                source: fs::canonicalize(&source).unwrap_or(source),
+                dest,
+            }),
+            (Err(e), _) | (_, Err(e)) => problems.push(format!("line {}: {}", line_number, e)),
+        }
+    }
+    problems.extend(check(&mappings));
+    if !problems.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid map {:?}: {}", path, problems.join("; ")),
+        ));
+    }
+    Ok(mappings)
+}
+
+/// What is wrong with `mappings`, as a whole.
+fn check(mappings: &[Mapping]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (i, a) in mappings.iter().enumerate() {
+        if !a.source.join(".git").exists() {
+            problems.push(format!(
+                "line {}: {:?} is not a git repository",
+                a.line, a.source
+            ));
+        }
+        for b in &mappings[..i] {
+            if a.source == b.source {
+                problems.push(format!(
+                    "lines {} and {} both move {:?}",
+                    b.line, a.line, a.source
+                ));
+            } else if let Some((inner, outer)) = nested(a, b, |m| &m.source) {
+                problems.push(format!(
+                    "line {}: {:?} is inside {:?} of line {}, which is moved too",
+                    inner.line, inner.source, outer.source, outer.line
+                ));
+            }
+            if a.dest == b.dest {
+                problems.push(format!(
+                    "lines {} and {} both go to {:?}",
+                    b.line, a.line, a.dest
+                ));
+            } else if let Some((inner, outer)) = nested(a, b, |m| &m.dest) {
+                problems.push(format!(
+                    "line {}: {:?} is inside {:?} of line {}",
+                    inner.line, inner.dest, outer.dest, outer.line
+                ));
+            }
+        }
+        for b in mappings {
+            if a.dest.starts_with(&b.source) {
+                problems.push(format!(
+                    "line {}: {:?} is inside {:?}, which line {} moves",
+                    a.line, a.dest, b.source, b.line
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// The one of `a` and `b` whose `path` is inside the other's, then the other.
+fn nested<'a>(
+    a: &'a Mapping,
+    b: &'a Mapping,
+    path: impl Fn(&Mapping) -> &PathBuf,
+) -> Option<(&'a Mapping, &'a Mapping)> {
+    if path(a).starts_with(path(b)) {
+        Some((a, b))
+    } else if path(b).starts_with(path(a)) {
+        Some((b, a))
+    } else {
+        None
+    }
+}
+
+/// The fields of the CSV row `line`, unquoting those in double quotes.
+fn split_row(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        while chars.next_if(|&c| c == ' ').is_some() {}
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err("unterminated double quote".to_string()),
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) {
+                return Err("expected a comma after a quoted field".to_string());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                field.push(c);
+            }
+        }
+        fields.push(field.trim().to_string());
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+/// `path` with `.` and `..` resolved without looking at the filesystem, where
+/// destinations don't exist yet.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_split_row() {
+        assert_eq!(
+            split_row(r#"a, "b, ""c""" "#.trim()),
+            Ok(vec!["a".to_string(), "b, \"c\"".to_string()])
+        );
+        assert!(split_row(r#""a"b,c"#).is_err());
+        assert!(split_row(r#""a,b"#).is_err());
+    }
+
+    #[test]
+    fn test_load() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().canonicalize()?;
+        for repo in ["api", "web", "web/plugin"] {
+            git::init_repo_with_commit(&root.join(repo), "README.md")?;
+        }
+        let map = root.join("map.csv");
+        fs::write(
+            &map,
+            format!(
+                "{}\n# moved to the archive\napi,archive/api\n\"{}\",/srv/web\n",
+                HEADER,
+                root.join("web").display()
+            ),
+        )?;
+        assert_eq!(
+            load(&map)?,
+            vec![
+                Mapping {
+                    line: 3,
+                    source: root.join("api"),
+                    dest: root.join("archive/api"),
+                },
+                Mapping {
+                    line: 4,
+                    source: root.join("web"),
+                    dest: PathBuf::from("/srv/web"),
+                },
+            ]
+        );
+
+        let invalid = [
+            ("missing,dest\n", "not a git repository"),
+            ("api\n", "expected source_path,destination_path"),
+            ("api,a\n./api,b\n", "lines 1 and 2 both move"),
+            ("api,a\nweb,a/\n", "lines 1 and 2 both go to"),
+            ("web,a\nweb/plugin,b\n", "is inside"),
+            ("api,a\nweb,a/web\n", "line 2"),
+            ("api,a\nweb,api/web\n", "which line 1 moves"),
+            ("api,api/sub\n", "which line 1 moves"),
+        ];
+        for (text, problem) in invalid {
+            fs::write(&map, text)?;
+            let e = load(&map).unwrap_err();
+            assert!(e.to_string().contains(problem), "{:?}: {}", text, e);
+        }
+        Ok(())
+    }
+}
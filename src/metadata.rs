@@ -0,0 +1,479 @@
+use std::io;
+use std::path::Path;
+
+/// Metadata carried over on top of content and permissions, from `--preserve`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Preserve {
+    /// Extended attributes (`user.*`, `security.*`, `trusted.*`), except those
+    /// selected by `acl` and `selinux`.
+    pub xattrs: bool,
+    /// POSIX ACLs, stored by Linux as `system.posix_acl_*` attributes.
+    pub acl: bool,
+    /// SELinux security context (`security.selinux`).
+    pub selinux: bool,
+}
+
+impl Preserve {
+    /// Parses a comma separated list such as `xattrs,acl`.
+    pub fn parse(value: &str) -> Result<Preserve, String> {
+        let mut preserve = Preserve::default();
+        for item in value.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            match item {
+                "xattrs" | "xattr" if cfg!(any(target_os = "linux", target_os = "macos")) => {
+                    preserve.xattrs = true
+                }
+                "acl" if cfg!(target_os = "linux") => preserve.acl = true,
+                "selinux" if cfg!(target_os = "linux") => preserve.selinux = true,
+                "xattrs" | "xattr" | "acl" | "selinux" => {
+                    return Err(format!(
+                        "--preserve {} is not supported on this platform",
+                        item
+                    ))
+                }
+                _ => {
+                    return Err(format!(
+                        "Unknown --preserve item {:?}, expected xattrs, acl or selinux",
+                        item
+                    ))
+                }
+            }
+        }
+        Ok(preserve)
+    }
+
+    /// Whether the extended attribute `name` is covered by this selection.
+    #[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+    fn wants_xattr(&self, name: &[u8]) -> bool {
+        if name.starts_with(b"system.posix_acl_") {
+            self.acl
+        } else if name == b"security.selinux" {
+            self.selinux
+        } else {
+            self.xattrs
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn any_xattr(&self) -> bool {
+        self.xattrs || self.acl || self.selinux
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod xattr {
+    use std::ffi::{CStr, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[cfg(target_os = "linux")]
+    unsafe fn list(path: &CStr, buf: *mut libc::c_char, size: usize) -> isize {
+        libc::llistxattr(path.as_ptr(), buf, size)
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn list(path: &CStr, buf: *mut libc::c_char, size: usize) -> isize {
+        libc::listxattr(path.as_ptr(), buf, size, libc::XATTR_NOFOLLOW)
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn get(path: &CStr, name: &CStr, buf: *mut libc::c_void, size: usize) -> isize {
+        libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf, size)
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn get(path: &CStr, name: &CStr, buf: *mut libc::c_void, size: usize) -> isize {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buf,
+            size,
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn set(path: &CStr, name: &CStr, value: &[u8]) -> libc::c_int {
+        libc::lsetxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe fn set(path: &CStr, name: &CStr, value: &[u8]) -> libc::c_int {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+            libc::XATTR_NOFOLLOW,
+        )
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    /// Calls `f` with a buffer until it is large enough for the value it returns.
+    fn read_growing(mut f: impl FnMut(*mut u8, usize) -> isize) -> io::Result<Vec<u8>> {
+        loop {
+            let size = f(std::ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buf = vec![0u8; size as usize];
+            let read = f(buf.as_mut_ptr(), buf.len());
+            if read >= 0 {
+                buf.truncate(read as usize);
+                return Ok(buf);
+            }
+            let err = io::Error::last_os_error();
+            // The value grew between the two calls.
+            if err.raw_os_error() != Some(libc::ERANGE) {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Names of the extended attributes of `path`.
+    pub fn names(path: &Path) -> io::Result<Vec<CString>> {
+        let path = c_path(path)?;
+        let list = read_growing(|buf, size| unsafe { list(&path, buf.cast(), size) })?;
+        Ok(list
+            .split(|b| *b == 0)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| CString::new(name).ok())
+            .collect())
+    }
+
+    pub fn get_value(path: &Path, name: &CStr) -> io::Result<Vec<u8>> {
+        let path = c_path(path)?;
+        read_growing(|buf, size| unsafe { get(&path, name, buf.cast(), size) })
+    }
+
+    pub fn set_value(path: &Path, name: &CStr, value: &[u8]) -> io::Result<()> {
+        let path = c_path(path)?;
+        if unsafe { set(&path, name, value) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Copies the extended attributes of `src` selected by `preserve` to `dst`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn copy_xattrs(src: &Path, dst: &Path, preserve: &Preserve) -> io::Result<()> {
+    for name in xattr::names(src)? {
+        if !preserve.wants_xattr(name.as_bytes()) {
+            continue;
+        }
+        let value = xattr::get_value(src, &name)?;
+        if let Err(e) = xattr::set_value(dst, &name, &value) {
+            // Unsupported by the destination filesystem, or a namespace reserved
+            // to privileged users: report it for this file and carry on.
+            match e.raw_os_error() {
+                Some(libc::ENOTSUP | libc::EPERM | libc::EACCES) => {
+                    tracing::warn!("Could not set {:?} on {:?}: {}", name, dst, e);
+                }
+                _ => return Err(e),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::{c_void, OsStr, OsString};
+    use std::fs::{self, File, FileTimes};
+    use std::io;
+    use std::os::windows::ffi::{OsStrExt, OsStringExt};
+    use std::os::windows::fs::{FileTimesExt, MetadataExt, OpenOptionsExt};
+    use std::path::Path;
+
+    const FILE_ATTRIBUTE_READONLY: u32 = 0x1;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+    const FILE_ATTRIBUTE_ARCHIVE: u32 = 0x20;
+    const FILE_ATTRIBUTE_NOT_CONTENT_INDEXED: u32 = 0x2000;
+    /// Attributes users set themselves, as opposed to those describing the file
+    /// (directory, reparse point, compressed, ...).
+    const COPIED_ATTRIBUTES: u32 = FILE_ATTRIBUTE_READONLY
+        | FILE_ATTRIBUTE_HIDDEN
+        | FILE_ATTRIBUTE_SYSTEM
+        | FILE_ATTRIBUTE_ARCHIVE
+        | FILE_ATTRIBUTE_NOT_CONTENT_INDEXED;
+
+    /// Lets `File::open`-style calls open directories, to set their times.
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    const FIND_STREAM_INFO_STANDARD: i32 = 0;
+    const ERROR_NO_MORE_FILES: i32 = 18;
+    const ERROR_HANDLE_EOF: i32 = 38;
+    const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+
+    #[repr(C)]
+    struct Win32FindStreamData {
+        stream_size: i64,
+        stream_name: [u16; 296],
+    }
+
+    extern "system" {
+        fn SetFileAttributesW(file_name: *const u16, attributes: u32) -> i32;
+        fn FindFirstStreamW(
+            file_name: *const u16,
+            info_level: i32,
+            find_stream_data: *mut c_void,
+            flags: u32,
+        ) -> *mut c_void;
+        fn FindNextStreamW(find_stream: *mut c_void, find_stream_data: *mut c_void) -> i32;
+        fn FindClose(find_file: *mut c_void) -> i32;
+    }
+
+    fn wide(path: &OsStr) -> Vec<u16> {
+        path.encode_wide().chain(Some(0)).collect()
+    }
+
+    /// Names (`:name:$DATA`) of the alternate data streams of `path`.
+    fn alternate_streams(path: &Path) -> io::Result<Vec<OsString>> {
+        let mut streams = Vec::new();
+        let mut data = Win32FindStreamData {
+            stream_size: 0,
+            stream_name: [0; 296],
+        };
+        let name = wide(path.as_os_str());
+        let handle = unsafe {
+            FindFirstStreamW(
+                name.as_ptr(),
+                FIND_STREAM_INFO_STANDARD,
+                &mut data as *mut _ as *mut c_void,
+                0,
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let err = io::Error::last_os_error();
+            // Directories without any named stream report "no more files".
+            return match err.raw_os_error() {
+                Some(ERROR_HANDLE_EOF | ERROR_NO_MORE_FILES) => Ok(streams),
+                _ => Err(err),
+            };
+        }
+        loop {
+            let len = data.stream_name.iter().position(|c| *c == 0).unwrap_or(0);
+            let stream = OsString::from_wide(&data.stream_name[..len]);
+            if stream != "::$DATA" {
+                streams.push(stream);
+            }
+            if unsafe { FindNextStreamW(handle, &mut data as *mut _ as *mut c_void) } == 0 {
+                break;
+            }
+        }
+        unsafe { FindClose(handle) };
+        Ok(streams)
+    }
+
+    /// Copies the alternate data streams of `src` (Zone.Identifier, Finder-style
+    /// metadata, ...) to `dst`.
+    pub fn copy_alternate_streams(src: &Path, dst: &Path) -> io::Result<()> {
+        for stream in alternate_streams(src)? {
+            // `:name:$DATA` is opened as `<path>:name`.
+            let name = stream.to_string_lossy();
+            let name = name.strip_suffix(":$DATA").unwrap_or(&name);
+            let mut src_stream = src.as_os_str().to_owned();
+            src_stream.push(name);
+            let mut dst_stream = dst.as_os_str().to_owned();
+            dst_stream.push(name);
+            io::copy(
+                &mut File::open(&src_stream)?,
+                &mut File::create(&dst_stream)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Copies creation/modification times and user-visible attributes.
+    pub fn copy_times_and_attributes(src: &Path, dst: &Path) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(src)?;
+        let times = FileTimes::new()
+            .set_created(metadata.created()?)
+            .set_modified(metadata.modified()?)
+            .set_accessed(metadata.accessed()?);
+        fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(dst)?
+            .set_times(times)?;
+
+        // Last, since a read-only file can't be opened for writing anymore.
+        let current = fs::symlink_metadata(dst)?.file_attributes();
+        let attributes =
+            (current & !COPIED_ATTRIBUTES) | (metadata.file_attributes() & COPIED_ATTRIBUTES);
+        if unsafe { SetFileAttributesW(wide(dst.as_os_str()).as_ptr(), attributes) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Resets the SELinux contexts below `path` to the policy defaults for its new
+/// location, so confined services can still access it.
+pub fn restorecon(path: &Path) -> io::Result<()> {
+    let output = std::process::Command::new("restorecon")
+        .arg("-R")
+        .arg(path)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "restorecon failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Copies the alternate data streams of `src` to `dst`, for copies that didn't go
+/// through `CopyFileEx` (which carries them over itself). A no-op outside Windows.
+pub fn copy_alternate_streams(src: &Path, dst: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    return windows::copy_alternate_streams(src, dst);
+    #[cfg(not(windows))]
+    {
+        let _ = (src, dst);
+        Ok(())
+    }
+}
+
+/// Carries over the metadata of `src` that copying its content doesn't: on
+/// Windows, hidden/system/read-only attributes and creation times; on Unix, the
+/// extended attributes and ACLs asked for in `preserve`.
+///
+/// For directories, this is meant to run after their content was copied.
+pub fn preserve(src: &Path, dst: &Path, preserve: &Preserve) -> io::Result<()> {
+    #[cfg(windows)]
+    windows::copy_times_and_attributes(src, dst)?;
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    if preserve.any_xattr() {
+        copy_xattrs(src, dst, preserve)?;
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    let _ = (src, dst, preserve);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_preserve() {
+        assert_eq!(Preserve::parse(""), Ok(Preserve::default()));
+        assert!(Preserve::parse("timestamps").is_err());
+        #[cfg(target_os = "linux")]
+        assert_eq!(
+            Preserve::parse("xattrs,acl"),
+            Ok(Preserve {
+                xattrs: true,
+                acl: true,
+                selinux: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_wants_xattr() {
+        let acl_only = Preserve {
+            acl: true,
+            ..Preserve::default()
+        };
+        assert!(acl_only.wants_xattr(b"system.posix_acl_access"));
+        assert!(!acl_only.wants_xattr(b"user.comment"));
+
+        let xattrs_only = Preserve {
+            xattrs: true,
+            ..Preserve::default()
+        };
+        assert!(xattrs_only.wants_xattr(b"security.capability"));
+        assert!(!xattrs_only.wants_xattr(b"security.selinux"));
+        assert!(Preserve::parse("selinux").is_ok_and(|p| p.wants_xattr(b"security.selinux")));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_preserve_xattrs_and_acl() -> io::Result<()> {
+        use std::ffi::CString;
+        use std::fs;
+
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        let dst = dir.path().join("dst.txt");
+        fs::write(&src, "content")?;
+        fs::write(&dst, "content")?;
+
+        let comment = CString::new("user.comment").unwrap();
+        let acl = CString::new("system.posix_acl_access").unwrap();
+        // Version 2 header, then (tag, perm, id) entries: user::rw-, user:1234:r--,
+        // group::r--, mask::r--, other::r--.
+        let mut acl_value = 2u32.to_le_bytes().to_vec();
+        for (tag, perm, id) in [
+            (0x01u16, 6u16, u32::MAX),
+            (0x02, 4, 1234),
+            (0x04, 4, u32::MAX),
+            (0x10, 4, u32::MAX),
+            (0x20, 4, u32::MAX),
+        ] {
+            acl_value.extend(tag.to_le_bytes());
+            acl_value.extend(perm.to_le_bytes());
+            acl_value.extend(id.to_le_bytes());
+        }
+        xattr::set_value(&src, &comment, b"hello")?;
+        xattr::set_value(&src, &acl, &acl_value)?;
+
+        let xattrs_only = Preserve {
+            xattrs: true,
+            ..Preserve::default()
+        };
+        preserve(&src, &dst, &xattrs_only)?;
+        assert_eq!(xattr::get_value(&dst, &comment)?, b"hello");
+        assert!(xattr::get_value(&dst, &acl).is_err());
+
+        preserve(&src, &dst, &Preserve::parse("acl").unwrap())?;
+        assert_eq!(xattr::get_value(&dst, &acl)?, acl_value);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+    use std::fs;
+    use std::os::windows::fs::MetadataExt;
+
+    #[test]
+    fn test_preserve_hidden_attribute_and_streams() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src.txt");
+        let dst = dir.path().join("dst.txt");
+        fs::write(&src, "content")?;
+        fs::write(dir.path().join("src.txt:extra"), "stream")?;
+        std::process::Command::new("attrib")
+            .arg("+h")
+            .arg(&src)
+            .status()?;
+        fs::write(&dst, "content")?;
+
+        copy_alternate_streams(&src, &dst)?;
+        preserve(&src, &dst, &Preserve::default())?;
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("dst.txt:extra"))?,
+            "stream"
+        );
+        assert_ne!(fs::metadata(&dst)?.file_attributes() & 0x2, 0);
+        Ok(())
+    }
+}
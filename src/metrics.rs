@@ -0,0 +1,230 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::summary::Summary;
+
+/// How long a client of `serve` has to send its request and read the answer.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct Counters {
+    runs: u64,
+    /// Runs that stopped before going through every repository.
+    run_errors: u64,
+    /// Repositories per status, in the order the statuses were first seen.
+    repos: Vec<(&'static str, u64)>,
+    files: u64,
+    bytes: u64,
+    last_run: Option<f64>,
+    /// Last run that went through every repository without a failure.
+    last_success: Option<f64>,
+}
+
+/// Counters of the runs of `--watch`, exposed in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
+
+impl Metrics {
+    /// Adds the outcome of a run.
+    pub fn record(&self, result: Result<&Summary, &io::Error>) {
+        let mut counters = self.counters.lock().unwrap();
+        let now = now();
+        counters.runs += 1;
+        counters.last_run = Some(now);
+        let summary = match result {
+            Ok(summary) => summary,
+            Err(_) => {
+                counters.run_errors += 1;
+                return;
+            }
+        };
+        for repo in &summary.repos {
+            let label = repo.outcome.label();
+            match counters.repos.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, count)) => *count += 1,
+                None => counters.repos.push((label, 1)),
+            }
+            counters.files += repo.transferred.files;
+            counters.bytes += repo.transferred.bytes;
+        }
+        if summary.failed() == 0 {
+            counters.last_success = Some(now);
+        }
+    }
+
+    /// The counters, in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+            let _ = writeln!(out, "# HELP mv_git_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE mv_git_{} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "mv_git_{}{} {}", name, labels, value);
+            }
+        };
+        metric(
+            "runs_total",
+            "counter",
+            "Runs started.",
+            &[(String::new(), counters.runs as f64)],
+        );
+        metric(
+            "run_errors_total",
+            "counter",
+            "Runs that stopped on an error.",
+            &[(String::new(), counters.run_errors as f64)],
+        );
+        let repos: Vec<(String, f64)> = counters
+            .repos
+            .iter()
+            .map(|(label, count)| (format!("{{status=\"{}\"}}", label), *count as f64))
+            .collect();
+        metric(
+            "repos_total",
+            "counter",
+            "Repositories gone through, by status.",
+            &repos,
+        );
+        metric(
+            "files_transferred_total",
+            "counter",
+            "Files copied.",
+            &[(String::new(), counters.files as f64)],
+        );
+        metric(
+            "bytes_transferred_total",
+            "counter",
+            "Bytes copied.",
+            &[(String::new(), counters.bytes as f64)],
+        );
+        let gauge = |time: Option<f64>| time.map(|t| vec![(String::new(), t)]).unwrap_or_default();
+        metric(
+            "last_run_timestamp_seconds",
+            "gauge",
+            "When the last run ended.",
+            &gauge(counters.last_run),
+        );
+        metric(
+            "last_success_timestamp_seconds",
+            "gauge",
+            "When the last run without failures ended.",
+            &gauge(counters.last_success),
+        );
+        out
+    }
+}
+
+/// Serves `metrics` at `http://<addr>/metrics` from a background thread.
+pub fn serve(addr: &str, metrics: Arc<Metrics>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let metrics = Arc::clone(&metrics);
+            // Each on its own, for a client that never sends its request to
+            // only hold up itself, until it times out.
+            thread::spawn(move || {
+                if let Err(e) = answer(stream, &metrics) {
+                    tracing::debug!("Could not answer a metrics request: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Reads the request of a client of `serve` and sends it the metrics.
+fn answer(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", metrics.render())
+    } else {
+        ("404 Not Found", "Not found\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Totals;
+    use crate::summary::{Outcome, Timing};
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_metrics() -> io::Result<()> {
+        let metrics = Arc::new(Metrics::default());
+        let mut summary = Summary::default();
+        let totals = Totals {
+            files: 3,
+            bytes: 1200,
+        };
+        summary.push(
+            PathBuf::from("a"),
+            PathBuf::from("b"),
+            Outcome::Synced,
+            totals,
+            Timing::default(),
+        );
+        summary.push(
+            PathBuf::from("c"),
+            PathBuf::from("d"),
+            Outcome::Failed("disk full".to_string()),
+            Totals::default(),
+            Timing::default(),
+        );
+        metrics.record(Ok(&summary));
+        metrics.record(Err(&io::Error::other("gone")));
+
+        let text = metrics.render();
+        assert!(text.contains("mv_git_runs_total 2\n"));
+        assert!(text.contains("mv_git_run_errors_total 1\n"));
+        assert!(text.contains("mv_git_repos_total{status=\"synced\"} 1\n"));
+        assert!(text.contains("mv_git_repos_total{status=\"failed\"} 1\n"));
+        assert!(text.contains("mv_git_bytes_transferred_total 1200\n"));
+        assert!(text.contains("\nmv_git_last_run_timestamp_seconds "));
+        assert!(!text.contains("\nmv_git_last_success_timestamp_seconds "));
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?.to_string();
+        drop(listener);
+        serve(&addr, metrics)?;
+        // Connected without asking anything.
+        let _idle = TcpStream::connect(&addr)?;
+        let mut stream = TcpStream::connect(&addr)?;
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("mv_git_runs_total 2\n"));
+        Ok(())
+    }
+}
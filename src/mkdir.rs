@@ -0,0 +1,102 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Whether missing directories leading to a destination are created, see `--mkdir`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mkdir {
+    #[default]
+    Auto,
+    /// Fail instead, e.g. to notice a backup drive that isn't mounted.
+    Never,
+}
+
+impl Mkdir {
+    pub fn parse(value: &str) -> Result<Mkdir, String> {
+        match value {
+            "auto" => Ok(Mkdir::Auto),
+            "never" => Ok(Mkdir::Never),
+            _ => Err(format!(
+                "Invalid --mkdir {:?}, expected auto or never",
+                value
+            )),
+        }
+    }
+}
+
+/// Parses an octal permission mode such as `755` or `0750`.
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("Invalid mode {:?}, expected octal such as 755", value))
+}
+
+#[cfg(unix)]
+fn set_mode(dir: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(dir, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_dir: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Makes sure the directory `dir` exists, creating it and the missing directories
+/// leading to it with `mode`, if given, unless `policy` is [`Mkdir::Never`].
+pub fn ensure_dir(dir: &Path, policy: Mkdir, mode: Option<u32>) -> io::Result<()> {
+    if dir.as_os_str().is_empty() || dir.is_dir() {
+        return Ok(());
+    }
+    if policy == Mkdir::Never {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("{:?} does not exist, create it or use --mkdir auto", dir),
+        ));
+    }
+    let mut missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    while let Some(dir) = missing.pop() {
+        match fs::create_dir(&dir) {
+            Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        if let Some(mode) = mode {
+            set_mode(&dir, mode)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_ensure_dir() -> io::Result<()> {
+        let dir = tempdir()?;
+        let dst = dir.path().join("backup/code");
+
+        assert!(ensure_dir(&dst, Mkdir::Never, None).is_err());
+        assert!(!dst.exists());
+
+        ensure_dir(&dst, Mkdir::Auto, Some(parse_mode("750").unwrap()))?;
+        assert!(dst.is_dir());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.path().join("backup"))?
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o7777, 0o750);
+        }
+        ensure_dir(&dst, Mkdir::Never, None)?;
+        assert!(parse_mode("9").is_err());
+        Ok(())
+    }
+}
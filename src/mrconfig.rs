@@ -0,0 +1,88 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+use crate::workspace;
+
+/// The repositories registered in the myrepos config `path`: its sections,
+/// named after them by path, absolute or relative to the directory of the
+/// config. Those that exist are canonicalized.
+pub fn projects(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let text = fs::read_to_string(path)?;
+    let base = base(path)?;
+    let mut projects = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) else {
+            continue;
+        };
+        let section = section.trim();
+        // Settings shared by the sections that follow, not a repository.
+        if section == "DEFAULT" {
+            continue;
+        }
+        let section = paths::expand(section).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Invalid section in {:?} at line {}: {}",
+                    path,
+                    number + 1,
+                    e
+                ),
+            )
+        })?;
+        let project = base.join(section);
+        projects.push(fs::canonicalize(&project).unwrap_or(project));
+    }
+    Ok(projects)
+}
+
+/// Points the sections of the myrepos config `path` at the new location of
+/// the moved repositories of `moves`, pairs of source and destination.
+/// Returns how many were rewritten.
+pub fn rewrite(path: &Path, moves: &[(PathBuf, PathBuf)]) -> io::Result<usize> {
+    workspace::rewrite_reference(path, moves, Some(&base(path)?))
+}
+
+/// The directory the sections of the config `path` are relative to.
+fn base(path: &Path) -> io::Result<PathBuf> {
+    Ok(std::path::absolute(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_mrconfig() -> io::Result<()> {
+        let dir = tempdir()?;
+        let home = dir.path().canonicalize()?;
+        fs::create_dir_all(home.join("src/api"))?;
+        let path = home.join(".mrconfig");
+        fs::write(
+            &path,
+            "[DEFAULT]\njobs = 4\n\n[src/api]\ncheckout = git clone x api\n\n[/srv/web]\n",
+        )?;
+        assert_eq!(
+            projects(&path)?,
+            vec![home.join("src/api"), PathBuf::from("/srv/web")]
+        );
+
+        let moves = vec![
+            (home.join("src/api"), home.join("work/api")),
+            (PathBuf::from("/srv/web"), PathBuf::from("/mnt/web")),
+        ];
+        assert_eq!(rewrite(&path, &moves)?, 2);
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "[DEFAULT]\njobs = 4\n\n[work/api]\ncheckout = git clone x api\n\n[/mnt/web]\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::Path;
+
+use crate::scan::{Entry, EntryKind};
+
+/// Sequence in which repositories and the files inside them are transferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// Biggest first, so the long transfers start while the rest still queues.
+    LargestFirst,
+    /// Smallest first, so most repositories are done early.
+    SmallestFirst,
+    /// By name.
+    Alpha,
+    /// Most recently modified first.
+    Mtime,
+}
+
+impl Order {
+    /// Parses `largest-first`, `smallest-first`, `alpha` or `mtime`.
+    pub fn parse(value: &str) -> Result<Order, String> {
+        match value {
+            "largest-first" => Ok(Order::LargestFirst),
+            "smallest-first" => Ok(Order::SmallestFirst),
+            "alpha" => Ok(Order::Alpha),
+            "mtime" => Ok(Order::Mtime),
+            _ => Err(format!(
+                "Invalid order {:?}, expected largest-first, smallest-first, alpha or mtime",
+                value
+            )),
+        }
+    }
+
+    /// Whether sorting needs the size of every item.
+    pub fn needs_size(self) -> bool {
+        matches!(self, Order::LargestFirst | Order::SmallestFirst)
+    }
+
+    /// Sorts `items` given the `(name, size, mtime)` of each. Ties keep their
+    /// current order.
+    pub fn sort<T>(self, items: &mut [T], key: impl Fn(&T) -> (&Path, u64, i64)) {
+        match self {
+            Order::LargestFirst => items.sort_by_key(|item| std::cmp::Reverse(key(item).1)),
+            Order::SmallestFirst => items.sort_by_key(|item| key(item).1),
+            Order::Alpha => items.sort_by(|a, b| key(a).0.cmp(key(b).0)),
+            Order::Mtime => items.sort_by_key(|item| std::cmp::Reverse(key(item).2)),
+        }
+    }
+
+    /// Sorts the entries of a pre-scan. Directories stay first and in path
+    /// order, so they exist before anything is copied into them.
+    pub fn sort_entries(self, entries: &mut [Entry]) {
+        let dirs = entries.iter().filter(|e| e.kind == EntryKind::Dir).count();
+        entries.sort_by_key(|e| e.kind != EntryKind::Dir);
+        self.sort(&mut entries[dirs..], |e| {
+            (e.rel.as_path(), e.stamp.size, e.stamp.mtime)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::FileStamp;
+    use std::path::PathBuf;
+
+    fn entry(rel: &str, kind: EntryKind, size: u64, mtime: i64) -> Entry {
+        Entry {
+            rel: PathBuf::from(rel),
+            kind,
+            stamp: FileStamp { size, mtime },
+        }
+    }
+
+    fn names(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|e| e.rel.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_sort_entries() {
+        let mut entries = vec![
+            entry("a.txt", EntryKind::File, 10, 3),
+            entry("docs", EntryKind::Dir, 0, 0),
+            entry("docs/big.pdf", EntryKind::File, 1000, 1),
+            entry("src", EntryKind::Dir, 0, 0),
+            entry("src/main.rs", EntryKind::File, 100, 2),
+        ];
+        Order::LargestFirst.sort_entries(&mut entries);
+        assert_eq!(
+            names(&entries),
+            ["docs", "src", "docs/big.pdf", "src/main.rs", "a.txt"]
+        );
+        Order::Mtime.sort_entries(&mut entries);
+        assert_eq!(
+            names(&entries),
+            ["docs", "src", "a.txt", "src/main.rs", "docs/big.pdf"]
+        );
+        Order::Alpha.sort_entries(&mut entries);
+        assert_eq!(
+            names(&entries),
+            ["docs", "src", "a.txt", "docs/big.pdf", "src/main.rs"]
+        );
+        assert_eq!(Order::parse("smallest-first"), Ok(Order::SmallestFirst));
+        assert!(Order::parse("random").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::io;
+use std::path::Path;
+
+/// Ownership given to what is created at the destination, see `--chown`,
+/// `--uid-map` and `--gid-map`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ownership {
+    /// Owner of everything copied, whoever owns the source.
+    pub uid: Option<u32>,
+    /// Group of everything copied, whatever the source's group.
+    pub gid: Option<u32>,
+    /// Source owners replaced by another one, other owners are kept.
+    pub uid_map: Vec<(u32, u32)>,
+    /// Source groups replaced by another one, other groups are kept.
+    pub gid_map: Vec<(u32, u32)>,
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<u32, String> {
+    if let Ok(uid) = name.parse() {
+        return Ok(uid);
+    }
+    let c_name = std::ffi::CString::new(name).map_err(|_| format!("Invalid user: {:?}", name))?;
+    let passwd = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if passwd.is_null() {
+        return Err(format!("Unknown user: {:?}", name));
+    }
+    Ok(unsafe { (*passwd).pw_uid })
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<u32, String> {
+    if let Ok(gid) = name.parse() {
+        return Ok(gid);
+    }
+    let c_name = std::ffi::CString::new(name).map_err(|_| format!("Invalid group: {:?}", name))?;
+    let group = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if group.is_null() {
+        return Err(format!("Unknown group: {:?}", name));
+    }
+    Ok(unsafe { (*group).gr_gid })
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_name: &str) -> Result<u32, String> {
+    Err("Ownership options are only supported on Unix".to_string())
+}
+
+#[cfg(not(unix))]
+fn lookup_group(name: &str) -> Result<u32, String> {
+    lookup_user(name)
+}
+
+/// Parses a `from:to,from:to` list of users or groups, by name or id.
+fn parse_map(
+    value: &str,
+    lookup: fn(&str) -> Result<u32, String>,
+) -> Result<Vec<(u32, u32)>, String> {
+    value
+        .split(',')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (from, to) = pair
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid mapping {:?}, expected from:to", pair))?;
+            Ok((lookup(from)?, lookup(to)?))
+        })
+        .collect()
+}
+
+impl Ownership {
+    /// Sets the owner and group from `user[:group]`, or `:group` alone.
+    pub fn parse_chown(&mut self, value: &str) -> Result<(), String> {
+        let (user, group) = value.split_once(':').unwrap_or((value, ""));
+        if !user.is_empty() {
+            self.uid = Some(lookup_user(user)?);
+        }
+        if !group.is_empty() {
+            self.gid = Some(lookup_group(group)?);
+        }
+        Ok(())
+    }
+
+    pub fn parse_uid_map(&mut self, value: &str) -> Result<(), String> {
+        self.uid_map.extend(parse_map(value, lookup_user)?);
+        Ok(())
+    }
+
+    pub fn parse_gid_map(&mut self, value: &str) -> Result<(), String> {
+        self.gid_map.extend(parse_map(value, lookup_group)?);
+        Ok(())
+    }
+
+    /// Whether ownership has to be set at all. Without options, copies belong to
+    /// whoever runs mv-git.
+    pub fn is_set(&self) -> bool {
+        self.uid.is_some()
+            || self.gid.is_some()
+            || !self.uid_map.is_empty()
+            || !self.gid_map.is_empty()
+    }
+
+    /// Owner and group for a copy of something owned by `uid:gid`.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn resolve(&self, uid: u32, gid: u32) -> (u32, u32) {
+        let map = |map: &[(u32, u32)], id| {
+            map.iter()
+                .find(|(from, _)| *from == id)
+                .map_or(id, |(_, to)| *to)
+        };
+        (
+            self.uid.unwrap_or_else(|| map(&self.uid_map, uid)),
+            self.gid.unwrap_or_else(|| map(&self.gid_map, gid)),
+        )
+    }
+}
+
+/// Gives `dst`, the copy of `src`, the ownership asked for in `ownership`. Links
+/// themselves are changed, not what they point to.
+pub fn apply(src: &Path, dst: &Path, ownership: &Ownership) -> io::Result<()> {
+    if !ownership.is_set() {
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = std::fs::symlink_metadata(src)?;
+        let (uid, gid) = ownership.resolve(metadata.uid(), metadata.gid());
+        std::os::unix::fs::lchown(dst, Some(uid), Some(gid))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (src, dst);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "ownership options are only supported on Unix",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse() {
+        let mut ownership = Ownership::default();
+        ownership.parse_chown("root:0").unwrap();
+        assert_eq!((ownership.uid, ownership.gid), (Some(0), Some(0)));
+
+        let mut ownership = Ownership::default();
+        ownership.parse_chown(":100").unwrap();
+        assert_eq!((ownership.uid, ownership.gid), (None, Some(100)));
+        ownership.parse_uid_map("1000:2000,root:1001").unwrap();
+        assert_eq!(ownership.uid_map, vec![(1000, 2000), (0, 1001)]);
+        assert!(ownership.parse_gid_map("1000").is_err());
+        assert!(ownership.parse_chown("no-such-user-here").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let ownership = Ownership {
+            gid: Some(50),
+            uid_map: vec![(1000, 2000)],
+            ..Ownership::default()
+        };
+        assert_eq!(ownership.resolve(1000, 1000), (2000, 50));
+        assert_eq!(ownership.resolve(1001, 1001), (1001, 50));
+        assert!(!Ownership::default().is_set());
+    }
+}
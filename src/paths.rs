@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Device names Windows reserves in every directory, whatever the extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Whether Windows refuses `name` as a file name because it is a device (`aux`,
+/// `con.txt`, `LPT1.tar.gz`, ...).
+pub fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// Fails with an actionable error if `rel` can't be created on Windows because
+/// one of its components is a reserved device name.
+pub fn check_windows_name(rel: &Path) -> io::Result<()> {
+    for component in rel.components() {
+        if let Component::Normal(name) = component {
+            let name = name.to_string_lossy();
+            if is_reserved_name(&name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Cannot create {:?} on Windows: {:?} is a reserved device name, \
+                         rename it in the source repository (e.g. with `git mv`)",
+                        rel, name
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Home directory of the current user.
+fn home_dir() -> Option<PathBuf> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    env::var_os(var)
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Home directory of the user called `name`.
+#[cfg(unix)]
+fn user_home_dir(name: &str) -> Option<PathBuf> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_name = CString::new(name).ok()?;
+    let passwd = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if passwd.is_null() {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr((*passwd).pw_dir) };
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(dir.to_bytes())))
+}
+
+#[cfg(not(unix))]
+fn user_home_dir(_name: &str) -> Option<PathBuf> {
+    None
+}
+
+/// The directory of mv-git in the user's state directory, for what runs leave
+/// for later commands.
+pub fn state_dir() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_STATE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+    };
+    dir.map(|dir| dir.join("mv-git"))
+}
+
+/// The directory of mv-git in the user's data directory, for what it keeps
+/// track of across runs.
+pub fn data_dir() -> Option<PathBuf> {
+    user_data_dir().map(|dir| dir.join("mv-git"))
+}
+
+/// The user's data directory: `$XDG_DATA_HOME`, `~/.local/share` without it,
+/// or `%APPDATA%` on Windows.
+pub fn user_data_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+    }
+}
+
+/// Writes `text` to a new `<id>.json` in `dir`, with an id made of the current
+/// time, and returns the id.
+pub fn write_new(dir: &Path, text: &str) -> io::Result<String> {
+    fs::create_dir_all(dir)?;
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    for n in 0.. {
+        let id = if n == 0 {
+            secs.to_string()
+        } else {
+            format!("{}-{}", secs, n)
+        };
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(format!("{}.json", id)))
+        {
+            Ok(mut file) => {
+                file.write_all(text.as_bytes())?;
+                return Ok(id);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+/// Expands `~`, `~user` at the start of `value` and `$VAR`, `${VAR}` anywhere in
+/// it (and `%VAR%` on Windows), as shells do for unquoted words. Undefined
+/// variables and unknown users are errors rather than empty strings.
+pub fn expand(value: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+    if let Some(after) = value.strip_prefix('~') {
+        let end = after.find(['/', '\\']).unwrap_or(after.len());
+        let (user, after) = after.split_at(end);
+        let home = if user.is_empty() {
+            home_dir().ok_or("Can't expand ~: HOME is not set")?
+        } else {
+            user_home_dir(user).ok_or_else(|| format!("Can't expand ~{}: unknown user", user))?
+        };
+        expanded.push_str(&home.to_string_lossy());
+        rest = after;
+    }
+    let variable = |name: &str| {
+        env::var(name).map_err(|_| format!("Can't expand ${} in {:?}: it is not set", name, value))
+    };
+    while let Some(start) = rest.find(|c| c == '$' || (cfg!(windows) && c == '%')) {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, len) = if rest[start..].starts_with('%') {
+            match after.find('%') {
+                Some(end) if end > 0 => (&after[..end], end + 1),
+                _ => ("", 0),
+            }
+        } else if let Some(braced) = after.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| format!("Unterminated ${{ in {:?}", value))?;
+            (&braced[..end], end + 2)
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        if name.is_empty() {
+            // A lone `$` or `%` is kept as it is.
+            expanded.push_str(&rest[start..start + 1]);
+            rest = after;
+        } else {
+            expanded.push_str(&variable(name)?);
+            rest = &after[len..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Unicode normalization applied to file names at the destination.
+///
+/// macOS filesystems store or compare names decomposed (NFD) while Linux keeps
+/// them byte for byte, so the same name may come back in either form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Names are copied as they are.
+    #[default]
+    Keep,
+    /// Names are composed (`é` as one code point), as most Linux tools write them.
+    Nfc,
+    /// Names are decomposed (`e` + combining accent), as HFS+ stores them.
+    Nfd,
+}
+
+impl Normalization {
+    /// Parses `keep`, `nfc` or `nfd`.
+    pub fn parse(value: &str) -> Result<Normalization, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "keep" => Ok(Normalization::Keep),
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            _ => Err(format!(
+                "Invalid normalization {:?}, expected keep, nfc or nfd",
+                value
+            )),
+        }
+    }
+
+    /// `rel` with every component normalized. Components that aren't valid
+    /// UTF-8 are left alone.
+    pub fn apply(self, rel: &Path) -> PathBuf {
+        if self == Normalization::Keep {
+            return rel.to_path_buf();
+        }
+        rel.components()
+            .map(|component| match component.as_os_str().to_str() {
+                Some(name) if self == Normalization::Nfc => name.nfc().collect::<String>().into(),
+                Some(name) => name.nfd().collect::<String>().into(),
+                None => component.as_os_str().to_os_string(),
+            })
+            .collect()
+    }
+}
+
+/// Groups of paths that differ only in their Unicode normalization, and so name
+/// the same file on filesystems that normalize names.
+pub fn normalization_collisions<'a>(
+    paths: impl IntoIterator<Item = &'a Path>,
+) -> Vec<Vec<PathBuf>> {
+    let mut by_form: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    for path in paths {
+        by_form
+            .entry(Normalization::Nfc.apply(path))
+            .or_default()
+            .push(path.to_path_buf());
+    }
+    let mut collisions: Vec<Vec<PathBuf>> = by_form
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    collisions.sort();
+    collisions
+}
+
+/// Turns `path` into an extended-length (`\\?\`) path on Windows, so it isn't
+/// limited to 260 characters. Other platforms get `path` back unchanged.
+pub fn extended(path: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        let Ok(absolute) = std::path::absolute(path) else {
+            return path.to_path_buf();
+        };
+        let raw = absolute.as_os_str().to_string_lossy();
+        if raw.starts_with(r"\\?\") {
+            absolute
+        } else if let Some(unc) = raw.strip_prefix(r"\\") {
+            PathBuf::from(format!(r"\\?\UNC\{}", unc))
+        } else {
+            PathBuf::from(format!(r"\\?\{}", raw))
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        env::set_var("MV_GIT_TEST_DIR", "/mnt/backup");
+        let home = home_dir().unwrap().to_string_lossy().into_owned();
+        assert_eq!(expand("~/code"), Ok(format!("{}/code", home)));
+        assert_eq!(expand("~"), Ok(home));
+        assert_eq!(
+            expand("$MV_GIT_TEST_DIR/code-${MV_GIT_TEST_DIR}"),
+            Ok("/mnt/backup/code-/mnt/backup".to_string())
+        );
+        assert_eq!(expand("a$ b~"), Ok("a$ b~".to_string()));
+        assert!(expand("$MV_GIT_TEST_UNSET").is_err());
+        assert!(expand("~no-such-user-here/code").is_err());
+        #[cfg(unix)]
+        assert_eq!(
+            expand("~root"),
+            Ok(user_home_dir("root")
+                .unwrap()
+                .to_string_lossy()
+                .into_owned())
+        );
+    }
+
+    #[test]
+    fn test_is_reserved_name() {
+        assert!(is_reserved_name("aux"));
+        assert!(is_reserved_name("CON"));
+        assert!(is_reserved_name("con.txt"));
+        assert!(is_reserved_name("Lpt1.tar.gz"));
+        assert!(is_reserved_name("nul "));
+        assert!(!is_reserved_name("auxiliary"));
+        assert!(!is_reserved_name("COM10"));
+        assert!(!is_reserved_name("src"));
+    }
+
+    #[test]
+    fn test_check_windows_name() {
+        assert!(check_windows_name(Path::new("src/lib.rs")).is_ok());
+        let err = check_windows_name(Path::new("src/aux/mod.rs")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("reserved device name"));
+    }
+
+    #[test]
+    fn test_normalization() {
+        let composed = Path::new("docs/caf\u{e9}.md");
+        let decomposed = Path::new("docs/cafe\u{301}.md");
+        assert_eq!(Normalization::Nfc.apply(decomposed), composed);
+        assert_eq!(Normalization::Nfd.apply(composed), decomposed);
+        assert_eq!(Normalization::Keep.apply(decomposed), decomposed);
+        assert_eq!(Normalization::parse("NFD"), Ok(Normalization::Nfd));
+        assert!(Normalization::parse("nfkc").is_err());
+
+        let paths = [composed, decomposed, Path::new("docs/cafe.md")];
+        assert_eq!(
+            normalization_collisions(paths),
+            vec![vec![composed.to_path_buf(), decomposed.to_path_buf()]]
+        );
+        assert!(normalization_collisions([composed, Path::new("docs")]).is_empty());
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_extended_is_a_no_op() {
+        assert_eq!(extended(Path::new("a/b")), PathBuf::from("a/b"));
+    }
+}
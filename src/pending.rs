@@ -0,0 +1,136 @@
+use std::fs;
+use std::io::{self, BufRead, ErrorKind, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths;
+
+pub const FINALIZE_USAGE: &str = "Usage: finalize <id>";
+
+/// A repository copied and verified by `--confirm-delete`, whose source is
+/// still to be deleted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingRepo {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+}
+
+/// The deletions a `--confirm-delete` run left for `finalize`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingDelete {
+    /// Command line of the run, without the program name: `finalize` parses it
+    /// again to delete the sources the same way the run would have.
+    pub args: Vec<String>,
+    pub repos: Vec<PendingRepo>,
+}
+
+impl PendingDelete {
+    /// Saves the deletions in `dir` under a new id, which is returned.
+    pub fn save_new(&self, dir: &Path) -> io::Result<String> {
+        paths::write_new(dir, &(serde_json::to_string_pretty(self)? + "\n"))
+    }
+
+    /// Saves what is left of the deletions `id`, removing them once there are none.
+    pub fn save(&self, dir: &Path, id: &str) -> io::Result<()> {
+        if self.repos.is_empty() {
+            return fs::remove_file(file(dir, id));
+        }
+        fs::write(file(dir, id), serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    pub fn load(dir: &Path, id: &str) -> io::Result<PendingDelete> {
+        let path = file(dir, id);
+        let text = match fs::read_to_string(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("No deletions are waiting with the id {:?}", id),
+                ))
+            }
+            text => text?,
+        };
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid pending deletions {:?}: {}", path, e),
+            )
+        })
+    }
+}
+
+fn file(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+/// Where the deletions waiting for `finalize` are kept: `mv-git/pending` in the
+/// user's state directory.
+pub fn dir() -> Option<PathBuf> {
+    paths::state_dir().map(|dir| dir.join("pending"))
+}
+
+/// Asks on the terminal whether to delete the sources of `repos` now. Without
+/// a terminal, or an answer, they are kept.
+pub fn ask(repos: &[PendingRepo]) -> io::Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    for repo in repos {
+        eprintln!("  {}", repo.source.display());
+    }
+    loop {
+        eprint!(
+            "The {} sources above were copied and verified. Delete them now? [y]es or [n]o, \
+             to finalize later? ",
+            repos.len()
+        );
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Ok(false);
+        }
+        match answer.trim() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" | "" => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+/// Parses the arguments of `finalize`, returning the id.
+pub fn parse_args(args: &[String]) -> Result<String, String> {
+    match args {
+        [id] if !id.starts_with('-') => Ok(id.clone()),
+        _ => Err(FINALIZE_USAGE.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_save_load() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut pending = PendingDelete {
+            args: vec!["src".to_string(), "dst".to_string()],
+            repos: vec![PendingRepo {
+                source: PathBuf::from("src/a"),
+                dest: PathBuf::from("dst/a"),
+            }],
+        };
+        let first = pending.save_new(dir.path())?;
+        let second = pending.save_new(dir.path())?;
+        assert_ne!(first, second);
+        let loaded = PendingDelete::load(dir.path(), &first)?;
+        assert_eq!(loaded.args, pending.args);
+        assert_eq!(loaded.repos, pending.repos);
+
+        pending.repos.clear();
+        pending.save(dir.path(), &first)?;
+        assert!(PendingDelete::load(dir.path(), &first).is_err());
+        assert!(parse_args(&["--all".to_string()]).is_err());
+        Ok(())
+    }
+}
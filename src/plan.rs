@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, ErrorKind, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+pub const USAGE: &str = "Usage: plan <source>... <destination> [options] -o <plan.json>";
+pub const APPLY_USAGE: &str = "Usage: apply <plan.json> [options]";
+
+/// What a plan does with one directory of the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Move, copy or sync it to a destination that is free, or synced before.
+    Transfer,
+    /// The destination already holds something else, copy the files over it.
+    Merge,
+    /// An identical copy is already at the destination, only remove the source
+    /// when moving.
+    Deduplicate,
+    /// Leave it where it is.
+    Skip,
+    /// Leave it where it is, it is pinned by the config or its `.mv-git.toml`.
+    Pin,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self {
+            Action::Transfer => "transfer",
+            Action::Merge => "merge",
+            Action::Deduplicate => "deduplicate",
+            Action::Skip => "skip",
+            Action::Pin => "pin",
+        };
+        f.pad(label)
+    }
+}
+
+/// Where the repositories found deeper than directly in the source go, see
+/// `--flatten` and `--preserve-paths`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Only the directories directly in the source are looked at.
+    #[default]
+    Top,
+    /// Nested repositories go directly in the destination.
+    Flatten,
+    /// Nested repositories go to the same path under the destination.
+    Preserve,
+}
+
+/// What to do with the directories of the source that aren't git repositories,
+/// see `--include-non-git`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NonGit {
+    #[default]
+    Skip,
+    /// Transfer them like repositories.
+    Move,
+    /// Ask about each one on the terminal.
+    Prompt,
+}
+
+impl NonGit {
+    pub fn parse(value: &str) -> Result<NonGit, String> {
+        match value {
+            "skip" => Ok(NonGit::Skip),
+            "move" => Ok(NonGit::Move),
+            "prompt" => Ok(NonGit::Prompt),
+            _ => Err(format!(
+                "Invalid --include-non-git {:?}, expected skip, move or prompt",
+                value
+            )),
+        }
+    }
+
+    /// Whether the directory `dir`, which isn't a git repository, is transferred.
+    /// Prompts are answered no when there is no terminal to ask on.
+    pub fn includes(self, dir: &Path) -> io::Result<bool> {
+        match self {
+            NonGit::Skip => Ok(false),
+            NonGit::Move => Ok(true),
+            NonGit::Prompt if !io::stdin().is_terminal() => Ok(false),
+            NonGit::Prompt => loop {
+                eprint!("{:?} is not a git repository, move it too? [y/N] ", dir);
+                io::stderr().flush()?;
+                let mut answer = String::new();
+                if io::stdin().lock().read_line(&mut answer)? == 0 {
+                    return Ok(false);
+                }
+                match answer.trim() {
+                    "y" | "yes" => return Ok(true),
+                    "n" | "no" | "" => return Ok(false),
+                    _ => {}
+                }
+            },
+        }
+    }
+}
+
+/// What to do when different repositories would land on the same destination,
+/// see `--on-collision`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// Add the name of the directory they are in, e.g. `api-acme`.
+    Suffix,
+    /// Refuse to plan.
+    #[default]
+    Fail,
+    /// Ask for a name on the terminal, failing when there is none.
+    Prompt,
+}
+
+impl Collision {
+    pub fn parse(value: &str) -> Result<Collision, String> {
+        match value {
+            "suffix" => Ok(Collision::Suffix),
+            "fail" => Ok(Collision::Fail),
+            "prompt" => Ok(Collision::Prompt),
+            _ => Err(format!(
+                "Invalid --on-collision {:?}, expected suffix, fail or prompt",
+                value
+            )),
+        }
+    }
+
+    /// The new name of `repo`, which would land on `dest` with other repositories,
+    /// `None` when the plan should fail instead.
+    fn rename(self, repo: &Path, dest: &Path) -> io::Result<Option<String>> {
+        let name = dest.file_name().unwrap_or_default().to_string_lossy();
+        let suffixed = match repo.parent().and_then(Path::file_name) {
+            Some(parent) => format!("{}-{}", name, parent.to_string_lossy()),
+            None => name.to_string(),
+        };
+        match self {
+            Collision::Fail => Ok(None),
+            Collision::Suffix => Ok(Some(suffixed)),
+            Collision::Prompt if !io::stdin().is_terminal() => Ok(None),
+            Collision::Prompt => loop {
+                eprint!(
+                    "{:?} would go to {:?} along with another repository, name it [{}]: ",
+                    repo, dest, suffixed
+                );
+                io::stderr().flush()?;
+                let mut answer = String::new();
+                if io::stdin().lock().read_line(&mut answer)? == 0 {
+                    return Ok(None);
+                }
+                match answer.trim() {
+                    "" => return Ok(Some(suffixed)),
+                    answer if answer.contains(['/', '\\']) || answer == "." || answer == ".." => {}
+                    answer => return Ok(Some(answer.to_string())),
+                }
+            },
+        }
+    }
+}
+
+/// Gives the repositories of `repos` that would land on the same destination
+/// one each, as `policy` says.
+pub fn resolve_collisions(repos: &mut [PlannedRepo], policy: Collision) -> io::Result<()> {
+    let moving = |repo: &PlannedRepo| !matches!(repo.action, Action::Skip | Action::Pin);
+    let mut by_dest: BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+    for (i, repo) in repos.iter().enumerate().filter(|(_, repo)| moving(repo)) {
+        by_dest.entry(repo.dest.clone()).or_default().push(i);
+    }
+    let mut taken: HashSet<PathBuf> = by_dest.keys().cloned().collect();
+    for (dest, colliding) in by_dest.into_iter().filter(|(_, c)| c.len() > 1) {
+        for i in colliding {
+            let repo = &mut repos[i];
+            let Some(name) = policy.rename(&repo.source, &dest)? else {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!(
+                        "Different repositories would go to {:?}, such as {:?}, \
+                         use --on-collision suffix or prompt",
+                        dest, repo.source
+                    ),
+                ));
+            };
+            let mut renamed = dest.with_file_name(&name);
+            let mut n = 2;
+            while !taken.insert(renamed.clone()) {
+                renamed = dest.with_file_name(format!("{}-{}", name, n));
+                n += 1;
+            }
+            repo.reason = format!(
+                "renamed from {:?}, another repository has the same name",
+                dest.file_name().unwrap_or_default()
+            );
+            repo.action = if renamed.exists() {
+                Action::Merge
+            } else {
+                Action::Transfer
+            };
+            repo.dest = renamed;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedRepo {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    pub action: Action,
+    /// Why the action was chosen, for whoever reviews the plan.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+    /// Directories, relative to the repository, that `--prune-empty-dirs` removes
+    /// once they are left empty by the transfer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub empty_dirs: Vec<PathBuf>,
+}
+
+/// What a run would do, written by `plan` to be reviewed, and edited if need be,
+/// before `apply` carries it out.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    /// Command line of the run, without the program name: `apply` parses it again
+    /// so that it runs with the same options.
+    pub args: Vec<String>,
+    pub repos: Vec<PlannedRepo>,
+}
+
+impl Plan {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    pub fn load(path: &Path) -> io::Result<Plan> {
+        let text = fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid plan {:?}: {}", path, e),
+            )
+        })
+    }
+}
+
+/// Splits the `plan` arguments (after the subcommand) into the file the plan is
+/// written to and the arguments of the planned run.
+pub fn parse_args(args: &[String]) -> Result<(PathBuf, Vec<String>), Box<dyn Error>> {
+    let mut output = None;
+    let mut run_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE))?;
+                output = Some(PathBuf::from(value));
+            }
+            _ => run_args.push(arg.clone()),
+        }
+    }
+    let output = output.ok_or_else(|| format!("Missing -o\n{}", USAGE))?;
+    Ok((output, run_args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_collisions() -> io::Result<()> {
+        let planned = |source: &str, dest: &str| PlannedRepo {
+            source: PathBuf::from(source),
+            dest: PathBuf::from(dest),
+            action: Action::Transfer,
+            reason: String::new(),
+            empty_dirs: Vec::new(),
+        };
+        let mut repos = vec![
+            planned("src/acme/api", "dst/api"),
+            planned("src/globex/api", "dst/api"),
+            planned("src/web", "dst/web"),
+        ];
+        assert!(resolve_collisions(&mut repos.clone(), Collision::Fail).is_err());
+
+        resolve_collisions(&mut repos, Collision::Suffix)?;
+        let dests: Vec<&Path> = repos.iter().map(|repo| repo.dest.as_path()).collect();
+        assert_eq!(
+            dests,
+            vec![
+                Path::new("dst/api-acme"),
+                Path::new("dst/api-globex"),
+                Path::new("dst/web")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_round_trip() -> io::Result<()> {
+        let args: Vec<String> = ["src", "dst", "-o", "plan.json", "--copy"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (output, args) = parse_args(&args).unwrap();
+        assert_eq!(output, PathBuf::from("plan.json"));
+        assert_eq!(args, vec!["src", "dst", "--copy"]);
+        assert!(parse_args(&args).is_err());
+
+        let dir = tempdir()?;
+        let path = dir.path().join("plan.json");
+        let plan = Plan {
+            args,
+            repos: vec![PlannedRepo {
+                source: PathBuf::from("src/a"),
+                dest: PathBuf::from("dst/a"),
+                action: Action::Merge,
+                reason: "destination exists".to_string(),
+                empty_dirs: vec![PathBuf::from("build")],
+            }],
+        };
+        plan.save(&path)?;
+        assert!(fs::read_to_string(&path)?.contains("\"action\": \"merge\""));
+        assert_eq!(Plan::load(&path)?, plan);
+
+        fs::write(
+            &path,
+            "{\"args\": [], \"repos\": [{\"action\": \"explode\"}]}",
+        )?;
+        assert!(Plan::load(&path).is_err());
+        Ok(())
+    }
+}
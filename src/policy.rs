@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// What to do when something fails while transferring a repository, see `--error-policy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop the whole run, leaving the repositories after this one alone.
+    Abort,
+    /// Carry on with the other files, failing the repository once they are done.
+    SkipFile,
+    /// Fail the repository and carry on with the next one.
+    #[default]
+    SkipRepo,
+}
+
+impl ErrorPolicy {
+    fn parse_one(value: &str) -> Result<ErrorPolicy, String> {
+        match value {
+            "abort" => Ok(ErrorPolicy::Abort),
+            "skip-file" => Ok(ErrorPolicy::SkipFile),
+            "skip-repo" => Ok(ErrorPolicy::SkipRepo),
+            _ => Err(format!(
+                "Invalid error policy {:?}, expected abort, skip-file or skip-repo",
+                value
+            )),
+        }
+    }
+
+    /// Parses the policies of the copy and delete phases: one policy for both,
+    /// such as `abort`, or a comma separated list such as `copy=skip-file,delete=abort`.
+    pub fn parse(value: &str) -> Result<(ErrorPolicy, ErrorPolicy), String> {
+        if !value.contains('=') {
+            let policy = ErrorPolicy::parse_one(value)?;
+            return Ok((policy, policy));
+        }
+        let (mut copy, mut delete) = (ErrorPolicy::default(), ErrorPolicy::default());
+        for item in value.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            match item.split_once('=') {
+                Some(("copy", policy)) => copy = ErrorPolicy::parse_one(policy)?,
+                Some(("delete", policy)) => delete = ErrorPolicy::parse_one(policy)?,
+                _ => {
+                    return Err(format!(
+                    "Invalid --error-policy item {:?}, expected copy=<policy> or delete=<policy>",
+                    item
+                ))
+                }
+            }
+        }
+        Ok((copy, delete))
+    }
+
+    /// `e`, marked to stop the run when this policy is `abort`.
+    pub fn escalate(self, e: io::Error) -> io::Error {
+        if self == ErrorPolicy::Abort && !is_abort(&e) {
+            io::Error::new(e.kind(), Abort(e))
+        } else {
+            e
+        }
+    }
+}
+
+/// Error that stops the run, under the `abort` policy.
+#[derive(Debug)]
+struct Abort(io::Error);
+
+impl fmt::Display for Abort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for Abort {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Whether `e` should stop the run.
+pub fn is_abort(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|e| e.is::<Abort>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ErrorPolicy::parse("abort"),
+            Ok((ErrorPolicy::Abort, ErrorPolicy::Abort))
+        );
+        assert_eq!(
+            ErrorPolicy::parse("copy=skip-file,delete=abort"),
+            Ok((ErrorPolicy::SkipFile, ErrorPolicy::Abort))
+        );
+        assert_eq!(
+            ErrorPolicy::parse("delete=abort"),
+            Ok((ErrorPolicy::SkipRepo, ErrorPolicy::Abort))
+        );
+        assert!(ErrorPolicy::parse("ignore").is_err());
+        assert!(ErrorPolicy::parse("move=abort").is_err());
+    }
+
+    #[test]
+    fn test_escalate() {
+        let e = ErrorPolicy::Abort.escalate(io::Error::other("disk full"));
+        assert!(is_abort(&e));
+        assert_eq!(e.to_string(), "disk full");
+        assert!(!is_abort(
+            &ErrorPolicy::SkipRepo.escalate(io::Error::other("disk full"))
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+use std::io;
+use std::path::{Component, Path};
+
+use crate::paths;
+use crate::scan::{Entry, EntryKind, Scan};
+
+/// Family of the destination filesystem, for the names and sizes it accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetFs {
+    /// ext4, APFS, XFS, ...: any byte but `/` and NUL.
+    Posix,
+    /// NTFS, exFAT and SMB shares: Windows naming rules.
+    Windows,
+    /// FAT32: Windows naming rules and files of less than 4 GiB.
+    Fat,
+}
+
+const WINDOWS_ILLEGAL: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+const FAT_MAX_FILE_SIZE: u64 = (4 << 30) - 1;
+
+impl TargetFs {
+    /// Parses `posix`, `ntfs`, `exfat`, `smb` or `fat32`.
+    pub fn parse(value: &str) -> Result<TargetFs, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "posix" => Ok(TargetFs::Posix),
+            "ntfs" | "exfat" | "smb" | "cifs" => Ok(TargetFs::Windows),
+            "fat" | "fat32" | "vfat" => Ok(TargetFs::Fat),
+            _ => Err(format!(
+                "Invalid filesystem {:?}, expected posix, ntfs, exfat, smb or fat32",
+                value
+            )),
+        }
+    }
+
+    /// Longest file name, in bytes for POSIX filesystems and UTF-16 units otherwise.
+    fn max_name(self) -> usize {
+        255
+    }
+
+    /// Longest full path, in the same units as `max_name`.
+    fn max_path(self) -> usize {
+        match self {
+            TargetFs::Posix => 4095,
+            TargetFs::Windows | TargetFs::Fat => 32767,
+        }
+    }
+
+    /// Why `name` can't be created on this filesystem, if it can't.
+    fn check_name(self, name: &str) -> Option<String> {
+        if self == TargetFs::Posix {
+            return (name.len() > self.max_name())
+                .then(|| format!("name is longer than {} bytes", self.max_name()));
+        }
+        if name.encode_utf16().count() > self.max_name() {
+            return Some(format!(
+                "name is longer than {} characters",
+                self.max_name()
+            ));
+        }
+        if let Some(c) = name
+            .chars()
+            .find(|c| WINDOWS_ILLEGAL.contains(c) || c.is_ascii_control())
+        {
+            return Some(format!("{:?} is not allowed in names", c));
+        }
+        if name.ends_with(['.', ' ']) {
+            return Some("names can't end with a dot or a space".to_string());
+        }
+        if paths::is_reserved_name(name) {
+            return Some(format!("{:?} is a reserved device name", name));
+        }
+        None
+    }
+
+    fn path_len(self, path: &Path) -> usize {
+        match self {
+            TargetFs::Posix => path.as_os_str().len(),
+            TargetFs::Windows | TargetFs::Fat => {
+                path.as_os_str().to_string_lossy().encode_utf16().count()
+            }
+        }
+    }
+}
+
+/// Guesses the family of the filesystem `dst` is (or will be) created on, from its
+/// closest existing ancestor.
+pub fn detect(dst: &Path) -> Option<TargetFs> {
+    let existing = dst.ancestors().find(|p| p.exists())?;
+    detect_existing(existing)
+}
+
+#[cfg(target_os = "linux")]
+fn detect_existing(path: &Path) -> Option<TargetFs> {
+    use std::os::unix::ffi::OsStrExt;
+
+    const MSDOS_SUPER_MAGIC: i64 = 0x4d44;
+    const EXFAT_SUPER_MAGIC: i64 = 0x2011_bab0;
+    const NTFS_SB_MAGIC: i64 = 0x5346_544e;
+    const NTFS3_SUPER_MAGIC: i64 = 0x7366_746e;
+    const CIFS_SUPER_MAGIC: i64 = 0xff53_4d42;
+    const SMB2_SUPER_MAGIC: i64 = 0xfe53_4d42;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    match stat.f_type as i64 {
+        MSDOS_SUPER_MAGIC => Some(TargetFs::Fat),
+        EXFAT_SUPER_MAGIC | NTFS_SB_MAGIC | NTFS3_SUPER_MAGIC | CIFS_SUPER_MAGIC
+        | SMB2_SUPER_MAGIC => Some(TargetFs::Windows),
+        _ => Some(TargetFs::Posix),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn detect_existing(path: &Path) -> Option<TargetFs> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    match name.to_bytes() {
+        b"msdos" => Some(TargetFs::Fat),
+        b"exfat" | b"ntfs" | b"smbfs" => Some(TargetFs::Windows),
+        _ => Some(TargetFs::Posix),
+    }
+}
+
+#[cfg(windows)]
+fn detect_existing(_path: &Path) -> Option<TargetFs> {
+    Some(TargetFs::Windows)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn detect_existing(_path: &Path) -> Option<TargetFs> {
+    None
+}
+
+/// Whether the filesystem `path` is on can clone files: btrfs, XFS and bcachefs on
+/// Linux, APFS on macOS.
+#[cfg(target_os = "linux")]
+pub fn supports_reflink(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    const BTRFS_SUPER_MAGIC: i64 = 0x9123_683e;
+    const XFS_SUPER_MAGIC: i64 = 0x5846_5342;
+    const BCACHEFS_SUPER_MAGIC: i64 = 0xca45_1a4e;
+
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    let kind = stat.f_type as i64;
+    matches!(
+        kind,
+        BTRFS_SUPER_MAGIC | XFS_SUPER_MAGIC | BCACHEFS_SUPER_MAGIC
+    )
+}
+
+#[cfg(target_os = "macos")]
+pub fn supports_reflink(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    name.to_bytes() == b"apfs"
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn supports_reflink(_path: &Path) -> bool {
+    false
+}
+
+/// Lists the entries that can't be created below `dst` on `target`, with the reason.
+pub fn problems(dst: &Path, entries: &[Entry], target: TargetFs) -> Vec<String> {
+    let mut problems = Vec::new();
+    for entry in entries {
+        let Some(Component::Normal(name)) = entry.rel.components().next_back() else {
+            continue;
+        };
+        let dest_path = dst.join(&entry.rel);
+        let reason = target
+            .check_name(&name.to_string_lossy())
+            .or_else(|| {
+                (target.path_len(&dest_path) > target.max_path())
+                    .then(|| format!("path is longer than {}", target.max_path()))
+            })
+            .or_else(|| {
+                (target == TargetFs::Fat
+                    && entry.kind == EntryKind::File
+                    && entry.stamp.size > FAT_MAX_FILE_SIZE)
+                    .then(|| "file is larger than 4 GiB".to_string())
+            });
+        if let Some(reason) = reason {
+            problems.push(format!("{:?}: {}", entry.rel, reason));
+        }
+    }
+    problems
+}
+
+/// Fails, listing every offending entry, if some entries of `scan` can't be created
+/// below `dst` on `target` (detected from `dst` when `None`).
+pub fn check(dst: &Path, scan: &Scan, target: Option<TargetFs>) -> io::Result<()> {
+    let Some(target) = target.or_else(|| detect(dst)) else {
+        return Ok(());
+    };
+    let mut problems = Vec::new();
+    for batch in scan.batches()? {
+        problems.extend(self::problems(dst, &batch?, target));
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "{} entries can't be created in {:?} ({:?} filesystem):\n  {}",
+            problems.len(),
+            dst,
+            target,
+            problems.join("\n  ")
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::FileStamp;
+    use std::path::PathBuf;
+
+    fn file(rel: &str, size: u64) -> Entry {
+        Entry {
+            rel: PathBuf::from(rel),
+            kind: EntryKind::File,
+            stamp: FileStamp { size, mtime: 0 },
+        }
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert_eq!(TargetFs::Posix.check_name("a:b?"), None);
+        assert!(TargetFs::Windows.check_name("a:b").is_some());
+        assert!(TargetFs::Windows.check_name("notes.").is_some());
+        assert!(TargetFs::Windows.check_name("aux.c").is_some());
+        assert_eq!(TargetFs::Windows.check_name("main.rs"), None);
+        assert!(TargetFs::Posix.check_name(&"x".repeat(256)).is_some());
+    }
+
+    #[test]
+    fn test_problems() {
+        let entries = [
+            file("src/main.rs", 10),
+            file("docs/what?.md", 10),
+            file("assets/video.mp4", 5 << 30),
+        ];
+        let dst = Path::new("/mnt/usb/repo");
+        assert!(problems(dst, &entries, TargetFs::Posix).is_empty());
+        assert_eq!(problems(dst, &entries, TargetFs::Windows).len(), 1);
+        assert_eq!(problems(dst, &entries, TargetFs::Fat).len(), 2);
+
+        let deep = file(&"d/".repeat(2100), 0);
+        assert_eq!(problems(dst, &[deep], TargetFs::Posix).len(), 1);
+        assert!(check(dst, &Scan::from(entries.to_vec()), Some(TargetFs::Fat)).is_err());
+    }
+}
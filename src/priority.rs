@@ -0,0 +1,100 @@
+use std::io;
+
+/// I/O scheduling class, as understood by `ionice(1)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    Realtime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+impl IoClass {
+    /// Parses `idle`, `best-effort[:<0-7>]` or `realtime[:<0-7>]`.
+    pub fn parse(value: &str) -> Result<IoClass, String> {
+        let (class, level) = match value.split_once(':') {
+            Some((class, level)) => {
+                let level: u8 = level
+                    .parse()
+                    .ok()
+                    .filter(|l| *l <= 7)
+                    .ok_or_else(|| format!("Invalid I/O priority level: {:?}", level))?;
+                (class, Some(level))
+            }
+            None => (value, None),
+        };
+        match (class, level) {
+            ("idle", None) => Ok(IoClass::Idle),
+            ("best-effort", level) => Ok(IoClass::BestEffort(level.unwrap_or(4))),
+            ("realtime", level) => Ok(IoClass::Realtime(level.unwrap_or(4))),
+            _ => Err(format!(
+                "Invalid I/O class {:?}, expected idle, best-effort[:N] or realtime[:N]",
+                value
+            )),
+        }
+    }
+}
+
+/// Lowers (or, with privileges, raises) the CPU scheduling priority of the process.
+pub fn set_nice(nice: i32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = nice;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--nice is not supported on this platform",
+        ))
+    }
+}
+
+/// Sets the I/O scheduling class of the process, so the disk is shared fairly with
+/// interactive work.
+pub fn set_ionice(class: IoClass) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+        let (class, level) = match class {
+            IoClass::Realtime(level) => (1, level),
+            IoClass::BestEffort(level) => (2, level),
+            IoClass::Idle => (3, 0),
+        };
+        let ioprio = (class << IOPRIO_CLASS_SHIFT) | level as libc::c_int;
+        let ret = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = class;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--ionice is only supported on Linux",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_io_class() {
+        assert_eq!(IoClass::parse("idle"), Ok(IoClass::Idle));
+        assert_eq!(IoClass::parse("best-effort"), Ok(IoClass::BestEffort(4)));
+        assert_eq!(IoClass::parse("realtime:0"), Ok(IoClass::Realtime(0)));
+        assert!(IoClass::parse("idle:3").is_err());
+        assert!(IoClass::parse("best-effort:8").is_err());
+        assert!(IoClass::parse("urgent").is_err());
+    }
+}
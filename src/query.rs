@@ -0,0 +1,357 @@
+use std::cmp::Ordering;
+use std::error::Error;
+
+use crate::inventory::{self, Inventory, InventoryRepo};
+use crate::units;
+
+pub const USAGE: &str = "Usage: query <expression>";
+
+/// What a condition looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Size,
+    LastCommit,
+    Dirty,
+    Path,
+    Name,
+    Remote,
+    Tag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    /// Contains.
+    Like,
+}
+
+impl Op {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+            Op::Like => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Size(u64),
+    Bool(bool),
+    Text(String),
+}
+
+/// A filter over the inventory, such as `size > 1GB and last_commit < 2023-01-01`.
+///
+/// Conditions compare `size`, `last_commit` (`YYYY-MM-DD`), `dirty` (`true` or
+/// `false`), `path`, `name`, `remote` or `tag` to a value with `=`, `!=`, `<`,
+/// `<=`, `>`, `>=`, or `~` for text containing the value. They combine with `and`,
+/// `or`, `not` and parentheses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Cond { field: Field, op: Op, value: Value },
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, at: 0 };
+        let query = parser.or()?;
+        match parser.tokens.get(parser.at) {
+            None => Ok(query),
+            Some(token) => Err(format!("Unexpected {:?} in query {:?}", token, text)),
+        }
+    }
+
+    pub fn matches(&self, repo: &InventoryRepo) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(repo) && b.matches(repo),
+            Query::Or(a, b) => a.matches(repo) || b.matches(repo),
+            Query::Not(query) => !query.matches(repo),
+            Query::Cond { field, op, value } => match (field, value) {
+                (Field::Size, Value::Size(size)) => op.holds(repo.size.cmp(size)),
+                (Field::Dirty, Value::Bool(dirty)) => op.holds(repo.dirty.cmp(dirty)),
+                (Field::LastCommit, Value::Text(date)) => repo
+                    .last_commit
+                    .as_ref()
+                    .is_some_and(|last| op.holds(last.as_str().cmp(date))),
+                (Field::Path, Value::Text(text)) => {
+                    matches_text(&repo.path.to_string_lossy(), *op, text)
+                }
+                (Field::Name, Value::Text(text)) => {
+                    let name = repo.path.file_name().unwrap_or_default().to_string_lossy();
+                    matches_text(&name, *op, text)
+                }
+                (Field::Remote, Value::Text(text)) if *op == Op::Ne => {
+                    !repo.remotes.iter().any(|r| r == text)
+                }
+                (Field::Remote, Value::Text(text)) => {
+                    repo.remotes.iter().any(|r| matches_text(r, *op, text))
+                }
+                (Field::Tag, Value::Text(tag)) => repo.tags.contains(tag) == (*op == Op::Eq),
+                _ => false,
+            },
+        }
+    }
+}
+
+fn matches_text(text: &str, op: Op, value: &str) -> bool {
+    match op {
+        Op::Like => text.contains(value),
+        op => op.holds(text.cmp(value)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '~' => Token::Op(Op::Like),
+            '=' => Token::Op(Op::Eq),
+            '!' | '<' | '>' => {
+                let or_equal = chars.next_if_eq(&'=').is_some();
+                match (c, or_equal) {
+                    ('!', true) => Token::Op(Op::Ne),
+                    ('<', false) => Token::Op(Op::Lt),
+                    ('<', true) => Token::Op(Op::Le),
+                    ('>', false) => Token::Op(Op::Gt),
+                    ('>', true) => Token::Op(Op::Ge),
+                    _ => return Err(format!("Expected != in query {:?}", text)),
+                }
+            }
+            '"' | '\'' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some(other) => word.push(other),
+                        None => return Err(format!("Unclosed {} in query {:?}", c, text)),
+                    }
+                }
+                Token::Word(word)
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(next) =
+                    chars.next_if(|c| !c.is_whitespace() && !"()~=!<>\"'".contains(*c))
+                {
+                    word.push(next);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.tokens.get(self.at), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+        {
+            self.at += 1;
+            return true;
+        }
+        false
+    }
+
+    fn or(&mut self) -> Result<Query, String> {
+        let mut query = self.and()?;
+        while self.keyword("or") {
+            query = Query::Or(Box::new(query), Box::new(self.and()?));
+        }
+        Ok(query)
+    }
+
+    fn and(&mut self) -> Result<Query, String> {
+        let mut query = self.not()?;
+        while self.keyword("and") {
+            query = Query::And(Box::new(query), Box::new(self.not()?));
+        }
+        Ok(query)
+    }
+
+    fn not(&mut self) -> Result<Query, String> {
+        if self.keyword("not") {
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        if self.tokens.get(self.at) == Some(&Token::Open) {
+            self.at += 1;
+            let query = self.or()?;
+            return match self.next() {
+                Some(Token::Close) => Ok(query),
+                _ => Err("Expected ) in query".to_string()),
+            };
+        }
+        self.cond()
+    }
+
+    fn cond(&mut self) -> Result<Query, String> {
+        let field = match self.next() {
+            Some(Token::Word(word)) => match word.as_str() {
+                "size" => Field::Size,
+                "last_commit" => Field::LastCommit,
+                "dirty" => Field::Dirty,
+                "path" => Field::Path,
+                "name" => Field::Name,
+                "remote" => Field::Remote,
+                "tag" => Field::Tag,
+                _ => {
+                    return Err(format!(
+                        "Unknown field {:?} in query, expected size, last_commit, dirty, \
+                         path, name, remote or tag",
+                        word
+                    ))
+                }
+            },
+            other => return Err(format!("Expected a field in query, found {:?}", other)),
+        };
+        let Some(Token::Op(op)) = self.next() else {
+            return Err(format!("Expected an operator after {:?} in query", field));
+        };
+        let Some(Token::Word(text)) = self.next() else {
+            return Err(format!("Expected a value after {:?} in query", field));
+        };
+        let value = match field {
+            Field::Size => Value::Size(units::parse_size(&text)?),
+            Field::Dirty => match text.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => return Err(format!("Expected true or false for dirty, not {:?}", text)),
+            },
+            Field::LastCommit => {
+                let is_date = text.len() == 10
+                    && text.char_indices().all(|(i, c)| {
+                        if i == 4 || i == 7 {
+                            c == '-'
+                        } else {
+                            c.is_ascii_digit()
+                        }
+                    });
+                if !is_date {
+                    return Err(format!("Expected a YYYY-MM-DD date, not {:?}", text));
+                }
+                Value::Text(text)
+            }
+            Field::Path | Field::Name | Field::Remote | Field::Tag => Value::Text(text),
+        };
+        let ordered = matches!(field, Field::Size | Field::LastCommit);
+        if (op == Op::Like && ordered)
+            || (matches!(field, Field::Dirty | Field::Tag) && !matches!(op, Op::Eq | Op::Ne))
+        {
+            return Err(format!("{:?} can't be compared with that operator", field));
+        }
+        Ok(Query::Cond { field, op, value })
+    }
+}
+
+/// The repositories of `inventory` that `query` matches.
+pub fn select<'a>(inventory: &'a Inventory, query: &Query) -> Vec<&'a InventoryRepo> {
+    inventory
+        .repos
+        .iter()
+        .filter(|repo| query.matches(repo))
+        .collect()
+}
+
+/// Runs `query` with the arguments after the subcommand, printing the path of
+/// every matching repository.
+pub fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.is_empty() {
+        return Err(USAGE.into());
+    }
+    let query = Query::parse(&args.join(" "))?;
+    let path = inventory::path().ok_or("HOME is not set, there is no inventory")?;
+    let inventory = Inventory::load(&path)?;
+    for repo in select(&inventory, &query) {
+        println!("{}", repo.path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn repo(path: &str, size: u64, last_commit: Option<&str>, dirty: bool) -> InventoryRepo {
+        InventoryRepo {
+            path: PathBuf::from(path),
+            remotes: vec!["git@github.com:me/app.git".to_string()],
+            size,
+            last_commit: last_commit.map(str::to_string),
+            dirty,
+            seen: 0,
+            tags: vec!["client-x".to_string()],
+            synced: None,
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let old_big = repo("/code/big", 2 << 30, Some("2022-06-01"), false);
+        let new_small = repo("/code/app", 1 << 20, Some("2024-01-01"), true);
+        let empty = repo("/code/empty", 0, None, false);
+
+        let query = Query::parse("size > 1GB and last_commit < 2023-01-01").unwrap();
+        assert!(query.matches(&old_big));
+        assert!(!query.matches(&new_small));
+        assert!(!query.matches(&empty));
+
+        let query = Query::parse("not (dirty = true or name = 'empty')").unwrap();
+        assert!(query.matches(&old_big));
+        assert!(!query.matches(&new_small));
+        assert!(!query.matches(&empty));
+
+        let query = Query::parse("remote ~ github.com:me/ and path ~ /code/").unwrap();
+        assert!(query.matches(&empty));
+        assert!(Query::parse("tag = client-x").unwrap().matches(&empty));
+        assert!(!Query::parse("tag != client-x").unwrap().matches(&empty));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Query::parse("size > big").is_err());
+        assert!(Query::parse("size ~ 1GB").is_err());
+        assert!(Query::parse("owner = me").is_err());
+        assert!(Query::parse("last_commit < 2023").is_err());
+        assert!(Query::parse("(dirty = true").is_err());
+        assert!(Query::parse("dirty = true name = x").is_err());
+    }
+}
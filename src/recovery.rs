@@ -0,0 +1,262 @@
+use std::fs;
+use std::io::{self, BufRead, ErrorKind, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::journal;
+
+const MARKER_SUFFIX: &str = ".mv-git-incomplete";
+
+/// How far the transfer of a repository got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Copying to the destination, the source is untouched.
+    Copy,
+    /// The copy is complete, deleting the source.
+    Delete,
+}
+
+/// Marker left next to a repository's destination while it is being transferred,
+/// e.g. `.repo.mv-git-incomplete` for `repo`. A marker still there when a run
+/// starts belongs to one that was interrupted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Marker {
+    pub source: PathBuf,
+    pub phase: Phase,
+    /// Whether the destination was there before the transfer, in which case
+    /// removing it would lose more than the interrupted copy.
+    pub dest_existed: bool,
+}
+
+/// What to do with the repositories an interrupted run left half-moved, see `--recover`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Complete the transfer.
+    Finish,
+    /// Remove the partial copy, leaving the source as it was.
+    Rollback,
+    /// Only remove the marker and the journals of partly copied files.
+    Clean,
+}
+
+impl Recovery {
+    pub fn parse(value: &str) -> Result<Recovery, String> {
+        match value {
+            "finish" => Ok(Recovery::Finish),
+            "rollback" => Ok(Recovery::Rollback),
+            "clean" => Ok(Recovery::Clean),
+            _ => Err(format!(
+                "Invalid recovery {:?}, expected finish, rollback or clean",
+                value
+            )),
+        }
+    }
+}
+
+fn marker_path(dst: &Path) -> PathBuf {
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+    dst.with_file_name(format!(".{}{}", name, MARKER_SUFFIX))
+}
+
+fn write_marker(dst: &Path, marker: &Marker) -> io::Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(marker_path(dst), serde_json::to_vec(marker)?)
+}
+
+/// Marks the transfer of `src` to `dst` as started.
+pub fn begin(src: &Path, dst: &Path) -> io::Result<()> {
+    let marker = Marker {
+        source: fs::canonicalize(src).unwrap_or_else(|_| src.to_path_buf()),
+        phase: Phase::Copy,
+        dest_existed: dst.exists(),
+    };
+    write_marker(dst, &marker)
+}
+
+/// Marks the copy to `dst` as complete, before the source is deleted.
+pub fn deleting(dst: &Path) -> io::Result<()> {
+    let mut marker = read_marker(&marker_path(dst))?;
+    marker.phase = Phase::Delete;
+    write_marker(dst, &marker)
+}
+
+/// Makes the interrupted transfer to `dst` start over from `src` when finished,
+/// instead of from where it was copied from, if it left a marker.
+pub fn resume_from(dst: &Path, src: &Path) -> io::Result<()> {
+    let mut marker = match read_marker(&marker_path(dst)) {
+        Ok(marker) => marker,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    marker.source = src.to_path_buf();
+    write_marker(dst, &marker)
+}
+
+/// Marks the transfer to `dst` as done.
+pub fn done(dst: &Path) -> io::Result<()> {
+    match fs::remove_file(marker_path(dst)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn read_marker(path: &Path) -> io::Result<Marker> {
+    serde_json::from_slice(&fs::read(path)?).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Destinations under `dest_root` that an interrupted run left half-transferred,
+/// with their markers.
+pub fn find(dest_root: &Path) -> io::Result<Vec<(PathBuf, Marker)>> {
+    let mut found = Vec::new();
+    let entries = match dest_root.read_dir() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(found),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let Some(repo) = name
+            .strip_prefix('.')
+            .and_then(|name| name.strip_suffix(MARKER_SUFFIX))
+        else {
+            continue;
+        };
+        match read_marker(&path) {
+            Ok(marker) => found.push((dest_root.join(repo), marker)),
+            Err(e) => tracing::warn!("Ignoring unreadable marker {:?}: {}", path, e),
+        }
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(found)
+}
+
+/// Removes the journals of partly copied files left in `dir`.
+fn remove_journals(dir: &Path) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            remove_journals(&path)?;
+        } else if path.to_string_lossy().ends_with(journal::SUFFIX) {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies `recovery` to the half-transferred `dst`. `finish_copy` redoes the
+/// transfer from the source, marking it as done itself.
+pub fn recover(
+    dst: &Path,
+    marker: &Marker,
+    recovery: Recovery,
+    finish_copy: impl FnOnce(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    match (recovery, marker.phase) {
+        (Recovery::Finish, Phase::Copy) => return finish_copy(&marker.source, dst),
+        (Recovery::Finish, Phase::Delete) => {
+            if marker.source.exists() {
+                fs::remove_dir_all(&marker.source)?;
+            }
+        }
+        (Recovery::Rollback, Phase::Copy) if !marker.dest_existed => {
+            if dst.exists() {
+                fs::remove_dir_all(dst)?;
+            }
+        }
+        (Recovery::Rollback, _) => {
+            return Err(io::Error::other(if marker.dest_existed {
+                "it was copied over an existing destination, which can't be restored"
+            } else {
+                "the source was already being deleted, finish instead"
+            }));
+        }
+        (Recovery::Clean, _) => remove_journals(dst)?,
+    }
+    done(dst)
+}
+
+/// Asks on the terminal what to do with `dst`, `None` to leave it alone.
+pub fn ask(dst: &Path, marker: &Marker) -> io::Result<Option<Recovery>> {
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let phase = match marker.phase {
+        Phase::Copy => "while copying",
+        Phase::Delete => "while deleting the source",
+    };
+    loop {
+        eprint!(
+            "{:?} was left half-moved from {:?} by a run interrupted {}. \
+             [f]inish, [r]oll back, [c]lean up or [l]eave it? ",
+            dst, marker.source, phase
+        );
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer)? == 0 {
+            return Ok(None);
+        }
+        match answer.trim() {
+            "f" | "finish" => return Ok(Some(Recovery::Finish)),
+            "r" | "rollback" => return Ok(Some(Recovery::Rollback)),
+            "c" | "clean" => return Ok(Some(Recovery::Clean)),
+            "l" | "leave" | "" => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_recover() -> io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src/repo");
+        let dst = dir.path().join("dst/repo");
+        fs::create_dir_all(&src)?;
+
+        begin(&src, &dst)?;
+        fs::create_dir_all(&dst)?;
+        fs::write(dst.join(".HEAD.mv-git-part"), "")?;
+        let found = find(&dir.path().join("dst"))?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, dst);
+        assert_eq!(found[0].1.phase, Phase::Copy);
+        assert!(!found[0].1.dest_existed);
+
+        resume_from(&dst, Path::new("/elsewhere"))?;
+        assert_eq!(
+            find(&dir.path().join("dst"))?[0].1.source,
+            Path::new("/elsewhere")
+        );
+        resume_from(&dir.path().join("dst/other"), &src)?;
+
+        recover(&dst, &found[0].1, Recovery::Clean, |_, _| unreachable!())?;
+        assert!(!dst.join(".HEAD.mv-git-part").exists());
+        assert!(find(&dir.path().join("dst"))?.is_empty());
+
+        begin(&src, &dst)?;
+        let marker = find(&dir.path().join("dst"))?.remove(0).1;
+        assert!(marker.dest_existed);
+        assert!(recover(&dst, &marker, Recovery::Rollback, |_, _| Ok(())).is_err());
+
+        deleting(&dst)?;
+        let marker = find(&dir.path().join("dst"))?.remove(0).1;
+        recover(&dst, &marker, Recovery::Finish, |_, _| unreachable!())?;
+        assert!(!src.exists());
+        assert!(dst.exists());
+        assert!(find(&dir.path().join("dst"))?.is_empty());
+        Ok(())
+    }
+}
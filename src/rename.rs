@@ -0,0 +1,186 @@
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::git;
+
+const PLACEHOLDERS: [&str; 4] = ["name", "parent", "year", "date"];
+
+/// How repositories are renamed at the destination, see `--rename`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rename {
+    /// A name made of `{name}`, `{parent}`, `{year}` and `{date}`, such as `{name}-{year}`.
+    Template(String),
+    /// A sed-style `s/from/to/`, replacing the first `from`, or every one with `g`.
+    Substitute {
+        from: String,
+        to: String,
+        global: bool,
+    },
+}
+
+impl Rename {
+    pub fn parse(value: &str) -> Result<Rename, String> {
+        let mut chars = value.chars();
+        if let (Some('s'), Some(delimiter)) = (chars.next(), chars.next()) {
+            if matches!(delimiter, '/' | '|' | '#') {
+                return Rename::parse_substitute(chars.as_str(), delimiter, value);
+            }
+        }
+        let mut rest = value;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Invalid --rename {:?}, unclosed {{", value))?;
+            let placeholder = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "Invalid --rename {:?}, unknown {{{}}}, expected one of {{{}}}",
+                    value,
+                    placeholder,
+                    PLACEHOLDERS.join("}, {")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        if value.contains(['/', '\\']) {
+            return Err(format!(
+                "Invalid --rename {:?}, names can't contain path separators",
+                value
+            ));
+        }
+        Ok(Rename::Template(value.to_string()))
+    }
+
+    fn parse_substitute(rest: &str, delimiter: char, value: &str) -> Result<Rename, String> {
+        let parts: Vec<&str> = rest.split(delimiter).collect();
+        let [from, to, flags] = parts[..] else {
+            return Err(format!(
+                "Invalid --rename {:?}, expected s{d}from{d}to{d}",
+                value,
+                d = delimiter
+            ));
+        };
+        if from.is_empty() || !matches!(flags, "" | "g") || to.contains(['/', '\\']) {
+            return Err(format!(
+                "Invalid --rename {:?}, expected s{d}from{d}to{d} or s{d}from{d}to{d}g",
+                value,
+                d = delimiter
+            ));
+        }
+        Ok(Rename::Substitute {
+            from: from.to_string(),
+            to: to.to_string(),
+            global: flags == "g",
+        })
+    }
+
+    /// The new name of `repo`, whose name is `name`. A rename that leaves
+    /// nothing keeps `name`.
+    pub fn apply(&self, name: &str, repo: &Path) -> String {
+        let renamed = match self {
+            Rename::Substitute { from, to, global } if *global => name.replace(from, to),
+            Rename::Substitute { from, to, .. } => name.replacen(from, to, 1),
+            Rename::Template(template) => {
+                let date = last_change(repo);
+                let parent = repo
+                    .parent()
+                    .and_then(Path::file_name)
+                    .map(|parent| parent.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                template
+                    .replace("{name}", name)
+                    .replace("{parent}", &parent)
+                    .replace("{year}", date.get(..4).unwrap_or_default())
+                    .replace("{date}", &date)
+            }
+        };
+        if renamed.is_empty() || renamed == "." || renamed == ".." {
+            tracing::warn!("--rename leaves nothing of {:?}, keeping its name", name);
+            return name.to_string();
+        }
+        renamed
+    }
+}
+
+/// Date of the last commit of `repo`, as `YYYY-MM-DD`, or of its last
+/// modification when it has none.
+fn last_change(repo: &Path) -> String {
+    if let Ok(date) = git::git(repo, ["log", "-1", "--format=%cs"]) {
+        if !date.is_empty() {
+            return date;
+        }
+    }
+    let secs = fs::metadata(repo)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    civil_date(secs / 86400)
+}
+
+/// `YYYY-MM-DD` of the day `days` after 1970-01-01.
+fn civil_date(days: u64) -> String {
+    // Howard Hinnant's days_from_civil, inverted.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Rename::parse("s/-old/-new/g"),
+            Ok(Rename::Substitute {
+                from: "-old".to_string(),
+                to: "-new".to_string(),
+                global: true,
+            })
+        );
+        assert_eq!(
+            Rename::parse("{name}-{year}"),
+            Ok(Rename::Template("{name}-{year}".to_string()))
+        );
+        assert!(Rename::parse("{name}-{month}").is_err());
+        assert!(Rename::parse("{name").is_err());
+        assert!(Rename::parse("s/a/b").is_err());
+        assert!(Rename::parse("archive/{name}").is_err());
+    }
+
+    #[test]
+    fn test_apply() -> io::Result<()> {
+        let dir = tempdir()?;
+        let repo = dir.path().join("clients").join("api");
+        git::init_repo_with_commit(&repo, "README.md")?;
+
+        let rename = Rename::parse("{parent}-{name}-{year}").unwrap();
+        let renamed = rename.apply("api", &repo);
+        assert!(renamed.starts_with("clients-api-20"), "{}", renamed);
+        assert_eq!(renamed.len(), "clients-api-2026".len());
+
+        let rename = Rename::parse("s/a/o/").unwrap();
+        assert_eq!(rename.apply("banana", &repo), "bonana");
+        let rename = Rename::parse("s|api||").unwrap();
+        assert_eq!(rename.apply("api", &repo), "api");
+        Ok(())
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), "1970-01-01");
+        assert_eq!(civil_date(19_782), "2024-02-29");
+    }
+}
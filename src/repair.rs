@@ -0,0 +1,298 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::links::rebase_target;
+
+pub const USAGE: &str = "Usage: repair <repo> [--from <old-path>]";
+
+/// Parses the `repair` arguments, after the subcommand: the repository and where
+/// it was before, if given.
+pub fn parse_args(args: &[String]) -> Result<(PathBuf, Option<PathBuf>), Box<dyn Error>> {
+    let mut repo = None;
+    let mut from = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE))?;
+                from = Some(PathBuf::from(value));
+            }
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("Unknown option: {}\n{}", arg, USAGE).into());
+            }
+            _ if repo.is_none() => repo = Some(PathBuf::from(arg)),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    Ok((repo.ok_or(USAGE)?, from))
+}
+
+/// `value` moved from under `old` to under `new`, `None` if it isn't a path under `old`.
+fn rebase_value(value: &str, old: &Path, new: &Path) -> Option<String> {
+    let rebased = rebase_target(Path::new(value), old, new);
+    if rebased == Path::new(value) {
+        return None;
+    }
+    // `old` itself comes out as `new/`, keep the trailing slash only if it was there.
+    let mut rebased = rebased.to_string_lossy().into_owned();
+    if rebased.ends_with('/') && !value.ends_with('/') {
+        rebased.pop();
+    }
+    Some(rebased)
+}
+
+/// Rewrites the lines of the file at `path` with `rewrite`, returning whether any changed.
+fn rewrite_lines(path: &Path, rewrite: impl Fn(&str) -> Option<String>) -> io::Result<bool> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Ok(false);
+    };
+    let mut changed = false;
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let (content, end) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        match rewrite(content) {
+            Some(new_line) => {
+                changed = true;
+                out.push_str(&new_line);
+            }
+            None => out.push_str(content),
+        }
+        out.push_str(end);
+    }
+    if changed {
+        fs::write(path, out)?;
+    }
+    Ok(changed)
+}
+
+/// `gitdir: <path>` files, as found in linked worktrees and submodules.
+fn rewrite_gitfile(path: &Path, old: &Path, new: &Path) -> io::Result<bool> {
+    rewrite_lines(path, |line| {
+        let value = line.strip_prefix("gitdir: ")?;
+        rebase_value(value, old, new).map(|value| format!("gitdir: {}", value))
+    })
+}
+
+/// Keys of git config files whose value is a path.
+const PATH_KEYS: [&str; 3] = ["worktree", "hooksPath", "excludesFile"];
+
+/// `core.worktree`, `core.hooksPath` and `core.excludesFile` values and
+/// `includeIf "gitdir:..."` conditions of a git config file.
+fn rewrite_config(path: &Path, old: &Path, new: &Path) -> io::Result<bool> {
+    rewrite_lines(path, |line| {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        if let Some((key, value)) = trimmed.split_once('=') {
+            let key = key.trim_end();
+            // Keys are case-insensitive.
+            if PATH_KEYS.iter().any(|k| k.eq_ignore_ascii_case(key)) {
+                let value = rebase_value(value.trim(), old, new)?;
+                return Some(format!("{}{} = {}", indent, key, value));
+            }
+        }
+        for condition in ["gitdir:", "gitdir/i:"] {
+            let prefix = format!("[includeIf \"{}", condition);
+            if let Some(rest) = trimmed.strip_prefix(&prefix) {
+                let (value, end) = rest.split_once('"')?;
+                let value = rebase_value(value, old, new)?;
+                return Some(format!("{}{}{}\"{}", indent, prefix, value, end));
+            }
+        }
+        None
+    })
+}
+
+fn rewrite_alternates(path: &Path, old: &Path, new: &Path) -> io::Result<bool> {
+    rewrite_lines(path, |line| rebase_value(line, old, new))
+}
+
+/// Repairs the git directory `git_dir` and those of the submodules it holds.
+fn repair_git_dir(
+    git_dir: &Path,
+    old: &Path,
+    new: &Path,
+    fixed: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let mut record = |path: PathBuf, changed: bool| {
+        if changed {
+            fixed.push(path);
+        }
+    };
+    let config = git_dir.join("config");
+    record(config.clone(), rewrite_config(&config, old, new)?);
+    let alternates = git_dir.join("objects").join("info").join("alternates");
+    record(
+        alternates.clone(),
+        rewrite_alternates(&alternates, old, new)?,
+    );
+
+    // Linked worktrees: their `gitdir` file here points to their `.git` file,
+    // which points back here, wherever the worktree is.
+    if let Ok(worktrees) = fs::read_dir(git_dir.join("worktrees")) {
+        for worktree in worktrees {
+            let admin = worktree?.path();
+            let gitdir = admin.join("gitdir");
+            record(
+                gitdir.clone(),
+                rewrite_lines(&gitdir, |line| rebase_value(line, old, new))?,
+            );
+            if let Ok(gitfile) = fs::read_to_string(&gitdir) {
+                let gitfile = PathBuf::from(gitfile.trim());
+                record(gitfile.clone(), rewrite_gitfile(&gitfile, old, new)?);
+            }
+        }
+    }
+
+    if let Ok(modules) = fs::read_dir(git_dir.join("modules")) {
+        for module in modules {
+            let module = module?.path();
+            if module.is_dir() {
+                repair_git_dir(&module, old, new, fixed)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `.git` files below `dir`, those of submodules and linked worktrees kept inside
+/// the repository.
+fn gitfiles(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if entry.file_name() == ".git" {
+            if file_type.is_file() {
+                found.push(entry.path());
+            }
+        } else if file_type.is_dir() {
+            gitfiles(&entry.path(), found)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fixes what moving the repository `repo` from `old` breaks: absolute paths under
+/// `old` in its config (`core.worktree`, `core.hooksPath`, `includeIf`...), alternates, linked
+/// worktrees and submodules now point to the same place under `repo`.
+/// Returns the files that were changed.
+pub fn repair(repo: &Path, old: &Path) -> io::Result<Vec<PathBuf>> {
+    let new = fs::canonicalize(repo)?;
+    let mut fixed = Vec::new();
+    if old == new {
+        return Ok(fixed);
+    }
+    let git_dir = new.join(".git");
+    if git_dir.is_dir() {
+        repair_git_dir(&git_dir, old, &new, &mut fixed)?;
+    }
+    let mut found = Vec::new();
+    for entry in fs::read_dir(&new)? {
+        let entry = entry?;
+        if entry.file_name() != ".git" && entry.file_type()?.is_dir() {
+            gitfiles(&entry.path(), &mut found)?;
+        }
+    }
+    for gitfile in found {
+        if rewrite_gitfile(&gitfile, old, &new)? {
+            fixed.push(gitfile);
+        }
+    }
+    fixed.sort();
+    fixed.dedup();
+    Ok(fixed)
+}
+
+/// Where the repository at `repo` was before a move, going by the `gitdir:` files
+/// of its submodules and linked worktrees that now point to a missing `.git`.
+pub fn guess_old_location(repo: &Path) -> io::Result<Option<PathBuf>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(repo)? {
+        let entry = entry?;
+        if entry.file_name() != ".git" && entry.file_type()?.is_dir() {
+            gitfiles(&entry.path(), &mut found)?;
+        }
+    }
+    for gitfile in found {
+        let Ok(text) = fs::read_to_string(&gitfile) else {
+            continue;
+        };
+        let Some(target) = text.trim().strip_prefix("gitdir: ").map(Path::new) else {
+            continue;
+        };
+        if !target.is_absolute() || target.exists() {
+            continue;
+        }
+        let mut old = PathBuf::new();
+        for component in target.components() {
+            if component == Component::Normal(".git".as_ref()) {
+                return Ok(Some(old));
+            }
+            old.push(component);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_repair() -> io::Result<()> {
+        let dir = tempdir()?;
+        let old = Path::new("/old/place/repo");
+        let repo = dir.path().join("repo");
+        fs::create_dir_all(repo.join(".git/objects/info"))?;
+        fs::create_dir_all(repo.join(".git/modules/lib"))?;
+        fs::create_dir_all(repo.join("lib"))?;
+        fs::write(
+            repo.join(".git/config"),
+            "[core]\n\tbare = false\n\tworktree = /old/place/repo\n\
+             \thookspath = /old/place/repo/.githooks\n\texcludesFile = /etc/gitignore\n\
+             [includeIf \"gitdir:/old/place/repo/\"]\n\tpath = work.inc\n",
+        )?;
+        fs::write(
+            repo.join(".git/objects/info/alternates"),
+            "/old/place/repo/../shared/objects\n/elsewhere/objects\n",
+        )?;
+        fs::write(
+            repo.join(".git/modules/lib/config"),
+            "[core]\n\tworktree = /old/place/repo/lib\n",
+        )?;
+        fs::write(
+            repo.join("lib/.git"),
+            "gitdir: /old/place/repo/.git/modules/lib\n",
+        )?;
+
+        assert_eq!(guess_old_location(&repo)?, Some(old.to_path_buf()));
+        let fixed = repair(&repo, old)?;
+        assert_eq!(fixed.len(), 4);
+
+        let new = fs::canonicalize(&repo)?;
+        let config = fs::read_to_string(repo.join(".git/config"))?;
+        assert!(config.contains(&format!("\tworktree = {}\n", new.display())));
+        assert!(config.contains(&format!(
+            "\thookspath = {}\n",
+            new.join(".githooks").display()
+        )));
+        assert!(config.contains("\texcludesFile = /etc/gitignore\n"));
+        assert!(config.contains(&format!("[includeIf \"gitdir:{}/\"]", new.display())));
+        let alternates = fs::read_to_string(repo.join(".git/objects/info/alternates"))?;
+        assert!(alternates.ends_with("\n/elsewhere/objects\n"));
+        assert_eq!(
+            fs::read_to_string(repo.join("lib/.git"))?,
+            format!("gitdir: {}\n", new.join(".git/modules/lib").display())
+        );
+        assert_eq!(guess_old_location(&repo)?, None);
+        assert!(repair(&repo, old)?.is_empty());
+        Ok(())
+    }
+}
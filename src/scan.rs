@@ -0,0 +1,997 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::git;
+use crate::paths;
+use crate::spill::{self, Spill};
+
+/// Size and modification time of a file, used to tell whether it changed between runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime: i64,
+}
+
+impl FileStamp {
+    pub fn from_metadata(metadata: &Metadata) -> FileStamp {
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos() as i64);
+        FileStamp {
+            size: metadata.len(),
+            mtime,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(unix), allow(dead_code))]
+pub enum EntryKind {
+    Dir,
+    File,
+    /// Symbolic link (or NTFS junction), recreated rather than followed.
+    Symlink,
+    /// Named pipe.
+    Fifo,
+    Socket,
+    /// Block or character device node.
+    Device,
+}
+
+impl EntryKind {
+    fn of(file_type: fs::FileType) -> EntryKind {
+        if file_type.is_symlink() {
+            return EntryKind::Symlink;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            if file_type.is_fifo() {
+                return EntryKind::Fifo;
+            }
+            if file_type.is_socket() {
+                return EntryKind::Socket;
+            }
+            if file_type.is_block_device() || file_type.is_char_device() {
+                return EntryKind::Device;
+            }
+        }
+        if file_type.is_dir() {
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        }
+    }
+}
+
+/// A directory or file found by the pre-scan, relative to the scanned root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub rel: PathBuf,
+    pub kind: EntryKind,
+    pub stamp: FileStamp,
+}
+
+/// Whether `gitignore` leaves out the entries named `name`. `.git` never is,
+/// whatever the ignored names say, as that would lose the repository.
+pub fn is_ignored(name: &str, gitignore: &Option<Vec<String>>) -> bool {
+    name != ".git"
+        && gitignore
+            .as_ref()
+            .is_some_and(|gi| gi.iter().any(|g| g == name))
+}
+
+/// Whether `rel` is in a `.git` directory, whose content the ignored names
+/// don't apply to: `config` or `logs` are meant for the worktree.
+fn in_git_dir(rel: &Path) -> bool {
+    rel.components().any(|c| c.as_os_str() == ".git")
+}
+
+/// What to do with hidden files and directories outside of `.git`, see `--hidden`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Hidden {
+    #[default]
+    Copy,
+    /// Leave them out, unless git tracks them.
+    Skip,
+}
+
+impl Hidden {
+    pub fn parse(value: &str) -> Result<Hidden, String> {
+        match value {
+            "copy" => Ok(Hidden::Copy),
+            "skip" => Ok(Hidden::Skip),
+            _ => Err(format!(
+                "Invalid hidden file policy {:?}, expected copy or skip",
+                value
+            )),
+        }
+    }
+}
+
+/// Which files of the worktree are transferred by their extension, see
+/// `--only-extensions` and `--skip-extensions`. Extensions are compared
+/// without their dot and regardless of case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Extensions {
+    /// Only files with one of these extensions.
+    Only(Vec<String>),
+    /// Files without one of these extensions.
+    Skip(Vec<String>),
+}
+
+impl Extensions {
+    /// Parses a comma-separated list of extensions, such as `rs,toml,md` or `.mp4,.iso`.
+    pub fn parse_list(value: &str) -> Result<Vec<String>, String> {
+        value
+            .split(',')
+            .map(|extension| {
+                let extension = extension.trim().trim_start_matches('.');
+                if extension.is_empty() {
+                    Err(format!("Invalid extension list {:?}", value))
+                } else {
+                    Ok(extension.to_lowercase())
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the file `rel` is transferred.
+    pub fn keeps(&self, rel: &Path) -> bool {
+        let extension = rel
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let listed = |extensions: &[String]| {
+            extension
+                .as_ref()
+                .is_some_and(|extension| extensions.contains(extension))
+        };
+        match self {
+            Extensions::Only(extensions) => listed(extensions),
+            Extensions::Skip(extensions) => !listed(extensions),
+        }
+    }
+}
+
+/// What the pre-scan should leave out, beyond the repository's ignore list.
+#[derive(Debug, Default, Clone)]
+pub struct ScanOptions {
+    /// Don't descend into directories on a different filesystem than the root.
+    pub one_file_system: bool,
+    /// Memory the pre-scan may take before entries other than directories are
+    /// spilled to a temporary file.
+    pub memory_limit: Option<usize>,
+    /// Leave out what git itself doesn't need in `.git`, see [`is_git_cruft`].
+    pub prune_git_cruft: bool,
+    /// Leave out the reflogs of `.git`, see [`is_reflog`].
+    pub strip_reflogs: bool,
+    /// Whether dotfiles such as `.env` or `.idea/` are transferred.
+    pub hidden: Hidden,
+    /// Leave out files larger than this many bytes, outside of `.git`.
+    pub max_file_size: Option<u64>,
+    /// Leave out the files of the worktree by their extension, never those of `.git`.
+    pub extensions: Option<Extensions>,
+}
+
+/// Reflogs untouched for this long are left out by `--prune-git-cruft`, as old as
+/// what `git gc` expires by default.
+const OLD_LOG_SECS: i64 = 90 * 86400;
+
+/// Whether the file `rel` in `.git` is something git can do without: temporary
+/// packs, lock files left by a crashed command, `gc.log`, and reflogs untouched
+/// for 90 days.
+fn is_git_cruft(rel: &Path, stamp: FileStamp, now: i64) -> bool {
+    let Ok(inside) = rel.strip_prefix(".git") else {
+        return false;
+    };
+    let name = inside.file_name().unwrap_or_default().to_string_lossy();
+    if inside.starts_with("objects/pack") {
+        return name.starts_with("tmp_") || name.starts_with(".tmp-");
+    }
+    if inside.starts_with("logs") {
+        return now - stamp.mtime / 1_000_000_000 > OLD_LOG_SECS;
+    }
+    name.ends_with(".lock") || inside == Path::new("gc.log")
+}
+
+/// Whether `rel` is a reflog, or a directory of them: the history of where
+/// the branches and `HEAD` of the repository or its linked worktrees pointed.
+fn is_reflog(rel: &Path) -> bool {
+    let Ok(inside) = rel.strip_prefix(".git") else {
+        return false;
+    };
+    let mut components = inside.components().map(|c| c.as_os_str());
+    match components.next() {
+        Some(dir) if dir == "logs" => true,
+        Some(dir) if dir == "worktrees" => components.nth(1).is_some_and(|dir| dir == "logs"),
+        _ => false,
+    }
+}
+
+/// Result of a pre-scan.
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Entries kept in memory: all of them, or the directories and whatever came
+    /// before the memory limit was reached.
+    pub entries: Vec<Entry>,
+    /// Directories, relative to the root, on which another filesystem is mounted,
+    /// or a bind mount.
+    pub mount_points: Vec<PathBuf>,
+    /// Files left out for being over `max_file_size`, relative to the root, with
+    /// their size.
+    pub too_large: Vec<(PathBuf, u64)>,
+    /// How many files were left out for their extension.
+    pub filtered: usize,
+    /// Entries past the memory limit, in no particular order.
+    spill: Option<Spill>,
+    memory: usize,
+}
+
+#[cfg(test)]
+impl From<Vec<Entry>> for Scan {
+    fn from(entries: Vec<Entry>) -> Scan {
+        Scan {
+            entries,
+            ..Scan::default()
+        }
+    }
+}
+
+impl Scan {
+    fn push(&mut self, entry: Entry, memory_limit: Option<usize>) -> io::Result<()> {
+        if let Some(spill) = self.spill.as_mut() {
+            if entry.kind != EntryKind::Dir {
+                return spill.push(&entry);
+            }
+        }
+        self.memory += spill::footprint(&entry);
+        self.entries.push(entry);
+        if self.spill.is_none() && memory_limit.is_some_and(|limit| self.memory > limit) {
+            let mut spill = Spill::new()?;
+            for entry in self.entries.iter().filter(|e| e.kind != EntryKind::Dir) {
+                spill.push(entry)?;
+            }
+            self.entries.retain(|e| e.kind == EntryKind::Dir);
+            self.memory = self.entries.iter().map(spill::footprint).sum();
+            self.spill = Some(spill);
+        }
+        Ok(())
+    }
+
+    /// Directories outside of `.git` holding nothing but other such directories,
+    /// left empty by what the scan left out.
+    pub fn empty_dirs(&self) -> io::Result<Vec<PathBuf>> {
+        let mut filled = HashSet::new();
+        for batch in self.batches()? {
+            for entry in batch?.iter().filter(|e| e.kind != EntryKind::Dir) {
+                let mut dir = entry.rel.parent();
+                while let Some(path) = dir.filter(|p| !p.as_os_str().is_empty()) {
+                    if !filled.insert(path.to_path_buf()) {
+                        break;
+                    }
+                    dir = path.parent();
+                }
+            }
+        }
+        Ok(self
+            .entries
+            .iter()
+            .filter(|e| e.kind == EntryKind::Dir)
+            .filter(|e| !e.rel.starts_with(".git") && !filled.contains(&e.rel))
+            .map(|e| e.rel.clone())
+            .collect())
+    }
+
+    /// Whether part of the entries had to be spilled to disk.
+    pub fn is_spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    /// All entries, in batches: first those in memory, sorted by path, then the
+    /// spilled ones. Directories are always in the first batch.
+    pub fn batches(&self) -> io::Result<impl Iterator<Item = io::Result<Cow<'_, [Entry]>>>> {
+        let spilled = match &self.spill {
+            Some(spill) => Some(spill.batches()?),
+            None => None,
+        };
+        Ok(std::iter::once(Ok(Cow::Borrowed(&self.entries[..]))).chain(
+            spilled
+                .into_iter()
+                .flatten()
+                .map(|batch| batch.map(Cow::Owned)),
+        ))
+    }
+}
+
+#[cfg(unix)]
+fn device(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+/// Mount points below `root`, relative to it, as the kernel lists them. This
+/// finds bind mounts too, which are on the device of the directory holding them.
+#[cfg(target_os = "linux")]
+fn mounts_below(root: &Path) -> HashSet<PathBuf> {
+    match (
+        fs::canonicalize(root),
+        fs::read_to_string("/proc/self/mountinfo"),
+    ) {
+        (Ok(root), Ok(mountinfo)) => parse_mounts_below(&root, &mountinfo),
+        _ => HashSet::new(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mounts_below(_root: &Path) -> HashSet<PathBuf> {
+    HashSet::new()
+}
+
+/// The mount points below `root` in `mountinfo`, the format of
+/// `/proc/self/mountinfo`: the fifth field of each line is a mount point, with
+/// spaces and the like escaped in octal.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_mounts_below(root: &Path, mountinfo: &str) -> HashSet<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(|field| PathBuf::from(unescape_octal(field)))
+        .filter_map(|mount| {
+            let rel = mount.strip_prefix(root).ok()?;
+            (!rel.as_os_str().is_empty()).then(|| rel.to_path_buf())
+        })
+        .collect()
+}
+
+/// `field` with its `\NNN` octal escapes replaced by the characters they stand for.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn unescape_octal(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(at) = rest.find('\\') {
+        unescaped.push_str(&rest[..at]);
+        let code = rest
+            .get(at + 1..at + 4)
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(char::from(code));
+                rest = &rest[at + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[at + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+struct WalkState {
+    pending: Vec<PathBuf>,
+    active: usize,
+    error: Option<io::Error>,
+}
+
+/// Shared state of a parallel walk below `root`.
+struct Walker<'a> {
+    root: &'a Path,
+    gitignore: &'a Option<Vec<String>>,
+    /// Paths tracked by git, kept even when `gitignore` matches them.
+    tracked: Option<HashSet<PathBuf>>,
+    options: &'a ScanOptions,
+    root_device: Option<u64>,
+    /// Mount points below `root`, relative to it, bind mounts included.
+    mounts: HashSet<PathBuf>,
+    /// Start of the walk, in seconds since the epoch.
+    now: i64,
+    state: Mutex<WalkState>,
+    wakeup: Condvar,
+    results: Mutex<Scan>,
+}
+
+/// Number of threads walking directories concurrently. Walking is bound by
+/// `read_dir`/`stat` latency rather than CPU, so this oversubscribes the cores.
+fn walk_threads() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get() * 2)
+}
+
+impl Walker<'_> {
+    /// Reads one directory, returning its entries and the subdirectories left to walk.
+    fn scan_dir(&self, rel: &Path) -> io::Result<Scan> {
+        let mut scan = Scan::default();
+        let skip_hidden = self.options.hidden == Hidden::Skip && !rel.starts_with(".git");
+        let left_out = |name: &str| {
+            is_ignored(name, self.gitignore)
+                || (skip_hidden && name.starts_with('.') && name != ".git")
+        };
+        // Only walked for the tracked files it holds.
+        let in_ignored = rel
+            .components()
+            .take_while(|c| c.as_os_str() != ".git")
+            .any(|c| left_out(&c.as_os_str().to_string_lossy()));
+        let inside_git_dir = in_git_dir(rel);
+        for entry in fs::read_dir(self.root.join(rel))? {
+            let entry = entry?;
+            let entry_name = entry.file_name().to_string_lossy().into_owned();
+            let entry_rel = rel.join(entry.file_name());
+            if (in_ignored || (!inside_git_dir && left_out(&entry_name)))
+                && !self
+                    .tracked
+                    .as_ref()
+                    .is_some_and(|tracked| tracked.contains(&entry_rel))
+            {
+                continue;
+            }
+
+            if self.options.strip_reflogs && is_reflog(&entry_rel) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            let kind = EntryKind::of(entry.file_type()?);
+            let stamp = FileStamp::from_metadata(&metadata);
+            if self.options.prune_git_cruft
+                && kind != EntryKind::Dir
+                && is_git_cruft(&entry_rel, stamp, self.now)
+            {
+                tracing::debug!("Leaving out {:?}", entry_rel);
+                continue;
+            }
+            if kind == EntryKind::File
+                && !entry_rel.starts_with(".git")
+                && self
+                    .options
+                    .max_file_size
+                    .is_some_and(|max| stamp.size > max)
+            {
+                scan.too_large.push((entry_rel, stamp.size));
+                continue;
+            }
+            if kind == EntryKind::File
+                && !in_git_dir(&entry_rel)
+                && self
+                    .options
+                    .extensions
+                    .as_ref()
+                    .is_some_and(|extensions| !extensions.keeps(&entry_rel))
+            {
+                scan.filtered += 1;
+                continue;
+            }
+            if kind == EntryKind::Dir
+                && (device(&metadata) != self.root_device || self.mounts.contains(&entry_rel))
+            {
+                scan.mount_points.push(entry_rel.clone());
+            }
+            scan.entries.push(Entry {
+                rel: entry_rel,
+                kind,
+                stamp,
+            });
+        }
+        Ok(scan)
+    }
+
+    /// Subdirectories of a scanned directory that the walk should descend into.
+    fn subdirs<'s>(&self, scan: &'s Scan) -> impl Iterator<Item = PathBuf> + 's {
+        let one_file_system = self.options.one_file_system;
+        scan.entries
+            .iter()
+            .filter(|e| e.kind == EntryKind::Dir)
+            .filter(move |e| !(one_file_system && scan.mount_points.contains(&e.rel)))
+            .map(|e| e.rel.clone())
+    }
+
+    /// Pulls directories off the shared queue until the walk is done or failed.
+    fn work(&self) {
+        loop {
+            let rel = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if state.error.is_some() {
+                        return;
+                    }
+                    if let Some(rel) = state.pending.pop() {
+                        state.active += 1;
+                        break rel;
+                    }
+                    if state.active == 0 {
+                        return;
+                    }
+                    state = self.wakeup.wait(state).unwrap();
+                }
+            };
+
+            let scanned = self.scan_dir(&rel);
+            let mut state = self.state.lock().unwrap();
+            state.active -= 1;
+            match scanned {
+                Ok(scan) => {
+                    state.pending.extend(self.subdirs(&scan));
+                    let mut results = self.results.lock().unwrap();
+                    results.mount_points.extend(scan.mount_points);
+                    results.too_large.extend(scan.too_large);
+                    results.filtered += scan.filtered;
+                    for entry in scan.entries {
+                        if let Err(e) = results.push(entry, self.options.memory_limit) {
+                            state.error = Some(e);
+                            break;
+                        }
+                    }
+                }
+                Err(e) => state.error = Some(e),
+            }
+            self.wakeup.notify_all();
+        }
+    }
+}
+
+/// Lists everything under `root` that should be transferred, skipping names in `gitignore`,
+/// and hidden ones if `options` say so, unless git tracks them. Directories are
+/// walked in parallel; the entries kept in memory are sorted by path, so
+/// directories always come before their content.
+pub fn scan(
+    root: &Path,
+    gitignore: &Option<Vec<String>>,
+    options: &ScanOptions,
+) -> io::Result<Scan> {
+    let tracked =
+        if (gitignore.is_some() || options.hidden == Hidden::Skip) && root.join(".git").exists() {
+            git::tracked_paths(root)
+                .inspect_err(|e| {
+                    tracing::warn!("Could not list the files tracked in {:?}: {}", root, e)
+                })
+                .ok()
+        } else {
+            None
+        };
+    let root = &paths::extended(root);
+    let walker = Walker {
+        root,
+        gitignore,
+        tracked,
+        options,
+        root_device: device(&fs::metadata(root)?),
+        mounts: mounts_below(root),
+        now: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64),
+        state: Mutex::new(WalkState {
+            pending: vec![PathBuf::new()],
+            active: 0,
+            error: None,
+        }),
+        wakeup: Condvar::new(),
+        results: Mutex::new(Scan::default()),
+    };
+
+    thread::scope(|s| {
+        for _ in 0..walk_threads() {
+            s.spawn(|| walker.work());
+        }
+    });
+
+    if let Some(e) = walker.state.into_inner().unwrap().error {
+        return Err(e);
+    }
+    let mut scan = walker.results.into_inner().unwrap();
+    scan.entries.sort_by(|a, b| a.rel.cmp(&b.rel));
+    scan.mount_points.sort();
+    scan.too_large.sort();
+    if let Some(spill) = scan.spill.as_mut() {
+        spill.flush()?;
+    }
+    Ok(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scan() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(dir.path().join("sub").join("file.txt"), "hello")?;
+        fs::create_dir(dir.path().join("ignored"))?;
+        File::create(dir.path().join("ignored").join("file.txt"))?;
+
+        let gitignore = Some(vec!["ignored".to_string()]);
+        let entries = scan(dir.path(), &gitignore, &ScanOptions::default())?.entries;
+
+        let rels: Vec<&Path> = entries.iter().map(|e| e.rel.as_path()).collect();
+        assert_eq!(rels, vec![Path::new("sub"), Path::new("sub/file.txt")]);
+        assert_eq!(entries[1].kind, EntryKind::File);
+        assert_eq!(entries[1].stamp.size, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_keeps_tracked_ignored_files() -> io::Result<()> {
+        let dir = tempdir()?;
+        git::init_repo_with_commit(dir.path(), "build")?;
+        fs::create_dir(dir.path().join("dist"))?;
+        fs::write(dir.path().join("dist").join("tracked.js"), "")?;
+        fs::write(dir.path().join("dist").join("built.js"), "")?;
+        git::git(dir.path(), ["add", "dist/tracked.js"])?;
+
+        let gitignore = Some(vec!["build".to_string(), "dist".to_string()]);
+        let entries = scan(dir.path(), &gitignore, &ScanOptions::default())?.entries;
+
+        let rels: Vec<&Path> = entries
+            .iter()
+            .map(|e| e.rel.as_path())
+            .filter(|rel| !rel.starts_with(".git"))
+            .collect();
+        assert_eq!(
+            rels,
+            vec![
+                Path::new("build"),
+                Path::new("dist"),
+                Path::new("dist/tracked.js")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_ignored() {
+        let gitignore = Some(vec![
+            ".git".to_string(),
+            ".git*".to_string(),
+            "dist".to_string(),
+        ]);
+        assert!(!is_ignored(".git", &gitignore));
+        assert!(is_ignored(".git*", &gitignore));
+        assert!(is_ignored("dist", &gitignore));
+        assert!(!is_ignored("dist", &None));
+        assert!(in_git_dir(Path::new(".git/refs")));
+        assert!(in_git_dir(Path::new("vendor/lib/.git")));
+        assert!(!in_git_dir(Path::new(".github/workflows")));
+    }
+
+    #[test]
+    fn test_scan_never_ignores_git_dir() -> io::Result<()> {
+        let dir = tempdir()?;
+        git::init_repo_with_commit(dir.path(), "README.md")?;
+        git::init_repo_with_commit(&dir.path().join("vendor/lib"), "README.md")?;
+        fs::create_dir_all(dir.path().join("logs"))?;
+        fs::write(dir.path().join("logs/app.log"), "")?;
+        fs::write(dir.path().join("config"), "")?;
+
+        // What a `.gitignore` of `.git/`, `/.git`, `logs/` and so on reads as.
+        let names = [".git", ".git*", "logs", "config", "HEAD", "objects", "refs"];
+        let gitignore = Some(names.iter().map(|name| name.to_string()).collect());
+        for hidden in [Hidden::Copy, Hidden::Skip] {
+            let options = ScanOptions {
+                hidden,
+                ..ScanOptions::default()
+            };
+            let entries = scan(dir.path(), &gitignore, &options)?.entries;
+            let rels: Vec<&Path> = entries.iter().map(|e| e.rel.as_path()).collect();
+            for kept in [
+                ".git/HEAD",
+                ".git/config",
+                ".git/logs/HEAD",
+                "vendor/lib/.git/HEAD",
+            ] {
+                assert!(rels.contains(&Path::new(kept)), "{:?}: {}", hidden, kept);
+            }
+            assert!(rels
+                .iter()
+                .any(|rel| rel.starts_with(".git/objects") && rel.components().count() > 3));
+            assert!(rels.contains(&Path::new("vendor/lib/.git/refs/heads")));
+            for left_out in ["logs", "logs/app.log", "config"] {
+                assert!(
+                    !rels.contains(&Path::new(left_out)),
+                    "{:?}: {}",
+                    hidden,
+                    left_out
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_dirs() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("web/node_modules/react"))?;
+        fs::create_dir_all(dir.path().join("web/src"))?;
+        fs::write(dir.path().join("web/src/app.js"), "")?;
+        fs::create_dir_all(dir.path().join("assets/cache"))?;
+        fs::create_dir_all(dir.path().join(".git/refs/tags"))?;
+
+        let gitignore = Some(vec!["node_modules".to_string()]);
+        let scan = scan(dir.path(), &gitignore, &ScanOptions::default())?;
+        assert_eq!(
+            scan.empty_dirs()?,
+            vec![PathBuf::from("assets"), PathBuf::from("assets/cache")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_leaves_out_large_files() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join(".git"))?;
+        fs::write(dir.path().join(".git").join("packed-refs"), "0123456789")?;
+        fs::write(dir.path().join("small.txt"), "hello")?;
+        fs::write(dir.path().join("dump.sql"), "0123456789")?;
+
+        let options = ScanOptions {
+            max_file_size: Some(5),
+            ..ScanOptions::default()
+        };
+        let scan = scan(dir.path(), &None, &options)?;
+
+        let rels: Vec<&Path> = scan.entries.iter().map(|e| e.rel.as_path()).collect();
+        assert_eq!(
+            rels,
+            vec![
+                Path::new(".git"),
+                Path::new(".git/packed-refs"),
+                Path::new("small.txt")
+            ]
+        );
+        assert_eq!(scan.too_large, vec![(PathBuf::from("dump.sql"), 10)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extensions() {
+        assert_eq!(
+            Extensions::parse_list("rs, .TOML,md"),
+            Ok(vec!["rs".to_string(), "toml".to_string(), "md".to_string()])
+        );
+        assert!(Extensions::parse_list("rs,,md").is_err());
+        let only = Extensions::Only(vec!["rs".to_string(), "md".to_string()]);
+        assert!(only.keeps(Path::new("src/main.rs")));
+        assert!(only.keeps(Path::new("README.MD")));
+        assert!(!only.keeps(Path::new("Makefile")));
+        let skip = Extensions::Skip(vec!["mp4".to_string()]);
+        assert!(!skip.keeps(Path::new("media/intro.mp4")));
+        assert!(skip.keeps(Path::new("Makefile")));
+    }
+
+    #[test]
+    fn test_scan_filters_extensions() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join(".git/objects/pack"))?;
+        fs::write(dir.path().join(".git/objects/pack/pack-1.pack"), "")?;
+        fs::write(dir.path().join(".git/HEAD"), "")?;
+        fs::create_dir(dir.path().join("media"))?;
+        fs::write(dir.path().join("media/intro.mp4"), "")?;
+        fs::write(dir.path().join("main.rs"), "")?;
+
+        let options = ScanOptions {
+            extensions: Some(Extensions::Only(vec!["rs".to_string()])),
+            ..ScanOptions::default()
+        };
+        let scan = scan(dir.path(), &None, &options)?;
+
+        let rels: Vec<&Path> = scan.entries.iter().map(|e| e.rel.as_path()).collect();
+        assert_eq!(
+            rels,
+            vec![
+                Path::new(".git"),
+                Path::new(".git/HEAD"),
+                Path::new(".git/objects"),
+                Path::new(".git/objects/pack"),
+                Path::new(".git/objects/pack/pack-1.pack"),
+                Path::new("main.rs"),
+                Path::new("media"),
+            ]
+        );
+        assert_eq!(scan.filtered, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_skips_hidden_files() -> io::Result<()> {
+        let dir = tempdir()?;
+        git::init_repo_with_commit(dir.path(), ".gitattributes")?;
+        fs::write(dir.path().join(".env"), "SECRET=1")?;
+        fs::create_dir(dir.path().join(".vscode"))?;
+        fs::write(dir.path().join(".vscode").join("settings.json"), "{}")?;
+        fs::write(dir.path().join(".vscode").join("launch.json"), "{}")?;
+        git::git(dir.path(), ["add", ".vscode/settings.json"])?;
+
+        let options = ScanOptions {
+            hidden: Hidden::Skip,
+            ..ScanOptions::default()
+        };
+        let entries = scan(dir.path(), &None, &options)?.entries;
+
+        assert!(entries.iter().any(|e| e.rel == Path::new(".git/HEAD")));
+        let rels: Vec<&Path> = entries
+            .iter()
+            .map(|e| e.rel.as_path())
+            .filter(|rel| !rel.starts_with(".git"))
+            .collect();
+        assert_eq!(
+            rels,
+            vec![
+                Path::new(".gitattributes"),
+                Path::new(".vscode"),
+                Path::new(".vscode/settings.json")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_git_cruft() {
+        let now = 1_000 * 86400;
+        let fresh = FileStamp {
+            size: 1,
+            mtime: (now - 86400) * 1_000_000_000,
+        };
+        let old = FileStamp {
+            size: 1,
+            mtime: (now - 100 * 86400) * 1_000_000_000,
+        };
+        let cruft = |rel: &str, stamp| is_git_cruft(Path::new(rel), stamp, now);
+        assert!(cruft(".git/objects/pack/tmp_pack_abc", fresh));
+        assert!(cruft(".git/index.lock", fresh));
+        assert!(cruft(".git/refs/heads/main.lock", fresh));
+        assert!(cruft(".git/gc.log", fresh));
+        assert!(cruft(".git/logs/HEAD", old));
+        assert!(!cruft(".git/logs/HEAD", fresh));
+        assert!(!cruft(".git/objects/pack/pack-abc.pack", old));
+        assert!(!cruft(".git/index", old));
+        assert!(!cruft("src/main.lock", fresh));
+    }
+
+    #[test]
+    fn test_is_reflog() {
+        assert!(is_reflog(Path::new(".git/logs")));
+        assert!(is_reflog(Path::new(".git/logs/refs/heads/main")));
+        assert!(is_reflog(Path::new(".git/worktrees/fix/logs/HEAD")));
+        assert!(!is_reflog(Path::new(".git/worktrees/logs")));
+        assert!(!is_reflog(Path::new(".git/refs/heads/logs")));
+        assert!(!is_reflog(Path::new("logs/app.log")));
+    }
+
+    #[test]
+    fn test_scan_deep_tree() -> io::Result<()> {
+        let dir = tempdir()?;
+        let mut expected = Vec::new();
+        for a in 0..5 {
+            for b in 0..5 {
+                let sub = PathBuf::from(format!("{}", a)).join(format!("{}", b));
+                fs::create_dir_all(dir.path().join(&sub))?;
+                File::create(dir.path().join(&sub).join("file"))?;
+                expected.push(sub.join("file"));
+            }
+        }
+
+        let entries = scan(dir.path(), &None, &ScanOptions::default())?.entries;
+
+        let files: Vec<PathBuf> = entries
+            .into_iter()
+            .filter(|e| e.kind == EntryKind::File)
+            .map(|e| e.rel)
+            .collect();
+        assert_eq!(files, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_spills_past_memory_limit() -> io::Result<()> {
+        let dir = tempdir()?;
+        for i in 0..50 {
+            fs::create_dir(dir.path().join(format!("dir{}", i)))?;
+            File::create(dir.path().join(format!("dir{}", i)).join("file"))?;
+        }
+        let options = ScanOptions {
+            memory_limit: Some(1024),
+            ..ScanOptions::default()
+        };
+        let scan = scan(dir.path(), &None, &options)?;
+
+        assert!(scan.is_spilled());
+        assert!(scan.entries.iter().all(|e| e.kind == EntryKind::Dir));
+        let mut all = Vec::new();
+        for batch in scan.batches()? {
+            all.extend(batch?.iter().cloned());
+        }
+        assert_eq!(all.len(), 100);
+        assert_eq!(all.iter().filter(|e| e.kind == EntryKind::File).count(), 50);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_missing_root() {
+        assert!(scan(Path::new("/does/not/exist"), &None, &ScanOptions::default()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_special_files() -> io::Result<()> {
+        let dir = tempdir()?;
+        let _socket = std::os::unix::net::UnixListener::bind(dir.path().join("socket"))?;
+
+        let entries = scan(dir.path(), &None, &ScanOptions::default())?.entries;
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].kind, EntryKind::Socket);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_does_not_follow_symlinks() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("target"))?;
+        File::create(dir.path().join("target").join("file"))?;
+        std::os::unix::fs::symlink("target", dir.path().join("link"))?;
+
+        let entries = scan(dir.path(), &None, &ScanOptions::default())?.entries;
+
+        let kinds: Vec<(&Path, EntryKind)> =
+            entries.iter().map(|e| (e.rel.as_path(), e.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (Path::new("link"), EntryKind::Symlink),
+                (Path::new("target"), EntryKind::Dir),
+                (Path::new("target/file"), EntryKind::File),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_mounts_below() {
+        let mountinfo = concat!(
+            "22 1 8:1 / / rw,relatime - ext4 /dev/sda1 rw\n",
+            "40 22 8:1 /data /home/me/src/api/data rw - ext4 /dev/sda1 rw\n",
+            "41 22 0:5 / /home/me/src/api/my\\040cache rw - tmpfs tmpfs rw\n",
+            "42 22 0:6 / /home/me/src/apis rw - tmpfs tmpfs rw\n",
+            "43 22 0:7 / /home/me/src/api rw - tmpfs tmpfs rw\n",
+        );
+        let mut mounts: Vec<PathBuf> = parse_mounts_below(Path::new("/home/me/src/api"), mountinfo)
+            .into_iter()
+            .collect();
+        mounts.sort();
+        assert_eq!(
+            mounts,
+            vec![PathBuf::from("data"), PathBuf::from("my cache")]
+        );
+        assert_eq!(unescape_octal(r"a\134b\x"), r"a\b\x");
+    }
+
+    #[test]
+    fn test_scan_without_mount_points() -> io::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("a").join("b"))?;
+
+        let options = ScanOptions {
+            one_file_system: true,
+            ..ScanOptions::default()
+        };
+        let scan = scan(dir.path(), &None, &options)?;
+
+        assert!(scan.mount_points.is_empty());
+        assert_eq!(scan.entries.len(), 2);
+        Ok(())
+    }
+}
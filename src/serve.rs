@@ -0,0 +1,292 @@
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::units;
+
+pub const USAGE: &str = "Usage: serve --stdio [--progress-interval DURATION]";
+
+/// Interval between the `progress` events of a running transfer, unless told otherwise.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Error codes of JSON-RPC 2.0.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// A request that was understood, but failed.
+pub const FAILED: i64 = -32000;
+
+/// Why a request got no result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn invalid_params(message: impl Display) -> RpcError {
+        RpcError {
+            code: INVALID_PARAMS,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn failed(message: impl Display) -> RpcError {
+        RpcError {
+            code: FAILED,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Where the messages go, one JSON object per line, shared by the responses
+/// and the notifications sent while a request runs.
+pub struct Output<W>(Arc<Mutex<W>>);
+
+impl<W> Clone for Output<W> {
+    fn clone(&self) -> Self {
+        Output(Arc::clone(&self.0))
+    }
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(writer: W) -> Output<W> {
+        Output(Arc::new(Mutex::new(writer)))
+    }
+
+    fn send(&self, message: &Value) -> io::Result<()> {
+        let mut writer = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        writeln!(writer, "{}", message)?;
+        writer.flush()
+    }
+
+    /// Sends the notification `method`, which gets no response.
+    pub fn notify(&self, method: &str, params: Value) -> io::Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+}
+
+/// Options of `serve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServeOptions {
+    /// Interval between the `progress` events telling how far the copy of the
+    /// repository in flight got.
+    pub progress_interval: Duration,
+}
+
+/// Parses the arguments of `serve`: only stdio is served for now.
+pub fn parse_args(args: &[String]) -> Result<ServeOptions, String> {
+    let mut stdio = false;
+    let mut opts = ServeOptions {
+        progress_interval: PROGRESS_INTERVAL,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--stdio" => stdio = true,
+            "--progress-interval" => {
+                let value = args.next().ok_or(USAGE)?;
+                opts.progress_interval = units::parse_duration(value)?;
+                if opts.progress_interval.is_zero() {
+                    return Err("--progress-interval must be more than 0".to_string());
+                }
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    if !stdio {
+        return Err(USAGE.to_string());
+    }
+    Ok(opts)
+}
+
+/// Calls a function every interval on another thread, until dropped.
+pub struct Ticker {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Ticker {
+    pub fn start(interval: Duration, mut tick: impl FnMut() + Send + 'static) -> Ticker {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while stopped.recv_timeout(interval) == Err(RecvTimeoutError::Timeout) {
+                tick();
+            }
+        });
+        Ticker {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        // Disconnecting wakes the thread up right away.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers the JSON-RPC 2.0 requests read from `input`, one per line, with
+/// what `handle` returns for their method and params, until `input` ends.
+pub fn serve<W: Write>(
+    input: impl BufRead,
+    output: &Output<W>,
+    mut handle: impl FnMut(&str, &Value) -> Result<Value, RpcError>,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => respond(&request, &mut handle),
+            Err(e) => Some(error(Value::Null, PARSE_ERROR, e)),
+        };
+        if let Some(response) = response {
+            output.send(&response)?;
+        }
+    }
+    Ok(())
+}
+
+/// The response to `request`, `None` for notifications.
+fn respond(
+    request: &Value,
+    handle: &mut impl FnMut(&str, &Value) -> Result<Value, RpcError>,
+) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        return Some(error(
+            id.unwrap_or(Value::Null),
+            INVALID_REQUEST,
+            "Expected a request with a method",
+        ));
+    };
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let result = handle(method, &params);
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => error(id, e.code, e.message),
+    })
+}
+
+fn error(id: Value, code: i64, message: impl Display) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.to_string() },
+    })
+}
+
+/// The array of strings `name` of `params`.
+pub fn strings(params: &Value, name: &str) -> Result<Vec<String>, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_array)
+        .and_then(|values| {
+            values
+                .iter()
+                .map(|value| value.as_str().map(str::to_string))
+                .collect()
+        })
+        .ok_or_else(|| {
+            RpcError::invalid_params(format!("Expected {:?}, an array of strings", name))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serve() -> io::Result<()> {
+        let input = concat!(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "echo", "params": {"args": ["a"]}}"#,
+            "\n\n",
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "echo", "params": {}}"#,
+            "\n",
+            r#"{"jsonrpc": "2.0", "method": "echo", "params": {"args": []}}"#,
+            "\n",
+            r#"{"jsonrpc": "2.0", "id": "x", "method": "move"}"#,
+            "\nnot json\n",
+        );
+        let output = Output::new(Vec::new());
+        let progress = output.clone();
+        serve(input.as_bytes(), &output, |method, params| match method {
+            "echo" => {
+                let args = strings(params, "args")?;
+                progress
+                    .notify("progress", json!({ "args": args }))
+                    .unwrap();
+                Ok(json!(args))
+            }
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unknown method {:?}", method),
+            }),
+        })?;
+
+        let written = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let messages: Vec<Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0]["method"], "progress");
+        assert_eq!(
+            messages[1],
+            json!({ "jsonrpc": "2.0", "id": 1, "result": ["a"] })
+        );
+        assert_eq!(messages[2]["error"]["code"], INVALID_PARAMS);
+        // The notification got no response, only its progress.
+        assert_eq!(messages[3]["params"], json!({ "args": [] }));
+        assert_eq!(messages[4]["id"], "x");
+        assert_eq!(messages[4]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(messages[5]["error"]["code"], PARSE_ERROR);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args =
+            |args: &[&str]| parse_args(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>());
+        assert_eq!(
+            args(&["--stdio"]).map(|opts| opts.progress_interval),
+            Ok(PROGRESS_INTERVAL)
+        );
+        assert_eq!(
+            args(&["--stdio", "--progress-interval", "250ms"]).map(|opts| opts.progress_interval),
+            Ok(Duration::from_millis(250))
+        );
+        assert!(args(&["--progress-interval", "1s"]).is_err());
+        assert!(args(&["--stdio", "--progress-interval", "0s"]).is_err());
+        assert!(args(&["--stdio", "--progress-interval"]).is_err());
+    }
+
+    #[test]
+    fn test_ticker() {
+        let ticks = Arc::new(Mutex::new(0));
+        let counted = Arc::clone(&ticks);
+        let ticker = Ticker::start(Duration::from_millis(10), move || {
+            *counted.lock().unwrap() += 1;
+        });
+        thread::sleep(Duration::from_millis(100));
+        drop(ticker);
+        let after = *ticks.lock().unwrap();
+        assert!(after > 0);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(*ticks.lock().unwrap(), after);
+    }
+}
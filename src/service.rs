@@ -0,0 +1,234 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Parsed `install-service` command line.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ServiceOptions {
+    /// Name of the unit files or launchd label.
+    pub name: String,
+    /// `hourly`, `daily`, `weekly`, `monthly`, or any systemd calendar expression.
+    pub schedule: String,
+    /// Arguments of the scheduled mv-git run.
+    pub args: Vec<String>,
+}
+
+pub const USAGE: &str =
+    "Usage: install-service [--name <name>] [--schedule <when>] --sync <source>... <destination> [options]";
+
+/// Splits the `install-service` arguments (after the subcommand) into the
+/// service's own options and those of the scheduled run.
+pub fn parse_args(args: &[String]) -> Result<ServiceOptions, Box<dyn Error>> {
+    let mut opts = ServiceOptions {
+        name: "mv-git-sync".to_string(),
+        schedule: "daily".to_string(),
+        args: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" | "--schedule" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE))?;
+                if arg == "--name" {
+                    opts.name = value.clone();
+                } else {
+                    opts.schedule = value.clone();
+                }
+            }
+            _ => opts.args.push(arg.clone()),
+        }
+    }
+    if !opts.args.iter().any(|arg| arg == "--sync") {
+        return Err(format!("install-service schedules --sync runs\n{}", USAGE).into());
+    }
+    Ok(opts)
+}
+
+/// `value` quoted for a systemd `ExecStart=` line.
+fn systemd_quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{}\"", escaped)
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Service and timer units running `exe args` in `cwd` on `schedule`.
+pub fn systemd_units(opts: &ServiceOptions, exe: &Path, cwd: &Path) -> (String, String) {
+    let command: Vec<String> = std::iter::once(exe.to_string_lossy().into_owned())
+        .chain(opts.args.iter().cloned())
+        .map(|arg| systemd_quote(&arg))
+        .collect();
+    let service = format!(
+        "[Unit]\n\
+         Description=mv-git sync ({name})\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         WorkingDirectory={cwd}\n\
+         ExecStart={command}\n",
+        name = opts.name,
+        cwd = systemd_quote(&cwd.to_string_lossy()),
+        command = command.join(" "),
+    );
+    let timer = format!(
+        "[Unit]\n\
+         Description=Run mv-git sync ({name}) {schedule}\n\
+         \n\
+         [Timer]\n\
+         OnCalendar={schedule}\n\
+         Persistent=true\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        name = opts.name,
+        schedule = opts.schedule,
+    );
+    (service, timer)
+}
+
+/// `StartCalendarInterval` entries for the schedules launchd can express.
+fn launchd_interval(schedule: &str) -> Result<&'static str, String> {
+    match schedule {
+        "hourly" => Ok("<key>Minute</key><integer>0</integer>"),
+        "daily" => Ok("<key>Hour</key><integer>0</integer><key>Minute</key><integer>0</integer>"),
+        "weekly" => Ok("<key>Weekday</key><integer>1</integer>\
+                        <key>Hour</key><integer>0</integer><key>Minute</key><integer>0</integer>"),
+        "monthly" => Ok("<key>Day</key><integer>1</integer>\
+                         <key>Hour</key><integer>0</integer><key>Minute</key><integer>0</integer>"),
+        _ => Err(format!(
+            "Unsupported schedule {:?} for launchd, expected hourly, daily, weekly or monthly",
+            schedule
+        )),
+    }
+}
+
+/// launchd agent running `exe args` in `cwd` on `schedule`.
+pub fn launchd_plist(opts: &ServiceOptions, exe: &Path, cwd: &Path) -> Result<String, String> {
+    let label = format!("io.github.mv-git.{}", opts.name);
+    let arguments: String = std::iter::once(exe.to_string_lossy().into_owned())
+        .chain(opts.args.iter().cloned())
+        .map(|arg| format!("    <string>{}</string>\n", xml_escape(&arg)))
+        .collect();
+    Ok(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         <key>Label</key><string>{label}</string>\n\
+         <key>WorkingDirectory</key><string>{cwd}</string>\n\
+         <key>ProgramArguments</key>\n\
+         <array>\n\
+         {arguments}\
+         </array>\n\
+         <key>StartCalendarInterval</key><dict>{interval}</dict>\n\
+         </dict>\n\
+         </plist>\n",
+        label = xml_escape(&label),
+        cwd = xml_escape(&cwd.to_string_lossy()),
+        arguments = arguments,
+        interval = launchd_interval(&opts.schedule)?,
+    ))
+}
+
+fn home() -> Result<PathBuf, Box<dyn Error>> {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| "HOME is not set".into())
+}
+
+/// Writes the user service for `opts` and prints how to enable it.
+pub fn install(opts: &ServiceOptions) -> Result<(), Box<dyn Error>> {
+    let exe = env::current_exe()?;
+    let cwd = env::current_dir()?;
+    if cfg!(target_os = "macos") {
+        let dir = home()?.join("Library/LaunchAgents");
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("io.github.mv-git.{}.plist", opts.name));
+        fs::write(&path, launchd_plist(opts, &exe, &cwd)?)?;
+        println!("Wrote {}", path.display());
+        println!("Enable it with: launchctl load -w {}", path.display());
+    } else if cfg!(unix) {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(config) => PathBuf::from(config),
+            None => home()?.join(".config"),
+        }
+        .join("systemd/user");
+        fs::create_dir_all(&dir)?;
+        let (service, timer) = systemd_units(opts, &exe, &cwd);
+        let service_path = dir.join(format!("{}.service", opts.name));
+        let timer_path = dir.join(format!("{}.timer", opts.name));
+        fs::write(&service_path, service)?;
+        fs::write(&timer_path, timer)?;
+        println!(
+            "Wrote {} and {}",
+            service_path.display(),
+            timer_path.display()
+        );
+        println!(
+            "Enable it with: systemctl --user daemon-reload && systemctl --user enable --now {}.timer",
+            opts.name
+        );
+    } else {
+        return Err("install-service supports systemd and launchd only".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let opts = parse_args(&args(&["--sync", "src", "dst", "--schedule", "weekly"])).unwrap();
+        assert_eq!(opts.schedule, "weekly");
+        assert_eq!(opts.name, "mv-git-sync");
+        assert_eq!(opts.args, args(&["--sync", "src", "dst"]));
+        assert!(parse_args(&args(&["src", "dst"])).is_err());
+    }
+
+    #[test]
+    fn test_systemd_units() {
+        let opts = parse_args(&args(&["--sync", "my repos", "/backup"])).unwrap();
+        let (service, timer) =
+            systemd_units(&opts, Path::new("/usr/bin/mv-git"), Path::new("/home/me"));
+        assert!(
+            service.contains("ExecStart=\"/usr/bin/mv-git\" \"--sync\" \"my repos\" \"/backup\"\n")
+        );
+        assert!(service.contains("WorkingDirectory=\"/home/me\"\n"));
+        assert!(timer.contains("OnCalendar=daily\n"));
+        assert_eq!(systemd_quote("100%"), "\"100%%\"");
+    }
+
+    #[test]
+    fn test_launchd_plist() {
+        let mut opts = parse_args(&args(&["--sync", "a&b", "/backup"])).unwrap();
+        let plist = launchd_plist(
+            &opts,
+            Path::new("/usr/local/bin/mv-git"),
+            Path::new("/Users/me"),
+        )
+        .unwrap();
+        assert!(plist.contains("<string>a&amp;b</string>"));
+        assert!(plist.contains("<key>Hour</key><integer>0</integer>"));
+        opts.schedule = "*-*-* 03:00".to_string();
+        assert!(launchd_plist(&opts, Path::new("mv-git"), Path::new("/")).is_err());
+    }
+}
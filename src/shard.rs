@@ -0,0 +1,236 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dedupe;
+
+/// Name of the file recording where each repository landed, kept at the root
+/// of every `--shard-across` destination.
+pub const INDEX_FILE: &str = "mv-git-shards.json";
+
+/// Where one repository was placed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placement {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+}
+
+/// Placements recorded by previous runs, the same on every destination so that
+/// any one of them tells where all the repositories are.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ShardIndex {
+    pub repos: Vec<Placement>,
+}
+
+impl ShardIndex {
+    /// Loads the index of the destination `root`, starting empty if there is none yet.
+    pub fn load(root: &Path) -> io::Result<ShardIndex> {
+        let path = root.join(INDEX_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ShardIndex::default()),
+            Err(e) => return Err(e),
+        };
+        serde_json::from_str(&text).map_err(|e| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid shard index {:?}: {}", path, e),
+            )
+        })
+    }
+
+    /// Loads and merges the indexes of all of `roots`.
+    pub fn load_all(roots: &[PathBuf]) -> io::Result<ShardIndex> {
+        let mut index = ShardIndex::default();
+        for root in roots {
+            for placement in ShardIndex::load(root)?.repos {
+                index.record(placement);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Records `placement`, replacing what was recorded for the same source or dest.
+    pub fn record(&mut self, placement: Placement) {
+        self.repos
+            .retain(|p| p.source != placement.source && p.dest != placement.dest);
+        self.repos.push(placement);
+        self.repos.sort_by(|a, b| a.dest.cmp(&b.dest));
+    }
+
+    /// Where `source` was placed by a previous run.
+    pub fn dest_of(&self, source: &Path) -> Option<&Path> {
+        self.repos
+            .iter()
+            .find(|p| p.source == source)
+            .map(|p| p.dest.as_path())
+    }
+
+    /// Writes the index to the root of every one of `roots`.
+    pub fn save_all(&self, roots: &[PathBuf]) -> io::Result<()> {
+        let text = serde_json::to_string_pretty(self)? + "\n";
+        for root in roots {
+            fs::write(root.join(INDEX_FILE), &text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem of `dir`.
+#[cfg(unix)]
+pub fn free_space(dir: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_dir: &Path) -> io::Result<u64> {
+    Ok(u64::MAX)
+}
+
+/// One destination of `--shard-across`, with what is left on it.
+#[derive(Debug)]
+struct Shard {
+    root: PathBuf,
+    available: u64,
+    placed: usize,
+}
+
+/// Spreads repositories across several destinations, see `--shard-across`.
+#[derive(Debug)]
+pub struct Shards {
+    shards: Vec<Shard>,
+    index: ShardIndex,
+}
+
+impl Shards {
+    /// Measures the free space of every one of `roots` and loads their index.
+    pub fn new(roots: &[PathBuf]) -> io::Result<Shards> {
+        let shards = roots
+            .iter()
+            .map(|root| {
+                Ok(Shard {
+                    root: root.clone(),
+                    available: free_space(root)?,
+                    placed: 0,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Shards {
+            shards,
+            index: ShardIndex::load_all(roots)?,
+        })
+    }
+
+    /// The destination `source` goes to: the one it was placed on by a previous
+    /// run, or else the one with the most space left, taking turns between those
+    /// with as much. Its size is set aside on the destination chosen.
+    pub fn place(&mut self, source: &Path) -> io::Result<PathBuf> {
+        let size = dedupe::dir_size(source)?;
+        let previous = self.index.dest_of(source).and_then(|dest| {
+            self.shards
+                .iter()
+                .position(|shard| dest.starts_with(&shard.root))
+        });
+        let i = match previous {
+            Some(i) => i,
+            None => (0..self.shards.len())
+                .max_by(|&a, &b| {
+                    let (a, b) = (&self.shards[a], &self.shards[b]);
+                    a.available.cmp(&b.available).then(b.placed.cmp(&a.placed))
+                })
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "No destination"))?,
+        };
+        let shard = &mut self.shards[i];
+        if previous.is_none() && shard.available < size {
+            tracing::warn!(
+                "{:?} does not fit on any destination, placing it on {:?}",
+                source,
+                shard.root
+            );
+        }
+        shard.available = shard.available.saturating_sub(size);
+        shard.placed += 1;
+        Ok(shard.root.clone())
+    }
+}
+
+/// Parses the comma separated destinations of `--shard-across`.
+pub fn parse_roots(value: &str) -> Result<Vec<String>, String> {
+    let roots: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|root| !root.is_empty())
+        .map(str::to_string)
+        .collect();
+    if roots.len() < 2 {
+        return Err(format!(
+            "Invalid --shard-across {:?}, expected at least two comma separated destinations",
+            value
+        ));
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_place() -> io::Result<()> {
+        let dir = tempdir()?;
+        let roots = vec![dir.path().join("a"), dir.path().join("b")];
+        for root in &roots {
+            fs::create_dir(root)?;
+        }
+        for repo in ["big", "small", "other"] {
+            fs::create_dir(dir.path().join(repo))?;
+        }
+        fs::write(dir.path().join("big/file"), "0123456789")?;
+        fs::write(dir.path().join("small/file"), "01")?;
+
+        let mut shards = Shards {
+            shards: roots
+                .iter()
+                .map(|root| Shard {
+                    root: root.clone(),
+                    available: 20,
+                    placed: 0,
+                })
+                .collect(),
+            index: ShardIndex::default(),
+        };
+        let big = shards.place(&dir.path().join("big"))?;
+        assert_ne!(shards.place(&dir.path().join("small"))?, big);
+        assert_ne!(shards.place(&dir.path().join("other"))?, big);
+
+        let mut index = ShardIndex::default();
+        index.record(Placement {
+            source: dir.path().join("big"),
+            dest: roots[1].join("big"),
+        });
+        index.save_all(&roots)?;
+        let mut shards = Shards::new(&roots)?;
+        assert_eq!(shards.place(&dir.path().join("big"))?, roots[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_roots() {
+        assert_eq!(
+            parse_roots("/mnt/a, /mnt/b"),
+            Ok(vec!["/mnt/a".to_string(), "/mnt/b".to_string()])
+        );
+        assert!(parse_roots("/mnt/a").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// The first SIGINT or SIGTERM received, 0 until then.
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// Whether SIGUSR1 paused the run.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(signal: libc::c_int) {
+    if RECEIVED.swap(signal, Ordering::SeqCst) != 0 {
+        // Asked twice: stop right away, the way the default action would.
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+        return;
+    }
+    // Only async-signal-safe calls in here, which rules out the logger.
+    let message = b"Stopping after the file in flight, interrupt again to stop right away\n";
+    unsafe {
+        libc::write(2, message.as_ptr().cast(), message.len());
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_pause(_: libc::c_int) {
+    let message: &[u8] = if toggle_pause() {
+        b"Pausing after the file in flight, send SIGUSR1 again to resume\n"
+    } else {
+        b"Resuming\n"
+    };
+    unsafe {
+        libc::write(2, message.as_ptr().cast(), message.len());
+    }
+}
+
+/// Makes SIGINT and SIGTERM stop the run after the file being copied rather
+/// than in the middle of it, see [`received`]. A second one stops it right away.
+/// SIGUSR1 pauses the run after the file being copied, and resumes it the next
+/// time, see [`wait_while_paused`].
+#[cfg(unix)]
+pub fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    let pause = on_pause as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+        libc::signal(libc::SIGUSR1, pause);
+    }
+}
+
+/// Signals keep their default action on other platforms.
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// The signal that asked the run to stop, if one did.
+pub fn received() -> Option<i32> {
+    Some(RECEIVED.load(Ordering::SeqCst)).filter(|&signal| signal != 0)
+}
+
+/// Pauses the run if it is running, resumes it otherwise. Returns whether it
+/// is now paused.
+pub fn toggle_pause() -> bool {
+    !PAUSED.fetch_xor(true, Ordering::SeqCst)
+}
+
+/// Blocks while the run is paused, unless asked to stop meanwhile. Returns
+/// whether it had to wait.
+pub fn wait_while_paused() -> bool {
+    let mut waited = false;
+    while PAUSED.load(Ordering::SeqCst) && received().is_none() {
+        waited = true;
+        thread::sleep(Duration::from_millis(100));
+    }
+    waited
+}
+
+/// The name of `signal`, for messages.
+pub fn name(signal: i32) -> String {
+    #[cfg(unix)]
+    match signal {
+        libc::SIGINT => return "SIGINT".to_string(),
+        libc::SIGTERM => return "SIGTERM".to_string(),
+        _ => {}
+    }
+    format!("signal {}", signal)
+}
+
+/// The error a copy stops with once asked to, between two files.
+pub fn interrupted() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Interrupted,
+        "interrupted between two files, the next run can finish the copy",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause() {
+        assert!(!wait_while_paused());
+        assert!(toggle_pause());
+        let resume = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(200));
+            toggle_pause()
+        });
+        assert!(wait_while_paused());
+        assert!(!resume.join().unwrap());
+        assert!(!wait_while_paused());
+    }
+}
@@ -0,0 +1,255 @@
+use std::ffi::OsStr;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::{debug, warn};
+
+/// A temporary read-only snapshot of the btrfs subvolume or ZFS dataset holding
+/// a repository, for `--snapshot` copies to read from while the repository
+/// keeps changing. Deleted when dropped.
+#[derive(Debug)]
+pub struct Snapshot {
+    kind: Kind,
+    /// The repository, as the snapshot holds it.
+    repo: PathBuf,
+}
+
+#[derive(Debug)]
+enum Kind {
+    /// A snapshot subvolume, at this path.
+    Btrfs(PathBuf),
+    /// A snapshot, by its `dataset@name`.
+    Zfs(String),
+}
+
+impl Snapshot {
+    /// Snapshots the filesystem holding the repository `repo`. Fails with
+    /// [`ErrorKind::Unsupported`] when it is neither btrfs nor ZFS.
+    pub fn take(repo: &Path) -> io::Result<Snapshot> {
+        let repo = std::fs::canonicalize(repo)?;
+        let name = format!("mv-git-{}-{}", std::process::id(), unique());
+        let snapshot = match filesystem(&repo) {
+            Some(Filesystem::Btrfs) => {
+                let subvolume = subvolume_root(&repo)?;
+                let path = subvolume.join(format!(".{}", name));
+                run(
+                    "btrfs",
+                    [
+                        OsStr::new("subvolume"),
+                        OsStr::new("snapshot"),
+                        OsStr::new("-r"),
+                        subvolume.as_os_str(),
+                        path.as_os_str(),
+                    ],
+                )?;
+                Snapshot {
+                    repo: path.join(relative(&repo, &subvolume)),
+                    kind: Kind::Btrfs(path),
+                }
+            }
+            Some(Filesystem::Zfs) => {
+                let listed = run(
+                    "zfs",
+                    [
+                        OsStr::new("list"),
+                        OsStr::new("-H"),
+                        OsStr::new("-o"),
+                        OsStr::new("name,mountpoint"),
+                        repo.as_os_str(),
+                    ],
+                )?;
+                let (dataset, mountpoint) = parse_zfs_list(&listed).ok_or_else(|| {
+                    io::Error::other(format!("no ZFS dataset mounted at {:?}", repo))
+                })?;
+                let snapshot = format!("{}@{}", dataset, name);
+                run("zfs", [OsStr::new("snapshot"), OsStr::new(&snapshot)])?;
+                Snapshot {
+                    repo: zfs_snapshot_dir(&mountpoint, &name).join(relative(&repo, &mountpoint)),
+                    kind: Kind::Zfs(snapshot),
+                }
+            }
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "not on btrfs or ZFS",
+                ))
+            }
+        };
+        debug!("Snapshotted {:?} at {:?}", repo, snapshot.repo);
+        Ok(snapshot)
+    }
+
+    /// The repository, as it was when the snapshot was taken.
+    pub fn repo(&self) -> &Path {
+        &self.repo
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let (snapshot, deleted) = match &self.kind {
+            Kind::Btrfs(path) => (
+                path.display().to_string(),
+                run(
+                    "btrfs",
+                    [
+                        OsStr::new("subvolume"),
+                        OsStr::new("delete"),
+                        path.as_os_str(),
+                    ],
+                ),
+            ),
+            Kind::Zfs(snapshot) => (
+                snapshot.clone(),
+                run("zfs", [OsStr::new("destroy"), OsStr::new(snapshot)]),
+            ),
+        };
+        if let Err(e) = deleted {
+            warn!("Could not delete the snapshot {}: {}", snapshot, e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Filesystem {
+    Btrfs,
+    Zfs,
+}
+
+/// Which of the filesystems that can be snapshotted `path` is on, if any.
+#[cfg(target_os = "linux")]
+fn filesystem(path: &Path) -> Option<Filesystem> {
+    use std::os::unix::ffi::OsStrExt;
+
+    const BTRFS_SUPER_MAGIC: i64 = 0x9123_683e;
+    const ZFS_SUPER_MAGIC: i64 = 0x2fc1_2fc1;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    match stat.f_type as i64 {
+        BTRFS_SUPER_MAGIC => Some(Filesystem::Btrfs),
+        ZFS_SUPER_MAGIC => Some(Filesystem::Zfs),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn filesystem(path: &Path) -> Option<Filesystem> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    (name.to_bytes() == b"zfs").then_some(Filesystem::Zfs)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn filesystem(_path: &Path) -> Option<Filesystem> {
+    None
+}
+
+/// The root of the btrfs subvolume holding `path`, the closest ancestor with
+/// the inode number btrfs gives subvolume roots.
+#[cfg(unix)]
+fn subvolume_root(path: &Path) -> io::Result<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    const BTRFS_FIRST_FREE_OBJECTID: u64 = 256;
+
+    for ancestor in path.ancestors() {
+        if std::fs::metadata(ancestor)?.ino() == BTRFS_FIRST_FREE_OBJECTID {
+            return Ok(ancestor.to_path_buf());
+        }
+    }
+    Err(io::Error::other(format!(
+        "no btrfs subvolume holds {:?}",
+        path
+    )))
+}
+
+#[cfg(not(unix))]
+fn subvolume_root(path: &Path) -> io::Result<PathBuf> {
+    Err(io::Error::other(format!(
+        "no btrfs subvolume holds {:?}",
+        path
+    )))
+}
+
+/// The dataset and mountpoint `zfs list -H -o name,mountpoint` printed.
+fn parse_zfs_list(output: &str) -> Option<(String, PathBuf)> {
+    let (dataset, mountpoint) = output.lines().next()?.split_once('\t')?;
+    Path::new(mountpoint)
+        .is_absolute()
+        .then(|| (dataset.to_string(), PathBuf::from(mountpoint)))
+}
+
+/// Where ZFS shows the snapshot `name` of the dataset mounted at `mountpoint`.
+fn zfs_snapshot_dir(mountpoint: &Path, name: &str) -> PathBuf {
+    mountpoint.join(".zfs").join("snapshot").join(name)
+}
+
+/// `path` relative to its ancestor `root`.
+fn relative<'a>(path: &'a Path, root: &Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(Path::new(""))
+}
+
+/// A number telling apart the snapshots one run takes.
+fn unique() -> usize {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Ordering::SeqCst)
+}
+
+/// Runs `program` with `args`, returning what it printed.
+fn run<'a>(program: &str, args: impl IntoIterator<Item = &'a OsStr>) -> io::Result<String> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_zfs_paths() {
+        assert_eq!(
+            parse_zfs_list("tank/home\t/home\n"),
+            Some(("tank/home".to_string(), PathBuf::from("/home")))
+        );
+        assert_eq!(parse_zfs_list("tank/vm\tlegacy\n"), None);
+        assert_eq!(parse_zfs_list(""), None);
+        let repo = Path::new("/home/me/src/api");
+        assert_eq!(
+            zfs_snapshot_dir(Path::new("/home"), "mv-git-1-0")
+                .join(relative(repo, Path::new("/home"))),
+            PathBuf::from("/home/.zfs/snapshot/mv-git-1-0/me/src/api")
+        );
+    }
+
+    #[test]
+    fn test_take_unsupported() -> io::Result<()> {
+        let dir = tempdir()?;
+        if filesystem(dir.path()).is_none() {
+            let e = Snapshot::take(dir.path()).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::Unsupported);
+        }
+        Ok(())
+    }
+}
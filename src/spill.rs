@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::scan::{Entry, EntryKind, FileStamp};
+
+/// Entries read back from a spill file at a time.
+pub const BATCH_LEN: usize = 16 * 1024;
+
+/// Rough memory taken by `entry` in a pre-scan, to enforce `--memory-limit`.
+pub fn footprint(entry: &Entry) -> usize {
+    std::mem::size_of::<Entry>() + entry.rel.as_os_str().len()
+}
+
+/// Entries of a pre-scan that didn't fit in its memory budget, kept in an
+/// anonymous temporary file removed when this is dropped.
+#[derive(Debug)]
+pub struct Spill {
+    writer: BufWriter<File>,
+    len: usize,
+}
+
+fn kind_byte(kind: EntryKind) -> u8 {
+    match kind {
+        EntryKind::Dir => 0,
+        EntryKind::File => 1,
+        EntryKind::Symlink => 2,
+        EntryKind::Fifo => 3,
+        EntryKind::Socket => 4,
+        EntryKind::Device => 5,
+    }
+}
+
+fn byte_kind(byte: u8) -> io::Result<EntryKind> {
+    Ok(match byte {
+        0 => EntryKind::Dir,
+        1 => EntryKind::File,
+        2 => EntryKind::Symlink,
+        3 => EntryKind::Fifo,
+        4 => EntryKind::Socket,
+        5 => EntryKind::Device,
+        _ => return Err(io::Error::new(ErrorKind::InvalidData, "corrupt spill file")),
+    })
+}
+
+#[cfg(unix)]
+fn path_bytes(rel: &std::path::Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    rel.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(rel: &std::path::Path) -> Vec<u8> {
+    rel.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn bytes_path(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn bytes_path(bytes: Vec<u8>) -> io::Result<PathBuf> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+impl Spill {
+    pub fn new() -> io::Result<Spill> {
+        Ok(Spill {
+            writer: BufWriter::new(tempfile::tempfile()?),
+            len: 0,
+        })
+    }
+
+    /// Appends `entry` as `(kind, size, mtime, path length, path)`.
+    pub fn push(&mut self, entry: &Entry) -> io::Result<()> {
+        let rel = path_bytes(&entry.rel);
+        self.writer.write_all(&[kind_byte(entry.kind)])?;
+        self.writer.write_all(&entry.stamp.size.to_le_bytes())?;
+        self.writer.write_all(&entry.stamp.mtime.to_le_bytes())?;
+        self.writer.write_all(&(rel.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&rel)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Writes out what is still buffered, so the entries can be read back.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Reads the entries back, `BATCH_LEN` at a time. Only one reader may be
+    /// used at once, as they share the file position.
+    pub fn batches(&self) -> io::Result<SpillBatches> {
+        let mut file = self.writer.get_ref().try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(SpillBatches {
+            reader: BufReader::new(file),
+            left: self.len,
+        })
+    }
+}
+
+pub struct SpillBatches {
+    reader: BufReader<File>,
+    left: usize,
+}
+
+impl SpillBatches {
+    fn read_entry(&mut self) -> io::Result<Entry> {
+        let mut head = [0; 1 + 8 + 8 + 4];
+        self.reader.read_exact(&mut head)?;
+        let kind = byte_kind(head[0])?;
+        let size = u64::from_le_bytes(head[1..9].try_into().unwrap());
+        let mtime = i64::from_le_bytes(head[9..17].try_into().unwrap());
+        let len = u32::from_le_bytes(head[17..21].try_into().unwrap()) as usize;
+        let mut rel = vec![0; len];
+        self.reader.read_exact(&mut rel)?;
+        Ok(Entry {
+            rel: bytes_path(rel)?,
+            kind,
+            stamp: FileStamp { size, mtime },
+        })
+    }
+}
+
+impl Iterator for SpillBatches {
+    type Item = io::Result<Vec<Entry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        let count = self.left.min(BATCH_LEN);
+        self.left -= count;
+        Some((0..count).map(|_| self.read_entry()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill_roundtrip() -> io::Result<()> {
+        let entries: Vec<Entry> = (0..BATCH_LEN + 10)
+            .map(|i| Entry {
+                rel: PathBuf::from(format!("dir/file-{}.txt", i)),
+                kind: if i % 2 == 0 {
+                    EntryKind::File
+                } else {
+                    EntryKind::Symlink
+                },
+                stamp: FileStamp {
+                    size: i as u64,
+                    mtime: -(i as i64),
+                },
+            })
+            .collect();
+
+        let mut spill = Spill::new()?;
+        for entry in &entries {
+            spill.push(entry)?;
+        }
+        spill.flush()?;
+
+        let batches = spill.batches()?.collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches.concat(), entries);
+        // Reading again starts over.
+        assert_eq!(spill.batches()?.count(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Number of the slowest files kept for the summary of each repository.
+const SLOWEST: usize = 5;
+
+/// Files and bytes copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Totals {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl std::ops::Sub for Totals {
+    type Output = Totals;
+
+    fn sub(self, earlier: Totals) -> Totals {
+        Totals {
+            files: self.files - earlier.files,
+            bytes: self.bytes - earlier.bytes,
+        }
+    }
+}
+
+/// A file that took long to copy.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SlowFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub secs: f64,
+}
+
+/// How far the copy of a repository got, as the `progress` events of `serve`
+/// tell it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub repo: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub files_done: u64,
+}
+
+/// The repository being copied: its name, the bytes it has to copy, and the
+/// totals of the run when it started.
+#[derive(Debug)]
+struct Current {
+    repo: String,
+    bytes_total: u64,
+    start: Totals,
+}
+
+/// Counters shared by all the copies of a run.
+#[derive(Debug, Default)]
+pub struct Stats {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    /// Bytes written of the files being copied, not recorded yet.
+    in_flight: AtomicU64,
+    /// Slowest files since the last [`Stats::take_slowest`], slowest first.
+    slowest: Mutex<Vec<SlowFile>>,
+    current: Mutex<Option<Current>>,
+}
+
+impl Stats {
+    pub fn record_file(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Counts `bytes` just written of a file being copied in the progress.
+    pub fn add_in_flight(&self, bytes: u64) {
+        self.in_flight.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Stops counting `bytes` of a file in the progress, as it was copied, and
+    /// recorded, or failed.
+    pub fn remove_in_flight(&self, bytes: u64) {
+        self.in_flight.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Records that copying `path` took `elapsed`, keeping it if it is among the slowest.
+    pub fn record_time(&self, path: &Path, bytes: u64, elapsed: Duration) {
+        let mut slowest = self.slowest.lock().unwrap();
+        let secs = elapsed.as_secs_f64();
+        if slowest.len() == SLOWEST && slowest.last().is_some_and(|f| f.secs >= secs) {
+            return;
+        }
+        let at = slowest.partition_point(|f| f.secs >= secs);
+        slowest.insert(
+            at,
+            SlowFile {
+                path: path.to_path_buf(),
+                bytes,
+                secs,
+            },
+        );
+        slowest.truncate(SLOWEST);
+    }
+
+    /// The slowest files recorded since the last call, slowest first.
+    pub fn take_slowest(&self) -> Vec<SlowFile> {
+        std::mem::take(&mut *self.slowest.lock().unwrap())
+    }
+
+    pub fn totals(&self) -> Totals {
+        Totals {
+            files: self.files.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Starts counting the progress of the repository `repo`, until [`Stats::end_repo`].
+    pub fn start_repo(&self, repo: &str) {
+        *self.current.lock().unwrap() = Some(Current {
+            repo: repo.to_string(),
+            bytes_total: 0,
+            start: self.totals(),
+        });
+    }
+
+    /// Adds `bytes` to what the repository in flight has to copy.
+    pub fn expect_bytes(&self, bytes: u64) {
+        if let Some(current) = self.current.lock().unwrap().as_mut() {
+            current.bytes_total += bytes;
+        }
+    }
+
+    pub fn end_repo(&self) {
+        *self.current.lock().unwrap() = None;
+    }
+
+    /// How far the repository in flight got, if there is one, down to the
+    /// parts of the files being copied.
+    pub fn progress(&self) -> Option<Progress> {
+        let current = self.current.lock().unwrap();
+        let current = current.as_ref()?;
+        let done = self.totals() - current.start;
+        Some(Progress {
+            repo: current.repo.clone(),
+            bytes_done: done.bytes + self.in_flight.load(Ordering::Relaxed),
+            bytes_total: current.bytes_total,
+            files_done: done.files,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::default();
+        let before = stats.totals();
+        stats.record_file(10);
+        stats.record_file(5);
+        assert_eq!(
+            stats.totals() - before,
+            Totals {
+                files: 2,
+                bytes: 15
+            }
+        );
+
+        for (i, ms) in [3, 9, 1, 7, 5, 8].into_iter().enumerate() {
+            stats.record_time(
+                Path::new(&format!("file{}", i)),
+                10,
+                Duration::from_millis(ms),
+            );
+        }
+        let slowest: Vec<f64> = stats.take_slowest().iter().map(|f| f.secs).collect();
+        assert_eq!(slowest, vec![0.009, 0.008, 0.007, 0.005, 0.003]);
+        assert!(stats.take_slowest().is_empty());
+    }
+
+    #[test]
+    fn test_progress() {
+        let stats = Stats::default();
+        stats.record_file(10);
+        assert_eq!(stats.progress(), None);
+        stats.start_repo("api");
+        stats.expect_bytes(30);
+        stats.record_file(5);
+        stats.add_in_flight(8);
+        assert_eq!(stats.progress().map(|p| p.bytes_done), Some(13));
+        stats.add_in_flight(12);
+        stats.remove_in_flight(20);
+        stats.record_file(20);
+        assert_eq!(
+            stats.progress(),
+            Some(Progress {
+                repo: "api".to_string(),
+                bytes_done: 25,
+                bytes_total: 30,
+                files_done: 2,
+            })
+        );
+        stats.end_repo();
+        assert_eq!(stats.progress(), None);
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::Path;
+
+use crate::bench::{self, BenchResults};
+use crate::copy::Strategy;
+use crate::preflight;
+
+/// How repositories are transferred, see `--strategy`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StrategyChoice {
+    /// Rename moves within a filesystem when nothing is left out, clone files on
+    /// filesystems that can, and otherwise copy the way `bench` found fastest.
+    #[default]
+    Auto,
+    /// Rename whole repositories when moving within a filesystem, ignored files
+    /// included.
+    Rename,
+    /// Copy the contents of files this way.
+    Copy(Strategy),
+}
+
+impl StrategyChoice {
+    pub fn parse(value: &str) -> Result<StrategyChoice, String> {
+        match value {
+            "auto" => Ok(StrategyChoice::Auto),
+            "rename" => Ok(StrategyChoice::Rename),
+            _ => Strategy::parse(value)
+                .map(StrategyChoice::Copy)
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid --strategy {:?}, expected auto, rename, reflink, \
+                     copy-file-range, read-write or mmap",
+                        value
+                    )
+                }),
+        }
+    }
+}
+
+/// Identifier of the filesystem `path` is, or will be created, on.
+pub fn device(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    bench::device(existing).ok()
+}
+
+/// Whether `src` and `dst` are on the same filesystem.
+pub fn same_filesystem(src: &Path, dst: &Path) -> bool {
+    matches!((device(src), device(dst)), (Some(a), Some(b)) if a == b)
+}
+
+/// The copy strategy for files going from `src` to `dst`: a clone on filesystems
+/// that can, else what `bench` measured fastest between these filesystems, else
+/// the platform default.
+pub fn pick_copy(src: &Path, dst: &Path, results: &BenchResults) -> Option<Strategy> {
+    let existing = dst.ancestors().find(|p| p.exists())?;
+    if same_filesystem(src, dst) && preflight::supports_reflink(existing) {
+        return Some(Strategy::Reflink);
+    }
+    let best = results.find(device(src)?, device(dst)?)?.best()?;
+    Strategy::parse(&best.strategy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{BenchResult, Measurement};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(StrategyChoice::parse("auto"), Ok(StrategyChoice::Auto));
+        assert_eq!(
+            StrategyChoice::parse("reflink"),
+            Ok(StrategyChoice::Copy(Strategy::Reflink))
+        );
+        assert_eq!(
+            StrategyChoice::parse("mmap"),
+            Ok(StrategyChoice::Copy(Strategy::Mmap))
+        );
+        assert!(StrategyChoice::parse("hardlink").is_err());
+    }
+
+    #[test]
+    fn test_pick_copy() -> std::io::Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src");
+        std::fs::create_dir(&src)?;
+        let dst = dir.path().join("dst").join("repo");
+        assert!(same_filesystem(&src, &dst));
+        if preflight::supports_reflink(dir.path()) {
+            return Ok(());
+        }
+
+        let mut results = BenchResults::default();
+        assert_eq!(pick_copy(&src, &dst, &results), None);
+        let dev = device(&src).unwrap();
+        results.record(BenchResult {
+            source_dev: dev,
+            dest_dev: dev,
+            source: src.clone(),
+            dest: dst.clone(),
+            measurements: vec![
+                Measurement {
+                    strategy: "read-write".to_string(),
+                    bytes_per_sec: Some(2e9),
+                },
+                Measurement {
+                    strategy: "copy-file-range".to_string(),
+                    bytes_per_sec: Some(1e9),
+                },
+                Measurement {
+                    strategy: "hardlink".to_string(),
+                    bytes_per_sec: Some(9e9),
+                },
+            ],
+        });
+        assert_eq!(pick_copy(&src, &dst, &results), Some(Strategy::ReadWrite));
+        Ok(())
+    }
+}
@@ -0,0 +1,406 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::color::{self, Color};
+use crate::stats::{SlowFile, Totals};
+use crate::units;
+
+/// What happened to one directory of the source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+pub enum Outcome {
+    Moved,
+    Copied,
+    /// Copied with `--sync`, at least one file had changed.
+    Synced,
+    /// Left alone by `--sync`, nothing changed since the last one.
+    Unchanged,
+    /// An identical copy was already at the destination.
+    Deduplicated,
+    /// Copied and verified with `--confirm-delete`, the source is kept until
+    /// the delete is confirmed.
+    Verified,
+    /// Copied, but the source is kept whole, as `--one-file-system` left out the
+    /// mount points inside it.
+    Kept,
+    /// Not a git repository.
+    Skipped,
+    /// Pinned by the config or its `.mv-git.toml`, intentionally left alone.
+    Pinned,
+    /// Left alone by `--sync`, changed both at the source and at the
+    /// destination since the last one, with what changed at the destination.
+    Conflicted(String),
+    Failed(String),
+}
+
+impl Outcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Moved => "moved",
+            Outcome::Copied => "copied",
+            Outcome::Synced => "synced",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Deduplicated => "deduplicated",
+            Outcome::Verified => "verified",
+            Outcome::Kept => "kept",
+            Outcome::Skipped => "skipped",
+            Outcome::Pinned => "pinned",
+            Outcome::Conflicted(_) => "conflicted",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Outcome::Moved
+            | Outcome::Copied
+            | Outcome::Synced
+            | Outcome::Deduplicated
+            | Outcome::Verified => Color::Green,
+            Outcome::Unchanged | Outcome::Kept | Outcome::Skipped | Outcome::Pinned => {
+                Color::Yellow
+            }
+            Outcome::Conflicted(_) | Outcome::Failed(_) => Color::Red,
+        }
+    }
+}
+
+/// How long the transfer of a repository took, and what slowed it down.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Timing {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_per_sec: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub slowest_files: Vec<SlowFile>,
+}
+
+impl Timing {
+    /// `  (2.5s, 12.0M/s)`, for the table, empty when the transfer was not timed.
+    fn describe(&self) -> String {
+        match (self.duration_secs, self.bytes_per_sec) {
+            (Some(secs), Some(rate)) => {
+                format!("  ({:.1}s, {}/s)", secs, units::format_size(rate as u64))
+            }
+            (Some(secs), None) => format!("  ({:.1}s)", secs),
+            _ => String::new(),
+        }
+    }
+
+    /// The timing of a transfer of `bytes` that took `elapsed`.
+    pub fn new(elapsed: Duration, bytes: u64, slowest_files: Vec<SlowFile>) -> Timing {
+        let secs = elapsed.as_secs_f64();
+        Timing {
+            duration_secs: Some(secs),
+            bytes_per_sec: (secs > 0.0).then(|| bytes as f64 / secs),
+            slowest_files,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RepoOutcome {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    #[serde(flatten)]
+    pub transferred: Totals,
+    #[serde(flatten)]
+    pub timing: Timing,
+}
+
+/// Outcome of a whole run, one line per directory of the source.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub repos: Vec<RepoOutcome>,
+}
+
+impl Summary {
+    pub fn push(
+        &mut self,
+        source: PathBuf,
+        dest: PathBuf,
+        outcome: Outcome,
+        transferred: Totals,
+        timing: Timing,
+    ) {
+        self.repos.push(RepoOutcome {
+            source,
+            dest,
+            outcome,
+            transferred,
+            timing,
+        });
+    }
+
+    /// Destinations of the repositories that are now there.
+    pub fn transferred(&self) -> Vec<PathBuf> {
+        self.repos
+            .iter()
+            .filter(|r| {
+                !matches!(
+                    r.outcome,
+                    Outcome::Skipped
+                        | Outcome::Pinned
+                        | Outcome::Conflicted(_)
+                        | Outcome::Failed(_)
+                )
+            })
+            .map(|r| r.dest.clone())
+            .collect()
+    }
+
+    /// Sources of the repositories `--sync` left alone as they changed on both sides.
+    pub fn conflicted(&self) -> Vec<&Path> {
+        self.repos
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Conflicted(_)))
+            .map(|r| r.source.as_path())
+            .collect()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.repos
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Failed(_)))
+            .count()
+    }
+
+    /// Counts per status, e.g. `3 moved, 1 failed`.
+    pub fn describe(&self) -> String {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for repo in &self.repos {
+            match counts
+                .iter_mut()
+                .find(|(label, _)| *label == repo.outcome.label())
+            {
+                Some((_, count)) => *count += 1,
+                None => counts.push((repo.outcome.label(), 1)),
+            }
+        }
+        if counts.is_empty() {
+            return "nothing to do".to_string();
+        }
+        counts
+            .iter()
+            .map(|(label, count)| format!("{} {}", count, label))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The summary as JSON: the run's parameters and timing, per-status counts and
+    /// totals, the conflicted repositories, then one object per directory of the source.
+    pub fn to_json(
+        &self,
+        sources: &[String],
+        dest: &str,
+        mode: &str,
+        started: SystemTime,
+    ) -> serde_json::Value {
+        let mut counts = std::collections::BTreeMap::new();
+        for repo in &self.repos {
+            *counts.entry(repo.outcome.label()).or_insert(0) += 1;
+        }
+        serde_json::json!({
+            "sources": sources,
+            "destination": dest,
+            "mode": mode,
+            "started_at": started.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64()),
+            "duration_secs": started.elapsed().map_or(0.0, |d| d.as_secs_f64()),
+            "counts": counts,
+            "failed": self.failed(),
+            "conflicted": self.conflicted(),
+            "files": self.repos.iter().map(|r| r.transferred.files).sum::<u64>(),
+            "bytes": self.repos.iter().map(|r| r.transferred.bytes).sum::<u64>(),
+            "repos": self.repos,
+        })
+    }
+
+    /// What [`Summary::to_json`] tells of a run that stopped with `error`
+    /// before it had a summary: its parameters and timing, and the error.
+    pub fn failure_json(
+        sources: &[String],
+        dest: &str,
+        mode: &str,
+        started: SystemTime,
+        error: &io::Error,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "sources": sources,
+            "destination": dest,
+            "mode": mode,
+            "started_at": started.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64()),
+            "duration_secs": started.elapsed().map_or(0.0, |d| d.as_secs_f64()),
+            "error": error.to_string(),
+        })
+    }
+
+    /// Writes the summary as JSON to `path`, for `--report`.
+    pub fn write_report(
+        &self,
+        path: &Path,
+        sources: &[String],
+        dest: &str,
+        mode: &str,
+        started: SystemTime,
+    ) -> io::Result<()> {
+        let report = self.to_json(sources, dest, mode, started);
+        fs::write(path, serde_json::to_string_pretty(&report)? + "\n")
+    }
+
+    /// The summary as aligned `status  source  destination` columns, followed by
+    /// the errors of failed repositories, and the time and rate of the transfers.
+    pub fn table(&self, colored: bool) -> String {
+        let status_width = self
+            .repos
+            .iter()
+            .map(|r| r.outcome.label().len())
+            .max()
+            .unwrap_or(0);
+        let source_width = self
+            .repos
+            .iter()
+            .map(|r| r.source.to_string_lossy().chars().count())
+            .max()
+            .unwrap_or(0);
+
+        let mut table = String::new();
+        for repo in &self.repos {
+            // Pad before coloring, escapes would count towards the width.
+            let status = format!("{:<width$}", repo.outcome.label(), width = status_width);
+            let source = repo.source.to_string_lossy();
+            let rest = match &repo.outcome {
+                Outcome::Skipped | Outcome::Pinned => source.to_string(),
+                Outcome::Failed(error) => {
+                    format!("{:<width$}  {}", source, error, width = source_width)
+                }
+                Outcome::Conflicted(changed) => format!(
+                    "{:<width$}  {}  ({})",
+                    source,
+                    repo.dest.display(),
+                    changed,
+                    width = source_width
+                ),
+                _ => format!(
+                    "{:<width$}  {}{}",
+                    source,
+                    repo.dest.display(),
+                    repo.timing.describe(),
+                    width = source_width
+                ),
+            };
+            table.push_str(&color::paint(&status, repo.outcome.color(), colored));
+            table.push_str("  ");
+            table.push_str(&rest);
+            table.push('\n');
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let mut summary = Summary::default();
+        summary.push(
+            "src/a".into(),
+            "dst/a".into(),
+            Outcome::Moved,
+            Totals {
+                files: 3,
+                bytes: 30,
+            },
+            Timing::new(Duration::from_secs(2), 30, Vec::new()),
+        );
+        summary.push(
+            "src/long-name".into(),
+            "dst/long-name".into(),
+            Outcome::Unchanged,
+            Totals::default(),
+            Timing::default(),
+        );
+        summary.push(
+            "src/notes".into(),
+            "dst/notes".into(),
+            Outcome::Skipped,
+            Totals::default(),
+            Timing::default(),
+        );
+        summary.push(
+            "src/b".into(),
+            "dst/b".into(),
+            Outcome::Failed("disk full".into()),
+            Totals::default(),
+            Timing::default(),
+        );
+
+        assert_eq!(
+            summary.table(false),
+            "moved      src/a          dst/a  (2.0s, 15B/s)\n\
+             unchanged  src/long-name  dst/long-name\n\
+             skipped    src/notes\n\
+             failed     src/b          disk full\n"
+        );
+        assert!(summary
+            .table(true)
+            .starts_with("\x1b[32mmoved    \x1b[0m  src/a"));
+        assert_eq!(summary.failed(), 1);
+        assert_eq!(
+            summary.describe(),
+            "1 moved, 1 unchanged, 1 skipped, 1 failed"
+        );
+        assert_eq!(Summary::default().describe(), "nothing to do");
+        assert_eq!(
+            serde_json::to_value(&summary.repos[3]).unwrap(),
+            serde_json::json!({
+                "source": "src/b",
+                "dest": "dst/b",
+                "status": "failed",
+                "error": "disk full",
+                "files": 0,
+                "bytes": 0,
+            })
+        );
+        assert_eq!(
+            summary.transferred(),
+            vec![PathBuf::from("dst/a"), PathBuf::from("dst/long-name")]
+        );
+
+        summary.push(
+            "src/c".into(),
+            "dst/c".into(),
+            Outcome::Conflicted("changed at the destination too: a.txt".into()),
+            Totals::default(),
+            Timing::default(),
+        );
+        assert!(summary.table(false).ends_with(
+            "conflicted  src/c          dst/c  (changed at the destination too: a.txt)\n"
+        ));
+        assert_eq!(summary.conflicted(), vec![Path::new("src/c")]);
+        assert_eq!(summary.transferred().len(), 2);
+    }
+
+    #[test]
+    fn test_failure_json() {
+        let error = io::Error::other("Could not read the source");
+        let json = Summary::failure_json(
+            &["src".to_string()],
+            "dst",
+            "move",
+            SystemTime::now(),
+            &error,
+        );
+        assert_eq!(json["destination"], "dst");
+        assert_eq!(json["error"], "Could not read the source");
+        assert!(json.get("repos").is_none());
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Moves `path` to the trash of the current user, where it can be restored
+/// from, see `--to-trash`. Returns where it went.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn trash(path: &Path) -> io::Result<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = std::path::absolute(path)?;
+    let home = home_trash()?;
+    let home_dev = home
+        .ancestors()
+        .find_map(|dir| fs::metadata(dir).ok())
+        .map(|m| m.dev());
+    let dev = fs::symlink_metadata(&path)?.dev();
+    if home_dev == Some(dev) {
+        return put_in(&home, &path, None);
+    }
+    // Renaming into the home trash would cross filesystems, use the trash at
+    // the top of the one `path` is on instead.
+    let top = path
+        .ancestors()
+        .take_while(|dir| fs::metadata(dir).is_ok_and(|m| m.dev() == dev))
+        .last()
+        .unwrap_or(&path)
+        .to_path_buf();
+    let uid = unsafe { libc::getuid() };
+    put_in(&top.join(format!(".Trash-{}", uid)), &path, Some(&top))
+}
+
+/// Moves `path` to the trash of the current user, see `--to-trash`. Returns
+/// where it went.
+#[cfg(target_os = "macos")]
+pub fn trash(path: &Path) -> io::Result<PathBuf> {
+    let home = std::env::var_os("HOME")
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "HOME is not set"))?;
+    let trash = Path::new(&home).join(".Trash");
+    let name = file_name(path)?;
+    for n in 1.. {
+        let dest = trash.join(numbered(&name, n));
+        if fs::symlink_metadata(&dest).is_err() {
+            fs::rename(path, &dest)?;
+            return Ok(dest);
+        }
+    }
+    unreachable!()
+}
+
+/// Sends `path` to the Recycle Bin, see `--to-trash`.
+#[cfg(windows)]
+pub fn trash(path: &Path) -> io::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let method = if path.is_dir() {
+        "DeleteDirectory"
+    } else {
+        "DeleteFile"
+    };
+    let script = format!(
+        "Add-Type -AssemblyName Microsoft.VisualBasic; \
+         [Microsoft.VisualBasic.FileIO.FileSystem]::{}('{}', 'OnlyErrorDialogs', 'SendToRecycleBin')",
+        method,
+        path.display().to_string().replace('\'', "''")
+    );
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-Command", &script])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Could not send {:?} to the Recycle Bin: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(PathBuf::from("Recycle Bin"))
+}
+
+/// The trash in the home directory: `Trash` in the user's data directory.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn home_trash() -> io::Result<PathBuf> {
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "HOME is not set"))?;
+    Ok(data.join("Trash"))
+}
+
+#[cfg(unix)]
+fn file_name(path: &Path) -> io::Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("Can't trash {:?}", path)))
+}
+
+/// `name` for the first item of that name in the trash, `name.n` for the next ones.
+#[cfg(unix)]
+fn numbered(name: &str, n: u32) -> String {
+    if n == 1 {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, n)
+    }
+}
+
+/// Moves `path` into the freedesktop.org trash directory `trash`, recording
+/// where it came from: relative to `top` for the trash at the top of a
+/// filesystem, absolute for the home trash.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn put_in(trash: &Path, path: &Path, top: Option<&Path>) -> io::Result<PathBuf> {
+    let (files, info) = (trash.join("files"), trash.join("info"));
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+    let original = match top {
+        Some(top) => path.strip_prefix(top).unwrap_or(path),
+        None => path,
+    };
+    let name = file_name(path)?;
+    for n in 1.. {
+        let name = numbered(&name, n);
+        // The info file is created first and exclusively, so that a name is
+        // never taken twice.
+        let info_path = info.join(format!("{}.trashinfo", name));
+        let mut info_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        let dest = files.join(&name);
+        if fs::symlink_metadata(&dest).is_ok() {
+            fs::remove_file(&info_path)?;
+            continue;
+        }
+        let written = write!(
+            info_file,
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode(original),
+            deletion_date()
+        )
+        .and_then(|_| fs::rename(path, &dest));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&info_path);
+            return Err(e);
+        }
+        return Ok(dest);
+    }
+    unreachable!()
+}
+
+/// `path` escaped the way URLs are, as trash info files want.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn encode(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The current local time, as `YYYY-MM-DDThh:mm:ss`.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn deletion_date() -> String {
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_put_in() -> io::Result<()> {
+        let dir = tempdir()?;
+        let trash = dir.path().join("Trash");
+        fs::create_dir_all(dir.path().join("my repo/src"))?;
+        fs::write(dir.path().join("my repo/src/main.rs"), "fn main() {}")?;
+        let first = put_in(&trash, &dir.path().join("my repo"), None)?;
+        assert_eq!(first, trash.join("files/my repo"));
+        assert!(first.join("src/main.rs").exists());
+        assert!(!dir.path().join("my repo").exists());
+
+        fs::create_dir(dir.path().join("my repo"))?;
+        let second = put_in(&trash, &dir.path().join("my repo"), Some(dir.path()))?;
+        assert_eq!(second, trash.join("files/my repo.2"));
+        let info = fs::read_to_string(trash.join("info/my repo.2.trashinfo"))?;
+        assert!(info.starts_with("[Trash Info]\nPath=my%20repo\nDeletionDate=20"));
+        let info = fs::read_to_string(trash.join("info/my repo.trashinfo"))?;
+        assert!(info.contains(&format!("Path={}", encode(&dir.path().join("my repo")))));
+        Ok(())
+    }
+}
@@ -0,0 +1,479 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use serde::Deserialize;
+
+/// The folders of a VS Code `.code-workspace` file.
+#[derive(Debug, Deserialize)]
+struct CodeWorkspace {
+    #[serde(default)]
+    folders: Vec<Folder>,
+}
+
+/// A folder is given by its `path`, relative to the workspace file or
+/// absolute, or by a `uri`.
+#[derive(Debug, Deserialize)]
+struct Folder {
+    path: Option<String>,
+    uri: Option<String>,
+}
+
+/// The projects referenced by the editor file `path`: the folders of a VS Code
+/// `.code-workspace` file, or the recent projects of a JetBrains
+/// `recentProjects.xml`. Those that exist are canonicalized.
+pub fn projects(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let text = fs::read_to_string(path)?;
+    let invalid = |e: String| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid workspace {:?}: {}", path, e),
+        )
+    };
+    let base = std::path::absolute(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let projects = if text.trim_start().starts_with('<') {
+        jetbrains_projects(&text).map_err(invalid)?
+    } else {
+        code_projects(&text, &base).map_err(invalid)?
+    };
+    Ok(projects
+        .into_iter()
+        .map(|project| fs::canonicalize(&project).unwrap_or(project))
+        .collect())
+}
+
+fn code_projects(text: &str, base: &Path) -> Result<Vec<PathBuf>, String> {
+    let workspace: CodeWorkspace =
+        serde_json::from_str(&strip_jsonc(text)).map_err(|e| e.to_string())?;
+    let mut projects = Vec::new();
+    for folder in workspace.folders {
+        match (folder.path, folder.uri) {
+            (Some(path), _) => projects.push(base.join(path)),
+            (None, Some(uri)) => match uri.strip_prefix("file://") {
+                Some(path) => projects.push(PathBuf::from(decode(path)?)),
+                None => return Err(format!("only file:// folders can be moved, not {}", uri)),
+            },
+            (None, None) => return Err("a folder has neither path nor uri".to_string()),
+        }
+    }
+    Ok(projects)
+}
+
+/// `text` as plain JSON: VS Code allows comments and trailing commas.
+fn strip_jsonc(text: &str) -> String {
+    let mut json = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                json.push(c);
+                while let Some(c) = chars.next() {
+                    json.push(c);
+                    match c {
+                        '\\' => json.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.next_if_eq(&'/').is_some() => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                while let Some(c) = chars.next() {
+                    if c == '*' && chars.next_if_eq(&'/').is_some() {
+                        break;
+                    }
+                }
+            }
+            '}' | ']' => {
+                let end = json.trim_end().len();
+                if json[..end].ends_with(',') {
+                    json.truncate(end - 1);
+                }
+                json.push(c);
+            }
+            c => json.push(c),
+        }
+    }
+    json
+}
+
+/// `%XX` escapes of a URI path decoded.
+fn decode(path: &str) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    let mut rest = path.bytes();
+    while let Some(byte) = rest.next() {
+        if byte == b'%' {
+            let hex = [rest.next(), rest.next()];
+            let hex = hex.iter().flatten().map(|&b| b as char).collect::<String>();
+            bytes.push(
+                u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape in {}", path))?,
+            );
+        } else {
+            bytes.push(byte);
+        }
+    }
+    let decoded = String::from_utf8(bytes).map_err(|_| format!("invalid escape in {}", path))?;
+    // file:///C:/code on Windows.
+    Ok(match decoded.strip_prefix('/') {
+        Some(drive) if cfg!(windows) && drive.get(1..2) == Some(":") => drive.to_string(),
+        _ => decoded,
+    })
+}
+
+/// An element of `recentProjects.xml`, while its children are read.
+struct Element {
+    name: String,
+    option: Option<String>,
+}
+
+/// The projects of a JetBrains `recentProjects.xml`: the keys of the
+/// `additionalInfo` map, or the `recentPaths` list of older versions.
+fn jetbrains_projects(text: &str) -> Result<Vec<PathBuf>, String> {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let mut projects = Vec::new();
+    let mut open: Vec<Element> = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..].find('>').ok_or("a tag is not closed")? + start;
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with(['?', '!']) {
+            continue;
+        }
+        if tag.starts_with('/') {
+            open.pop();
+            continue;
+        }
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let in_option = |option: &str| {
+            open.len() >= 2 && open[open.len() - 2].option.as_deref() == Some(option)
+        };
+        let project = match (name, open.last().map(|e| e.name.as_str())) {
+            ("entry", Some("map")) if in_option("additionalInfo") => attribute(tag, "key"),
+            ("option", Some("list")) if in_option("recentPaths") => attribute(tag, "value"),
+            _ => None,
+        };
+        if let Some(project) = project {
+            projects.push(PathBuf::from(project.replace("$USER_HOME$", &home)));
+        }
+        if !tag.ends_with('/') {
+            open.push(Element {
+                name: name.to_string(),
+                option: (name == "option").then(|| attribute(tag, "name")).flatten(),
+            });
+        }
+    }
+    Ok(projects)
+}
+
+/// The value of the attribute `name` of `tag`, unescaped.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(
+        tag[start..start + len]
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+/// Rewrites the paths pointing into the moved repositories of `moves`, pairs
+/// of source and destination, in the editor file `path`: a `.code-workspace`,
+/// a JetBrains `recentProjects.xml` or any other text file, like an `.envrc`.
+/// Returns how many paths were rewritten; the file is only written if any was.
+pub fn rewrite(path: &Path, moves: &[(PathBuf, PathBuf)]) -> io::Result<usize> {
+    let text = fs::read_to_string(path)?;
+    let home = std::env::var("HOME").ok().filter(|home| !home.is_empty());
+    let base = std::path::absolute(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let mut rewritten = text.clone();
+    let mut count = 0;
+    if path.extension().is_some_and(|ext| ext == "code-workspace") {
+        // Folders relative to the workspace file are written as absolute paths,
+        // since their new location may not be under the same directory.
+        if let Ok(workspace) = serde_json::from_str::<CodeWorkspace>(&strip_jsonc(&text)) {
+            for relative in workspace.folders.iter().filter_map(|f| f.path.as_deref()) {
+                if Path::new(relative).is_absolute() {
+                    continue;
+                }
+                let Some(moved) = moved_to(&normalize(&base.join(relative)), moves) else {
+                    continue;
+                };
+                let (text, n) = replace_path(
+                    &rewritten,
+                    &json_string(relative),
+                    &json_string(&moved.to_string_lossy()),
+                );
+                rewritten = text;
+                count += n;
+            }
+        }
+    }
+    for (source, dest) in moves {
+        let (source, dest) = (source.to_string_lossy(), dest.to_string_lossy());
+        let (text, n) = replace_path(&rewritten, &source, &dest);
+        rewritten = text;
+        count += n;
+        // JetBrains writes paths under the home directory with a macro.
+        if let Some(home) = &home {
+            let under_home = |path: &str| {
+                path.strip_prefix(home.as_str())
+                    .filter(|rest| rest.starts_with('/'))
+                    .map(|rest| format!("$USER_HOME${}", rest))
+            };
+            if let Some(source) = under_home(&source) {
+                let dest = under_home(&dest).unwrap_or_else(|| dest.to_string());
+                let (text, n) = replace_path(&rewritten, &source, &dest);
+                rewritten = text;
+                count += n;
+            }
+        }
+    }
+    if count > 0 {
+        fs::write(path, rewritten)?;
+    }
+    Ok(count)
+}
+
+/// Rewrites the paths pointing into the moved repositories of `moves` in the
+/// file `path` of another tool, as [`rewrite`] does, and with `base` those
+/// relative to it too. Returns how many paths were rewritten.
+pub fn rewrite_reference(
+    path: &Path,
+    moves: &[(PathBuf, PathBuf)],
+    base: Option<&Path>,
+) -> io::Result<usize> {
+    let mut count = rewrite(path, moves)?;
+    let Some(base) = base else {
+        return Ok(count);
+    };
+    let text = fs::read_to_string(path)?;
+    let mut rewritten = text.clone();
+    for (source, dest) in moves {
+        let Ok(source) = source.strip_prefix(base) else {
+            continue;
+        };
+        // Destinations outside of `base` can only be named by absolute paths.
+        let dest = dest.strip_prefix(base).unwrap_or(dest);
+        let (text, n) = replace_path(
+            &rewritten,
+            &source.to_string_lossy(),
+            &dest.to_string_lossy(),
+        );
+        rewritten = text;
+        count += n;
+    }
+    if rewritten != text {
+        fs::write(path, rewritten)?;
+    }
+    Ok(count)
+}
+
+/// Where `path` is now, if it is in one of the moved repositories of `moves`.
+fn moved_to(path: &Path, moves: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+    moves
+        .iter()
+        .find_map(|(source, dest)| match path.strip_prefix(source) {
+            Ok(rest) if rest.as_os_str().is_empty() => Some(dest.clone()),
+            Ok(rest) => Some(dest.join(rest)),
+            Err(_) => None,
+        })
+}
+
+/// `path` with `.` and `..` resolved without looking at the filesystem, where
+/// it may no longer be.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn json_string(text: &str) -> String {
+    serde_json::Value::from(text).to_string()
+}
+
+/// Whether `c` can be part of a path name, so that a path followed or
+/// preceded by it is a different path.
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || "-_.~+@".contains(c)
+}
+
+/// `text` with the paths `from`, and those under it, starting with `to`
+/// instead, and how many were replaced.
+fn replace_path(text: &str, from: &str, to: &str) -> (String, usize) {
+    let mut replaced = String::new();
+    let mut count = 0;
+    let mut rest = text;
+    while let Some(at) = rest.find(from) {
+        let (before, after) = (&rest[..at], &rest[at + from.len()..]);
+        replaced.push_str(before);
+        let starts = before
+            .chars()
+            .next_back()
+            .is_none_or(|c| !is_name_char(c) && c != '/');
+        let ends = after.chars().next().is_none_or(|c| !is_name_char(c));
+        if starts && ends {
+            replaced.push_str(to);
+            count += 1;
+        } else {
+            replaced.push_str(from);
+        }
+        rest = after;
+    }
+    replaced.push_str(rest);
+    (replaced, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_code_workspace() -> io::Result<()> {
+        let dir = tempdir()?;
+        let ws = dir.path().join("ws");
+        fs::create_dir_all(dir.path().join("code/api"))?;
+        fs::create_dir(&ws)?;
+        let file = ws.join("all.code-workspace");
+        fs::write(
+            &file,
+            r#"{
+                // Everything for work.
+                "folders": [
+                    { "path": "../code/api", },
+                    { "name": "web /* not a comment */", "uri": "file:///srv/my%20web" },
+                ],
+                "settings": {},
+            }"#,
+        )?;
+        assert_eq!(
+            projects(&file)?,
+            vec![
+                dir.path().join("code/api").canonicalize()?,
+                PathBuf::from("/srv/my web")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite() -> io::Result<()> {
+        let dir = tempdir()?;
+        let moves = vec![(PathBuf::from("/old/api"), PathBuf::from("/new/api"))];
+        let envrc = dir.path().join(".envrc");
+        fs::write(
+            &envrc,
+            "PATH_add /old/api/bin\nexport OTHER=/old/api-v2:/other/old/api\n",
+        )?;
+        assert_eq!(rewrite(&envrc, &moves)?, 1);
+        assert_eq!(
+            fs::read_to_string(&envrc)?,
+            "PATH_add /new/api/bin\nexport OTHER=/old/api-v2:/other/old/api\n"
+        );
+
+        let code = dir.path().join("ws/all.code-workspace");
+        fs::create_dir(dir.path().join("ws"))?;
+        fs::write(
+            &code,
+            r#"{ "folders": [{ "path": "../api" }, { "path": "/old/api" }] }"#,
+        )?;
+        let moves = vec![
+            (dir.path().join("api"), PathBuf::from("/new/api")),
+            (PathBuf::from("/old/api"), PathBuf::from("/new/web")),
+        ];
+        assert_eq!(rewrite(&code, &moves)?, 2);
+        assert_eq!(
+            fs::read_to_string(&code)?,
+            r#"{ "folders": [{ "path": "/new/api" }, { "path": "/new/web" }] }"#
+        );
+        assert_eq!(rewrite(&code, &[])?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_reference() -> io::Result<()> {
+        let dir = tempdir()?;
+        let moves = vec![
+            (
+                PathBuf::from("/home/me/src/api"),
+                PathBuf::from("/home/me/work/api"),
+            ),
+            (PathBuf::from("/home/me/src/web"), PathBuf::from("/mnt/web")),
+        ];
+        let mrconfig = dir.path().join(".mrconfig");
+        fs::write(
+            &mrconfig,
+            "[src/api]\ncheckout = git clone x api\n\n[src/web]\n\n[/home/me/src/api/sub]\n",
+        )?;
+        assert_eq!(
+            rewrite_reference(&mrconfig, &moves, Some(Path::new("/home/me")))?,
+            3
+        );
+        assert_eq!(
+            fs::read_to_string(&mrconfig)?,
+            "[work/api]\ncheckout = git clone x api\n\n[/mnt/web]\n\n[/home/me/work/api/sub]\n"
+        );
+
+        let repos = dir.path().join("repos.csv");
+        fs::write(&repos, "/home/me/src/web,web\nsrc/api,api\n")?;
+        assert_eq!(rewrite_reference(&repos, &moves, None)?, 1);
+        assert_eq!(fs::read_to_string(&repos)?, "/mnt/web,web\nsrc/api,api\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_jetbrains_projects() {
+        let home = std::env::var("HOME").unwrap_or_default();
+        let xml = r#"<application>
+  <component name="RecentProjectsManager">
+    <option name="additionalInfo">
+      <map>
+        <entry key="$USER_HOME$/IdeaProjects/api">
+          <value>
+            <RecentProjectMetaInfo frameTitle="api &amp; more">
+              <option name="build" value="IU-241" />
+            </RecentProjectMetaInfo>
+          </value>
+        </entry>
+        <entry key="/srv/web" />
+      </map>
+    </option>
+    <option name="lastOpenedProject" value="/srv/other" />
+  </component>
+</application>"#;
+        assert_eq!(
+            jetbrains_projects(xml).unwrap(),
+            vec![
+                PathBuf::from(format!("{}/IdeaProjects/api", home)),
+                PathBuf::from("/srv/web")
+            ]
+        );
+        let old = r#"<option name="recentPaths"><list><option value="/srv/old" /></list></option>"#;
+        assert_eq!(
+            jetbrains_projects(old).unwrap(),
+            vec![PathBuf::from("/srv/old")]
+        );
+    }
+}